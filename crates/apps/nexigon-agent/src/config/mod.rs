@@ -7,6 +7,8 @@ sidex::include_bundle!(
 pub use generated::commands;
 pub use generated::config::*;
 pub use generated::operation_ledger;
+pub use generated::outbox;
+pub use generated::tasks;
 
 /// Terminal service is available only when it is explicitly enabled.
 pub fn terminal_enabled(config: &Config) -> bool {
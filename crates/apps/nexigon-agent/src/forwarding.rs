@@ -0,0 +1,394 @@
+//! Port forwarding policy.
+//!
+//! Forwarding channels connect to local TCP and UDP ports of the device. To not expose
+//! every loopback-only service, e.g., databases and debug ports, to anyone with hub
+//! access, only allowed ports can be reached, regardless of the protocol. Without
+//! `forwarding.allow`, these are the ports of the configured exports.
+//!
+//! In gateway mode, forwarding channels connect to hosts behind the device. Such hosts
+//! can only be reached if they are allowed by an explicit `forwarding.hosts` rule.
+//!
+//! Unix sockets are reached by names declared in `forwarding.sockets`. Channels never
+//! carry socket paths themselves.
+//!
+//! Reverse forwarding binds loopback ports of the device. It is disabled unless
+//! `forwarding.reverse.enabled` is set and only binds ports in
+//! `forwarding.reverse.allow`.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::bail;
+use ipnet::IpNet;
+
+use crate::config::Config;
+use crate::config::ExportConfig;
+
+mod reverse;
+mod udp;
+
+pub(crate) use reverse::bind_reverse_listener;
+pub(crate) use reverse::run_reverse_forwarding;
+pub(crate) use udp::relay_udp_forwarding;
+
+/// Ports and sockets forwarding channels may connect to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ForwardingPolicy {
+    allow: Vec<RangeInclusive<u16>>,
+    deny: Vec<RangeInclusive<u16>>,
+    hosts: Vec<HostRule>,
+    sockets: HashMap<String, PathBuf>,
+    reverse: Vec<RangeInclusive<u16>>,
+}
+
+/// Hosts behind the device and their ports forwarding channels may connect to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HostRule {
+    host: HostPattern,
+    ports: RangeInclusive<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostPattern {
+    /// Addresses of a network, including single addresses.
+    Network(IpNet),
+    /// Host name, allowing all addresses it resolves to.
+    Name(String),
+}
+
+impl ForwardingPolicy {
+    /// Policy of the `forwarding` section of `config`.
+    pub(crate) fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let forwarding = config.forwarding.as_ref();
+        let allow = match forwarding.and_then(|forwarding| forwarding.allow.as_ref()) {
+            Some(allow) => parse_port_ranges(allow).context("invalid `forwarding.allow`")?,
+            None => config
+                .exports
+                .iter()
+                .flatten()
+                .map(|export| match export {
+                    ExportConfig::Http(export) => export.port..=export.port,
+                })
+                .collect(),
+        };
+        let deny = match forwarding.and_then(|forwarding| forwarding.deny.as_ref()) {
+            Some(deny) => parse_port_ranges(deny).context("invalid `forwarding.deny`")?,
+            None => Vec::new(),
+        };
+        let hosts = match forwarding.and_then(|forwarding| forwarding.hosts.as_ref()) {
+            Some(hosts) => hosts
+                .iter()
+                .map(|rule| parse_host_rule(rule))
+                .collect::<anyhow::Result<_>>()
+                .context("invalid `forwarding.hosts`")?,
+            None => Vec::new(),
+        };
+        let sockets = forwarding
+            .and_then(|forwarding| forwarding.sockets.as_ref())
+            .into_iter()
+            .flatten()
+            .map(|(name, path)| (name.clone(), path.clone()))
+            .collect::<HashMap<_, _>>();
+        for (name, path) in &sockets {
+            if name.is_empty() || name.contains('/') {
+                bail!("invalid `forwarding.sockets`: invalid name {name:?}");
+            }
+            if !path.is_absolute() {
+                bail!(
+                    "invalid `forwarding.sockets`: path of {name:?} must be absolute, found {}",
+                    path.display()
+                );
+            }
+        }
+        let reverse = match forwarding.and_then(|forwarding| forwarding.reverse.as_ref()) {
+            Some(reverse) if reverse.enabled == Some(true) => match &reverse.allow {
+                Some(allow) => {
+                    parse_port_ranges(allow).context("invalid `forwarding.reverse.allow`")?
+                }
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            allow,
+            deny,
+            hosts,
+            sockets,
+            reverse,
+        })
+    }
+
+    /// Check whether forwarding to `port` is allowed.
+    pub(crate) fn allows(&self, port: u16) -> bool {
+        self.allow.iter().any(|range| range.contains(&port))
+            && !self.deny.iter().any(|range| range.contains(&port))
+    }
+
+    /// Check whether forwarding to `port` of `host` may be allowed.
+    ///
+    /// For host names, this does not resolve the name. Use [`ForwardingPolicy::resolve`]
+    /// to obtain the addresses forwarding may actually connect to.
+    pub(crate) fn may_allow_host(&self, host: &str, port: u16) -> bool {
+        let address = host.parse::<IpAddr>().ok();
+        self.host_rules(port)
+            .any(|rule| match (&rule.host, address) {
+                (HostPattern::Network(network), Some(address)) => {
+                    network.contains(&address.to_canonical())
+                }
+                (HostPattern::Network(_), None) => true,
+                (HostPattern::Name(name), _) => name.eq_ignore_ascii_case(host),
+            })
+    }
+
+    /// Resolve `host` and return the addresses forwarding to `port` may connect to.
+    pub(crate) async fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        let addresses = match host.parse::<IpAddr>() {
+            Ok(address) => vec![SocketAddr::new(address, port)],
+            Err(_) => tokio::net::lookup_host((host, port)).await?.collect(),
+        };
+        let named = self.host_rules(port).any(
+            |rule| matches!(&rule.host, HostPattern::Name(name) if name.eq_ignore_ascii_case(host)),
+        );
+        Ok(addresses
+            .into_iter()
+            .filter(|address| {
+                named
+                    || self.host_rules(port).any(|rule| match &rule.host {
+                        HostPattern::Network(network) => {
+                            network.contains(&address.ip().to_canonical())
+                        }
+                        HostPattern::Name(_) => false,
+                    })
+            })
+            .collect())
+    }
+
+    /// Path of the Unix socket declared as `name`.
+    pub(crate) fn socket(&self, name: &str) -> Option<&Path> {
+        self.sockets.get(name).map(PathBuf::as_path)
+    }
+
+    /// Check whether reverse forwarding may bind `port`.
+    pub(crate) fn allows_reverse(&self, port: u16) -> bool {
+        self.reverse.iter().any(|range| range.contains(&port))
+    }
+
+    fn host_rules(&self, port: u16) -> impl Iterator<Item = &HostRule> {
+        self.hosts
+            .iter()
+            .filter(move |rule| rule.ports.contains(&port))
+    }
+}
+
+/// Parse a host rule like `192.168.1.0/24:502`, `[fd00::/64]:80`, or `camera:554`.
+fn parse_host_rule(rule: &str) -> anyhow::Result<HostRule> {
+    let (host, ports) = rule
+        .rsplit_once(':')
+        .with_context(|| format!("host rule {rule:?} lacks ports"))?;
+    let ports = parse_port_range(ports)?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    let host = if host.contains('/') {
+        HostPattern::Network(
+            host.parse::<IpNet>()
+                .with_context(|| format!("invalid network {host:?}"))?
+                .trunc(),
+        )
+    } else if let Ok(address) = host.parse::<IpAddr>() {
+        HostPattern::Network(IpNet::from(address))
+    } else if !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        HostPattern::Name(host.to_ascii_lowercase())
+    } else {
+        bail!("invalid host {host:?}");
+    };
+    Ok(HostRule { host, ports })
+}
+
+/// Parse ports and port ranges like `22` and `8000-8100`.
+fn parse_port_ranges(ranges: &[String]) -> anyhow::Result<Vec<RangeInclusive<u16>>> {
+    ranges.iter().map(|range| parse_port_range(range)).collect()
+}
+
+fn parse_port_range(range: &str) -> anyhow::Result<RangeInclusive<u16>> {
+    let parse = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .with_context(|| format!("invalid port {port:?}"))
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => {
+            let port = parse(range)?;
+            (port, port)
+        }
+    };
+    if start > end {
+        bail!("port range {range:?} is empty");
+    }
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+
+    use super::ForwardingPolicy;
+    use crate::config::Config;
+    use crate::config::ExportConfig;
+    use crate::config::ForwardingConfig;
+    use crate::config::HttpExportConfig;
+    use crate::config::ReverseForwardingConfig;
+
+    fn config() -> Config {
+        Config::new(PathBuf::from("unused-fingerprint")).with_exports(Some(vec![
+            ExportConfig::Http(HttpExportConfig::new("web".to_owned(), 8080)),
+        ]))
+    }
+
+    #[test]
+    fn forwarding_defaults_to_exported_ports() {
+        let policy = ForwardingPolicy::from_config(&config()).unwrap();
+        assert!(policy.allows(8080));
+        assert!(!policy.allows(22));
+        assert!(!policy.allows(5432));
+
+        let policy =
+            ForwardingPolicy::from_config(&Config::new(PathBuf::from("unused-fingerprint")))
+                .unwrap();
+        assert!(!policy.allows(8080));
+    }
+
+    #[test]
+    fn denied_ports_take_precedence_over_allowed_ranges() {
+        let config = config().with_forwarding(Some(
+            ForwardingConfig::new()
+                .with_allow(Some(vec!["22".to_owned(), "8000 - 8100".to_owned()]))
+                .with_deny(Some(vec!["8050-8059".to_owned()])),
+        ));
+        let policy = ForwardingPolicy::from_config(&config).unwrap();
+        assert!(policy.allows(22));
+        assert!(policy.allows(8000));
+        assert!(policy.allows(8100));
+        assert!(!policy.allows(8055));
+        assert!(!policy.allows(8101));
+    }
+
+    #[tokio::test]
+    async fn hosts_are_allowed_by_networks_and_names() {
+        let config =
+            config().with_forwarding(Some(ForwardingConfig::new().with_hosts(Some(vec![
+                "192.168.1.0/24:502".to_owned(),
+                "[fd00::/64]:80-90".to_owned(),
+                "localhost:554".to_owned(),
+            ]))));
+        let policy = ForwardingPolicy::from_config(&config).unwrap();
+        assert!(policy.may_allow_host("192.168.1.20", 502));
+        assert!(!policy.may_allow_host("192.168.1.20", 503));
+        assert!(!policy.may_allow_host("192.168.2.20", 502));
+        assert!(policy.may_allow_host("fd00::1", 85));
+        assert!(policy.may_allow_host("LOCALHOST", 554));
+        assert!(!policy.may_allow_host("camera", 554));
+        // Names may resolve to allowed networks.
+        assert!(policy.may_allow_host("plc", 502));
+
+        let addresses = policy.resolve("::ffff:192.168.1.7", 502).await.unwrap();
+        assert_eq!(
+            addresses,
+            vec!["[::ffff:192.168.1.7]:502".parse::<SocketAddr>().unwrap()]
+        );
+        assert!(policy.resolve("10.0.0.1", 502).await.unwrap().is_empty());
+        assert!(!policy.resolve("localhost", 554).await.unwrap().is_empty());
+        assert!(policy.resolve("localhost", 502).await.unwrap().is_empty());
+        // Local ports are not governed by host rules.
+        assert!(!policy.allows(502));
+    }
+
+    #[test]
+    fn sockets_are_only_reached_by_their_name() {
+        let sockets = [("docker", "/run/docker.sock")]
+            .map(|(name, path)| (name.to_owned(), PathBuf::from(path)))
+            .into_iter()
+            .collect();
+        let config =
+            config().with_forwarding(Some(ForwardingConfig::new().with_sockets(Some(sockets))));
+        let policy = ForwardingPolicy::from_config(&config).unwrap();
+        assert_eq!(
+            policy.socket("docker"),
+            Some(PathBuf::from("/run/docker.sock").as_path())
+        );
+        assert_eq!(policy.socket("/run/docker.sock"), None);
+        assert_eq!(policy.socket("postgres"), None);
+
+        for (name, path) in [("", "/run/a.sock"), ("a/b", "/run/a.sock"), ("a", "a.sock")] {
+            let sockets = [(name.to_owned(), PathBuf::from(path))]
+                .into_iter()
+                .collect();
+            let config =
+                config().with_forwarding(Some(ForwardingConfig::new().with_sockets(Some(sockets))));
+            assert!(
+                ForwardingPolicy::from_config(&config).is_err(),
+                "{name:?} at {path:?} must be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn reverse_forwarding_requires_enabling() {
+        let reverse = ReverseForwardingConfig::new().with_allow(Some(vec!["9000-9010".to_owned()]));
+        let config = config().with_forwarding(Some(
+            ForwardingConfig::new().with_reverse(Some(reverse.clone())),
+        ));
+        let policy = ForwardingPolicy::from_config(&config).unwrap();
+        assert!(!policy.allows_reverse(9000));
+
+        let config = config().with_forwarding(Some(
+            ForwardingConfig::new().with_reverse(Some(reverse.with_enabled(Some(true)))),
+        ));
+        let policy = ForwardingPolicy::from_config(&config).unwrap();
+        assert!(policy.allows_reverse(9000));
+        assert!(policy.allows_reverse(9010));
+        assert!(!policy.allows_reverse(8080));
+        // Reverse ports are independent of forwarding ports.
+        assert!(!policy.allows(9000));
+    }
+
+    #[test]
+    fn invalid_port_ranges_are_rejected() {
+        for range in ["", "ssh", "70000", "100-10", "1-2-3"] {
+            let config = config().with_forwarding(Some(
+                ForwardingConfig::new().with_allow(Some(vec![range.to_owned()])),
+            ));
+            assert!(
+                ForwardingPolicy::from_config(&config).is_err(),
+                "{range:?} must be rejected"
+            );
+        }
+        for rule in [
+            "192.168.1.1",
+            "10.0.0.0/33:80",
+            "bad host:80",
+            ":80",
+            "[fd00::1]",
+        ] {
+            let config = config().with_forwarding(Some(
+                ForwardingConfig::new().with_hosts(Some(vec![rule.to_owned()])),
+            ));
+            assert!(
+                ForwardingPolicy::from_config(&config).is_err(),
+                "{rule:?} must be rejected"
+            );
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Reverse forwarding.
+//!
+//! A `reverse/tcp/<port>` channel from the hub is the control channel of a reverse
+//! forward: while it is open, the agent listens on `port` of the loopback interface.
+//! For every accepted connection, the agent opens a `reverse/tcp/<port>` channel to the
+//! hub, which routes it to the CLI session holding the control channel. As a port is
+//! bound at most once, it identifies that session. Closing the control channel stops
+//! the listener and closes its connections. The endpoint contract is documented at
+//! [`reverse_tcp_endpoint`].
+
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use nexigon_agent_protocol::reverse_tcp_endpoint;
+use nexigon_multiplex::Channel;
+use nexigon_multiplex::ConnectionRef;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpSocket;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::metrics::AgentMetrics;
+
+/// Maximum number of concurrent connections per reverse forwarding listener.
+const MAX_REVERSE_CONNECTIONS: usize = 64;
+/// Delay after a failed accept, e.g., when running out of file descriptors.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Bind the reverse forwarding listener for `port` on the loopback interface.
+pub(crate) fn bind_reverse_listener(port: u16) -> std::io::Result<TcpListener> {
+    let socket = TcpSocket::new_v4()?;
+    socket.set_reuseaddr(true)?;
+    socket.bind((Ipv4Addr::LOCALHOST, port).into())?;
+    socket.listen(1024)
+}
+
+/// Relay connections accepted by `listener` to the hub until `control` is closed.
+pub(crate) async fn run_reverse_forwarding(
+    control: Channel,
+    listener: TcpListener,
+    hub: ConnectionRef,
+    metrics: Arc<AgentMetrics>,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    let port = listener
+        .local_addr()
+        .context("reading reverse forwarding address")?
+        .port();
+    let endpoint = reverse_tcp_endpoint(port);
+    // The control channel carries no data, it only ends the forward when it is closed.
+    let (_control_sender, mut control_receiver) = control.split();
+    let closed = async {
+        let mut buf = [0u8; 64];
+        while control_receiver.read(&mut buf).await.is_ok_and(|n| n > 0) {}
+    };
+    tokio::pin!(closed);
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            () = cancellation.cancelled() => break,
+            () = &mut closed => {
+                debug!(port, "reverse forwarding control channel closed");
+                break;
+            }
+            Some(_) = connections.join_next() => {}
+            accepted = listener.accept() => match accepted {
+                Ok((socket, peer)) if connections.len() >= MAX_REVERSE_CONNECTIONS => {
+                    debug!(port, %peer, "too many reverse forwarding connections");
+                    drop(socket);
+                }
+                Ok((socket, _)) => {
+                    connections.spawn(relay_reverse_connection(
+                        socket,
+                        hub.clone(),
+                        endpoint.clone(),
+                        metrics.clone(),
+                    ));
+                }
+                Err(error) => {
+                    debug!(?error, port, "unable to accept reverse forwarding connection");
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            },
+        }
+    }
+    connections.shutdown().await;
+    Ok(())
+}
+
+async fn relay_reverse_connection(
+    mut socket: TcpStream,
+    mut hub: ConnectionRef,
+    endpoint: String,
+    metrics: Arc<AgentMetrics>,
+) {
+    let mut channel = match hub.open(endpoint.as_bytes()).await {
+        Ok(channel) => channel,
+        Err(error) => {
+            debug!(%error, endpoint, "unable to open reverse forwarding channel");
+            return;
+        }
+    };
+    let _tracked = metrics.track_channel("reverse-tcp", channel.statistics());
+    if let Err(error) = tokio::io::copy_bidirectional(&mut socket, &mut channel).await {
+        debug!(?error, endpoint, "reverse forwarding connection failed");
+    }
+}
@@ -0,0 +1,80 @@
+//! UDP forwarding.
+//!
+//! A `forward/udp/<port>` channel carries one association between a peer on the other
+//! end of the channel and a local port. Every datagram is sent as a frame with a
+//! two-byte length prefix, so datagram boundaries survive the byte-oriented channel.
+//! An association without datagrams in either direction expires after
+//! [`UDP_ASSOCIATION_IDLE_TIMEOUT`](nexigon_agent_protocol::UDP_ASSOCIATION_IDLE_TIMEOUT).
+
+use std::io::ErrorKind;
+use std::net::Ipv4Addr;
+
+use anyhow::Context;
+use nexigon_agent_protocol::MAX_DATAGRAM_LEN;
+use nexigon_agent_protocol::UdpAssociationExpiry;
+use nexigon_agent_protocol::read_datagram;
+use nexigon_agent_protocol::write_datagram;
+use nexigon_multiplex::Channel;
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// Relay datagrams between `channel` and `port` on localhost until the association
+/// ends or expires.
+pub(crate) async fn relay_udp_forwarding(
+    channel: Channel,
+    port: u16,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .context("binding UDP forwarding socket")?;
+    socket
+        .connect((Ipv4Addr::LOCALHOST, port))
+        .await
+        .context("connecting UDP forwarding socket")?;
+    let (mut sender, mut receiver) = channel.split();
+    let expiry = UdpAssociationExpiry::new();
+
+    let inbound = async {
+        while let Some(datagram) = read_datagram(&mut receiver)
+            .await
+            .context("reading forwarded datagram")?
+        {
+            expiry.touch();
+            if let Err(error) = socket.send(&datagram).await {
+                debug!(?error, port, "unable to send forwarded datagram");
+            }
+        }
+        anyhow::Ok(())
+    };
+    let outbound = async {
+        let mut buf = vec![0; MAX_DATAGRAM_LEN];
+        loop {
+            match socket.recv(&mut buf).await {
+                Ok(n) => {
+                    expiry.touch();
+                    write_datagram(&mut sender, &buf[..n])
+                        .await
+                        .context("writing forwarded datagram")?;
+                }
+                // Connected sockets report ICMP errors for earlier datagrams, e.g., when
+                // nothing listens on the port (yet).
+                Err(error) if error.kind() == ErrorKind::ConnectionRefused => {
+                    debug!(port, "UDP forwarding target is unreachable");
+                }
+                Err(error) => return Err(error).context("receiving forwarded datagram"),
+            }
+        }
+    };
+
+    tokio::select! {
+        () = cancellation.cancelled() => Ok(()),
+        result = inbound => result,
+        result = outbound => result,
+        () = expiry.expired() => {
+            debug!(port, "UDP forwarding association expired");
+            Ok(())
+        }
+    }
+}
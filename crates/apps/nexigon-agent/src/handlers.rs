@@ -59,7 +59,6 @@ const DEFAULT_COMMAND_TIMEOUT: u64 = 30;
 
 #[cfg(unix)]
 const MAX_COMMAND_DEFINITIONS: usize = 1024;
-#[cfg(unix)]
 const MAX_COMMAND_DIRECTORY_ENTRIES: usize = 4096;
 const MAX_COMMAND_DEFINITION_BYTES: usize = 256 * 1024;
 const MAX_COMMAND_REGISTRY_BYTES: usize = 16 * 1024 * 1024;
@@ -178,6 +177,16 @@ impl CommandRegistry {
         self.commands.get(name).map(|command| &command.definition)
     }
 
+    /// Number of loaded commands.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Whether no commands are loaded.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
     fn get_loaded(&self, name: &str) -> Option<&LoadedCommand> {
         self.commands.get(name)
     }
@@ -206,6 +215,67 @@ impl CommandRegistry {
     }
 }
 
+/// Metadata snapshot of the definition files in a commands directory.
+///
+/// Capturing a snapshot reads no definitions and performs none of the trust checks; it
+/// only tells a watcher when [`CommandRegistry::load_external`] has to run again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CommandDirectoryState {
+    files: Vec<CommandFileState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CommandFileState {
+    name: OsString,
+    len: u64,
+    modified: Option<std::time::SystemTime>,
+    /// Inode change time, which also covers ownership and permission changes.
+    changed: Option<(i64, i64)>,
+}
+
+impl CommandDirectoryState {
+    /// Capture the state of `directory`, or `None` if it does not exist.
+    pub(crate) fn capture(directory: &Path) -> std::io::Result<Option<Self>> {
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let mut files = Vec::new();
+        for entry in entries.take(MAX_COMMAND_DIRECTORY_ENTRIES + 1) {
+            let entry = entry?;
+            let name = entry.file_name();
+            if Path::new(&name)
+                .extension()
+                .is_none_or(|extension| extension != "toml")
+            {
+                continue;
+            }
+            // Follow symlinks like the loader does, so retargeted definitions are noticed.
+            let metadata = match std::fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+            #[cfg(unix)]
+            let changed = {
+                use std::os::unix::fs::MetadataExt;
+                Some((metadata.ctime(), metadata.ctime_nsec()))
+            };
+            #[cfg(not(unix))]
+            let changed = None;
+            files.push(CommandFileState {
+                name,
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+                changed,
+            });
+        }
+        files.sort();
+        Ok(Some(Self { files }))
+    }
+}
+
 /// Load and validate one external command definition.
 fn load_external_command(
     resolved_commands_directory: &Path,
@@ -281,7 +351,9 @@ fn validate_manifest_size(manifest: &DeviceCommandManifest) -> anyhow::Result<()
 type CommandDirectoryHandle = nix::dir::Dir;
 
 #[cfg(unix)]
-fn open_command_directory(directory: &Path) -> anyhow::Result<Option<(PathBuf, Vec<OsString>)>> {
+pub(crate) fn open_command_directory(
+    directory: &Path,
+) -> anyhow::Result<Option<(PathBuf, Vec<OsString>)>> {
     let resolved_directory = match std::fs::canonicalize(directory) {
         Ok(directory) => directory,
         Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
@@ -430,7 +502,7 @@ fn validate_trusted_owner(owner: u32, label: &str) -> anyhow::Result<()> {
 }
 
 #[cfg(unix)]
-fn open_command_file(directory: &Path, name: &OsString) -> anyhow::Result<File> {
+pub(crate) fn open_command_file(directory: &Path, name: &OsString) -> anyhow::Result<File> {
     let path = std::fs::canonicalize(directory.join(name)).with_context(|| {
         format!(
             "failed to resolve command definition {:?} and its symlink target",
@@ -470,7 +542,10 @@ fn open_trusted_regular_file(path: &Path, label: &str) -> anyhow::Result<File> {
 
 #[cfg(unix)]
 /// Resolve and validate a command handler while retaining its canonical path.
-fn validate_handler_executable(program: &str, command_directory: &Path) -> anyhow::Result<PathBuf> {
+pub(crate) fn validate_handler_executable(
+    program: &str,
+    command_directory: &Path,
+) -> anyhow::Result<PathBuf> {
     let program = Path::new(program);
     let program = resolve_handler_program(program, command_directory)?;
 
@@ -528,7 +603,9 @@ fn resolve_handler_program(program: &Path, command_directory: &Path) -> anyhow::
 }
 
 #[cfg(not(unix))]
-fn open_command_directory(directory: &Path) -> anyhow::Result<Option<(PathBuf, Vec<OsString>)>> {
+pub(crate) fn open_command_directory(
+    directory: &Path,
+) -> anyhow::Result<Option<(PathBuf, Vec<OsString>)>> {
     match std::fs::symlink_metadata(directory) {
         Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
         Err(error) => Err(error).context("failed to inspect commands directory"),
@@ -539,16 +616,19 @@ fn open_command_directory(directory: &Path) -> anyhow::Result<Option<(PathBuf, V
 }
 
 #[cfg(not(unix))]
-fn open_command_file(_directory: &Path, _name: &OsString) -> anyhow::Result<File> {
+pub(crate) fn open_command_file(_directory: &Path, _name: &OsString) -> anyhow::Result<File> {
     anyhow::bail!("secure external command loading is unsupported on this platform")
 }
 
 #[cfg(not(unix))]
-fn validate_handler_executable(_program: &str, _directory: &Path) -> anyhow::Result<PathBuf> {
+pub(crate) fn validate_handler_executable(
+    _program: &str,
+    _directory: &Path,
+) -> anyhow::Result<PathBuf> {
     anyhow::bail!("secure external command loading is unsupported on this platform")
 }
 
-fn read_bounded_definition(file: &mut File) -> anyhow::Result<String> {
+pub(crate) fn read_bounded_definition(file: &mut File) -> anyhow::Result<String> {
     let mut bytes = Vec::new();
     file.take((MAX_COMMAND_DEFINITION_BYTES + 1) as u64)
         .read_to_end(&mut bytes)?;
@@ -560,16 +640,19 @@ fn read_bounded_definition(file: &mut File) -> anyhow::Result<String> {
 
 fn validate_command_definition(definition: &CommandDefinition) -> anyhow::Result<()> {
     validate_command_name(&definition.command.name)?;
-    if definition.exec.handler.is_empty()
-        || definition.exec.handler.len() > MAX_COMMAND_HANDLER_PARTS
-    {
+    validate_handler_parts(&definition.exec.handler)
+}
+
+/// Validate the executable and arguments of a configured handler.
+pub(crate) fn validate_handler_parts(handler: &[String]) -> anyhow::Result<()> {
+    if handler.is_empty() || handler.len() > MAX_COMMAND_HANDLER_PARTS {
         anyhow::bail!("command handler must contain 1 to {MAX_COMMAND_HANDLER_PARTS} parts");
     }
-    if definition.exec.handler[0].is_empty() {
+    if handler[0].is_empty() {
         anyhow::bail!("command handler program must not be empty");
     }
     let mut total_bytes = 0usize;
-    for part in &definition.exec.handler {
+    for part in handler {
         if part.as_bytes().contains(&0) {
             anyhow::bail!("command handler contains a NUL byte");
         }
@@ -583,7 +666,7 @@ fn validate_command_definition(definition: &CommandDefinition) -> anyhow::Result
     Ok(())
 }
 
-fn validate_command_name(name: &str) -> anyhow::Result<()> {
+pub(crate) fn validate_command_name(name: &str) -> anyhow::Result<()> {
     if name.is_empty() || name.len() > MAX_COMMAND_NAME_BYTES {
         anyhow::bail!("command name must contain 1 to {MAX_COMMAND_NAME_BYTES} bytes");
     }
@@ -995,16 +1078,19 @@ pub async fn handle_handler_channel(
     config: &Arc<Config>,
     registry: &Arc<CommandRegistry>,
 ) -> anyhow::Result<()> {
-    handle_handler_channel_inner(channel, config, registry, None).await
+    handle_handler_channel_inner(channel, config, registry, None).await?;
+    Ok(())
 }
 
 /// Handle a command channel owned by a cancellable agent task group.
+///
+/// Returns the result sent to the hub, if the invocation has been answered.
 pub(crate) async fn handle_handler_channel_with_cancellation(
     channel: nexigon_multiplex::Channel,
     config: &Arc<Config>,
     registry: &Arc<CommandRegistry>,
     cancellation: CancellationToken,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<DeviceCommandDoneData>> {
     handle_handler_channel_inner(channel, config, registry, Some(&cancellation)).await
 }
 
@@ -1013,7 +1099,7 @@ async fn handle_handler_channel_inner(
     _config: &Arc<Config>,
     registry: &Arc<CommandRegistry>,
     cancellation: Option<&CancellationToken>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<DeviceCommandDoneData>> {
     let (mut chan_writer, mut chan_reader) = channel.split();
 
     let read_frame = read_initial_hub_frame(&mut chan_reader);
@@ -1021,7 +1107,7 @@ async fn handle_handler_channel_inner(
     let frame = if let Some(cancellation) = cancellation {
         tokio::select! {
             biased;
-            () = cancellation.cancelled() => return Ok(()),
+            () = cancellation.cancelled() => return Ok(None),
             result = &mut read_frame => result,
         }
     } else {
@@ -1037,16 +1123,20 @@ async fn handle_handler_channel_inner(
     let DeviceCommandHubFrame::Invoke(request) = frame;
 
     if validate_command_name(&request.command).is_err() {
-        let frame = DeviceCommandDeviceFrame::Done(DeviceCommandDoneData {
+        let done = DeviceCommandDoneData {
             status: DeviceCommandStatus::Error,
             output: None,
             error: Some("invalid command name".to_owned()),
             log_tail: Vec::new(),
             duration_ms: 0,
-        });
-        write_command_frame(&mut chan_writer, &frame).await?;
+        };
+        write_command_frame(
+            &mut chan_writer,
+            &DeviceCommandDeviceFrame::Done(done.clone()),
+        )
+        .await?;
         chan_writer.shutdown().await.ok();
-        return Ok(());
+        return Ok(Some(done));
     }
 
     debug!(
@@ -1056,16 +1146,20 @@ async fn handle_handler_channel_inner(
     );
 
     let Some(command) = registry.get_loaded(&request.command) else {
-        let frame = DeviceCommandDeviceFrame::Done(DeviceCommandDoneData {
+        let done = DeviceCommandDoneData {
             status: DeviceCommandStatus::Error,
             output: None,
             error: Some("command not found".to_owned()),
             log_tail: Vec::new(),
             duration_ms: 0,
-        });
-        write_command_frame(&mut chan_writer, &frame).await?;
+        };
+        write_command_frame(
+            &mut chan_writer,
+            &DeviceCommandDeviceFrame::Done(done.clone()),
+        )
+        .await?;
         chan_writer.shutdown().await.ok();
-        return Ok(());
+        return Ok(Some(done));
     };
 
     let result =
@@ -1076,14 +1170,17 @@ async fn handle_handler_channel_inner(
                 return Err(error);
             }
         };
-    let done_frame = DeviceCommandDeviceFrame::Done(result.into_command_done());
-
-    write_command_frame(&mut chan_writer, &done_frame)
-        .await
-        .ok();
+    let done = result.into_command_done();
+
+    write_command_frame(
+        &mut chan_writer,
+        &DeviceCommandDeviceFrame::Done(done.clone()),
+    )
+    .await
+    .ok();
     chan_writer.shutdown().await.ok();
 
-    Ok(())
+    Ok(Some(done))
 }
 
 async fn read_initial_hub_frame(
@@ -1408,7 +1505,10 @@ enum CommandExecution {
     TimedOut(u64),
 }
 
-async fn terminate_command_child(child: &mut tokio::process::Child, process_group: Option<i32>) {
+pub(crate) async fn terminate_command_child(
+    child: &mut tokio::process::Child,
+    process_group: Option<i32>,
+) {
     #[cfg(unix)]
     {
         let group_killed = kill_command_process_group(process_group);
@@ -1425,7 +1525,7 @@ async fn terminate_command_child(child: &mut tokio::process::Child, process_grou
 }
 
 #[cfg(unix)]
-fn kill_command_process_group(process_group: Option<i32>) -> bool {
+pub(crate) fn kill_command_process_group(process_group: Option<i32>) -> bool {
     process_group.is_some_and(|group| {
         nix::sys::signal::killpg(
             nix::unistd::Pid::from_raw(group),
@@ -1436,7 +1536,7 @@ fn kill_command_process_group(process_group: Option<i32>) -> bool {
 }
 
 #[cfg(not(unix))]
-fn kill_command_process_group(_process_group: Option<i32>) -> bool {
+pub(crate) fn kill_command_process_group(_process_group: Option<i32>) -> bool {
     false
 }
 
@@ -1446,7 +1546,7 @@ fn bounded_command_timeout(requested: Option<u64>, configured: Option<u64>) -> s
 }
 
 #[derive(Clone)]
-struct CommandOutputBudget {
+pub(crate) struct CommandOutputBudget {
     state: Arc<Mutex<CommandOutputBudgetState>>,
     max_bytes: usize,
     max_lines: usize,
@@ -1463,7 +1563,7 @@ impl CommandOutputBudget {
         Self::with_limits(MAX_COMMAND_OUTPUT_BYTES, MAX_COMMAND_OUTPUT_LINES)
     }
 
-    fn with_limits(max_bytes: usize, max_lines: usize) -> Self {
+    pub(crate) fn with_limits(max_bytes: usize, max_lines: usize) -> Self {
         Self {
             state: Arc::new(Mutex::new(CommandOutputBudgetState::default())),
             max_bytes,
@@ -1508,7 +1608,7 @@ impl CommandOutputBudget {
 
 /// Read one line without allowing `AsyncBufReadExt::read_line` to grow a peer-
 /// controlled allocation. An EOF-terminated final line is returned normally.
-async fn read_bounded_line(
+pub(crate) async fn read_bounded_line(
     reader: &mut (impl AsyncBufRead + Unpin),
     budget: &CommandOutputBudget,
     max_line_len: usize,
@@ -1546,7 +1646,7 @@ async fn read_bounded_line(
     }
 }
 
-fn trim_ascii_whitespace(mut bytes: &[u8]) -> &[u8] {
+pub(crate) fn trim_ascii_whitespace(mut bytes: &[u8]) -> &[u8] {
     while bytes.first().is_some_and(u8::is_ascii_whitespace) {
         bytes = &bytes[1..];
     }
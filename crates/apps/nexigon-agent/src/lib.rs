@@ -7,8 +7,9 @@
 //! after dispatch is failed conservatively because replaying an external side effect is
 //! unsafe.
 //!
-//! The operation protocol also defines durable `DeviceTask` work, but this agent does not
-//! currently poll for or execute task steps.
+//! When tasks are enabled, the agent also executes durable `DeviceTask` steps. A running
+//! task records its latest checkpoint in the same ledger and is resumed from there after
+//! an interruption instead of being failed.
 
 use std::future::Future;
 use std::io::ErrorKind;
@@ -36,12 +37,20 @@ use tracing::info;
 pub use nexigon_client::install_crypto_provider;
 
 pub mod config;
+mod forwarding;
 pub mod handlers;
 #[cfg(unix)]
 pub mod local_api;
+mod metrics;
 mod operation_ledger;
+mod outbox;
 pub mod provisioning;
+#[cfg(unix)]
+mod reload;
+mod status;
 pub mod system_info;
+mod systemd;
+pub mod tasks;
 #[cfg(target_os = "linux")]
 pub mod terminal;
 
@@ -51,6 +60,7 @@ pub use run::run;
 pub use run::run_with_connection;
 
 use crate::config::Config;
+use crate::systemd::SystemdNotifier;
 
 /// Default directory for persistent agent data.
 pub const DEFAULT_DATA_PATH: &str = "/var/lib/nexigon/agent";
@@ -84,7 +94,7 @@ struct ResolvedCredentials {
     token: DeploymentToken,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct DeviceIdentity {
     client_identity: ClientIdentity,
     pub(crate) fingerprint: DeviceFingerprint,
@@ -114,6 +124,7 @@ pub async fn load_config(config_path: &Path) -> anyhow::Result<(Arc<Config>, Pat
         .context("agent config must be a private non-symlink regular file")?;
     let raw_config = String::from_utf8(raw_config).context("agent config is not valid UTF-8")?;
     let config = toml::from_str::<Config>(&raw_config).context("cannot parse config")?;
+    forwarding::ForwardingPolicy::from_config(&config)?;
     Ok((Arc::new(config), config_dir))
 }
 
@@ -363,6 +374,8 @@ pub(crate) async fn run_agent(
     config_path: PathBuf,
     shutdown: impl Future<Output = ()> + Send + 'static,
     ready: Option<oneshot::Sender<DeviceId>>,
+    reload_on_hangup: bool,
+    systemd: SystemdNotifier,
 ) -> anyhow::Result<()> {
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
     let shutdown_task = tokio::spawn(async move {
@@ -385,6 +398,7 @@ pub(crate) async fn run_agent(
                     );
                 }
                 let identity = load_device_identity(&config, &config_dir).await?;
+                systemd.status("waiting for a pairing key");
                 let credentials = provisioning::serve_until_paired(
                     &config,
                     &config_dir,
@@ -395,22 +409,31 @@ pub(crate) async fn run_agent(
                 (credentials.resolved(), identity)
             }
         };
-        // Network connection setup is cancellation-safe. Identity loading is deliberately
-        // completed before this race because the fingerprint subprocess does not yet have the
+        // Network connection setup is cancellation-safe, so the reconnect supervisor may
+        // drop an attempt on shutdown. Identity loading is deliberately completed once up
+        // front because the fingerprint subprocess does not yet have the
         // bounded/cancellation-aware contract tracked by P1-12.
-        let connection = tokio::select! {
-            biased;
-            () = shutdown_signal(shutdown_rx.clone()) => return Ok(()),
-            connection = connect_with_identity(&config, &credentials, identity, true) => connection?,
-        };
-        // Once connected, run_with_connection owns cooperative cancellation and awaited task
-        // cleanup. Do not race and drop the whole session from this outer layer.
-        run_with_connection(
+        let hub_url = credentials.hub_url.clone();
+        let connect_config = config.clone();
+        let connect: run::ConnectFn = Box::new(move || {
+            let config = connect_config.clone();
+            let credentials = credentials.clone();
+            let identity = identity.clone();
+            Box::pin(
+                async move { connect_with_identity(&config, &credentials, identity, true).await },
+            )
+        });
+        // The supervisor owns cooperative cancellation and awaited task cleanup. Do not race
+        // and drop the whole agent from this outer layer.
+        run::run_reconnecting(
             config,
             &config_dir,
-            connection,
+            hub_url,
+            reload_on_hangup.then_some(config_path.as_path()),
+            connect,
             shutdown_signal(shutdown_rx.clone()),
             ready,
+            systemd,
         )
         .await
     }
@@ -427,8 +450,8 @@ pub(crate) async fn run_agent(
 
 /// Handle for an agent running in the background.
 ///
-/// Returned by [`spawn`]. The agent runs until either [`AgentHandle::stop`]
-/// is called or the connection to the hub is lost.
+/// Returned by [`spawn`]. The agent runs until [`AgentHandle::stop`] is called,
+/// reconnecting to the hub whenever the connection is lost.
 pub struct AgentHandle {
     /// Resolves to the device id once the agent has registered with the hub.
     pub ready: oneshot::Receiver<DeviceId>,
@@ -458,7 +481,14 @@ pub fn spawn(config_path: PathBuf) -> AgentHandle {
         let shutdown = async move {
             let _ = shutdown_rx.await;
         };
-        run_agent(config_path, shutdown, Some(ready_tx)).await
+        run_agent(
+            config_path,
+            shutdown,
+            Some(ready_tx),
+            false,
+            SystemdNotifier::default(),
+        )
+        .await
     });
     AgentHandle {
         ready: ready_rx,
@@ -1,54 +1,91 @@
 //! Unix-socket-based local API for in-host clients.
 //!
 //! Listens on a Unix socket and accepts handshakes defined by the
-//! [`nexigon_agent_api`] crate. The only endpoint supported in this
-//! revision is `"executor"`, which is bridged byte-for-byte to a hub-side
-//! executor channel — local clients can speak `nexigon-rpc` directly to the
-//! hub over the agent's existing connection.
+//! [`nexigon_agent_api`] crate. The `"executor"` endpoint is bridged
+//! byte-for-byte to a hub-side executor channel — local clients can speak
+//! `nexigon-rpc` directly to the hub over the agent's existing connection.
+//! The `"outbox"` endpoint durably queues events and property writes for
+//! delivery once the hub is reachable. The `"tasks"` endpoint hands claimed
+//! device task work to local workers and relays their reports. The `"status"`
+//! endpoint reports the state of the agent to diagnostics tools.
 
 #![cfg(unix)]
 
+use std::collections::HashMap;
 use std::future::Future;
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
 
 use anyhow::Context;
 use nexigon_agent_api::DEFAULT_SOCKET_PATH;
 use nexigon_agent_api::MAGIC;
 use nexigon_agent_api::MAX_HANDSHAKE_LEN;
+use nexigon_agent_api::MAX_MESSAGE_LEN;
 use nexigon_agent_api::VERSION;
 use nexigon_agent_api::types::handshake::ClientHello;
 use nexigon_agent_api::types::handshake::ServerError;
 use nexigon_agent_api::types::handshake::ServerErrorCode;
 use nexigon_agent_api::types::handshake::ServerHello;
 use nexigon_agent_api::types::handshake::ServerOk;
+use nexigon_agent_api::types::outbox::OutboxItem;
+use nexigon_agent_api::types::tasks::TaskReport;
+use nexigon_agent_api::types::tasks::TaskSubscription;
+use nexigon_api::types::devices::DeviceOperationStepReportStatus;
 use nexigon_multiplex::ConnectionRef;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
 use tokio::net::UnixListener;
 use tokio::net::UnixStream;
+use tokio::sync::watch;
 use tokio::task::JoinSet;
 use tracing::debug;
 use tracing::info;
 use tracing::warn;
 
 use crate::config::LocalApiConfig;
+use crate::metrics::AgentMetrics;
+use crate::outbox::SharedOutbox;
+use crate::status::StatusSource;
+use crate::tasks::TaskRegistry;
+use crate::tasks::TaskWorkerSubscription;
 
 /// File mode applied to the socket after binding.
 const SOCKET_MODE: u32 = 0o660;
 const MAX_CONCURRENT_LOCAL_API_CLIENTS: usize = 32;
 
+/// Agent state reachable from local API endpoints.
+#[derive(Clone)]
+pub struct LocalApiContext {
+    /// Current hub connection, if any.
+    pub(crate) hub: watch::Receiver<Option<ConnectionRef>>,
+    /// Durable outbox, if enabled.
+    pub(crate) outbox: Option<Arc<SharedOutbox>>,
+    /// Task registry, if tasks are enabled.
+    pub(crate) tasks: Option<Arc<TaskRegistry>>,
+    /// Agent state reported by the `"status"` endpoint.
+    pub(crate) status: StatusSource,
+    /// Agent metrics, which count local API clients.
+    pub(crate) metrics: Arc<AgentMetrics>,
+}
+
 /// Serve the agent local API until `shutdown` resolves.
 ///
 /// Binds the Unix socket described by `config`, accepts connections, and
 /// dispatches each one to the endpoint requested in its `ClientHello`.
 /// The socket is removed when this function returns.
 ///
-/// `hub_ref` is cloned per accepted connection to open hub-side channels.
+/// The hub connection in `context` is sampled per accepted connection to open
+/// hub-side channels, so the listener survives reconnects.
 /// A failure on a single client is logged and does not affect the listener.
 pub async fn serve(
     config: &LocalApiConfig,
-    hub_ref: ConnectionRef,
+    context: LocalApiContext,
     shutdown: impl Future<Output = ()>,
 ) -> anyhow::Result<()> {
     let socket_path = config
@@ -105,11 +142,16 @@ pub async fn serve(
                 let (stream, _) = res.context("accepting local API connection")?;
                 if clients.len() >= MAX_CONCURRENT_LOCAL_API_CLIENTS {
                     warn!("local API connection limit reached; rejecting client");
+                    context.metrics.local_api_client_rejected();
                     drop(stream);
                     continue;
                 }
-                let hub_ref = hub_ref.clone();
-                clients.spawn(handle_client(stream, hub_ref));
+                let client = context.metrics.local_api_client_accepted();
+                let client_context = context.clone();
+                clients.spawn(async move {
+                    let _client = client;
+                    handle_client(stream, client_context).await
+                });
             }
         }
     }
@@ -125,7 +167,7 @@ pub async fn serve(
     Ok(())
 }
 
-async fn handle_client(mut stream: UnixStream, mut hub_ref: ConnectionRef) -> anyhow::Result<()> {
+async fn handle_client(mut stream: UnixStream, context: LocalApiContext) -> anyhow::Result<()> {
     let mut magic = [0u8; 4];
     stream
         .read_exact(&mut magic)
@@ -174,6 +216,15 @@ async fn handle_client(mut stream: UnixStream, mut hub_ref: ConnectionRef) -> an
     }
     match hello.endpoint.as_str() {
         "executor" => {
+            let hub_ref = context.hub.borrow().clone();
+            let Some(mut hub_ref) = hub_ref else {
+                return reject(
+                    &mut stream,
+                    ServerErrorCode::Internal,
+                    "hub is not connected".to_owned(),
+                )
+                .await;
+            };
             let mut hub_channel = match hub_ref.open(b"executor").await {
                 Ok(channel) => channel,
                 Err(error) => {
@@ -192,6 +243,96 @@ async fn handle_client(mut stream: UnixStream, mut hub_ref: ConnectionRef) -> an
                 .context("splicing executor channel to hub")?;
             Ok(())
         }
+        "outbox" => {
+            let Some(outbox) = &context.outbox else {
+                return reject(
+                    &mut stream,
+                    ServerErrorCode::EndpointDisabled,
+                    "outbox is not enabled".to_owned(),
+                )
+                .await;
+            };
+            let item = match hello.options.map(serde_json::from_value::<OutboxItem>) {
+                Some(Ok(item)) => item,
+                Some(Err(e)) => {
+                    return reject(
+                        &mut stream,
+                        ServerErrorCode::InvalidRequest,
+                        format!("malformed outbox item: {e}"),
+                    )
+                    .await;
+                }
+                None => {
+                    return reject(
+                        &mut stream,
+                        ServerErrorCode::InvalidRequest,
+                        "outbox item is missing".to_owned(),
+                    )
+                    .await;
+                }
+            };
+            if let Err(error) = outbox.push(item).await {
+                warn!(?error, "cannot queue local outbox item");
+                return reject(
+                    &mut stream,
+                    ServerErrorCode::Internal,
+                    "cannot queue outbox item".to_owned(),
+                )
+                .await;
+            }
+            send_hello(&mut stream, ServerHello::Ok(ServerOk { version: VERSION })).await
+        }
+        "tasks" => {
+            let Some(tasks) = &context.tasks else {
+                return reject(
+                    &mut stream,
+                    ServerErrorCode::EndpointDisabled,
+                    "tasks are not enabled".to_owned(),
+                )
+                .await;
+            };
+            let subscription = match hello
+                .options
+                .map(serde_json::from_value::<TaskSubscription>)
+            {
+                Some(Ok(subscription)) => subscription,
+                Some(Err(e)) => {
+                    return reject(
+                        &mut stream,
+                        ServerErrorCode::InvalidRequest,
+                        format!("malformed task subscription: {e}"),
+                    )
+                    .await;
+                }
+                None => {
+                    return reject(
+                        &mut stream,
+                        ServerErrorCode::InvalidRequest,
+                        "task subscription is missing".to_owned(),
+                    )
+                    .await;
+                }
+            };
+            let subscription = match tasks.subscribe(subscription.tasks) {
+                Ok(subscription) => subscription,
+                Err(error) => {
+                    return reject(
+                        &mut stream,
+                        ServerErrorCode::InvalidRequest,
+                        format!("{error:#}"),
+                    )
+                    .await;
+                }
+            };
+            send_hello(&mut stream, ServerHello::Ok(ServerOk { version: VERSION })).await?;
+            serve_task_worker(stream, subscription).await
+        }
+        "status" => {
+            let hub_ref = context.hub.borrow().clone();
+            let status = context.status.status(hub_ref.as_ref()).await;
+            send_hello(&mut stream, ServerHello::Ok(ServerOk { version: VERSION })).await?;
+            write_message(&mut stream, &status).await
+        }
         other => {
             reject(
                 &mut stream,
@@ -220,6 +361,107 @@ async fn send_hello(stream: &mut UnixStream, hello: ServerHello) -> anyhow::Resu
     Ok(())
 }
 
+/// Exchange work items and reports with a subscribed task worker until it disconnects.
+async fn serve_task_worker(
+    stream: UnixStream,
+    mut subscription: TaskWorkerSubscription,
+) -> anyhow::Result<()> {
+    let (mut rx, mut tx) = stream.into_split();
+    let assigned = Mutex::new(HashMap::new());
+    let send_work = async {
+        while let Some(assignment) = subscription.assignments.recv().await {
+            assigned
+                .lock()
+                .map_err(|_| anyhow::anyhow!("task assignment mutex poisoned"))?
+                .insert(
+                    (
+                        assignment.item.device_operation_id.clone(),
+                        assignment.item.step_index,
+                    ),
+                    assignment.reports,
+                );
+            write_message(&mut tx, &assignment.item).await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let receive_reports = async {
+        while let Some(message) = read_message::<TaskReport>(&mut rx).await? {
+            let key = (message.device_operation_id, message.step_index);
+            let finished = !matches!(
+                message.report.status,
+                DeviceOperationStepReportStatus::Running
+            );
+            let reports = {
+                let mut assigned = assigned
+                    .lock()
+                    .map_err(|_| anyhow::anyhow!("task assignment mutex poisoned"))?;
+                if finished {
+                    assigned.remove(&key)
+                } else {
+                    assigned.get(&key).cloned()
+                }
+            };
+            let Some(reports) = reports else {
+                debug!(
+                    device_operation_id = %key.0,
+                    step_index = key.1,
+                    "ignoring report for a task step that is not assigned"
+                );
+                continue;
+            };
+            // An interrupted step is assigned again later, so its reports are dropped.
+            reports.send(message.report).await.ok();
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    // Dropping the subscription and the report senders hands interrupted steps back.
+    tokio::select! {
+        result = send_work => result,
+        result = receive_reports => result,
+    }
+}
+
+async fn write_message(
+    stream: &mut (impl AsyncWrite + Unpin),
+    message: &impl Serialize,
+) -> anyhow::Result<()> {
+    let body = serde_json::to_vec(message).context("serializing message")?;
+    anyhow::ensure!(
+        body.len() <= MAX_MESSAGE_LEN as usize,
+        "message too large: {} > {MAX_MESSAGE_LEN}",
+        body.len(),
+    );
+    stream.write_all(&(body.len() as u32).to_be_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read one message, or `None` if the peer closed the connection between messages.
+async fn read_message<T: DeserializeOwned>(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> anyhow::Result<Option<T>> {
+    let mut len_buf = [0u8; 4];
+    match stream.read_exact(&mut len_buf).await {
+        Ok(_) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error).context("reading message length"),
+    }
+    let len = u32::from_be_bytes(len_buf);
+    anyhow::ensure!(
+        len <= MAX_MESSAGE_LEN,
+        "message too large: {len} > {MAX_MESSAGE_LEN}",
+    );
+    let mut body = vec![0u8; len as usize];
+    stream
+        .read_exact(&mut body)
+        .await
+        .context("reading message body")?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .context("malformed message")
+}
+
 struct SocketGuard {
     path: PathBuf,
 }
@@ -244,9 +486,39 @@ mod tests {
     use nexigon_multiplex::ConnectionEvent;
     use nexigon_multiplex::transport::InMemory;
     use tempfile::tempdir;
+    use tokio::task::JoinHandle;
 
     use super::*;
 
+    /// Context without a hub connection, an outbox, or a task registry.
+    fn test_context() -> LocalApiContext {
+        LocalApiContext {
+            hub: watch::channel(None).1,
+            outbox: None,
+            tasks: None,
+            status: StatusSource::detached(),
+            metrics: Arc::default(),
+        }
+    }
+
+    /// Connect a client to a handler for `context` and complete the handshake.
+    ///
+    /// Returns the client stream, the server hello, and the handler task.
+    async fn connect(
+        context: LocalApiContext,
+        hello: ClientHello,
+    ) -> (UnixStream, ServerHello, JoinHandle<anyhow::Result<()>>) {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let handler = tokio::spawn(handle_client(server, context));
+        client.write_all(&MAGIC).await.unwrap();
+        write_message(&mut client, &hello).await.unwrap();
+        let mut magic = [0u8; 4];
+        client.read_exact(&mut magic).await.unwrap();
+        assert_eq!(magic, MAGIC);
+        let response = read_message(&mut client).await.unwrap().unwrap();
+        (client, response, handler)
+    }
+
     #[tokio::test]
     async fn shutdown_removes_listener_and_awaits_idle_clients() {
         let root = tempdir().unwrap();
@@ -254,11 +526,14 @@ mod tests {
         let config = LocalApiConfig::new().with_socket_path(Some(socket_path.clone()));
         let (_peer_transport, agent_transport) = InMemory::<Bytes, Bytes>::new_buffered(8);
         let agent_connection = Connection::new(agent_transport);
-        let hub_ref = agent_connection.make_ref();
+        let context = LocalApiContext {
+            hub: watch::channel(Some(agent_connection.make_ref())).1,
+            ..test_context()
+        };
         let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
 
         let server = tokio::spawn(async move {
-            serve(&config, hub_ref, async move {
+            serve(&config, context, async move {
                 let _ = shutdown_rx.await;
             })
             .await
@@ -320,25 +595,14 @@ mod tests {
             }
         });
 
-        let (mut client, server) = UnixStream::pair().unwrap();
-        let handler = tokio::spawn(handle_client(server, hub_ref));
-        let hello = ClientHello::new(VERSION, "executor".to_owned());
-        let body = serde_json::to_vec(&hello).unwrap();
-        client.write_all(&MAGIC).await.unwrap();
-        client
-            .write_all(&(body.len() as u32).to_be_bytes())
-            .await
-            .unwrap();
-        client.write_all(&body).await.unwrap();
-        client.flush().await.unwrap();
-
-        let mut magic = [0u8; 4];
-        client.read_exact(&mut magic).await.unwrap();
-        assert_eq!(magic, MAGIC);
-        let len = client.read_u32().await.unwrap() as usize;
-        let mut body = vec![0u8; len];
-        client.read_exact(&mut body).await.unwrap();
-        let response: ServerHello = serde_json::from_slice(&body).unwrap();
+        let (_client, response, handler) = connect(
+            LocalApiContext {
+                hub: watch::channel(Some(hub_ref)).1,
+                ..test_context()
+            },
+            ClientHello::new(VERSION, "executor".to_owned()),
+        )
+        .await;
         let ServerHello::Error(error) = response else {
             panic!("local API acknowledged an unavailable upstream executor");
         };
@@ -354,4 +618,132 @@ mod tests {
         let _ = hub_driver.await;
         let _ = agent_driver.await;
     }
+
+    #[tokio::test]
+    async fn executor_is_rejected_while_the_hub_is_disconnected() {
+        let (_client, response, handler) = connect(
+            test_context(),
+            ClientHello::new(VERSION, "executor".to_owned()),
+        )
+        .await;
+        let ServerHello::Error(error) = response else {
+            panic!("local API acknowledged a disconnected hub");
+        };
+        assert!(matches!(error.code, ServerErrorCode::Internal));
+        assert_eq!(error.message, "hub is not connected");
+
+        handler
+            .await
+            .expect("local API handler panicked")
+            .expect("local API handler failed");
+    }
+
+    #[tokio::test]
+    async fn task_workers_receive_work_and_finish_it() {
+        use nexigon_api::types::devices::DeviceOperationDeviceTaskStep;
+        use nexigon_api::types::devices::DeviceOperationId;
+        use nexigon_api::types::devices::DeviceOperationStepReport;
+        use nexigon_api::types::devices::DeviceOperationWorkItem;
+        use nexigon_api::types::devices::DeviceOperationWorkStep;
+        use nexigon_ids::ids::DeviceOperationWorkClaimId;
+        use serde_json::json;
+        use tokio_util::sync::CancellationToken;
+
+        use crate::tasks::TaskProgress;
+
+        let registry = Arc::new(TaskRegistry::default());
+        let (mut client, response, handler) = connect(
+            LocalApiContext {
+                tasks: Some(registry.clone()),
+                ..test_context()
+            },
+            ClientHello::new(VERSION, "tasks".to_owned())
+                .with_options(Some(json!({"tasks": ["inventory"]}))),
+        )
+        .await;
+        assert!(matches!(response, ServerHello::Ok(_)));
+
+        let item = DeviceOperationWorkItem::new(
+            DeviceOperationId::generate(),
+            DeviceOperationWorkClaimId::generate(),
+            "inventory".to_owned(),
+            0,
+            DeviceOperationWorkStep::DeviceTask(DeviceOperationDeviceTaskStep::new(
+                "inventory".to_owned(),
+                json!(null),
+            )),
+        );
+        let (progress, _) = watch::channel(TaskProgress::default());
+        let cancellation = CancellationToken::new();
+        let execution = crate::tasks::run_task(
+            &registry,
+            item,
+            Duration::from_secs(10),
+            &progress,
+            &cancellation,
+        );
+        let worker = async {
+            let item: DeviceOperationWorkItem = read_message(&mut client).await.unwrap().unwrap();
+            let report = TaskReport {
+                device_operation_id: item.device_operation_id,
+                step_index: item.step_index,
+                report: DeviceOperationStepReport {
+                    status: DeviceOperationStepReportStatus::Succeeded,
+                    output: Some(json!(["sda"])),
+                    checkpoint: None,
+                    error: None,
+                },
+            };
+            write_message(&mut client, &report).await.unwrap();
+        };
+        let (report, ()) = tokio::join!(execution, worker);
+        let report = report.expect("task step was interrupted");
+        assert!(matches!(
+            report.status,
+            DeviceOperationStepReportStatus::Succeeded
+        ));
+        assert_eq!(report.output, Some(json!(["sda"])));
+
+        drop(client);
+        handler
+            .await
+            .expect("local API handler panicked")
+            .expect("local API handler failed");
+    }
+
+    #[tokio::test]
+    async fn status_reports_the_agent_state() {
+        use nexigon_agent_api::types::status::AgentStatus;
+
+        let status = StatusSource::detached();
+        let _terminal = status.active_tasks.enter("terminal");
+        let (mut client, response, handler) = connect(
+            LocalApiContext {
+                status,
+                ..test_context()
+            },
+            ClientHello::new(VERSION, "status".to_owned()),
+        )
+        .await;
+        assert!(matches!(response, ServerHello::Ok(_)));
+        let status: AgentStatus = read_message(&mut client).await.unwrap().unwrap();
+        assert!(!status.hub.connected);
+        assert_eq!(status.hub.round_trip_time_ms, None);
+        assert!(status.device_id.is_none());
+        assert_eq!(status.tasks.len(), 1);
+        assert_eq!(status.tasks[0].kind, "terminal");
+        assert_eq!(status.tasks[0].count, 1);
+        assert!(status.operations.is_none());
+        assert!(
+            read_message::<AgentStatus>(&mut client)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        handler
+            .await
+            .expect("local API handler panicked")
+            .expect("local API handler failed");
+    }
 }
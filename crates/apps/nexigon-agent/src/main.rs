@@ -17,7 +17,19 @@ use nexigon_agent_api::DEFAULT_SOCKET_PATH;
 #[cfg(unix)]
 use nexigon_agent_api::client::LocalExecutor;
 #[cfg(unix)]
+use nexigon_agent_api::client::agent_status;
+#[cfg(unix)]
 use nexigon_agent_api::client::connect_local_executor;
+#[cfg(unix)]
+use nexigon_agent_api::client::enqueue_outbox_item;
+#[cfg(unix)]
+use nexigon_agent_api::types::outbox::OutboxEvents;
+#[cfg(unix)]
+use nexigon_agent_api::types::outbox::OutboxItem;
+#[cfg(unix)]
+use nexigon_agent_api::types::status::AgentStatus;
+#[cfg(unix)]
+use nexigon_agent_api::types::status::OperationLedgerState;
 use nexigon_api::Action;
 use nexigon_api::types::actor::Actor;
 use nexigon_api::types::actor::GetActorAction;
@@ -71,17 +83,85 @@ async fn main() -> anyhow::Result<()> {
             run_device_cmd(&mut session.executor, &session.actor, cmd).await?;
         }
         Cmd::Events(cmd) => {
-            let mut session = OneShot::open(&config_path).await?;
-            run_events_cmd(&mut session.executor, &session.actor, cmd).await?;
+            run_events_cmd(&config_path, cmd).await?;
         }
         Cmd::Repositories(cmd) => {
             let mut session = OneShot::open(&config_path).await?;
             execute_repositories_cmd(&cmd, &mut session.executor).await?;
         }
+        Cmd::Status { json } => {
+            run_status_cmd(json).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn run_status_cmd(json: bool) -> anyhow::Result<()> {
+    let socket_path = Path::new(DEFAULT_SOCKET_PATH);
+    let status = agent_status(socket_path)
+        .await
+        .with_context(|| format!("unable to query agent status on {socket_path:?}"))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status).unwrap());
+    } else {
+        print_status(&status);
     }
     Ok(())
 }
 
+#[cfg(not(unix))]
+async fn run_status_cmd(_json: bool) -> anyhow::Result<()> {
+    bail!("the agent status is only available on Unix")
+}
+
+#[cfg(unix)]
+fn print_status(status: &AgentStatus) {
+    println!("Version:    {}", status.version);
+    let url = status.hub.url.as_deref().unwrap_or("unknown URL");
+    if status.hub.connected {
+        println!("Hub:        connected to {url}");
+    } else {
+        println!("Hub:        disconnected from {url}");
+    }
+    if let Some(round_trip_time_ms) = status.hub.round_trip_time_ms {
+        println!("Round trip: {round_trip_time_ms} ms");
+    }
+    if let (Some(sent), Some(received)) = (status.hub.frames_sent, status.hub.frames_received) {
+        println!("Frames:     {sent} sent, {received} received");
+    }
+    match &status.device_id {
+        Some(device_id) => println!("Device:     {device_id}"),
+        None => println!("Device:     not identified yet"),
+    }
+    match status.commands {
+        Some(commands) => println!("Commands:   {commands} loaded"),
+        None => println!("Commands:   disabled"),
+    }
+    println!("Tasks:");
+    for task in &status.tasks {
+        println!("  {:<16} {}", task.kind, task.count);
+    }
+    match &status.operations {
+        Some(operations) if operations.is_empty() => println!("Operations: no pending steps"),
+        Some(operations) => {
+            println!("Operations:");
+            for entry in operations {
+                let state = match entry.state {
+                    OperationLedgerState::InProgress => "in progress",
+                    OperationLedgerState::Completed => "completed, not reported",
+                    OperationLedgerState::TaskRunning => "task running",
+                };
+                println!(
+                    "  {} step {}: {state}",
+                    entry.device_operation_id, entry.step_index
+                );
+            }
+        }
+        None => println!("Operations: polling disabled"),
+    }
+}
+
 /// Session scaffolding for one-shot CLI subcommands.
 ///
 /// Resolves the device actor and an executor that targets either the
@@ -224,11 +304,7 @@ async fn run_device_cmd(
     Ok(())
 }
 
-async fn run_events_cmd(
-    executor: &mut OneShotExecutor,
-    actor: &nexigon_api::types::actor::DeviceActor,
-    cmd: EventsCmd,
-) -> anyhow::Result<()> {
+async fn run_events_cmd(config_path: &Path, cmd: EventsCmd) -> anyhow::Result<()> {
     match cmd {
         EventsCmd::Emit {
             severity,
@@ -241,30 +317,50 @@ async fn run_events_cmd(
                 Some(raw) => raw.parse().context("unable to parse `--emitted-at`")?,
                 None => Timestamp::now(),
             };
-            let publish_events = PublishDeviceEventsAction::new(
-                actor.device_id.clone(),
-                vec![
-                    DeviceEvent::new(
-                        DeviceEventId::generate(),
-                        severity,
-                        serde_json::from_str(&body).context("unable to parse event body")?,
-                        {
-                            let mut map = HashMap::new();
-                            for attribute in attributes {
-                                let Some((key, value)) = attribute.split_once('=') else {
-                                    bail!("invalid attribute: {attribute}")
-                                };
-                                map.insert(key.to_owned(), serde_json::from_str(value)?);
-                            }
-                            map
-                        },
-                        timestamp,
-                    )
-                    .with_category(category),
-                ],
-            );
-            executor
-                .execute(publish_events)
+            let events = vec![
+                DeviceEvent::new(
+                    DeviceEventId::generate(),
+                    severity,
+                    serde_json::from_str(&body).context("unable to parse event body")?,
+                    {
+                        let mut map = HashMap::new();
+                        for attribute in attributes {
+                            let Some((key, value)) = attribute.split_once('=') else {
+                                bail!("invalid attribute: {attribute}")
+                            };
+                            map.insert(key.to_owned(), serde_json::from_str(value)?);
+                        }
+                        map
+                    },
+                    timestamp,
+                )
+                .with_category(category),
+            ];
+            // Prefer the agent's durable outbox so events emitted while the hub is
+            // unreachable are delivered later instead of being lost.
+            #[cfg(unix)]
+            {
+                let socket_path = Path::new(DEFAULT_SOCKET_PATH);
+                let item = OutboxItem::Events(OutboxEvents {
+                    events: events.clone(),
+                });
+                match enqueue_outbox_item(socket_path, &item).await {
+                    Ok(()) => return Ok(()),
+                    Err(e) => {
+                        debug!(
+                            ?socket_path,
+                            "agent outbox unavailable ({e}); publishing directly"
+                        );
+                    }
+                }
+            }
+            let mut session = OneShot::open(config_path).await?;
+            session
+                .executor
+                .execute(PublishDeviceEventsAction::new(
+                    session.actor.device_id.clone(),
+                    events,
+                ))
                 .await
                 .context("unable to emit event")??;
         }
@@ -301,6 +397,12 @@ enum Cmd {
     /// Repositories subcommand.
     #[clap(subcommand)]
     Repositories(RepositoriesCmd),
+    /// Show the status of the running agent.
+    Status {
+        /// Output the status as JSON.
+        #[clap(long)]
+        json: bool,
+    },
 }
 
 /// Device subcommand.
@@ -0,0 +1,690 @@
+//! Prometheus/OpenMetrics exporter for agent statistics.
+//!
+//! The agent counts channel requests, command executions, operation claims and
+//! reports, and local API clients while it runs. Hub connection statistics, channel
+//! traffic, and active tasks are sampled when the metrics are scraped. Like the
+//! provisioning endpoint, the listener is not a general-purpose HTTP server: it
+//! answers `GET /metrics` and closes the connection.
+//!
+//! Hub connection counters belong to the current connection and restart from zero
+//! after a reconnect, which Prometheus handles as a counter reset.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+
+use anyhow::Context;
+use nexigon_agent_api::types::status::AgentTaskCount;
+use nexigon_api::types::devices::DeviceCommandDoneData;
+use nexigon_api::types::devices::DeviceCommandStatus;
+use nexigon_multiplex::ChannelStatistics;
+use nexigon_multiplex::ConnectionRef;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+use crate::config::MetricsConfig;
+use crate::status::ActiveTasks;
+
+const DEFAULT_BIND: &str = "127.0.0.1:9947";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+const MAX_CONCURRENT_SCRAPES: usize = 4;
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Whether the metrics listener is enabled.
+pub(crate) fn metrics_enabled(config: Option<&MetricsConfig>) -> bool {
+    config.and_then(|metrics| metrics.enabled).unwrap_or(false)
+}
+
+/// Source of a command execution.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CommandSource {
+    /// Interactive invocation over a `handler` channel.
+    Channel,
+    /// Command step of a device operation.
+    Operation,
+}
+
+impl CommandSource {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Channel => "channel",
+            Self::Operation => "operation",
+        }
+    }
+}
+
+/// Counters and channel statistics collected by the agent.
+#[derive(Debug, Default)]
+pub(crate) struct AgentMetrics {
+    /// Channel requests by endpoint, result, and reject reason.
+    channel_requests: Mutex<BTreeMap<[&'static str; 3], u64>>,
+    /// Traffic of accepted channels by endpoint.
+    channels: Mutex<BTreeMap<&'static str, ChannelTraffic>>,
+    next_channel: AtomicU64,
+    /// Command executions by source and status.
+    commands: Mutex<BTreeMap<[&'static str; 2], CommandExecutions>>,
+    /// Operation work claims by result.
+    operation_claims: Mutex<BTreeMap<&'static str, u64>>,
+    /// Work items received in successful operation claims.
+    operation_work_items: AtomicU64,
+    /// Operation step reports by result.
+    operation_reports: Mutex<BTreeMap<&'static str, u64>>,
+    /// Local API clients by result.
+    local_api_clients: Mutex<BTreeMap<&'static str, u64>>,
+    /// Local API clients that are currently connected.
+    local_api_active_clients: AtomicU64,
+}
+
+/// Traffic of the channels of one endpoint.
+#[derive(Debug, Default)]
+struct ChannelTraffic {
+    /// Bytes sent over channels that have been closed.
+    closed_bytes_sent: u64,
+    /// Bytes received over channels that have been closed.
+    closed_bytes_received: u64,
+    /// Statistics of open channels.
+    open: HashMap<u64, Arc<ChannelStatistics>>,
+}
+
+#[derive(Debug, Default)]
+struct CommandExecutions {
+    count: u64,
+    duration_ms: u64,
+}
+
+impl AgentMetrics {
+    /// Count an accepted channel request.
+    pub(crate) fn channel_accepted(&self, endpoint: &'static str) {
+        increment(&self.channel_requests, [endpoint, "accepted", ""]);
+    }
+
+    /// Count a rejected channel request.
+    pub(crate) fn channel_rejected(&self, endpoint: &'static str, reason: &'static str) {
+        increment(&self.channel_requests, [endpoint, "rejected", reason]);
+    }
+
+    /// Count the traffic of a channel of `endpoint` until the returned guard is dropped.
+    pub(crate) fn track_channel(
+        self: &Arc<Self>,
+        endpoint: &'static str,
+        statistics: Arc<ChannelStatistics>,
+    ) -> TrackedChannel {
+        let id = self.next_channel.fetch_add(1, atomic::Ordering::Relaxed);
+        if let Ok(mut channels) = self.channels.lock() {
+            channels
+                .entry(endpoint)
+                .or_default()
+                .open
+                .insert(id, statistics);
+        }
+        TrackedChannel {
+            metrics: self.clone(),
+            endpoint,
+            id,
+        }
+    }
+
+    /// Count a finished command execution.
+    pub(crate) fn command_executed(&self, source: CommandSource, done: &DeviceCommandDoneData) {
+        let status = match done.status {
+            DeviceCommandStatus::Ok => "ok",
+            DeviceCommandStatus::Error => "error",
+        };
+        if let Ok(mut commands) = self.commands.lock() {
+            let executions = commands.entry([source.name(), status]).or_default();
+            executions.count += 1;
+            executions.duration_ms = executions.duration_ms.saturating_add(done.duration_ms);
+        }
+    }
+
+    /// Count an operation work claim with `result` and the number of claimed items.
+    pub(crate) fn operation_claimed(&self, result: &'static str, work_items: usize) {
+        increment(&self.operation_claims, result);
+        self.operation_work_items
+            .fetch_add(work_items as u64, atomic::Ordering::Relaxed);
+    }
+
+    /// Count an operation step report, `acknowledged` by the hub or not.
+    pub(crate) fn operation_reported(&self, acknowledged: bool) {
+        let result = if acknowledged {
+            "acknowledged"
+        } else {
+            "unacknowledged"
+        };
+        increment(&self.operation_reports, result);
+    }
+
+    /// Count a local API client rejected because of the connection limit.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn local_api_client_rejected(&self) {
+        increment(&self.local_api_clients, "rejected");
+    }
+
+    /// Count an accepted local API client as connected until the guard is dropped.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn local_api_client_accepted(self: &Arc<Self>) -> LocalApiClient {
+        increment(&self.local_api_clients, "accepted");
+        self.local_api_active_clients
+            .fetch_add(1, atomic::Ordering::Relaxed);
+        LocalApiClient {
+            metrics: self.clone(),
+        }
+    }
+
+    /// Render the metrics in the OpenMetrics text format.
+    pub(crate) fn render(&self, hub: Option<&ConnectionRef>, tasks: &[AgentTaskCount]) -> String {
+        let mut out = String::new();
+
+        family(&mut out, "nexigon_agent_build", "info", "Agent version.");
+        sample(
+            &mut out,
+            "nexigon_agent_build_info",
+            &[("version", nexigon_version::NEXIGON_GIT_VERSION)],
+            1,
+        );
+
+        family(
+            &mut out,
+            "nexigon_agent_hub_connected",
+            "gauge",
+            "Whether the agent is connected to the hub.",
+        );
+        sample(
+            &mut out,
+            "nexigon_agent_hub_connected",
+            &[],
+            u64::from(hub.is_some()),
+        );
+        if let Some(hub) = hub {
+            if let Some(rtt) = hub.estimate_round_trip_time() {
+                family(
+                    &mut out,
+                    "nexigon_agent_hub_round_trip_time_seconds",
+                    "gauge",
+                    "Estimated round-trip time of the hub connection.",
+                );
+                sample(
+                    &mut out,
+                    "nexigon_agent_hub_round_trip_time_seconds",
+                    &[],
+                    rtt.as_secs_f64(),
+                );
+            }
+            for (name, help, value) in [
+                (
+                    "nexigon_agent_hub_frames_sent",
+                    "Frames sent over the hub connection.",
+                    hub.estimate_frames_sent(),
+                ),
+                (
+                    "nexigon_agent_hub_frames_received",
+                    "Frames received over the hub connection.",
+                    hub.estimate_frames_received(),
+                ),
+                (
+                    "nexigon_agent_hub_bytes_sent",
+                    "Encoded frame bytes sent over the hub connection.",
+                    hub.estimate_bytes_sent(),
+                ),
+                (
+                    "nexigon_agent_hub_bytes_received",
+                    "Encoded frame bytes received over the hub connection.",
+                    hub.estimate_bytes_received(),
+                ),
+            ] {
+                family(&mut out, name, "counter", help);
+                sample(&mut out, &format!("{name}_total"), &[], value);
+            }
+        }
+
+        family(
+            &mut out,
+            "nexigon_agent_channel_requests",
+            "counter",
+            "Channel requests of the hub by endpoint and result.",
+        );
+        for ([endpoint, result, reason], count) in snapshot(&self.channel_requests) {
+            let mut labels = vec![("endpoint", endpoint), ("result", result)];
+            if !reason.is_empty() {
+                labels.push(("reason", reason));
+            }
+            sample(
+                &mut out,
+                "nexigon_agent_channel_requests_total",
+                &labels,
+                count,
+            );
+        }
+
+        let traffic = self.channel_traffic();
+        family(
+            &mut out,
+            "nexigon_agent_channel_bytes_sent",
+            "counter",
+            "Bytes sent over accepted channels by endpoint.",
+        );
+        for (endpoint, sent, _) in &traffic {
+            sample(
+                &mut out,
+                "nexigon_agent_channel_bytes_sent_total",
+                &[("endpoint", endpoint)],
+                *sent,
+            );
+        }
+        family(
+            &mut out,
+            "nexigon_agent_channel_bytes_received",
+            "counter",
+            "Bytes received over accepted channels by endpoint.",
+        );
+        for (endpoint, _, received) in &traffic {
+            sample(
+                &mut out,
+                "nexigon_agent_channel_bytes_received_total",
+                &[("endpoint", endpoint)],
+                *received,
+            );
+        }
+
+        family(
+            &mut out,
+            "nexigon_agent_tasks",
+            "gauge",
+            "Active agent tasks by kind, including terminal sessions and forwarding relays.",
+        );
+        for task in tasks {
+            sample(
+                &mut out,
+                "nexigon_agent_tasks",
+                &[("kind", &task.kind)],
+                task.count,
+            );
+        }
+
+        let commands = match self.commands.lock() {
+            Ok(commands) => commands
+                .iter()
+                .map(|(labels, executions)| (*labels, executions.count, executions.duration_ms))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        family(
+            &mut out,
+            "nexigon_agent_command_executions",
+            "counter",
+            "Finished command executions by source and status.",
+        );
+        for ([source, status], count, _) in &commands {
+            sample(
+                &mut out,
+                "nexigon_agent_command_executions_total",
+                &[("source", source), ("status", status)],
+                *count,
+            );
+        }
+        family(
+            &mut out,
+            "nexigon_agent_command_duration_seconds",
+            "counter",
+            "Total duration of finished command executions by source and status.",
+        );
+        for ([source, status], _, duration_ms) in &commands {
+            sample(
+                &mut out,
+                "nexigon_agent_command_duration_seconds_total",
+                &[("source", source), ("status", status)],
+                *duration_ms as f64 / 1000.0,
+            );
+        }
+
+        family(
+            &mut out,
+            "nexigon_agent_operation_claims",
+            "counter",
+            "Operation work claims by result.",
+        );
+        for (result, count) in snapshot(&self.operation_claims) {
+            sample(
+                &mut out,
+                "nexigon_agent_operation_claims_total",
+                &[("result", result)],
+                count,
+            );
+        }
+        family(
+            &mut out,
+            "nexigon_agent_operation_work_items",
+            "counter",
+            "Work items received in operation claims.",
+        );
+        sample(
+            &mut out,
+            "nexigon_agent_operation_work_items_total",
+            &[],
+            self.operation_work_items.load(atomic::Ordering::Relaxed),
+        );
+        family(
+            &mut out,
+            "nexigon_agent_operation_reports",
+            "counter",
+            "Operation step reports by result.",
+        );
+        for (result, count) in snapshot(&self.operation_reports) {
+            sample(
+                &mut out,
+                "nexigon_agent_operation_reports_total",
+                &[("result", result)],
+                count,
+            );
+        }
+
+        family(
+            &mut out,
+            "nexigon_agent_local_api_clients",
+            "counter",
+            "Local API clients by result.",
+        );
+        for (result, count) in snapshot(&self.local_api_clients) {
+            sample(
+                &mut out,
+                "nexigon_agent_local_api_clients_total",
+                &[("result", result)],
+                count,
+            );
+        }
+        family(
+            &mut out,
+            "nexigon_agent_local_api_active_clients",
+            "gauge",
+            "Connected local API clients.",
+        );
+        sample(
+            &mut out,
+            "nexigon_agent_local_api_active_clients",
+            &[],
+            self.local_api_active_clients
+                .load(atomic::Ordering::Relaxed),
+        );
+
+        out.push_str("# EOF\n");
+        out
+    }
+
+    /// Bytes sent and received over the channels of each endpoint.
+    fn channel_traffic(&self) -> Vec<(&'static str, u64, u64)> {
+        let Ok(channels) = self.channels.lock() else {
+            return Vec::new();
+        };
+        channels
+            .iter()
+            .map(|(endpoint, traffic)| {
+                let (sent, received) = traffic.open.values().fold(
+                    (traffic.closed_bytes_sent, traffic.closed_bytes_received),
+                    |(sent, received), statistics| {
+                        (
+                            sent + statistics.estimate_bytes_sent(),
+                            received + statistics.estimate_bytes_received(),
+                        )
+                    },
+                );
+                (*endpoint, sent, received)
+            })
+            .collect()
+    }
+}
+
+/// Channel counted by [`AgentMetrics::track_channel`].
+pub(crate) struct TrackedChannel {
+    metrics: Arc<AgentMetrics>,
+    endpoint: &'static str,
+    id: u64,
+}
+
+impl Drop for TrackedChannel {
+    fn drop(&mut self) {
+        let Ok(mut channels) = self.metrics.channels.lock() else {
+            return;
+        };
+        if let Some(traffic) = channels.get_mut(self.endpoint)
+            && let Some(statistics) = traffic.open.remove(&self.id)
+        {
+            traffic.closed_bytes_sent += statistics.estimate_bytes_sent();
+            traffic.closed_bytes_received += statistics.estimate_bytes_received();
+        }
+    }
+}
+
+/// Local API client counted by [`AgentMetrics::local_api_client_accepted`].
+pub(crate) struct LocalApiClient {
+    metrics: Arc<AgentMetrics>,
+}
+
+impl Drop for LocalApiClient {
+    fn drop(&mut self) {
+        self.metrics
+            .local_api_active_clients
+            .fetch_sub(1, atomic::Ordering::Relaxed);
+    }
+}
+
+fn increment<K: Ord>(counters: &Mutex<BTreeMap<K, u64>>, key: K) {
+    if let Ok(mut counters) = counters.lock() {
+        *counters.entry(key).or_default() += 1;
+    }
+}
+
+fn snapshot<K: Ord + Copy>(counters: &Mutex<BTreeMap<K, u64>>) -> Vec<(K, u64)> {
+    match counters.lock() {
+        Ok(counters) => counters.iter().map(|(key, count)| (*key, *count)).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    let _ = writeln!(out, "# HELP {name} {help}");
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        out.push('{');
+        for (index, (label, value)) in labels.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{label}=\"");
+            for c in value.chars() {
+                match c {
+                    '\\' => out.push_str("\\\\"),
+                    '"' => out.push_str("\\\""),
+                    '\n' => out.push_str("\\n"),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        out.push('}');
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+/// Serve the metrics of the agent until `cancellation` fires.
+pub(crate) async fn serve(
+    config: &MetricsConfig,
+    metrics: Arc<AgentMetrics>,
+    active_tasks: Arc<ActiveTasks>,
+    hub: watch::Receiver<Option<ConnectionRef>>,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    let bind = config.bind.as_deref().unwrap_or(DEFAULT_BIND);
+    let listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("binding metrics listener at {bind}"))?;
+    info!(bind, "agent metrics listening");
+
+    let mut scrapes = JoinSet::new();
+    loop {
+        tokio::select! {
+            biased;
+            () = cancellation.cancelled() => break,
+            Some(result) = scrapes.join_next(), if !scrapes.is_empty() => {
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(error)) => debug!(?error, "metrics client failed"),
+                    Err(error) => warn!(?error, "metrics client task panicked"),
+                }
+            }
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted.context("accepting metrics connection")?;
+                if scrapes.len() >= MAX_CONCURRENT_SCRAPES {
+                    debug!(%peer, "metrics connection limit reached; rejecting client");
+                    continue;
+                }
+                let metrics = metrics.clone();
+                let active_tasks = active_tasks.clone();
+                let hub = hub.clone();
+                scrapes.spawn(async move {
+                    tokio::time::timeout(
+                        REQUEST_TIMEOUT,
+                        handle_client(stream, &metrics, &active_tasks, &hub),
+                    )
+                    .await
+                    .context("metrics request timed out")?
+                });
+            }
+        }
+    }
+    scrapes.abort_all();
+    while scrapes.join_next().await.is_some() {}
+    Ok(())
+}
+
+async fn handle_client(
+    mut stream: TcpStream,
+    metrics: &AgentMetrics,
+    active_tasks: &ActiveTasks,
+    hub: &watch::Receiver<Option<ConnectionRef>>,
+) -> anyhow::Result<()> {
+    let request_line = read_request_line(&mut stream).await?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    // Scrapers may append query parameters, which are not used.
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => {
+            let hub = hub.borrow().clone();
+            let body = metrics.render(hub.as_ref(), &active_tasks.counts());
+            (200, CONTENT_TYPE, body)
+        }
+        ("GET", _) => (404, "text/plain", "not found\n".to_owned()),
+        _ => (405, "text/plain", "method not allowed\n".to_owned()),
+    };
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Method Not Allowed",
+    };
+    let response = format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len(),
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read the request headers and return the request line.
+async fn read_request_line(stream: &mut TcpStream) -> anyhow::Result<String> {
+    let mut buf = Vec::new();
+    loop {
+        if let Some(header_end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            let headers =
+                std::str::from_utf8(&buf[..header_end]).context("request headers must be UTF-8")?;
+            let request_line = headers.split("\r\n").next().unwrap_or_default();
+            return Ok(request_line.to_owned());
+        }
+        anyhow::ensure!(buf.len() < MAX_HEADER_BYTES, "request headers too large");
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).await.context("reading request")?;
+        anyhow::ensure!(n > 0, "connection closed before headers");
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nexigon_agent_api::types::status::AgentTaskCount;
+    use nexigon_api::types::devices::DeviceCommandDoneData;
+    use nexigon_api::types::devices::DeviceCommandStatus;
+
+    use super::AgentMetrics;
+    use super::CommandSource;
+
+    #[test]
+    fn metrics_are_rendered_as_openmetrics() {
+        let metrics = Arc::new(AgentMetrics::default());
+        metrics.channel_accepted("handler");
+        metrics.channel_rejected("terminal", "terminal not enabled or terminal user invalid");
+        metrics.command_executed(
+            CommandSource::Channel,
+            &DeviceCommandDoneData {
+                status: DeviceCommandStatus::Ok,
+                output: None,
+                error: None,
+                log_tail: Vec::new(),
+                duration_ms: 1500,
+            },
+        );
+        metrics.operation_claimed("ok", 2);
+        metrics.operation_reported(true);
+        let client = metrics.local_api_client_accepted();
+        let rendered = metrics.render(
+            None,
+            &[AgentTaskCount {
+                kind: "terminal".to_owned(),
+                count: 1,
+            }],
+        );
+        for line in [
+            "nexigon_agent_hub_connected 0",
+            "nexigon_agent_channel_requests_total{endpoint=\"handler\",result=\"accepted\"} 1",
+            "nexigon_agent_channel_requests_total{endpoint=\"terminal\",result=\"rejected\",reason=\"terminal not enabled or terminal user invalid\"} 1",
+            "nexigon_agent_tasks{kind=\"terminal\"} 1",
+            "nexigon_agent_command_executions_total{source=\"channel\",status=\"ok\"} 1",
+            "nexigon_agent_command_duration_seconds_total{source=\"channel\",status=\"ok\"} 1.5",
+            "nexigon_agent_operation_claims_total{result=\"ok\"} 1",
+            "nexigon_agent_operation_work_items_total 2",
+            "nexigon_agent_operation_reports_total{result=\"acknowledged\"} 1",
+            "nexigon_agent_local_api_active_clients 1",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing `{line}`");
+        }
+        assert!(rendered.ends_with("# EOF\n"));
+        drop(client);
+        let rendered = metrics.render(None, &[]);
+        assert!(
+            rendered
+                .lines()
+                .any(|l| l == "nexigon_agent_local_api_active_clients 0")
+        );
+    }
+}
@@ -5,6 +5,9 @@
 //! persists dispatch before execution and the completed report before sending it. A
 //! completed result is sent again when the work is leased again without re-executing;
 //! an execution interrupted after dispatch is failed conservatively rather than replayed.
+//!
+//! Device tasks are designed to be resumed, so a running task instead records its latest
+//! checkpoint, and an interrupted task is started again from there.
 
 use std::collections::BTreeMap;
 use std::io::Write;
@@ -14,6 +17,7 @@ use std::path::PathBuf;
 use crate::config::operation_ledger::OperationExecutionCompleted;
 use crate::config::operation_ledger::OperationExecutionEntry;
 use crate::config::operation_ledger::OperationExecutionInProgress;
+use crate::config::operation_ledger::OperationExecutionTaskRunning;
 use anyhow::Context;
 use anyhow::bail;
 use nexigon_api::types::devices::DeviceOperationId;
@@ -29,6 +33,8 @@ pub(super) enum PreviousExecution {
     None,
     InProgress,
     Completed(DeviceOperationStepReport),
+    /// A device task was started; carries its latest checkpoint.
+    TaskRunning(Option<serde_json::Value>),
 }
 
 pub(super) struct OperationLedger {
@@ -65,9 +71,17 @@ impl OperationLedger {
             Some(OperationExecutionEntry::Completed(entry)) => {
                 PreviousExecution::Completed(entry.report.clone())
             }
+            Some(OperationExecutionEntry::TaskRunning(entry)) => {
+                PreviousExecution::TaskRunning(entry.checkpoint.clone())
+            }
         }
     }
 
+    /// Entries whose results have not been reported yet.
+    pub(super) fn entries(&self) -> impl Iterator<Item = &OperationExecutionEntry> {
+        self.entries.values()
+    }
+
     pub(super) async fn mark_in_progress(
         &mut self,
         operation_id: &DeviceOperationId,
@@ -86,6 +100,26 @@ impl OperationLedger {
         .await
     }
 
+    pub(super) async fn mark_task_running(
+        &mut self,
+        operation_id: &DeviceOperationId,
+        step_index: u32,
+        claim_id: &DeviceOperationWorkClaimId,
+        checkpoint: Option<serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        self.replace(
+            operation_id,
+            step_index,
+            OperationExecutionEntry::TaskRunning(OperationExecutionTaskRunning {
+                device_operation_id: operation_id.clone(),
+                step_index,
+                claim_id: claim_id.clone(),
+                checkpoint,
+            }),
+        )
+        .await
+    }
+
     pub(super) async fn mark_completed(
         &mut self,
         operation_id: &DeviceOperationId,
@@ -235,10 +269,13 @@ fn entry_identity(entry: &OperationExecutionEntry) -> (&DeviceOperationId, u32)
             (&entry.device_operation_id, entry.step_index)
         }
         OperationExecutionEntry::Completed(entry) => (&entry.device_operation_id, entry.step_index),
+        OperationExecutionEntry::TaskRunning(entry) => {
+            (&entry.device_operation_id, entry.step_index)
+        }
     }
 }
 
-async fn sync_directory(path: &Path) -> anyhow::Result<()> {
+pub(crate) async fn sync_directory(path: &Path) -> anyhow::Result<()> {
     #[cfg(unix)]
     {
         tokio::fs::File::open(path)
@@ -314,6 +351,36 @@ mod tests {
         let _ = tokio::fs::remove_dir_all(directory).await;
     }
 
+    #[tokio::test]
+    async fn running_tasks_recover_their_latest_checkpoint() {
+        let operation_id = DeviceOperationId::generate();
+        let claim_id = DeviceOperationWorkClaimId::generate();
+        let directory = temporary_directory(&operation_id);
+
+        let mut ledger = OperationLedger::load(&directory).await.unwrap();
+        ledger
+            .mark_task_running(&operation_id, 2, &claim_id, None)
+            .await
+            .unwrap();
+        ledger
+            .mark_task_running(
+                &operation_id,
+                2,
+                &claim_id,
+                Some(serde_json::json!({"chunk": 7})),
+            )
+            .await
+            .unwrap();
+
+        let ledger = OperationLedger::load(&directory).await.unwrap();
+        let PreviousExecution::TaskRunning(checkpoint) = ledger.previous(&operation_id, 2) else {
+            panic!("running task was not recovered");
+        };
+        assert_eq!(checkpoint, Some(serde_json::json!({"chunk": 7})));
+
+        let _ = tokio::fs::remove_dir_all(directory).await;
+    }
+
     #[tokio::test]
     async fn failed_persistence_does_not_change_the_in_memory_state() {
         let operation_id = DeviceOperationId::generate();
@@ -0,0 +1,605 @@
+//! Durable offline outbox for device events and property writes.
+//!
+//! Every queued item is one atomic file named after its sequence number, written the same
+//! way as the operation ledger. Items are delivered in sequence order once the agent is
+//! connected; consecutive event items are batched into a single publish. Property writes
+//! are latest-wins, so a device that stays offline for long does not accumulate stale
+//! values of the same property.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::bail;
+use jiff::Timestamp;
+use nexigon_agent_api::types::outbox::OutboxItem;
+use nexigon_api::types::devices::DeviceEvent;
+use nexigon_api::types::devices::PublishDeviceEventsAction;
+use nexigon_api::types::devices::SetDevicePropertyAction;
+use nexigon_client::ClientExecutor;
+use nexigon_ids::ids::DeviceId;
+use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::warn;
+
+use crate::config::OutboxConfig;
+use crate::config::outbox::OutboxEntry;
+use crate::operation_ledger::sync_directory;
+
+const OUTBOX_DIRECTORY_NAME: &str = "outbox";
+const TEMPORARY_OUTBOX_ENTRY_PREFIX: &str = ".outbox-entry-";
+const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Upper bound for the number of events published in one batch.
+const MAX_BATCH_EVENTS: usize = 100;
+/// Delay before retrying delivery after a transport failure.
+const DELIVERY_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Whether the outbox is enabled in the configuration.
+pub(super) fn outbox_enabled(config: Option<&OutboxConfig>) -> bool {
+    config.and_then(|outbox| outbox.enabled).unwrap_or(false)
+}
+
+/// Items taken from the front of the outbox for one delivery.
+#[derive(Debug)]
+pub(super) enum OutboxBatch {
+    Events {
+        sequences: Vec<u64>,
+        events: Vec<DeviceEvent>,
+    },
+    Property {
+        sequence: u64,
+        name: String,
+        value: serde_json::Value,
+    },
+}
+
+impl OutboxBatch {
+    fn sequences(&self) -> &[u64] {
+        match self {
+            Self::Events { sequences, .. } => sequences,
+            Self::Property { sequence, .. } => std::slice::from_ref(sequence),
+        }
+    }
+}
+
+struct StoredEntry {
+    entry: OutboxEntry,
+    size: u64,
+}
+
+pub(super) struct Outbox {
+    directory: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+    entries: BTreeMap<u64, StoredEntry>,
+    /// Sequence of the queued write for each property.
+    properties: HashMap<String, u64>,
+    next_sequence: u64,
+    total_bytes: u64,
+}
+
+impl Outbox {
+    pub(super) async fn load(
+        data_path: &Path,
+        config: Option<&OutboxConfig>,
+    ) -> anyhow::Result<Self> {
+        tokio::fs::create_dir_all(data_path)
+            .await
+            .with_context(|| format!("creating agent data directory {}", data_path.display()))?;
+        let directory = data_path.join(OUTBOX_DIRECTORY_NAME);
+        tokio::fs::create_dir_all(&directory)
+            .await
+            .with_context(|| format!("creating outbox {}", directory.display()))?;
+
+        let mut outbox = Self {
+            directory,
+            max_bytes: config
+                .and_then(|outbox| outbox.max_bytes)
+                .unwrap_or(DEFAULT_MAX_BYTES),
+            max_age: config
+                .and_then(|outbox| outbox.max_age_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_MAX_AGE),
+            entries: BTreeMap::new(),
+            properties: HashMap::new(),
+            next_sequence: 0,
+            total_bytes: 0,
+        };
+        outbox.load_entries().await?;
+        Ok(outbox)
+    }
+
+    /// Durably queue an item, dropping the oldest items if the size cap is exceeded.
+    pub(super) async fn push(&mut self, item: OutboxItem) -> anyhow::Result<()> {
+        let entry = OutboxEntry {
+            sequence: self.next_sequence,
+            queued_at: Timestamp::now(),
+            item,
+        };
+        let bytes = serde_json::to_vec(&entry).context("serializing outbox entry")?;
+        let size = bytes.len() as u64;
+        if size > self.max_bytes {
+            bail!(
+                "outbox item of {size} bytes exceeds the outbox size limit of {} bytes",
+                self.max_bytes
+            );
+        }
+        self.expire().await?;
+        let superseded = match &entry.item {
+            OutboxItem::Property(property) => self.properties.get(&property.name).copied(),
+            OutboxItem::Events(_) => None,
+        };
+        // The superseded write is removed once the new one is committed, so its space
+        // counts as free already.
+        let reclaimable = superseded
+            .and_then(|sequence| self.entries.get(&sequence))
+            .map_or(0, |stored| stored.size);
+        while self.total_bytes - reclaimable + size > self.max_bytes {
+            let Some(&oldest) = self
+                .entries
+                .keys()
+                .find(|sequence| Some(**sequence) != superseded)
+            else {
+                break;
+            };
+            warn!(
+                sequence = oldest,
+                "outbox is full, dropping oldest queued item"
+            );
+            self.remove(oldest).await?;
+        }
+
+        self.persist_entry(entry.sequence, bytes).await?;
+        self.next_sequence += 1;
+        if let OutboxItem::Property(property) = &entry.item {
+            self.properties
+                .insert(property.name.clone(), entry.sequence);
+        }
+        self.total_bytes += size;
+        self.entries
+            .insert(entry.sequence, StoredEntry { entry, size });
+        // The new write is committed, so a crash from here on keeps at most both values,
+        // which loading resolves in favour of the newer one.
+        if let Some(sequence) = superseded {
+            self.remove(sequence).await?;
+        }
+        Ok(())
+    }
+
+    /// Return the next batch to deliver after dropping expired items.
+    pub(super) async fn next_batch(&mut self) -> anyhow::Result<Option<OutboxBatch>> {
+        self.expire().await?;
+        let mut entries = self.entries.values();
+        let Some(first) = entries.next() else {
+            return Ok(None);
+        };
+        let batch = match &first.entry.item {
+            OutboxItem::Property(property) => OutboxBatch::Property {
+                sequence: first.entry.sequence,
+                name: property.name.clone(),
+                value: property.value.clone(),
+            },
+            OutboxItem::Events(first_events) => {
+                let mut sequences = vec![first.entry.sequence];
+                let mut events = first_events.events.clone();
+                for stored in entries {
+                    let OutboxItem::Events(more) = &stored.entry.item else {
+                        break;
+                    };
+                    if events.len() + more.events.len() > MAX_BATCH_EVENTS {
+                        break;
+                    }
+                    sequences.push(stored.entry.sequence);
+                    events.extend(more.events.iter().cloned());
+                }
+                OutboxBatch::Events { sequences, events }
+            }
+        };
+        Ok(Some(batch))
+    }
+
+    /// Remove the items of a batch that was delivered or permanently rejected.
+    pub(super) async fn acknowledge(&mut self, batch: &OutboxBatch) -> anyhow::Result<()> {
+        for sequence in batch.sequences() {
+            self.remove(*sequence).await?;
+        }
+        Ok(())
+    }
+
+    async fn expire(&mut self) -> anyhow::Result<()> {
+        let now = Timestamp::now();
+        let max_age =
+            jiff::SignedDuration::try_from(self.max_age).unwrap_or(jiff::SignedDuration::MAX);
+        loop {
+            let Some((&sequence, oldest)) = self.entries.first_key_value() else {
+                return Ok(());
+            };
+            if now.duration_since(oldest.entry.queued_at) <= max_age {
+                return Ok(());
+            }
+            warn!(sequence, "dropping expired outbox item");
+            self.remove(sequence).await?;
+        }
+    }
+
+    async fn remove(&mut self, sequence: u64) -> anyhow::Result<()> {
+        let path = self.entry_path(sequence);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("removing outbox entry {}", path.display()));
+            }
+        }
+        sync_directory(&self.directory).await?;
+        if let Some(stored) = self.entries.remove(&sequence) {
+            self.total_bytes -= stored.size;
+            if let OutboxItem::Property(property) = &stored.entry.item
+                && self.properties.get(&property.name) == Some(&sequence)
+            {
+                self.properties.remove(&property.name);
+            }
+        }
+        Ok(())
+    }
+
+    async fn persist_entry(&self, sequence: u64, bytes: Vec<u8>) -> anyhow::Result<()> {
+        let path = self.entry_path(sequence);
+        let directory = self.directory.clone();
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let mut temporary = tempfile::Builder::new()
+                .prefix(TEMPORARY_OUTBOX_ENTRY_PREFIX)
+                .tempfile_in(&directory)
+                .with_context(|| {
+                    format!("creating temporary outbox entry in {}", directory.display())
+                })?;
+            temporary
+                .write_all(&bytes)
+                .with_context(|| format!("writing outbox entry {}", path.display()))?;
+            temporary
+                .as_file()
+                .sync_all()
+                .with_context(|| format!("syncing outbox entry {}", path.display()))?;
+            temporary
+                .persist(&path)
+                .map_err(|error| error.error)
+                .with_context(|| format!("committing outbox entry {}", path.display()))?;
+            Ok(())
+        })
+        .await
+        .context("waiting for outbox entry persistence")??;
+        sync_directory(&self.directory).await
+    }
+
+    async fn load_entries(&mut self) -> anyhow::Result<()> {
+        let mut directory = tokio::fs::read_dir(&self.directory)
+            .await
+            .with_context(|| format!("reading outbox {}", self.directory.display()))?;
+        while let Some(item) = directory.next_entry().await? {
+            let path = item.path();
+            let is_temporary = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(TEMPORARY_OUTBOX_ENTRY_PREFIX));
+            if is_temporary {
+                // A crash before rename can leave only a temporary file. The item was never
+                // acknowledged to its producer, so it is safe to discard.
+                let _ = tokio::fs::remove_file(path).await;
+                continue;
+            }
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let bytes = tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("reading outbox entry {}", path.display()))?;
+            let entry: OutboxEntry = serde_json::from_slice(&bytes)
+                .with_context(|| format!("parsing outbox entry {}", path.display()))?;
+            if self.entry_path(entry.sequence) != path {
+                bail!("outbox entry has inconsistent filename: {}", path.display());
+            }
+            self.next_sequence = self.next_sequence.max(entry.sequence + 1);
+            self.total_bytes += bytes.len() as u64;
+            self.entries.insert(
+                entry.sequence,
+                StoredEntry {
+                    entry,
+                    size: bytes.len() as u64,
+                },
+            );
+        }
+
+        // A crash between committing a property write and removing the value it
+        // superseded leaves both behind. Keep only the newest.
+        let mut superseded = Vec::new();
+        for (&sequence, stored) in &self.entries {
+            if let OutboxItem::Property(property) = &stored.entry.item
+                && let Some(previous) = self.properties.insert(property.name.clone(), sequence)
+            {
+                superseded.push(previous);
+            }
+        }
+        for sequence in superseded {
+            self.remove(sequence).await?;
+        }
+        if !self.entries.is_empty() {
+            debug!(
+                entries = self.entries.len(),
+                bytes = self.total_bytes,
+                "loaded queued outbox items"
+            );
+        }
+        Ok(())
+    }
+
+    fn entry_path(&self, sequence: u64) -> PathBuf {
+        self.directory.join(format!("{sequence:020}.json"))
+    }
+}
+
+/// Outbox shared between producers and the flusher of the current hub connection.
+pub(super) struct SharedOutbox {
+    outbox: Mutex<Outbox>,
+    queued: Notify,
+}
+
+impl SharedOutbox {
+    pub(super) fn new(outbox: Outbox) -> Self {
+        Self {
+            outbox: Mutex::new(outbox),
+            queued: Notify::new(),
+        }
+    }
+
+    /// Durably queue an item and wake the flusher.
+    pub(super) async fn push(&self, item: OutboxItem) -> anyhow::Result<()> {
+        self.outbox.lock().await.push(item).await?;
+        self.queued.notify_one();
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum DeliveryAttempt {
+    Delivered,
+    Rejected(String),
+    TransportFailed(String),
+}
+
+pub(super) trait OutboxDelivery {
+    fn deliver(
+        &mut self,
+        device_id: &DeviceId,
+        batch: &OutboxBatch,
+    ) -> impl Future<Output = DeliveryAttempt>;
+}
+
+impl OutboxDelivery for ClientExecutor {
+    async fn deliver(&mut self, device_id: &DeviceId, batch: &OutboxBatch) -> DeliveryAttempt {
+        let result = match batch {
+            OutboxBatch::Events { events, .. } => self
+                .execute(PublishDeviceEventsAction::new(
+                    device_id.clone(),
+                    events.clone(),
+                ))
+                .await
+                .map(|result| result.map(|_| ())),
+            OutboxBatch::Property { name, value, .. } => self
+                .execute(SetDevicePropertyAction::new(
+                    device_id.clone(),
+                    name.clone(),
+                    value.clone(),
+                ))
+                .await
+                .map(|result| result.map(|_| ())),
+        };
+        match result {
+            Ok(Ok(())) => DeliveryAttempt::Delivered,
+            Ok(Err(error)) => DeliveryAttempt::Rejected(error.message),
+            Err(error) => DeliveryAttempt::TransportFailed(error.to_string()),
+        }
+    }
+}
+
+/// Deliver queued items over the current hub connection until `cancellation` fires.
+pub(super) async fn run_outbox_flusher(
+    outbox: &SharedOutbox,
+    delivery: &mut impl OutboxDelivery,
+    device_id: &DeviceId,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<()> {
+    loop {
+        let batch = outbox.outbox.lock().await.next_batch().await?;
+        let Some(batch) = batch else {
+            tokio::select! {
+                biased;
+                () = cancellation.cancelled() => return Ok(()),
+                () = outbox.queued.notified() => continue,
+            }
+        };
+        let attempt = tokio::select! {
+            biased;
+            () = cancellation.cancelled() => return Ok(()),
+            attempt = delivery.deliver(device_id, &batch) => attempt,
+        };
+        match attempt {
+            DeliveryAttempt::Delivered => {}
+            DeliveryAttempt::Rejected(message) => {
+                // Retrying a rejected item would block everything queued behind it.
+                warn!(%message, "hub rejected queued outbox item, dropping it");
+            }
+            DeliveryAttempt::TransportFailed(error) => {
+                warn!(%error, "failed to deliver queued outbox item");
+                tokio::select! {
+                    biased;
+                    () = cancellation.cancelled() => return Ok(()),
+                    () = tokio::time::sleep(DELIVERY_RETRY_DELAY) => continue,
+                }
+            }
+        }
+        outbox.outbox.lock().await.acknowledge(&batch).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use nexigon_agent_api::types::outbox::OutboxEvents;
+    use nexigon_agent_api::types::outbox::OutboxProperty;
+    use nexigon_api::types::devices::DeviceEventSeverity;
+    use nexigon_ids::Generate;
+    use nexigon_ids::ids::DeviceEventId;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn event() -> OutboxItem {
+        OutboxItem::Events(OutboxEvents {
+            events: vec![DeviceEvent::new(
+                DeviceEventId::generate(),
+                DeviceEventSeverity::Info,
+                serde_json::json!("body"),
+                HashMap::new(),
+                nexigon_api::types::datetime::Timestamp::now(),
+            )],
+        })
+    }
+
+    fn property(name: &str, value: serde_json::Value) -> OutboxItem {
+        OutboxItem::Property(OutboxProperty {
+            name: name.to_owned(),
+            value,
+        })
+    }
+
+    #[tokio::test]
+    async fn queued_items_survive_a_restart_in_order_with_latest_property_values() {
+        let directory = TempDir::new().unwrap();
+        let mut outbox = Outbox::load(directory.path(), None).await.unwrap();
+        outbox.push(event()).await.unwrap();
+        outbox
+            .push(property("a", serde_json::json!(1)))
+            .await
+            .unwrap();
+        outbox.push(event()).await.unwrap();
+        outbox.push(event()).await.unwrap();
+        outbox
+            .push(property("a", serde_json::json!(2)))
+            .await
+            .unwrap();
+        drop(outbox);
+
+        let mut outbox = Outbox::load(directory.path(), None).await.unwrap();
+        let Some(OutboxBatch::Events { events, .. }) = outbox.next_batch().await.unwrap() else {
+            panic!("expected the first event batch");
+        };
+        assert_eq!(events.len(), 3);
+        let batch = outbox.next_batch().await.unwrap().unwrap();
+        outbox.acknowledge(&batch).await.unwrap();
+        let Some(OutboxBatch::Property { name, value, .. }) = outbox.next_batch().await.unwrap()
+        else {
+            panic!("expected the property write");
+        };
+        assert_eq!(name, "a");
+        assert_eq!(value, serde_json::json!(2));
+        let batch = outbox.next_batch().await.unwrap().unwrap();
+        outbox.acknowledge(&batch).await.unwrap();
+        assert!(outbox.next_batch().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn size_cap_drops_the_oldest_items() {
+        let directory = TempDir::new().unwrap();
+        let mut outbox = Outbox::load(directory.path(), None).await.unwrap();
+        outbox
+            .push(property("a", serde_json::json!("x")))
+            .await
+            .unwrap();
+        // Leave room for two entries; the slack absorbs varying timestamp lengths.
+        outbox.max_bytes = outbox.total_bytes * 2 + 32;
+        outbox
+            .push(property("b", serde_json::json!("x")))
+            .await
+            .unwrap();
+        outbox
+            .push(property("c", serde_json::json!("x")))
+            .await
+            .unwrap();
+
+        assert!(outbox.total_bytes <= outbox.max_bytes);
+        assert_eq!(outbox.entries.len(), 2);
+        let Some(OutboxBatch::Property { name, .. }) = outbox.next_batch().await.unwrap() else {
+            panic!("expected a property write");
+        };
+        assert_eq!(name, "b");
+    }
+
+    #[tokio::test]
+    async fn expired_items_are_not_delivered() {
+        let directory = TempDir::new().unwrap();
+        let config = OutboxConfig::new().with_max_age_secs(Some(0));
+        let mut outbox = Outbox::load(directory.path(), Some(&config)).await.unwrap();
+        outbox.push(event()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(outbox.next_batch().await.unwrap().is_none());
+        assert_eq!(outbox.total_bytes, 0);
+    }
+
+    struct MockDelivery {
+        attempts: VecDeque<DeliveryAttempt>,
+        delivered: Vec<usize>,
+        cancellation: CancellationToken,
+    }
+
+    impl OutboxDelivery for MockDelivery {
+        async fn deliver(&mut self, _: &DeviceId, batch: &OutboxBatch) -> DeliveryAttempt {
+            let attempt = self.attempts.pop_front().expect("one result per call");
+            if matches!(attempt, DeliveryAttempt::Delivered) {
+                self.delivered.push(batch.sequences().len());
+            }
+            if self.attempts.is_empty() {
+                self.cancellation.cancel();
+            }
+            attempt
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flusher_retries_transport_failures_and_drops_rejections() {
+        let directory = TempDir::new().unwrap();
+        let mut outbox = Outbox::load(directory.path(), None).await.unwrap();
+        outbox
+            .push(property("rejected", serde_json::json!(1)))
+            .await
+            .unwrap();
+        outbox.push(event()).await.unwrap();
+        let outbox = SharedOutbox::new(outbox);
+        let cancellation = CancellationToken::new();
+        let mut delivery = MockDelivery {
+            attempts: VecDeque::from([
+                DeliveryAttempt::Rejected("invalid".to_owned()),
+                DeliveryAttempt::TransportFailed("offline".to_owned()),
+                DeliveryAttempt::Delivered,
+            ]),
+            delivered: Vec::new(),
+            cancellation: cancellation.clone(),
+        };
+
+        run_outbox_flusher(&outbox, &mut delivery, &DeviceId::generate(), &cancellation)
+            .await
+            .unwrap();
+
+        assert_eq!(delivery.delivered, vec![1]);
+        assert!(outbox.outbox.lock().await.entries.is_empty());
+    }
+}
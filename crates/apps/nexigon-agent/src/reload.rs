@@ -0,0 +1,191 @@
+//! Runtime configuration reload.
+//!
+//! Only the sections listed in [`LIVE_SECTIONS`] and the operation settings listed in
+//! [`LIVE_OPERATIONS_SETTINGS`] are applied to a running agent. Everything else is bound
+//! to state created at startup, such as the hub connection, the device identity, or the
+//! durable stores below the data directory, and keeps its running value until the agent
+//! is restarted.
+
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::config::OperationsConfig;
+
+/// Top-level configuration sections that are applied to a running agent.
+const LIVE_SECTIONS: &[&str] = &["terminal", "commands", "exports", "telemetry", "local-api"];
+
+/// Operation settings that are applied to a running agent.
+const LIVE_OPERATIONS_SETTINGS: &[&str] = &["poll-interval-secs", "max-concurrency"];
+
+/// Return the configuration that results from applying the live sections of
+/// `reloaded` to `running`.
+pub(crate) fn apply_live_changes(running: &Config, reloaded: &Config) -> Config {
+    let mut applied = running.clone();
+    applied.terminal = reloaded.terminal.clone();
+    applied.commands = reloaded.commands.clone();
+    applied.exports = reloaded.exports.clone();
+    applied.telemetry = reloaded.telemetry.clone();
+    applied.local_api = reloaded.local_api.clone();
+    let reloaded_operations = reloaded.operations.as_ref();
+    let poll_interval_secs =
+        reloaded_operations.and_then(|operations| operations.poll_interval_secs);
+    let max_concurrency = reloaded_operations.and_then(|operations| operations.max_concurrency);
+    if applied.operations.is_some() || poll_interval_secs.is_some() || max_concurrency.is_some() {
+        // A section added on reload only carries the live settings, its other settings
+        // are reported as requiring a restart.
+        let operations = applied.operations.get_or_insert_with(OperationsConfig::new);
+        operations.poll_interval_secs = poll_interval_secs;
+        operations.max_concurrency = max_concurrency;
+    }
+    applied
+}
+
+/// Return the configuration keys that changed but only take effect after a restart.
+pub(crate) fn restart_required_changes(running: &Config, reloaded: &Config) -> Vec<String> {
+    let running = sections(running);
+    let reloaded = sections(reloaded);
+    let mut keys = running
+        .keys()
+        .chain(reloaded.keys())
+        .filter(|key| !LIVE_SECTIONS.contains(&key.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+
+    let mut changed = Vec::new();
+    for key in keys {
+        let before = running.get(&key).unwrap_or(&Value::Null);
+        let after = reloaded.get(&key).unwrap_or(&Value::Null);
+        if key == "operations" {
+            changed.extend(
+                changed_settings(before, after, LIVE_OPERATIONS_SETTINGS)
+                    .into_iter()
+                    .map(|setting| format!("operations.{setting}")),
+            );
+        } else if before != after {
+            changed.push(key);
+        }
+    }
+    changed
+}
+
+fn sections(config: &Config) -> serde_json::Map<String, Value> {
+    match serde_json::to_value(config) {
+        Ok(Value::Object(sections)) => sections,
+        _ => serde_json::Map::new(),
+    }
+}
+
+fn changed_settings(before: &Value, after: &Value, live: &[&str]) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+    let mut settings = before
+        .keys()
+        .chain(after.keys())
+        .filter(|setting| !live.contains(&setting.as_str()))
+        .filter(|setting| {
+            before.get(*setting).unwrap_or(&Value::Null)
+                != after.get(*setting).unwrap_or(&Value::Null)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    settings.sort();
+    settings.dedup();
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::apply_live_changes;
+    use super::restart_required_changes;
+    use crate::config::Config;
+    use crate::config::OperationsConfig;
+    use crate::config::TerminalConfig;
+
+    fn config() -> Config {
+        Config::new(PathBuf::from("fingerprint"))
+            .with_hub_url(Some("https://hub.example".to_owned()))
+            .with_operations(Some(
+                OperationsConfig::new()
+                    .with_enabled(Some(true))
+                    .with_poll_interval_secs(Some(60)),
+            ))
+    }
+
+    #[test]
+    fn live_sections_do_not_require_a_restart() {
+        let running = config();
+        let reloaded = config()
+            .with_terminal(Some(TerminalConfig::new().with_enabled(Some(true))))
+            .with_operations(Some(
+                OperationsConfig::new()
+                    .with_enabled(Some(true))
+                    .with_poll_interval_secs(Some(5))
+                    .with_max_concurrency(Some(4)),
+            ));
+
+        assert!(restart_required_changes(&running, &reloaded).is_empty());
+        let applied = apply_live_changes(&running, &reloaded);
+        assert_eq!(
+            applied.terminal.and_then(|terminal| terminal.enabled),
+            Some(true)
+        );
+        assert_eq!(
+            applied
+                .operations
+                .as_ref()
+                .and_then(|operations| operations.poll_interval_secs),
+            Some(5)
+        );
+        assert_eq!(
+            applied
+                .operations
+                .and_then(|operations| operations.max_concurrency),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn live_operations_settings_apply_to_an_added_section() {
+        let running = Config::new(PathBuf::from("fingerprint"));
+        let reloaded = running.clone().with_operations(Some(
+            OperationsConfig::new()
+                .with_enabled(Some(true))
+                .with_poll_interval_secs(Some(5)),
+        ));
+
+        assert_eq!(
+            restart_required_changes(&running, &reloaded),
+            vec!["operations.enabled".to_owned()]
+        );
+        let applied = apply_live_changes(&running, &reloaded);
+        let operations = applied.operations.unwrap();
+        assert_eq!(operations.poll_interval_secs, Some(5));
+        assert_eq!(operations.enabled, None);
+
+        assert!(apply_live_changes(&running, &running).operations.is_none());
+    }
+
+    #[test]
+    fn restart_only_changes_are_reported_and_not_applied() {
+        let running = config();
+        let reloaded = config()
+            .with_hub_url(Some("https://other.example".to_owned()))
+            .with_operations(Some(OperationsConfig::new().with_enabled(Some(false))));
+
+        assert_eq!(
+            restart_required_changes(&running, &reloaded),
+            vec!["hub-url".to_owned(), "operations.enabled".to_owned()]
+        );
+        let applied = apply_live_changes(&running, &reloaded);
+        assert_eq!(applied.hub_url.as_deref(), Some("https://hub.example"));
+        assert_eq!(
+            applied.operations.and_then(|operations| operations.enabled),
+            Some(true)
+        );
+    }
+}
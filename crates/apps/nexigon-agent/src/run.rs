@@ -4,20 +4,26 @@
 //! agent in-process (notably the test helper that hosts multiple agents
 //! inside a single hub-side process).
 
+use std::collections::HashMap;
 use std::future::Future;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 use std::panic::AssertUnwindSafe;
 use std::path::Path;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Context;
 use anyhow::bail;
 use futures::FutureExt;
 use futures::Stream;
 use futures::StreamExt;
+use nexigon_agent_api::types::outbox::OutboxItem;
+use nexigon_agent_api::types::outbox::OutboxProperty;
+use nexigon_agent_protocol::ForwardingRejection;
 use nexigon_agent_protocol::MAX_CONCURRENT_COMMANDS;
 use nexigon_api::types::actor::Actor;
 use nexigon_api::types::actor::GetActorAction;
@@ -27,6 +33,7 @@ use nexigon_api::types::devices::DeviceCommandStatus;
 use nexigon_api::types::devices::DeviceOperationId;
 use nexigon_api::types::devices::DeviceOperationStepReport;
 use nexigon_api::types::devices::DeviceOperationStepReportStatus;
+use nexigon_api::types::devices::DeviceOperationWorkItem;
 use nexigon_api::types::devices::DeviceOperationWorkKind;
 use nexigon_api::types::devices::DeviceOperationWorkStep;
 use nexigon_api::types::devices::ReportDeviceOperationStepAction;
@@ -37,10 +44,15 @@ use nexigon_client::connect_executor;
 use nexigon_ids::ids::DeviceId;
 use nexigon_ids::ids::DeviceOperationWorkClaimId;
 use nexigon_multiplex::ConnectionEvent;
+use nexigon_multiplex::ConnectionRef;
 use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::sync::OwnedSemaphorePermit;
 use tokio::sync::Semaphore;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use tokio::sync::watch;
 use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
@@ -49,11 +61,35 @@ use tracing::warn;
 
 use crate::config::Config;
 use crate::config::OperationsConfig;
+use crate::forwarding::ForwardingPolicy;
+use crate::forwarding::bind_reverse_listener;
+use crate::forwarding::relay_udp_forwarding;
+use crate::forwarding::run_reverse_forwarding;
 use crate::handlers;
+use crate::handlers::CommandDirectoryState;
 use crate::handlers::CommandRegistry;
+use crate::metrics::AgentMetrics;
+use crate::metrics::CommandSource;
+use crate::metrics::metrics_enabled;
 use crate::operation_ledger::OperationLedger;
 use crate::operation_ledger::PreviousExecution;
+use crate::outbox::Outbox;
+use crate::outbox::SharedOutbox;
+use crate::outbox::outbox_enabled;
+use crate::outbox::run_outbox_flusher;
+use crate::status::ActiveTasks;
 use crate::system_info::get_system_info;
+use crate::systemd::SystemdNotifier;
+use crate::tasks::TaskProgress;
+use crate::tasks::TaskRegistry;
+#[cfg(target_os = "linux")]
+use crate::terminal::AttachMode;
+#[cfg(target_os = "linux")]
+use crate::terminal::TerminalRecordings;
+#[cfg(target_os = "linux")]
+use crate::terminal::TerminalSessions;
+#[cfg(target_os = "linux")]
+use crate::terminal::run_recording_delivery;
 
 #[cfg(target_os = "linux")]
 const MAX_CONCURRENT_TERMINALS: usize = 4;
@@ -64,6 +100,20 @@ const MAX_CONCURRENT_TERMINALS: usize = 0;
 const SUPERVISOR_QUEUE_CAPACITY: usize = 32;
 const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 const TASK_SHUTDOWN_GRACE: Duration = Duration::from_secs(8);
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+/// A connection that stayed up this long resets the reconnect backoff.
+const RECONNECT_STABLE_SESSION: Duration = Duration::from_secs(60);
+/// How often the commands directory is checked for changed definitions.
+const COMMANDS_WATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// Deadline of a device task step that does not set one, matching the hub's default.
+const DEFAULT_TASK_TIMEOUT_SECS: u32 = 24 * 60 * 60;
+/// Longest time a running task step goes without a report to the hub.
+const TASK_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Shortest idle poll interval once the hub has shown that it wakes the agent for work.
+const OPERATIONS_WAKE_FALLBACK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Time without a pong after which the hub connection is considered hung.
+const HUB_LIVENESS_TIMEOUT: Duration = Duration::from_secs(60);
 
 type BoxedTask = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'static>>;
 
@@ -73,29 +123,100 @@ enum TaskKind {
     ShutdownSignal,
     TcpConnect,
     TcpForward,
+    UdpForward,
+    ReverseForward,
+    #[cfg(unix)]
+    UnixConnect,
+    #[cfg(unix)]
+    UnixForward,
     #[cfg(target_os = "linux")]
     Terminal,
+    #[cfg(target_os = "linux")]
+    Exec,
+    #[cfg(target_os = "linux")]
+    TerminalSession,
     Handler,
     SystemInfo,
     Operations,
+    Outbox,
+    Manifest,
+    CommandWatcher,
     #[cfg(unix)]
     LocalApi,
+    #[cfg(unix)]
+    ConfigReload,
+    Metrics,
+    #[cfg(target_os = "linux")]
+    TerminalRecordings,
 }
 
 impl TaskKind {
+    /// Identifier of the kind in the agent status.
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Connection => "connection",
+            Self::ShutdownSignal => "shutdown-signal",
+            Self::TcpConnect => "tcp-connect",
+            Self::TcpForward => "tcp-forward",
+            Self::UdpForward => "udp-forward",
+            Self::ReverseForward => "reverse-forward",
+            #[cfg(unix)]
+            Self::UnixConnect => "unix-connect",
+            #[cfg(unix)]
+            Self::UnixForward => "unix-forward",
+            #[cfg(target_os = "linux")]
+            Self::Terminal => "terminal",
+            #[cfg(target_os = "linux")]
+            Self::Exec => "exec",
+            #[cfg(target_os = "linux")]
+            Self::TerminalSession => "terminal-session",
+            Self::Handler => "handler",
+            Self::SystemInfo => "system-info",
+            Self::Operations => "operations",
+            Self::Outbox => "outbox",
+            Self::Manifest => "manifest",
+            Self::CommandWatcher => "command-watcher",
+            #[cfg(unix)]
+            Self::LocalApi => "local-api",
+            #[cfg(unix)]
+            Self::ConfigReload => "config-reload",
+            Self::Metrics => "metrics",
+            #[cfg(target_os = "linux")]
+            Self::TerminalRecordings => "terminal-recordings",
+        }
+    }
+
     const fn description(self) -> &'static str {
         match self {
             Self::Connection => "hub connection",
             Self::ShutdownSignal => "shutdown signal",
             Self::TcpConnect => "TCP forwarding connection",
             Self::TcpForward => "TCP forwarding relay",
+            Self::UdpForward => "UDP forwarding relay",
+            Self::ReverseForward => "reverse forwarding listener",
+            #[cfg(unix)]
+            Self::UnixConnect => "Unix socket forwarding connection",
+            #[cfg(unix)]
+            Self::UnixForward => "Unix socket forwarding relay",
             #[cfg(target_os = "linux")]
             Self::Terminal => "terminal session",
+            #[cfg(target_os = "linux")]
+            Self::Exec => "exec session",
+            #[cfg(target_os = "linux")]
+            Self::TerminalSession => "persistent terminal session",
             Self::Handler => "command handler",
             Self::SystemInfo => "system-info publisher",
             Self::Operations => "operation poller",
+            Self::Outbox => "outbox flusher",
+            Self::Manifest => "command manifest publisher",
+            Self::CommandWatcher => "commands directory watcher",
             #[cfg(unix)]
             Self::LocalApi => "local API listener",
+            #[cfg(unix)]
+            Self::ConfigReload => "configuration reload",
+            Self::Metrics => "metrics listener",
+            #[cfg(target_os = "linux")]
+            Self::TerminalRecordings => "terminal recording delivery",
         }
     }
 }
@@ -139,6 +260,21 @@ impl EndpointLimits {
     }
 }
 
+/// State shared by the channel endpoints of a hub connection.
+#[derive(Clone)]
+struct Endpoints {
+    limits: EndpointLimits,
+    /// Hub connection for channels opened by the agent, e.g., for reverse forwarding.
+    hub: ConnectionRef,
+    /// Notified when the hub requests an operation poll, if operation polling is enabled.
+    operation_wake: Option<Arc<Notify>>,
+    metrics: Arc<AgentMetrics>,
+    #[cfg(target_os = "linux")]
+    terminal_recordings: Arc<TerminalRecordings>,
+    #[cfg(target_os = "linux")]
+    terminal_sessions: Arc<TerminalSessions>,
+}
+
 fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
     if let Some(message) = payload.downcast_ref::<&str>() {
         (*message).to_owned()
@@ -149,8 +285,14 @@ fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
     }
 }
 
-fn spawn_supervised(tasks: &mut JoinSet<TaskCompletion>, task: SupervisedTask) {
+fn spawn_supervised(
+    tasks: &mut JoinSet<TaskCompletion>,
+    active_tasks: &Arc<ActiveTasks>,
+    task: SupervisedTask,
+) {
+    let active = active_tasks.enter(task.kind.name());
     tasks.spawn(async move {
+        let _active = active;
         let kind = task.kind;
         let result = AssertUnwindSafe(task.future)
             .catch_unwind()
@@ -179,11 +321,21 @@ async fn run_until_cancelled<T>(
     }
 }
 
-/// Run a Nexigon agent until `shutdown` resolves or the connection closes.
+/// Run a Nexigon agent until `shutdown` resolves.
 ///
-/// `shutdown` is awaited concurrently with the connection's event loop.
-/// When it resolves, the connection is dropped (which closes any open
-/// channels) and this function returns `Ok(())`.
+/// The agent reconnects to the hub with jittered exponential backoff whenever the
+/// connection is lost. `shutdown` is awaited concurrently with the connection's event
+/// loop; when it resolves, open channels are closed, child tasks are awaited, and this
+/// function returns `Ok(())`.
+///
+/// When started by systemd as a `Type=notify` service, the agent reports readiness once
+/// it has identified itself to the hub, keeps the service status up to date, and pings
+/// the service watchdog while the hub connection is responsive.
+///
+/// On Unix, `SIGHUP` re-reads the configuration file and applies the `terminal`,
+/// `commands`, `exports`, `telemetry` and `local-api` sections as well as the operation
+/// poll interval and concurrency. Other changes are logged and take effect after a
+/// restart.
 ///
 /// The caller must ensure a Rustls crypto provider has been installed
 /// before invoking `run`; see [`crate::install_crypto_provider`].
@@ -191,11 +343,21 @@ pub async fn run(
     config_path: &Path,
     shutdown: impl Future<Output = ()> + Send + 'static,
 ) -> anyhow::Result<()> {
-    crate::run_agent(config_path.to_path_buf(), shutdown, None).await
+    crate::run_agent(
+        config_path.to_path_buf(),
+        shutdown,
+        None,
+        true,
+        SystemdNotifier::from_environment(),
+    )
+    .await
 }
 
 /// Run the agent loop on an already-established connection.
 ///
+/// Unlike [`run`], this does not reconnect: it returns once `shutdown` resolves or the
+/// connection closes.
+///
 /// `ready`, if provided, is fulfilled with the agent's [`DeviceId`] as soon
 /// as the agent has registered with the hub — useful for in-process hosts
 /// that need to expose the device id to a test harness before the agent's
@@ -207,33 +369,362 @@ pub async fn run_with_connection(
     shutdown: impl Future<Output = ()> + Send + 'static,
     ready: Option<oneshot::Sender<DeviceId>>,
 ) -> anyhow::Result<()> {
-    let mut connection_ref = connection.make_ref();
+    let hub_url = config.hub_url.clone();
+    run_agent_supervisor(
+        config,
+        config_dir,
+        hub_url,
+        None,
+        Some(connection),
+        None,
+        shutdown,
+        ready,
+        SystemdNotifier::default(),
+    )
+    .await
+}
 
-    let commands_enabled = config
-        .commands
-        .as_ref()
-        .and_then(|h| h.enabled)
-        .unwrap_or(false);
-    let command_registry = if commands_enabled {
-        let commands_dir = config
-            .commands
-            .as_ref()
-            .and_then(|h| h.directory.as_deref())
-            .unwrap_or(Path::new("/etc/nexigon/agent/commands"));
-        let registry = load_command_registry(commands_dir);
-        Some(Arc::new(registry))
-    } else {
-        None
+/// Establishes a fresh hub connection for the reconnect supervisor.
+pub(crate) type ConnectFn = Box<
+    dyn FnMut() -> Pin<Box<dyn Future<Output = anyhow::Result<WebsocketConnection>> + Send>> + Send,
+>;
+
+/// Run the agent, establishing hub connections with `connect` until `shutdown` resolves.
+///
+/// With `config_path`, the configuration is reloaded from that file on `SIGHUP`.
+pub(crate) async fn run_reconnecting(
+    config: Arc<Config>,
+    config_dir: &Path,
+    hub_url: String,
+    config_path: Option<&Path>,
+    connect: ConnectFn,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    ready: Option<oneshot::Sender<DeviceId>>,
+    systemd: SystemdNotifier,
+) -> anyhow::Result<()> {
+    run_agent_supervisor(
+        config,
+        config_dir,
+        Some(hub_url),
+        config_path,
+        None,
+        Some(connect),
+        shutdown,
+        ready,
+        systemd,
+    )
+    .await
+}
+
+/// Agent state that outlives individual hub connections.
+struct AgentState {
+    /// Running configuration, replaced when the configuration is reloaded.
+    config: watch::Sender<Arc<Config>>,
+    /// Command registry of the running configuration.
+    command_registry: watch::Sender<Option<Arc<CommandRegistry>>>,
+    /// Forwarding policy of the running configuration.
+    forwarding_policy: watch::Sender<Arc<ForwardingPolicy>>,
+    command_slots: Arc<Semaphore>,
+    endpoint_limits: EndpointLimits,
+    operation_ledger: Option<Arc<Mutex<OperationLedger>>>,
+    /// Held by the operation loop of the current connection while it dispatches work.
+    operation_dispatch: Arc<Mutex<()>>,
+    /// Task registry, loaded once at startup because running tasks hold onto it.
+    task_registry: Option<Arc<TaskRegistry>>,
+    outbox: Option<Arc<SharedOutbox>>,
+    /// URL of the hub the agent connects to, if known.
+    hub_url: Option<String>,
+    /// ID of the device, set once the agent has identified itself to the hub.
+    device_id: watch::Sender<Option<DeviceId>>,
+    /// Tasks run by the agent supervisor.
+    active_tasks: Arc<ActiveTasks>,
+    /// Statistics exported by the metrics listener.
+    metrics: Arc<AgentMetrics>,
+    /// Notifications to systemd, if the agent runs as a service.
+    systemd: Arc<SystemdNotifier>,
+    /// Recordings of terminal sessions.
+    #[cfg(target_os = "linux")]
+    terminal_recordings: Arc<TerminalRecordings>,
+    /// Persistent terminal sessions, which survive reconnects.
+    #[cfg(target_os = "linux")]
+    terminal_sessions: Arc<TerminalSessions>,
+}
+
+impl AgentState {
+    async fn load(
+        config: Arc<Config>,
+        config_dir: &Path,
+        hub_url: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let command_registry = commands_directory(&config)
+            .map(|commands_dir| Arc::new(load_command_registry(commands_dir)));
+        let forwarding_policy = Arc::new(ForwardingPolicy::from_config(&config)?);
+        let operation_ledger = if operation_polling_enabled(config.operations.as_ref()) {
+            let ledger = OperationLedger::load(&crate::data_path(&config, config_dir))
+                .await
+                .context("cannot open operation execution ledger")?;
+            Some(Arc::new(Mutex::new(ledger)))
+        } else {
+            None
+        };
+        let task_registry = match tasks_directory(&config) {
+            Some(_) if operation_ledger.is_none() => {
+                warn!("tasks are enabled but operation polling is not, no tasks are executed");
+                None
+            }
+            Some(tasks_dir) => Some(Arc::new(load_task_registry(tasks_dir))),
+            None => None,
+        };
+        let outbox = if outbox_enabled(config.outbox.as_ref()) {
+            let outbox = Outbox::load(
+                &crate::data_path(&config, config_dir),
+                config.outbox.as_ref(),
+            )
+            .await
+            .context("cannot open outbox")?;
+            Some(Arc::new(SharedOutbox::new(outbox)))
+        } else {
+            None
+        };
+        #[cfg(target_os = "linux")]
+        let terminal_recordings = Arc::new(TerminalRecordings::new(crate::data_path(
+            &config, config_dir,
+        )));
+        #[cfg(target_os = "linux")]
+        let terminal_sessions = Arc::new(TerminalSessions::new(terminal_recordings.clone()));
+        let command_slots = command_slots();
+        Ok(Self {
+            config: watch::Sender::new(config),
+            command_registry: watch::Sender::new(command_registry),
+            forwarding_policy: watch::Sender::new(forwarding_policy),
+            endpoint_limits: EndpointLimits::new(command_slots.clone()),
+            command_slots,
+            operation_ledger,
+            operation_dispatch: Arc::default(),
+            task_registry,
+            outbox,
+            hub_url,
+            device_id: watch::Sender::new(None),
+            active_tasks: Arc::default(),
+            metrics: Arc::default(),
+            systemd: Arc::default(),
+            #[cfg(target_os = "linux")]
+            terminal_recordings,
+            #[cfg(target_os = "linux")]
+            terminal_sessions,
+        })
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+
+    fn command_registry(&self) -> Option<Arc<CommandRegistry>> {
+        self.command_registry.borrow().clone()
+    }
+}
+
+/// Re-read the configuration at `config_path` and apply it to the running agent.
+///
+/// Only the sections selected by [`crate::reload`] take effect; other changes are logged
+/// as requiring a restart. A configuration that cannot be parsed, or whose command
+/// definitions cannot be loaded, is rejected as a whole and leaves the agent untouched.
+#[cfg(unix)]
+async fn reload_config(state: &AgentState, config_path: &Path) -> anyhow::Result<()> {
+    let (reloaded, _) = crate::load_config(config_path).await?;
+    let command_registry = match commands_directory(&reloaded) {
+        Some(commands_dir) => Some(Arc::new(
+            CommandRegistry::load_external(commands_dir).with_context(|| {
+                format!("cannot load command definitions from {commands_dir:?}")
+            })?,
+        )),
+        None => None,
     };
-    let command_slots = command_slots();
-    let endpoint_limits = EndpointLimits::new(command_slots.clone());
+    let running = state.config();
+    let applied = crate::reload::apply_live_changes(&running, &reloaded);
+    // Without `forwarding.allow`, the allowed ports follow the live exports.
+    let forwarding_policy = ForwardingPolicy::from_config(&applied)?;
+    for key in crate::reload::restart_required_changes(&running, &reloaded) {
+        warn!(
+            %key,
+            "configuration change requires an agent restart to take effect"
+        );
+    }
+    state.command_registry.send_replace(command_registry);
+    state
+        .forwarding_policy
+        .send_replace(Arc::new(forwarding_policy));
+    state.config.send_replace(Arc::new(applied));
+    info!("configuration reloaded");
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(
+    state: Arc<AgentState>,
+    config_path: PathBuf,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::SignalKind;
+    use tokio::signal::unix::signal;
+
+    let mut hangup = signal(SignalKind::hangup()).context("cannot install SIGHUP handler")?;
+    loop {
+        tokio::select! {
+            () = cancellation.cancelled() => return Ok(()),
+            received = hangup.recv() => {
+                if received.is_none() {
+                    return Ok(());
+                }
+            }
+        }
+        info!(path = ?config_path, "SIGHUP received, reloading configuration");
+        if let Err(error) = reload_config(&state, &config_path).await {
+            warn!(
+                ?error,
+                "configuration reload rejected, keeping the running configuration"
+            );
+        }
+    }
+}
+
+/// Debounced change detection for the configured commands directory.
+struct CommandsDirectoryWatch {
+    directory: Option<PathBuf>,
+    /// State the current registry was loaded from.
+    loaded: Option<CommandDirectoryState>,
+    /// Changed state that has not yet been seen twice in a row.
+    pending: Option<Option<CommandDirectoryState>>,
+}
+
+impl CommandsDirectoryWatch {
+    fn new(config: &Config) -> Self {
+        let directory = commands_directory(config).map(Path::to_path_buf);
+        let loaded = directory
+            .as_deref()
+            .and_then(|directory| CommandDirectoryState::capture(directory).ok().flatten());
+        Self {
+            directory,
+            loaded,
+            pending: None,
+        }
+    }
+
+    /// Check the directory and return a rebuilt registry if its definitions changed.
+    ///
+    /// A change is acted upon only once two consecutive checks agree on it, so definitions
+    /// that are still being written are not picked up half-way.
+    fn check(&mut self, config: &Config) -> anyhow::Result<Option<CommandRegistry>> {
+        let directory = commands_directory(config);
+        if directory != self.directory.as_deref() {
+            // A configuration reload switched the directory and rebuilt the registry.
+            *self = Self::new(config);
+            return Ok(None);
+        }
+        let Some(directory) = directory else {
+            return Ok(None);
+        };
+        let current = CommandDirectoryState::capture(directory)
+            .with_context(|| format!("cannot inspect commands directory {directory:?}"))?;
+        if current == self.loaded {
+            self.pending = None;
+            return Ok(None);
+        }
+        if self.pending.as_ref() != Some(&current) {
+            self.pending = Some(current);
+            return Ok(None);
+        }
+        // A registry that fails to load is retried only after the directory changes again.
+        self.pending = None;
+        self.loaded = current;
+        CommandRegistry::load_external(directory).map(Some)
+    }
+}
+
+/// Rebuild the command registry whenever the definitions in the commands directory change.
+///
+/// The rebuilt registry goes through the same trust checks as the one loaded at startup.
+/// Swapping it republishes the manifest through the session's manifest publisher.
+async fn watch_commands_directory(
+    state: Arc<AgentState>,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut watch = CommandsDirectoryWatch::new(&state.config());
+    loop {
+        tokio::select! {
+            () = cancellation.cancelled() => return Ok(()),
+            () = tokio::time::sleep(COMMANDS_WATCH_INTERVAL) => {}
+        }
+        match watch.check(&state.config()) {
+            Ok(Some(registry)) => {
+                info!("command definitions changed, registry reloaded");
+                state
+                    .command_registry
+                    .send_replace(Some(Arc::new(registry)));
+            }
+            Ok(None) => {}
+            Err(error) => {
+                warn!(
+                    ?error,
+                    "cannot reload command definitions, keeping the loaded ones"
+                );
+            }
+        }
+    }
+}
+
+/// Jittered exponential backoff between hub connection attempts.
+struct ReconnectBackoff {
+    next: Duration,
+}
+
+impl ReconnectBackoff {
+    fn new() -> Self {
+        Self {
+            next: RECONNECT_INITIAL_DELAY,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.next = RECONNECT_INITIAL_DELAY;
+    }
+
+    /// Return the delay before the next attempt and grow the base for the one after.
+    fn next_delay(&mut self) -> Duration {
+        let base = self.next;
+        self.next = (self.next * 2).min(RECONNECT_MAX_DELAY);
+        // Jitter within the upper half of the base so a fleet that lost the hub at the
+        // same moment does not reconnect in lockstep, while never retrying immediately.
+        let half = base / 2;
+        half + half.mul_f64(rand::random::<f64>())
+    }
+}
+
+async fn run_agent_supervisor(
+    config: Arc<Config>,
+    config_dir: &Path,
+    hub_url: Option<String>,
+    config_path: Option<&Path>,
+    connection: Option<WebsocketConnection>,
+    connect: Option<ConnectFn>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    ready: Option<oneshot::Sender<DeviceId>>,
+    systemd: SystemdNotifier,
+) -> anyhow::Result<()> {
+    let mut state = AgentState::load(config, config_dir, hub_url).await?;
+    state.systemd = Arc::new(systemd);
+    let state = Arc::new(state);
+    let active_tasks = state.active_tasks.clone();
     let cancellation = CancellationToken::new();
     let (task_tx, mut task_rx) = mpsc::channel(SUPERVISOR_QUEUE_CAPACITY);
+    let (hub_tx, hub_rx) = watch::channel(None);
     let mut tasks = JoinSet::new();
+    let mut watchdog = AgentWatchdog::new(state.systemd.clone(), hub_rx.clone());
 
     let shutdown_cancellation = cancellation.clone();
     spawn_supervised(
         &mut tasks,
+        &active_tasks,
         SupervisedTask::new(TaskKind::ShutdownSignal, async move {
             tokio::select! {
                 () = shutdown => {
@@ -246,376 +737,104 @@ pub async fn run_with_connection(
         }),
     );
 
-    let event_loop_config = config.clone();
-    let event_loop_registry = command_registry.clone();
-    let event_loop_cancellation = cancellation.clone();
-    let event_loop_task_tx = task_tx.clone();
+    // The metrics listener is bound once at startup; reloads do not move it.
+    if let Some(metrics_config) = state
+        .config()
+        .metrics
+        .clone()
+        .filter(|metrics| metrics_enabled(Some(metrics)))
+    {
+        let metrics = state.metrics.clone();
+        let metrics_active_tasks = active_tasks.clone();
+        let metrics_hub = hub_rx.clone();
+        let metrics_cancellation = cancellation.clone();
+        spawn_supervised(
+            &mut tasks,
+            &active_tasks,
+            SupervisedTask::new(TaskKind::Metrics, async move {
+                crate::metrics::serve(
+                    &metrics_config,
+                    metrics,
+                    metrics_active_tasks,
+                    metrics_hub,
+                    metrics_cancellation,
+                )
+                .await
+            }),
+        );
+    }
+
+    // The local API outlives hub connections. While the hub is unreachable it keeps
+    // accepting clients and reports the upstream as unavailable.
+    #[cfg(unix)]
     spawn_supervised(
         &mut tasks,
-        SupervisedTask::new(TaskKind::Connection, async move {
-            run_connection_event_loop(
-                connection,
-                event_loop_config,
-                event_loop_registry,
-                endpoint_limits,
-                event_loop_task_tx,
-                event_loop_cancellation,
-            )
-            .await
-        }),
+        &active_tasks,
+        SupervisedTask::new(
+            TaskKind::LocalApi,
+            run_local_api(state.clone(), hub_rx, cancellation.clone()),
+        ),
     );
+    #[cfg(not(unix))]
+    drop(hub_rx);
 
-    let agent_run = async {
-        let mut executor = connect_executor(&mut connection_ref)
-            .await
-            .context("cannot open executor channel")?;
-        let device_id = match executor
-            .execute(GetActorAction::new())
-            .await
-            .context("cannot execute GetActor")?
-            .map_err(|e| anyhow::anyhow!("GetActor failed: {}", e.message))?
-            .actor
-        {
-            Actor::Device(actor) => {
-                info!(device_id = %actor.device_id);
-                actor.device_id
-            }
-            _ => bail!("received unexpected actor type"),
-        };
-
-        if let Some(ready) = ready {
-            let _ = ready.send(device_id.clone());
-        }
-
-        let system_info_enabled = config
-            .telemetry
-            .as_ref()
-            .and_then(|t| t.system_info)
-            .unwrap_or(true);
-        if system_info_enabled {
-            let sysinfo_config = config.clone();
-            let sysinfo_device_id = device_id.clone();
-            let mut sysinfo_executor = connect_executor(&mut connection_ref)
-                .await
-                .context("cannot open sysinfo executor channel")?;
-            let sysinfo_cancellation = cancellation.clone();
-            spawn_supervised(
-                &mut tasks,
-                SupervisedTask::new(TaskKind::SystemInfo, async move {
-                    loop {
-                        let system_info = get_system_info(&sysinfo_config);
-                        let value = serde_json::to_value(system_info)
-                            .context("cannot serialize system information")?;
-                        let update = sysinfo_executor.execute(SetDevicePropertyAction::new(
-                            sysinfo_device_id.clone(),
-                            "dev.nexigon.system.info".to_owned(),
-                            value,
-                        ));
-                        let update = tokio::select! {
-                            () = sysinfo_cancellation.cancelled() => return Ok(()),
-                            update = update => update,
-                        };
-                        match update {
-                            Ok(Ok(_)) => {}
-                            Ok(Err(error)) => {
-                                warn!(message = %error.message, "system-info update rejected");
-                            }
-                            Err(error) => {
-                                warn!(?error, "failed to publish system information");
-                            }
-                        }
-                        tokio::select! {
-                            () = sysinfo_cancellation.cancelled() => return Ok(()),
-                            () = tokio::time::sleep(Duration::from_secs(30 * 60)) => {}
-                        }
-                    }
-                }),
-            );
-        }
-
-        if let Some(registry) = &command_registry {
-            let manifest = registry.manifest();
-            let manifest =
-                serde_json::to_value(manifest).context("cannot serialize command manifest")?;
-            if let Err(error) = executor
-                .execute(SetDevicePropertyAction::new(
-                    device_id.clone(),
-                    "dev.nexigon.commands".to_owned(),
-                    manifest,
-                ))
-                .await
-            {
-                warn!(?error, "failed to publish command manifest");
-            }
-        }
+    #[cfg(unix)]
+    if let Some(config_path) = config_path {
+        spawn_supervised(
+            &mut tasks,
+            &active_tasks,
+            SupervisedTask::new(
+                TaskKind::ConfigReload,
+                reload_on_hangup(
+                    state.clone(),
+                    config_path.to_path_buf(),
+                    cancellation.clone(),
+                ),
+            ),
+        );
+    }
+    #[cfg(not(unix))]
+    let _ = config_path;
 
-        let operations_enabled = operation_polling_enabled(config.operations.as_ref());
-        if operations_enabled {
-            let mut operation_ledger =
-                OperationLedger::load(&crate::data_path(&config, config_dir))
-                    .await
-                    .context("cannot open operation execution ledger")?;
-            let poll_interval = Duration::from_secs(
-                config
-                    .operations
-                    .as_ref()
-                    .and_then(|o| o.poll_interval_secs)
-                    .unwrap_or(60),
-            );
-            let operations_command_registry = command_registry.clone();
-            let operations_command_slots = command_slots;
-            let operations_device_id = device_id.clone();
-            let mut operations_executor = connect_executor(&mut connection_ref)
-                .await
-                .context("cannot open operations executor channel")?;
-            let operation_cancellation = cancellation.clone();
-            let operation_loop = async move {
-                loop {
-                    if operation_cancellation.is_cancelled() {
-                        return Ok(());
-                    }
-                    // Reserve execution capacity before claiming work so a lease never
-                    // waits behind already-running interactive commands.
-                    let command_permit = if operations_command_registry.is_some() {
-                        let permit = tokio::select! {
-                            biased;
-                            () = operation_cancellation.cancelled() => return Ok(()),
-                            permit = operations_command_slots.clone().acquire_owned() => permit,
-                        };
-                        match permit {
-                            Ok(permit) => Some(permit),
-                            Err(_) => {
-                                anyhow::bail!("command concurrency limiter was closed");
-                            }
-                        }
-                    } else {
-                        None
-                    };
-                    // Whether this poll found anything. The hub releases a device operation's
-                    // next step as soon as the previous one is reported, so a poll that found
-                    // work is very likely to find more; sleeping out the whole idle interval
-                    // between the steps of one operation would waste that. Only an empty poll
-                    // means there is genuinely nothing to do.
-                    let mut found_work = false;
-                    let claim = operations_executor.execute(
-                        ClaimDeviceOperationWorkAction::new(operations_device_id.clone())
-                            // Commands are executed serially. Lease only the command we
-                            // are about to execute so later work cannot expire while an
-                            // earlier handler is still running.
-                            .with_limit(Some(1))
-                            .with_kinds(Some(vec![DeviceOperationWorkKind::DeviceCommand])),
-                    );
-                    let result = tokio::select! {
-                        biased;
-                        () = operation_cancellation.cancelled() => return Ok(()),
-                        result = claim => result,
-                    };
-                    match result {
-                        Ok(Ok(output)) => {
-                            for item in output.work {
-                                let device_operation_id = item.device_operation_id;
-                                let step_index = item.step_index;
-                                let claim_id = item.claim_id;
-                                let report = match operation_ledger
-                                    .previous(&device_operation_id, step_index)
-                                {
-                                    PreviousExecution::Completed(report) => {
-                                        // A completed result may be returning under a fresh
-                                        // lease after an earlier reporting outage. Commit the
-                                        // current claim before trying it again so a crash
-                                        // cannot leave the outbox tied to the expired claim.
-                                        if let Err(error) = operation_ledger
-                                            .mark_completed(
-                                                &device_operation_id,
-                                                step_index,
-                                                &claim_id,
-                                                report.clone(),
-                                            )
-                                            .await
-                                        {
-                                            warn!(
-                                                ?error,
-                                                "failed to persist renewed operation claim"
-                                            );
-                                            continue;
-                                        }
-                                        report
-                                    }
-                                    PreviousExecution::InProgress => {
-                                        let report = DeviceOperationStepReport {
-                                            status: DeviceOperationStepReportStatus::Failed,
-                                            output: None,
-                                            checkpoint: None,
-                                            error: Some(
-                                                "previous command execution was interrupted after dispatch; refusing unsafe automatic replay"
-                                                    .to_owned(),
-                                            ),
-                                        };
-                                        if let Err(error) = operation_ledger
-                                            .mark_completed(
-                                                &device_operation_id,
-                                                step_index,
-                                                &claim_id,
-                                                report.clone(),
-                                            )
-                                            .await
-                                        {
-                                            warn!(
-                                                ?error,
-                                                "failed to persist interrupted operation result"
-                                            );
-                                            continue;
-                                        }
-                                        report
-                                    }
-                                    PreviousExecution::None => match item.step {
-                                        DeviceOperationWorkStep::DeviceCommand(step) => {
-                                            if let Err(error) = operation_ledger
-                                                .mark_in_progress(
-                                                    &device_operation_id,
-                                                    step_index,
-                                                    &claim_id,
-                                                )
-                                                .await
-                                            {
-                                                warn!(
-                                                    ?error,
-                                                    "failed to persist operation dispatch; command not executed"
-                                                );
-                                                continue;
-                                            }
-                                            let done = if let Some(registry) =
-                                                operations_command_registry.as_ref()
-                                            {
-                                                let request = DeviceCommandInvokeData::new(
-                                                    step.command,
-                                                    step.input,
-                                                )
-                                                .with_stream_log(Some(false))
-                                                .with_timeout_secs(Some(
-                                                    step.timeout_secs.unwrap_or(3600),
-                                                ));
-                                                handlers::invoke_registered_command_with_cancellation(
-                                                    registry,
-                                                    request,
-                                                    &operation_cancellation,
-                                                )
-                                                .await
-                                            } else {
-                                                nexigon_api::types::devices::DeviceCommandDoneData {
-                                                    status: DeviceCommandStatus::Error,
-                                                    output: None,
-                                                    error: Some("commands not enabled".to_owned()),
-                                                    log_tail: Vec::new(),
-                                                    duration_ms: 0,
-                                                }
-                                            };
-                                            if operation_cancellation.is_cancelled() {
-                                                return Ok(());
-                                            }
-                                            let status = match done.status {
-                                                DeviceCommandStatus::Ok => {
-                                                    DeviceOperationStepReportStatus::Succeeded
-                                                }
-                                                DeviceCommandStatus::Error => {
-                                                    DeviceOperationStepReportStatus::Failed
-                                                }
-                                            };
-                                            let report = DeviceOperationStepReport {
-                                                status,
-                                                output: done.output,
-                                                checkpoint: None,
-                                                error: done.error,
-                                            };
-                                            if let Err(error) = operation_ledger
-                                                .mark_completed(
-                                                    &device_operation_id,
-                                                    step_index,
-                                                    &claim_id,
-                                                    report.clone(),
-                                                )
-                                                .await
-                                            {
-                                                warn!(
-                                                    ?error,
-                                                    "failed to persist operation result; result not reported"
-                                                );
-                                                continue;
-                                            }
-                                            report
-                                        }
-                                        DeviceOperationWorkStep::DeviceTask(_) => {
-                                            debug!(
-                                                device_operation_id = %device_operation_id,
-                                                step_index,
-                                                "device task work is not handled by the agent"
-                                            );
-                                            continue;
-                                        }
-                                    },
-                                };
-                                found_work = true;
-                                // Retry transport failures promptly while this claim's lease
-                                // is certainly still current. The shortest valid command
-                                // lease is 61 seconds; these five attempts span 15 seconds.
-                                // A persistent outage falls back to normal lease expiry and
-                                // reacquisition, at which point the saved result is submitted
-                                // under the fresh claim without executing again.
-                                let acknowledged = report_operation_step_with_retry(
-                                    &mut operations_executor,
-                                    &operations_device_id,
-                                    &device_operation_id,
-                                    step_index,
-                                    &claim_id,
-                                    &report,
-                                    Some(&operation_cancellation),
-                                )
-                                .await;
-                                if acknowledged
-                                    && let Err(error) = operation_ledger
-                                        .remove(&device_operation_id, step_index)
-                                        .await
-                                {
-                                    warn!(?error, "failed to prune reported operation result");
-                                }
-                            }
-                        }
-                        Ok(Err(error)) => {
-                            warn!(message = %error.message, "operation work claim rejected");
-                        }
-                        Err(error) => {
-                            warn!(?error, "failed to claim operation work");
-                        }
-                    }
-                    drop(command_permit);
-                    if !found_work {
-                        tokio::select! {
-                            biased;
-                            () = operation_cancellation.cancelled() => return Ok(()),
-                            () = tokio::time::sleep(poll_interval) => {}
-                        }
-                    }
-                }
-            };
-            spawn_supervised(
-                &mut tasks,
-                SupervisedTask::new(TaskKind::Operations, operation_loop),
-            );
-        }
+    spawn_supervised(
+        &mut tasks,
+        &active_tasks,
+        SupervisedTask::new(
+            TaskKind::CommandWatcher,
+            watch_commands_directory(state.clone(), cancellation.clone()),
+        ),
+    );
 
-        #[cfg(unix)]
-        if let Some(local_api) = local_api_task(&config, &connection_ref, cancellation.clone()) {
-            spawn_supervised(&mut tasks, local_api);
-        }
+    spawn_supervised(
+        &mut tasks,
+        &active_tasks,
+        SupervisedTask::new(
+            TaskKind::Connection,
+            run_hub_sessions(
+                state.clone(),
+                connection,
+                connect,
+                hub_tx,
+                task_tx.clone(),
+                cancellation.clone(),
+                ready,
+            ),
+        ),
+    );
 
-        drop(executor);
-        supervise_tasks(&mut tasks, &mut task_rx, &cancellation).await
-    };
-    let result = run_until_cancelled(&cancellation, agent_run)
-        .await
-        .unwrap_or(Ok(()));
+    let result = supervise_tasks(
+        &mut tasks,
+        &active_tasks,
+        &mut task_rx,
+        watchdog.as_mut(),
+        &cancellation,
+    )
+    .await;
 
+    state.systemd.stopping();
     cancellation.cancel();
+    #[cfg(target_os = "linux")]
+    state.terminal_sessions.close();
     drop(task_tx);
     task_rx.close();
     while task_rx.try_recv().is_ok() {}
@@ -624,17 +843,928 @@ pub async fn run_with_connection(
     result
 }
 
-async fn run_connection_event_loop<S, E>(
-    connection: S,
-    config: Arc<Config>,
-    command_registry: Option<Arc<CommandRegistry>>,
-    limits: EndpointLimits,
+/// Pings the systemd watchdog from the supervisor loop while the agent is healthy.
+///
+/// A stuck supervisor loop stops the pings. So does a hub connection whose event loop
+/// no longer processes pongs: while it is polled, the connection fails on its own once
+/// a ping stays unanswered, so a connection without a pong for much longer than that is
+/// hung.
+struct AgentWatchdog {
+    systemd: Arc<SystemdNotifier>,
+    interval: tokio::time::Interval,
+    hub: watch::Receiver<Option<ConnectionRef>>,
+    /// Time at which the current hub connection has been observed first.
+    connected_at: Instant,
+    /// Whether the previous check found the hub connection unresponsive.
+    unresponsive: bool,
+}
+
+impl AgentWatchdog {
+    fn new(
+        systemd: Arc<SystemdNotifier>,
+        hub: watch::Receiver<Option<ConnectionRef>>,
+    ) -> Option<Self> {
+        let interval = systemd.watchdog_interval()?;
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Some(Self {
+            systemd,
+            interval,
+            hub,
+            connected_at: Instant::now(),
+            unresponsive: false,
+        })
+    }
+
+    /// Wait for the next watchdog interval and ping the watchdog if the agent is healthy.
+    async fn check(&mut self) {
+        self.interval.tick().await;
+        if self.hub.has_changed().unwrap_or(false) {
+            self.hub.borrow_and_update();
+            self.connected_at = Instant::now();
+        }
+        let silent_for = self.hub.borrow().as_ref().map(|hub| {
+            hub.time_since_last_pong()
+                .unwrap_or_else(|| self.connected_at.elapsed())
+        });
+        // Without a connection, the agent is reconnecting with bounded backoff.
+        if silent_for.is_some_and(|silent_for| silent_for > HUB_LIVENESS_TIMEOUT) {
+            if !self.unresponsive {
+                warn!(
+                    ?silent_for,
+                    "hub connection is unresponsive, withholding watchdog"
+                );
+                self.systemd.status("hub connection is unresponsive");
+                self.unresponsive = true;
+            }
+            return;
+        }
+        self.unresponsive = false;
+        self.systemd.watchdog();
+    }
+}
+
+/// Run hub sessions back to back, reconnecting with backoff when `connect` is given.
+///
+/// Without `connect`, the outcome of the single session on `connection` is returned.
+async fn run_hub_sessions(
+    state: Arc<AgentState>,
+    mut connection: Option<WebsocketConnection>,
+    mut connect: Option<ConnectFn>,
+    hub_tx: watch::Sender<Option<ConnectionRef>>,
     task_tx: mpsc::Sender<SupervisedTask>,
     cancellation: CancellationToken,
-) -> anyhow::Result<()>
-where
-    S: Stream<Item = Result<ConnectionEvent, E>> + Send + 'static,
-    E: std::fmt::Display,
+    mut ready: Option<oneshot::Sender<DeviceId>>,
+) -> anyhow::Result<()> {
+    let mut backoff = ReconnectBackoff::new();
+    loop {
+        let connection = match connection.take() {
+            Some(connection) => connection,
+            None => {
+                let Some(connect) = connect.as_mut() else {
+                    return Ok(());
+                };
+                state.systemd.status("connecting to hub");
+                match run_until_cancelled(&cancellation, connect()).await {
+                    None => return Ok(()),
+                    Some(Ok(connection)) => connection,
+                    Some(Err(error)) => {
+                        let delay = backoff.next_delay();
+                        warn!(?error, ?delay, "cannot connect to hub, retrying");
+                        state
+                            .systemd
+                            .status(&format!("cannot connect to hub, retrying in {delay:.1?}"));
+                        if run_until_cancelled(&cancellation, tokio::time::sleep(delay))
+                            .await
+                            .is_none()
+                        {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let connected_at = Instant::now();
+        let session_cancellation = cancellation.child_token();
+        hub_tx.send_replace(Some(connection.make_ref()));
+        let result = run_hub_session(
+            &state,
+            connection,
+            &task_tx,
+            &session_cancellation,
+            &mut ready,
+        )
+        .await;
+        // Endpoint and publisher tasks of this session are bound to the dead connection.
+        session_cancellation.cancel();
+        hub_tx.send_replace(None);
+        if cancellation.is_cancelled() {
+            return Ok(());
+        }
+        if connect.is_none() {
+            return result;
+        }
+
+        if connected_at.elapsed() >= RECONNECT_STABLE_SESSION {
+            backoff.reset();
+        }
+        let delay = backoff.next_delay();
+        match result {
+            Ok(()) => info!(?delay, "hub connection lost, reconnecting"),
+            Err(error) => warn!(?error, ?delay, "hub session failed, reconnecting"),
+        }
+        state
+            .systemd
+            .status(&format!("hub connection lost, reconnecting in {delay:.1?}"));
+        if run_until_cancelled(&cancellation, tokio::time::sleep(delay))
+            .await
+            .is_none()
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Serve a single hub connection until it closes or `cancellation` fires.
+async fn run_hub_session(
+    state: &Arc<AgentState>,
+    connection: WebsocketConnection,
+    task_tx: &mpsc::Sender<SupervisedTask>,
+    cancellation: &CancellationToken,
+    ready: &mut Option<oneshot::Sender<DeviceId>>,
+) -> anyhow::Result<()> {
+    let mut connection_ref = connection.make_ref();
+    let operation_wake = state
+        .operation_ledger
+        .as_ref()
+        .map(|_| Arc::new(Notify::new()));
+    let endpoints = Endpoints {
+        limits: state.endpoint_limits.clone(),
+        hub: connection_ref.clone(),
+        operation_wake: operation_wake.clone(),
+        metrics: state.metrics.clone(),
+        #[cfg(target_os = "linux")]
+        terminal_recordings: state.terminal_recordings.clone(),
+        #[cfg(target_os = "linux")]
+        terminal_sessions: state.terminal_sessions.clone(),
+    };
+    let event_loop = run_connection_event_loop(
+        connection,
+        state.config.subscribe(),
+        state.command_registry.subscribe(),
+        state.forwarding_policy.subscribe(),
+        endpoints,
+        task_tx.clone(),
+        cancellation.clone(),
+    );
+    tokio::pin!(event_loop);
+    // The event loop drives the connection, so it must run while the session is set up.
+    let setup = start_hub_session(
+        state,
+        &mut connection_ref,
+        operation_wake,
+        task_tx,
+        cancellation,
+        ready,
+    );
+    tokio::select! {
+        result = &mut event_loop => {
+            result?;
+            bail!("hub connection closed during session setup");
+        }
+        setup = setup => setup?,
+    }
+    event_loop.await
+}
+
+/// Identify the device and start the per-connection publishers and pollers.
+async fn start_hub_session(
+    state: &Arc<AgentState>,
+    connection_ref: &mut ConnectionRef,
+    operation_wake: Option<Arc<Notify>>,
+    task_tx: &mpsc::Sender<SupervisedTask>,
+    cancellation: &CancellationToken,
+    ready: &mut Option<oneshot::Sender<DeviceId>>,
+) -> anyhow::Result<()> {
+    let mut executor = connect_executor(connection_ref)
+        .await
+        .context("cannot open executor channel")?;
+    let device_id = match executor
+        .execute(GetActorAction::new())
+        .await
+        .context("cannot execute GetActor")?
+        .map_err(|e| anyhow::anyhow!("GetActor failed: {}", e.message))?
+        .actor
+    {
+        Actor::Device(actor) => {
+            info!(device_id = %actor.device_id);
+            actor.device_id
+        }
+        _ => bail!("received unexpected actor type"),
+    };
+    state.device_id.send_replace(Some(device_id.clone()));
+    state
+        .systemd
+        .ready(&format!("connected to hub as device {device_id}"));
+
+    if let Some(ready) = ready.take() {
+        let _ = ready.send(device_id.clone());
+    }
+
+    // Both publishers follow the running configuration, so they are started even when
+    // their feature is currently disabled and pick it up after a reload.
+    let sink = match &state.outbox {
+        Some(outbox) => SystemInfoSink::Outbox(outbox.clone()),
+        None => SystemInfoSink::Hub(
+            connect_executor(connection_ref)
+                .await
+                .context("cannot open sysinfo executor channel")?,
+        ),
+    };
+    queue_task(
+        task_tx,
+        SupervisedTask::new(
+            TaskKind::SystemInfo,
+            publish_system_info(
+                sink,
+                state.config.subscribe(),
+                device_id.clone(),
+                cancellation.clone(),
+            ),
+        ),
+    )
+    .await?;
+
+    queue_task(
+        task_tx,
+        SupervisedTask::new(
+            TaskKind::Manifest,
+            publish_command_manifest(
+                executor,
+                state.command_registry.subscribe(),
+                device_id.clone(),
+                cancellation.clone(),
+            ),
+        ),
+    )
+    .await?;
+
+    if let Some(outbox) = &state.outbox {
+        let mut outbox_executor = connect_executor(connection_ref)
+            .await
+            .context("cannot open outbox executor channel")?;
+        let outbox = outbox.clone();
+        let outbox_device_id = device_id.clone();
+        let outbox_cancellation = cancellation.clone();
+        queue_task(
+            task_tx,
+            SupervisedTask::new(TaskKind::Outbox, async move {
+                run_outbox_flusher(
+                    &outbox,
+                    &mut outbox_executor,
+                    &outbox_device_id,
+                    &outbox_cancellation,
+                )
+                .await
+            }),
+        )
+        .await?;
+    }
+
+    // Like the publishers, recording delivery follows the running configuration.
+    #[cfg(target_os = "linux")]
+    {
+        let mut recordings_executor = connect_executor(connection_ref)
+            .await
+            .context("cannot open terminal recordings executor channel")?;
+        let recordings = state.terminal_recordings.clone();
+        let recordings_config = state.config.subscribe();
+        let recordings_device_id = device_id.clone();
+        let recordings_cancellation = cancellation.clone();
+        queue_task(
+            task_tx,
+            SupervisedTask::new(TaskKind::TerminalRecordings, async move {
+                run_recording_delivery(
+                    &recordings,
+                    recordings_config,
+                    &mut recordings_executor,
+                    &recordings_device_id,
+                    &recordings_cancellation,
+                )
+                .await
+            }),
+        )
+        .await?;
+    }
+
+    if let Some((operation_ledger, operation_wake)) =
+        state.operation_ledger.as_ref().zip(operation_wake)
+    {
+        let operations_executor = connect_executor(connection_ref)
+            .await
+            .context("cannot open operations executor channel")?;
+        queue_task(
+            task_tx,
+            SupervisedTask::new(
+                TaskKind::Operations,
+                run_operation_loop(
+                    state.clone(),
+                    operation_ledger.clone(),
+                    operations_executor,
+                    operation_wake,
+                    device_id,
+                    cancellation.clone(),
+                ),
+            ),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn queue_task(
+    task_tx: &mpsc::Sender<SupervisedTask>,
+    task: SupervisedTask,
+) -> anyhow::Result<()> {
+    task_tx
+        .send(task)
+        .await
+        .map_err(|_| anyhow::anyhow!("agent task queue closed"))
+}
+
+/// Destination of periodic system-information updates.
+enum SystemInfoSink {
+    /// Publish directly over the current hub connection.
+    Hub(ClientExecutor),
+    /// Queue in the durable outbox, which delivers over the current hub connection.
+    Outbox(Arc<SharedOutbox>),
+}
+
+async fn publish_system_info(
+    mut sink: SystemInfoSink,
+    mut config: watch::Receiver<Arc<Config>>,
+    device_id: DeviceId,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    loop {
+        let current = config.borrow_and_update().clone();
+        let enabled = current
+            .telemetry
+            .as_ref()
+            .and_then(|t| t.system_info)
+            .unwrap_or(true);
+        if enabled {
+            publish_system_info_once(&mut sink, &current, &device_id, &cancellation).await?;
+        }
+        // The published information reflects the configuration, so a reload
+        // triggers an update right away.
+        tokio::select! {
+            () = cancellation.cancelled() => return Ok(()),
+            () = tokio::time::sleep(Duration::from_secs(30 * 60)) => {}
+            changed = config.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+async fn publish_system_info_once(
+    sink: &mut SystemInfoSink,
+    config: &Config,
+    device_id: &DeviceId,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<()> {
+    let system_info = get_system_info(config);
+    let value = serde_json::to_value(system_info).context("cannot serialize system information")?;
+    match sink {
+        SystemInfoSink::Hub(executor) => {
+            let update = executor.execute(SetDevicePropertyAction::new(
+                device_id.clone(),
+                "dev.nexigon.system.info".to_owned(),
+                value,
+            ));
+            let update = tokio::select! {
+                () = cancellation.cancelled() => return Ok(()),
+                update = update => update,
+            };
+            match update {
+                Ok(Ok(_)) => {}
+                Ok(Err(error)) => {
+                    warn!(message = %error.message, "system-info update rejected");
+                }
+                Err(error) => {
+                    warn!(?error, "failed to publish system information");
+                }
+            }
+        }
+        SystemInfoSink::Outbox(outbox) => {
+            // Queued property writes are latest-wins, so an outage leaves one update.
+            let item = OutboxItem::Property(OutboxProperty {
+                name: "dev.nexigon.system.info".to_owned(),
+                value,
+            });
+            if let Err(error) = outbox.push(item).await {
+                warn!(?error, "failed to queue system information");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Publish the command manifest, and publish it again whenever the registry changes.
+async fn publish_command_manifest(
+    mut executor: ClientExecutor,
+    mut command_registry: watch::Receiver<Option<Arc<CommandRegistry>>>,
+    device_id: DeviceId,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut published = None;
+    loop {
+        let registry = command_registry.borrow_and_update().clone();
+        // Disabling commands at runtime withdraws an earlier manifest with an empty one.
+        let manifest = match registry {
+            Some(registry) => Some(registry.manifest()),
+            None if published.is_some() => Some(CommandRegistry::default().manifest()),
+            None => None,
+        };
+        let manifest = manifest
+            .map(serde_json::to_value)
+            .transpose()
+            .context("cannot serialize command manifest")?;
+        // Definition changes that leave the manifest alone are not republished.
+        if let Some(manifest) = manifest.filter(|manifest| published.as_ref() != Some(manifest)) {
+            let update = executor.execute(SetDevicePropertyAction::new(
+                device_id.clone(),
+                "dev.nexigon.commands".to_owned(),
+                manifest.clone(),
+            ));
+            let update = tokio::select! {
+                () = cancellation.cancelled() => return Ok(()),
+                update = update => update,
+            };
+            match update {
+                Ok(Ok(_)) => published = Some(manifest),
+                Ok(Err(error)) => {
+                    warn!(message = %error.message, "command manifest update rejected");
+                }
+                Err(error) => warn!(?error, "failed to publish command manifest"),
+            }
+        }
+        tokio::select! {
+            () = cancellation.cancelled() => return Ok(()),
+            changed = command_registry.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+async fn run_operation_loop(
+    state: Arc<AgentState>,
+    operation_ledger: Arc<Mutex<OperationLedger>>,
+    operations_executor: ClientExecutor,
+    operation_wake: Arc<Notify>,
+    operations_device_id: DeviceId,
+    operation_cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    // A poller of a previous connection keeps dispatching until it observes its
+    // cancellation and its running steps have stopped, so only one session ever
+    // dispatches work.
+    let _dispatch = tokio::select! {
+        biased;
+        () = operation_cancellation.cancelled() => return Ok(()),
+        dispatch = state.operation_dispatch.clone().lock_owned() => dispatch,
+    };
+    let execution = Arc::new(OperationExecution {
+        state: state.clone(),
+        ledger: operation_ledger,
+        executor: Arc::new(Mutex::new(operations_executor)),
+        device_id: operations_device_id,
+        cancellation: operation_cancellation.clone(),
+    });
+    let mut running = JoinSet::new();
+    let mut running_operations = HashMap::new();
+    let operations_command_slots = state.command_slots.clone();
+    // Whether the hub of this session has sent an `operations/wake` request. Such a
+    // hub announces new work, so the idle poll is only a fallback for missed wakes.
+    let mut hub_sends_wakes = false;
+    'poll: loop {
+        if operation_cancellation.is_cancelled() {
+            break;
+        }
+        // All of these follow configuration reloads.
+        let operations_config = state.config().operations.clone();
+        let mut poll_interval = Duration::from_secs(
+            operations_config
+                .as_ref()
+                .and_then(|o| o.poll_interval_secs)
+                .unwrap_or(60),
+        );
+        if hub_sends_wakes {
+            poll_interval = poll_interval.max(OPERATIONS_WAKE_FALLBACK_INTERVAL);
+        }
+        let max_concurrency = operations_max_concurrency(operations_config.as_ref());
+        let operations_command_registry = state.command_registry();
+        let tasks_enabled = state.task_registry.is_some();
+        if running.len() >= max_concurrency {
+            tokio::select! {
+                biased;
+                () = operation_cancellation.cancelled() => break,
+                Some(result) = running.join_next_with_id() => {
+                    finish_operation_item(&mut running_operations, result);
+                }
+            }
+            continue;
+        }
+        let capacity = max_concurrency - running.len();
+        // Reserve execution capacity before claiming work so a lease never
+        // waits behind already-running interactive commands. Command steps share
+        // the slots of interactive commands; task steps may run for hours and are
+        // bounded by `max_concurrency` alone.
+        let mut command_permits = Vec::new();
+        if operations_command_registry.is_some() {
+            while command_permits.len() < capacity {
+                match operations_command_slots.clone().try_acquire_owned() {
+                    Ok(permit) => command_permits.push(permit),
+                    Err(_) => break,
+                }
+            }
+            // Without task steps to claim, there is nothing to do until a slot is free.
+            if command_permits.is_empty() && !tasks_enabled {
+                let permit = tokio::select! {
+                    biased;
+                    () = operation_cancellation.cancelled() => break,
+                    Some(result) = running.join_next_with_id() => {
+                        finish_operation_item(&mut running_operations, result);
+                        continue;
+                    }
+                    permit = operations_command_slots.clone().acquire_owned() => permit,
+                };
+                match permit {
+                    Ok(permit) => command_permits.push(permit),
+                    Err(_) => {
+                        anyhow::bail!("command concurrency limiter was closed");
+                    }
+                }
+            }
+        }
+        let mut kinds = vec![DeviceOperationWorkKind::DeviceCommand];
+        if tasks_enabled {
+            kinds.push(DeviceOperationWorkKind::DeviceTask);
+        }
+        // Whether this poll found anything. The hub releases a device operation's
+        // next step as soon as the previous one is reported, so a poll that found
+        // work is very likely to find more; sleeping out the whole idle interval
+        // between the steps of one operation would waste that. Only an empty poll
+        // means there is genuinely nothing to do.
+        let mut found_work = false;
+        let mut claimed = 0;
+        for kind in kinds {
+            let limit = match kind {
+                DeviceOperationWorkKind::DeviceCommand if operations_command_registry.is_some() => {
+                    command_permits.len()
+                }
+                _ => capacity.saturating_sub(claimed),
+            };
+            if limit == 0 {
+                continue;
+            }
+            let result = {
+                let mut executor = execution.executor.lock().await;
+                let claim = executor.execute(
+                    ClaimDeviceOperationWorkAction::new(execution.device_id.clone())
+                        // Lease only the steps we can execute right away so later work
+                        // cannot expire while earlier handlers are still running.
+                        .with_limit(Some(limit as u32))
+                        .with_kinds(Some(vec![kind])),
+                );
+                tokio::select! {
+                    biased;
+                    () = operation_cancellation.cancelled() => break 'poll,
+                    result = claim => result,
+                }
+            };
+            let metrics = &state.metrics;
+            match result {
+                Ok(Ok(output)) => {
+                    metrics.operation_claimed("ok", output.work.len());
+                    claimed += output.work.len();
+                    for item in output.work {
+                        // Steps of one operation run strictly one after another. A step
+                        // whose lease expired while it still runs here is left to its
+                        // running execution; once the result is in the ledger, it is
+                        // reported again under the next lease without executing again.
+                        if running_operations
+                            .values()
+                            .any(|operation_id| operation_id == &item.device_operation_id)
+                        {
+                            debug!(
+                                device_operation_id = %item.device_operation_id,
+                                step_index = item.step_index,
+                                "operation already has a running step, skipping work item"
+                            );
+                            continue;
+                        }
+                        found_work = true;
+                        let operation_id = item.device_operation_id.clone();
+                        let permit = match item.step {
+                            DeviceOperationWorkStep::DeviceCommand(_) => command_permits.pop(),
+                            _ => None,
+                        };
+                        let handle = running.spawn(execute_operation_item(
+                            execution.clone(),
+                            item,
+                            operations_command_registry.clone(),
+                            permit,
+                        ));
+                        running_operations.insert(handle.id(), operation_id);
+                    }
+                }
+                Ok(Err(error)) => {
+                    metrics.operation_claimed("rejected", 0);
+                    warn!(message = %error.message, "operation work claim rejected");
+                }
+                Err(error) => {
+                    metrics.operation_claimed("failed", 0);
+                    warn!(?error, "failed to claim operation work");
+                }
+            }
+        }
+        drop(command_permits);
+        if !found_work {
+            // A finished step may release the next step of its operation, so poll
+            // again as soon as one finishes. A wake that arrived during the poll
+            // is kept by the notifier and ends the wait right away.
+            tokio::select! {
+                biased;
+                () = operation_cancellation.cancelled() => break,
+                () = operation_wake.notified() => {
+                    debug!("operation loop woken by the hub");
+                    hub_sends_wakes = true;
+                }
+                Some(result) = running.join_next_with_id() => {
+                    finish_operation_item(&mut running_operations, result);
+                }
+                () = tokio::time::sleep(poll_interval) => {}
+            }
+        }
+    }
+    // Running steps observe the cancellation themselves and are resumed or failed
+    // conservatively by the next session.
+    while let Some(result) = running.join_next_with_id().await {
+        finish_operation_item(&mut running_operations, result);
+    }
+    Ok(())
+}
+
+/// Maximum number of operation work items executed at the same time.
+fn operations_max_concurrency(config: Option<&OperationsConfig>) -> usize {
+    config
+        .and_then(|operations| operations.max_concurrency)
+        .map_or(1, |max_concurrency| max_concurrency.clamp(1, 20) as usize)
+}
+
+fn finish_operation_item(
+    running_operations: &mut HashMap<tokio::task::Id, DeviceOperationId>,
+    result: Result<(tokio::task::Id, ()), tokio::task::JoinError>,
+) {
+    let id = match result {
+        Ok((id, ())) => id,
+        Err(error) => {
+            warn!(?error, "operation work item task failed");
+            error.id()
+        }
+    };
+    running_operations.remove(&id);
+}
+
+/// State shared by the work items executed by one operation loop.
+struct OperationExecution {
+    state: Arc<AgentState>,
+    ledger: Arc<Mutex<OperationLedger>>,
+    executor: Arc<Mutex<ClientExecutor>>,
+    device_id: DeviceId,
+    cancellation: CancellationToken,
+}
+
+/// Execute one claimed work item and report its result.
+async fn execute_operation_item(
+    execution: Arc<OperationExecution>,
+    mut item: DeviceOperationWorkItem,
+    operations_command_registry: Option<Arc<CommandRegistry>>,
+    _command_permit: Option<OwnedSemaphorePermit>,
+) {
+    let ledger = &execution.ledger;
+    let mut reporter = execution.executor.clone();
+    let operation_cancellation = &execution.cancellation;
+    let device_operation_id = item.device_operation_id.clone();
+    let step_index = item.step_index;
+    let claim_id = item.claim_id.clone();
+    let step_claim = OperationStepClaim {
+        device_id: &execution.device_id,
+        device_operation_id: &device_operation_id,
+        step_index,
+        claim_id: &claim_id,
+    };
+    let previous = ledger
+        .lock()
+        .await
+        .previous(&device_operation_id, step_index);
+    let report = match previous {
+        PreviousExecution::Completed(report) => {
+            // A completed result may be returning under a fresh
+            // lease after an earlier reporting outage. Commit the
+            // current claim before trying it again so a crash
+            // cannot leave the outbox tied to the expired claim.
+            if let Err(error) = ledger
+                .lock()
+                .await
+                .mark_completed(&device_operation_id, step_index, &claim_id, report.clone())
+                .await
+            {
+                warn!(?error, "failed to persist renewed operation claim");
+                return;
+            }
+            report
+        }
+        PreviousExecution::InProgress => {
+            let report = DeviceOperationStepReport {
+                status: DeviceOperationStepReportStatus::Failed,
+                output: None,
+                checkpoint: None,
+                error: Some(
+                    "previous command execution was interrupted after dispatch; refusing unsafe automatic replay"
+                        .to_owned(),
+                ),
+            };
+            if let Err(error) = ledger
+                .lock()
+                .await
+                .mark_completed(&device_operation_id, step_index, &claim_id, report.clone())
+                .await
+            {
+                warn!(?error, "failed to persist interrupted operation result");
+                return;
+            }
+            report
+        }
+        PreviousExecution::TaskRunning(checkpoint) => {
+            if !matches!(item.step, DeviceOperationWorkStep::DeviceTask(_)) {
+                warn!(
+                    device_operation_id = %device_operation_id,
+                    step_index,
+                    "operation ledger records a task for a command step"
+                );
+                return;
+            }
+            // The ledger checkpoint is persisted before it is
+            // reported, so it is never older than the hub's.
+            item.checkpoint = checkpoint.or(item.checkpoint.take());
+            let Some(report) = execute_task_step(
+                ledger,
+                &mut reporter,
+                execution.state.task_registry.as_deref(),
+                &step_claim,
+                item,
+                operation_cancellation,
+            )
+            .await
+            else {
+                return;
+            };
+            report
+        }
+        PreviousExecution::None => match item.step {
+            DeviceOperationWorkStep::DeviceCommand(step) => {
+                if let Err(error) = ledger
+                    .lock()
+                    .await
+                    .mark_in_progress(&device_operation_id, step_index, &claim_id)
+                    .await
+                {
+                    warn!(
+                        ?error,
+                        "failed to persist operation dispatch; command not executed"
+                    );
+                    return;
+                }
+                let done = if let Some(registry) = operations_command_registry.as_ref() {
+                    let request = DeviceCommandInvokeData::new(step.command, step.input)
+                        .with_stream_log(Some(false))
+                        .with_timeout_secs(Some(step.timeout_secs.unwrap_or(3600)));
+                    handlers::invoke_registered_command_with_cancellation(
+                        registry,
+                        request,
+                        operation_cancellation,
+                    )
+                    .await
+                } else {
+                    nexigon_api::types::devices::DeviceCommandDoneData {
+                        status: DeviceCommandStatus::Error,
+                        output: None,
+                        error: Some("commands not enabled".to_owned()),
+                        log_tail: Vec::new(),
+                        duration_ms: 0,
+                    }
+                };
+                if operation_cancellation.is_cancelled() {
+                    return;
+                }
+                execution
+                    .state
+                    .metrics
+                    .command_executed(CommandSource::Operation, &done);
+                let status = match done.status {
+                    DeviceCommandStatus::Ok => DeviceOperationStepReportStatus::Succeeded,
+                    DeviceCommandStatus::Error => DeviceOperationStepReportStatus::Failed,
+                };
+                let report = DeviceOperationStepReport {
+                    status,
+                    output: done.output,
+                    checkpoint: None,
+                    error: done.error,
+                };
+                if let Err(error) = ledger
+                    .lock()
+                    .await
+                    .mark_completed(&device_operation_id, step_index, &claim_id, report.clone())
+                    .await
+                {
+                    warn!(
+                        ?error,
+                        "failed to persist operation result; result not reported"
+                    );
+                    return;
+                }
+                report
+            }
+            DeviceOperationWorkStep::DeviceTask(step) => {
+                let item = DeviceOperationWorkItem {
+                    step: DeviceOperationWorkStep::DeviceTask(step),
+                    ..item
+                };
+                let Some(report) = execute_task_step(
+                    ledger,
+                    &mut reporter,
+                    execution.state.task_registry.as_deref(),
+                    &step_claim,
+                    item,
+                    operation_cancellation,
+                )
+                .await
+                else {
+                    return;
+                };
+                report
+            }
+        },
+    };
+    // Retry transport failures promptly while this claim's lease
+    // is certainly still current. The shortest valid command
+    // lease is 61 seconds; these five attempts span 15 seconds.
+    // A persistent outage falls back to normal lease expiry and
+    // reacquisition, at which point the saved result is submitted
+    // under the fresh claim without executing again.
+    let acknowledged = report_operation_step_with_retry(
+        &mut reporter,
+        &execution.device_id,
+        &device_operation_id,
+        step_index,
+        &claim_id,
+        &report,
+        Some(operation_cancellation),
+    )
+    .await;
+    execution.state.metrics.operation_reported(acknowledged);
+    if acknowledged
+        && let Err(error) = ledger
+            .lock()
+            .await
+            .remove(&device_operation_id, step_index)
+            .await
+    {
+        warn!(?error, "failed to prune reported operation result");
+    }
+}
+
+async fn run_connection_event_loop<S, E>(
+    connection: S,
+    config: watch::Receiver<Arc<Config>>,
+    command_registry: watch::Receiver<Option<Arc<CommandRegistry>>>,
+    forwarding_policy: watch::Receiver<Arc<ForwardingPolicy>>,
+    endpoints: Endpoints,
+    task_tx: mpsc::Sender<SupervisedTask>,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()>
+where
+    S: Stream<Item = Result<ConnectionEvent, E>> + Send + 'static,
+    E: std::fmt::Display,
 {
     tokio::pin!(connection);
     loop {
@@ -648,11 +1778,16 @@ where
                 };
                 match event {
                     Ok(ConnectionEvent::RequestChannel(request)) => {
+                        // Each channel runs with the configuration current at its request.
+                        let config = config.borrow().clone();
+                        let command_registry = command_registry.borrow().clone();
+                        let forwarding_policy = forwarding_policy.borrow().clone();
                         handle_channel_request(
                             request,
                             &config,
                             command_registry.as_ref(),
-                            &limits,
+                            &forwarding_policy,
+                            &endpoints,
                             &task_tx,
                             &cancellation,
                         );
@@ -666,64 +1801,443 @@ where
                 }
             }
         }
-    }
-}
-
-fn handle_channel_request(
-    request: nexigon_multiplex::ChannelRequest,
-    config: &Arc<Config>,
-    command_registry: Option<&Arc<CommandRegistry>>,
-    limits: &EndpointLimits,
-    task_tx: &mpsc::Sender<SupervisedTask>,
-    cancellation: &CancellationToken,
-) {
-    let endpoint = match std::str::from_utf8(request.endpoint()) {
-        Ok(endpoint) => endpoint,
-        Err(_) => {
-            request.reject(b"invalid endpoint");
+    }
+}
+
+fn handle_channel_request(
+    request: nexigon_multiplex::ChannelRequest,
+    config: &Arc<Config>,
+    command_registry: Option<&Arc<CommandRegistry>>,
+    policy: &Arc<ForwardingPolicy>,
+    endpoints: &Endpoints,
+    task_tx: &mpsc::Sender<SupervisedTask>,
+    cancellation: &CancellationToken,
+) {
+    let limits = &endpoints.limits;
+    let metrics = &endpoints.metrics;
+    let endpoint = match std::str::from_utf8(request.endpoint()) {
+        Ok(endpoint) => endpoint,
+        Err(_) => {
+            reject_channel(request, metrics, "invalid", "invalid endpoint");
+            return;
+        }
+    };
+    debug!(endpoint, "channel request");
+
+    if let Some(target) = endpoint.strip_prefix("forward/tcp/") {
+        // `forward/tcp/<port>` connects to localhost, `forward/tcp/<host>/<port>` to a
+        // host behind the device.
+        let (host, port) = match target.rsplit_once('/') {
+            Some((host, port)) => (Some(host), port),
+            None => (None, target),
+        };
+        let Some(port) = port
+            .parse::<u16>()
+            .ok()
+            .filter(|_| !host.is_some_and(str::is_empty))
+        else {
+            reject_channel(
+                request,
+                metrics,
+                "forward-tcp",
+                "invalid TCP forwarding endpoint",
+            );
+            return;
+        };
+        let target = match host {
+            None if policy.allows(port) => TcpForwardTarget::Local(port),
+            None => {
+                warn!(
+                    port,
+                    "rejected TCP forwarding to a port that is not allowed"
+                );
+                reject_not_allowed(
+                    request,
+                    metrics,
+                    "forward-tcp",
+                    "TCP forwarding to this port is not allowed",
+                );
+                return;
+            }
+            Some(host) if policy.may_allow_host(host, port) => TcpForwardTarget::Host {
+                host: host.to_owned(),
+                port,
+                policy: policy.clone(),
+            },
+            Some(host) => {
+                warn!(
+                    host,
+                    port, "rejected TCP forwarding to a host that is not allowed"
+                );
+                reject_not_allowed(
+                    request,
+                    metrics,
+                    "forward-tcp",
+                    "TCP forwarding to this host is not allowed",
+                );
+                return;
+            }
+        };
+        let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
+            reject_channel(request, metrics, "forward-tcp", "agent task queue is full");
+            return;
+        };
+        task_slot.send(SupervisedTask::new(
+            TaskKind::TcpConnect,
+            connect_tcp_forwarding(
+                request,
+                target,
+                metrics.clone(),
+                task_tx.clone(),
+                cancellation.clone(),
+            ),
+        ));
+        return;
+    }
+
+    if let Some(name) = endpoint.strip_prefix("forward/unix/") {
+        #[cfg(unix)]
+        {
+            let Some(path) = policy.socket(name) else {
+                warn!(
+                    socket = name,
+                    "rejected forwarding to a Unix socket that is not declared"
+                );
+                reject_not_allowed(
+                    request,
+                    metrics,
+                    "forward-unix",
+                    "Unix socket forwarding to this name is not allowed",
+                );
+                return;
+            };
+            let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
+                reject_channel(request, metrics, "forward-unix", "agent task queue is full");
+                return;
+            };
+            task_slot.send(SupervisedTask::new(
+                TaskKind::UnixConnect,
+                connect_unix_forwarding(
+                    request,
+                    path.to_owned(),
+                    metrics.clone(),
+                    task_tx.clone(),
+                    cancellation.clone(),
+                ),
+            ));
+            return;
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = name;
+            reject_channel(
+                request,
+                metrics,
+                "forward-unix",
+                "Unix socket forwarding not supported on this platform",
+            );
+            return;
+        }
+    }
+
+    if let Some(port) = endpoint.strip_prefix("forward/udp/") {
+        let Ok(port) = port.parse::<u16>() else {
+            reject_channel(
+                request,
+                metrics,
+                "forward-udp",
+                "invalid UDP forwarding endpoint",
+            );
+            return;
+        };
+        if !policy.allows(port) {
+            warn!(
+                port,
+                "rejected UDP forwarding to a port that is not allowed"
+            );
+            reject_not_allowed(
+                request,
+                metrics,
+                "forward-udp",
+                "UDP forwarding to this port is not allowed",
+            );
+            return;
+        }
+        let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
+            reject_channel(request, metrics, "forward-udp", "agent task queue is full");
+            return;
+        };
+        let metrics = metrics.clone();
+        let cancellation = cancellation.clone();
+        metrics.channel_accepted("forward-udp");
+        request.accept(move |channel| {
+            task_slot.send(SupervisedTask::new(TaskKind::UdpForward, async move {
+                let _tracked = metrics.track_channel("forward-udp", channel.statistics());
+                relay_udp_forwarding(channel, port, cancellation).await
+            }));
+        });
+        return;
+    }
+
+    if let Some(port) = endpoint.strip_prefix("reverse/tcp/") {
+        let Ok(port) = port.parse::<u16>() else {
+            reject_channel(
+                request,
+                metrics,
+                "reverse-tcp",
+                "invalid reverse forwarding endpoint",
+            );
+            return;
+        };
+        if !policy.allows_reverse(port) {
+            warn!(
+                port,
+                "rejected reverse forwarding on a port that is not allowed"
+            );
+            reject_not_allowed(
+                request,
+                metrics,
+                "reverse-tcp",
+                "reverse forwarding on this port is not allowed",
+            );
+            return;
+        }
+        // Bind before accepting, so that ports already in use are reported to the hub.
+        let listener = match bind_reverse_listener(port) {
+            Ok(listener) => listener,
+            Err(error) => {
+                debug!(?error, port, "unable to bind reverse forwarding port");
+                reject_channel(
+                    request,
+                    metrics,
+                    "reverse-tcp",
+                    "reverse forwarding port is unavailable",
+                );
+                return;
+            }
+        };
+        let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
+            reject_channel(request, metrics, "reverse-tcp", "agent task queue is full");
+            return;
+        };
+        let hub = endpoints.hub.clone();
+        let metrics = metrics.clone();
+        let cancellation = cancellation.clone();
+        metrics.channel_accepted("reverse-tcp");
+        request.accept(move |channel| {
+            task_slot.send(SupervisedTask::new(TaskKind::ReverseForward, async move {
+                let _tracked = metrics.track_channel("reverse-tcp", channel.statistics());
+                run_reverse_forwarding(channel, listener, hub, metrics.clone(), cancellation).await
+            }));
+        });
+        return;
+    }
+
+    if endpoint == "terminal" || endpoint.starts_with("terminal/") {
+        #[cfg(target_os = "linux")]
+        {
+            if !crate::config::terminal_enabled(config) {
+                reject_channel(
+                    request,
+                    metrics,
+                    "terminal",
+                    "terminal not enabled or terminal user invalid",
+                );
+                return;
+            }
+            let Ok(terminal_permit) = limits.terminals.clone().try_acquire_owned() else {
+                reject_channel(
+                    request,
+                    metrics,
+                    "terminal",
+                    "too many concurrent terminal sessions",
+                );
+                return;
+            };
+            let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
+                reject_channel(request, metrics, "terminal", "agent task queue is full");
+                return;
+            };
+            let Some(target) = crate::terminal::TerminalTarget::from_endpoint(endpoint) else {
+                reject_channel(request, metrics, "terminal", "invalid terminal endpoint");
+                return;
+            };
+            let config = config.clone();
+            let recordings = endpoints.terminal_recordings.clone();
+            let metrics = metrics.clone();
+            let cancellation = cancellation.clone();
+            metrics.channel_accepted("terminal");
+            request.accept(move |channel| {
+                task_slot.send(SupervisedTask::new(TaskKind::Terminal, async move {
+                    let _terminal_permit = terminal_permit;
+                    let _tracked = metrics.track_channel("terminal", channel.statistics());
+                    crate::terminal::handle_terminal_session_with_cancellation(
+                        channel,
+                        &config,
+                        &target,
+                        Some(&recordings),
+                        cancellation,
+                    )
+                    .await
+                }));
+            });
+            return;
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            reject_channel(
+                request,
+                metrics,
+                "terminal",
+                "terminal not supported on this platform",
+            );
             return;
         }
-    };
-    debug!(endpoint, "channel request");
+    }
 
-    if let Some(port_str) = endpoint.strip_prefix("forward/tcp/") {
-        let Ok(port) = port_str.parse::<u16>() else {
-            request.reject(b"invalid TCP forwarding endpoint");
+    if endpoint.starts_with("terminal-session/") || endpoint.starts_with("terminal-observe/") {
+        #[cfg(target_os = "linux")]
+        {
+            if !crate::config::terminal_enabled(config) {
+                reject_channel(
+                    request,
+                    metrics,
+                    "terminal-session",
+                    "terminal not enabled or terminal user invalid",
+                );
+                return;
+            }
+            // `terminal-session/<id>[/<user>]` attaches the writer and creates the session
+            // if necessary, `terminal-observe/<id>` attaches an observer.
+            let (mode, target) = match endpoint.strip_prefix("terminal-session/") {
+                Some(target) => (AttachMode::Writer, target),
+                None => (
+                    AttachMode::Observer,
+                    endpoint.trim_start_matches("terminal-observe/"),
+                ),
+            };
+            let (id, requested_user) = match target.split_once('/') {
+                Some((id, user)) if mode == AttachMode::Writer => (id, Some(user.to_owned())),
+                // Observers cannot request a user, the empty ID is rejected below.
+                Some(_) => ("", None),
+                None => (target, None),
+            };
+            if !crate::terminal::valid_session_id(id) {
+                reject_channel(
+                    request,
+                    metrics,
+                    "terminal-session",
+                    "invalid terminal session endpoint",
+                );
+                return;
+            }
+            let sessions = endpoints.terminal_sessions.clone();
+            let running = sessions.contains(id);
+            if mode == AttachMode::Observer && !running {
+                reject_channel(
+                    request,
+                    metrics,
+                    "terminal-session",
+                    "terminal session is not running",
+                );
+                return;
+            }
+            // Attaching to a running session does not need a terminal slot.
+            let permit = match mode {
+                AttachMode::Writer => limits.terminals.clone().try_acquire_owned().ok(),
+                AttachMode::Observer => None,
+            };
+            if permit.is_none() && !running {
+                reject_channel(
+                    request,
+                    metrics,
+                    "terminal-session",
+                    "too many concurrent terminal sessions",
+                );
+                return;
+            }
+            let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
+                reject_channel(
+                    request,
+                    metrics,
+                    "terminal-session",
+                    "agent task queue is full",
+                );
+                return;
+            };
+            let id = id.to_owned();
+            let config = config.clone();
+            let metrics = metrics.clone();
+            let task_tx = task_tx.clone();
+            let cancellation = cancellation.clone();
+            metrics.channel_accepted("terminal-session");
+            request.accept(move |channel| {
+                task_slot.send(SupervisedTask::new(TaskKind::Terminal, async move {
+                    let _tracked = metrics.track_channel("terminal-session", channel.statistics());
+                    let (viewer, session_task) = sessions
+                        .attach(&config, &id, requested_user.as_deref(), mode, permit)
+                        .await?;
+                    if let Some(session_task) = session_task {
+                        queue_task(
+                            &task_tx,
+                            SupervisedTask::new(TaskKind::TerminalSession, session_task),
+                        )
+                        .await?;
+                    }
+                    viewer.serve(channel, cancellation).await
+                }));
+            });
             return;
-        };
-        let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
-            request.reject(b"agent task queue is full");
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            reject_channel(
+                request,
+                metrics,
+                "terminal-session",
+                "terminal not supported on this platform",
+            );
             return;
-        };
-        task_slot.send(SupervisedTask::new(
-            TaskKind::TcpConnect,
-            connect_tcp_forwarding(request, port, task_tx.clone(), cancellation.clone()),
-        ));
-        return;
+        }
     }
 
-    if endpoint == "terminal" || endpoint.starts_with("terminal/") {
+    if endpoint == "exec" || endpoint.starts_with("exec/") {
         #[cfg(target_os = "linux")]
         {
+            // Exec grants the same access as a terminal and shares its configuration
+            // and its concurrency limit.
             if !crate::config::terminal_enabled(config) {
-                request.reject(b"terminal not enabled or terminal user invalid");
+                reject_channel(
+                    request,
+                    metrics,
+                    "exec",
+                    "terminal not enabled or terminal user invalid",
+                );
                 return;
             }
             let Ok(terminal_permit) = limits.terminals.clone().try_acquire_owned() else {
-                request.reject(b"too many concurrent terminal sessions");
+                reject_channel(
+                    request,
+                    metrics,
+                    "exec",
+                    "too many concurrent terminal sessions",
+                );
                 return;
             };
             let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
-                request.reject(b"agent task queue is full");
+                reject_channel(request, metrics, "exec", "agent task queue is full");
                 return;
             };
-            let requested_user = endpoint.strip_prefix("terminal/").map(str::to_owned);
+            let requested_user = endpoint.strip_prefix("exec/").map(str::to_owned);
             let config = config.clone();
+            let metrics = metrics.clone();
             let cancellation = cancellation.clone();
+            metrics.channel_accepted("exec");
             request.accept(move |channel| {
-                task_slot.send(SupervisedTask::new(TaskKind::Terminal, async move {
+                task_slot.send(SupervisedTask::new(TaskKind::Exec, async move {
                     let _terminal_permit = terminal_permit;
-                    crate::terminal::handle_terminal_session_with_cancellation(
+                    let _tracked = metrics.track_channel("exec", channel.statistics());
+                    crate::terminal::handle_exec_session_with_cancellation(
                         channel,
                         &config,
                         requested_user.as_deref(),
@@ -736,77 +2250,190 @@ fn handle_channel_request(
         }
         #[cfg(not(target_os = "linux"))]
         {
-            request.reject(b"terminal not supported on this platform");
+            reject_channel(
+                request,
+                metrics,
+                "exec",
+                "exec not supported on this platform",
+            );
             return;
         }
     }
 
+    if endpoint == "operations/wake" {
+        let Some(operation_wake) = &endpoints.operation_wake else {
+            reject_channel(
+                request,
+                metrics,
+                "operations-wake",
+                "operation polling not enabled",
+            );
+            return;
+        };
+        // The request carries no data. Closing the accepted channel acknowledges it.
+        operation_wake.notify_one();
+        metrics.channel_accepted("operations-wake");
+        request.accept(drop);
+        return;
+    }
+
     if endpoint == "handler" {
         let Some(registry) = command_registry else {
-            request.reject(b"commands not enabled");
+            reject_channel(request, metrics, "handler", "commands not enabled");
             return;
         };
         let Ok(command_permit) = limits.commands.clone().try_acquire_owned() else {
-            request.reject(b"too many concurrent commands");
+            reject_channel(request, metrics, "handler", "too many concurrent commands");
             return;
         };
         let Ok(task_slot) = task_tx.clone().try_reserve_owned() else {
-            request.reject(b"agent task queue is full");
+            reject_channel(request, metrics, "handler", "agent task queue is full");
             return;
         };
         let config = config.clone();
         let registry = registry.clone();
+        let metrics = metrics.clone();
         let cancellation = cancellation.clone();
+        metrics.channel_accepted("handler");
         request.accept(move |channel| {
             task_slot.send(SupervisedTask::new(TaskKind::Handler, async move {
                 let _command_permit = command_permit;
-                handlers::handle_handler_channel_with_cancellation(
+                let _tracked = metrics.track_channel("handler", channel.statistics());
+                let done = handlers::handle_handler_channel_with_cancellation(
                     channel,
                     &config,
                     &registry,
                     cancellation,
                 )
-                .await
+                .await?;
+                if let Some(done) = done {
+                    metrics.command_executed(CommandSource::Channel, &done);
+                }
+                Ok(())
             }));
         });
         return;
     }
 
     warn!(endpoint, "unknown endpoint requested");
-    request.reject(b"unknown endpoint");
+    reject_channel(request, metrics, "unknown", "unknown endpoint");
+}
+
+/// Reject a channel request to `endpoint` and count the rejection.
+fn reject_channel(
+    request: nexigon_multiplex::ChannelRequest,
+    metrics: &AgentMetrics,
+    endpoint: &'static str,
+    reason: &'static str,
+) {
+    metrics.channel_rejected(endpoint, reason);
+    request.reject(reason.as_bytes());
+}
+
+/// Reject a forwarding channel denied by the forwarding policy.
+///
+/// The reason carries the code of [`ForwardingRejection::NotAllowed`] on the wire.
+fn reject_not_allowed(
+    request: nexigon_multiplex::ChannelRequest,
+    metrics: &AgentMetrics,
+    endpoint: &'static str,
+    reason: &'static str,
+) {
+    metrics.channel_rejected(endpoint, reason);
+    request.reject(ForwardingRejection::not_allowed_reason(reason).as_bytes());
+}
+
+/// Target of a TCP forwarding channel.
+enum TcpForwardTarget {
+    /// Port on localhost.
+    Local(u16),
+    /// Port of a host behind the device, reached in gateway mode.
+    Host {
+        host: String,
+        port: u16,
+        /// Policy the addresses of the host are checked against.
+        policy: Arc<ForwardingPolicy>,
+    },
+}
+
+impl std::fmt::Display for TcpForwardTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Local(port) => write!(f, "localhost:{port}"),
+            Self::Host { host, port, .. } => write!(f, "{host}:{port}"),
+        }
+    }
 }
 
 async fn connect_tcp_forwarding(
     request: nexigon_multiplex::ChannelRequest,
-    port: u16,
+    target: TcpForwardTarget,
+    metrics: Arc<AgentMetrics>,
     task_tx: mpsc::Sender<SupervisedTask>,
     cancellation: CancellationToken,
 ) -> anyhow::Result<()> {
-    let address = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
-    let connect = tokio::time::timeout(TCP_CONNECT_TIMEOUT, TcpStream::connect(address));
+    let (unavailable, timed_out) = match &target {
+        TcpForwardTarget::Local(_) => (
+            "local TCP forwarding target is unavailable",
+            "local TCP forwarding target timed out",
+        ),
+        TcpForwardTarget::Host { .. } => (
+            "TCP forwarding host is unavailable",
+            "TCP forwarding host timed out",
+        ),
+    };
+    // Resolves to `None` if the host only resolves to addresses that are not allowed.
+    let connect = async {
+        match &target {
+            TcpForwardTarget::Local(port) => {
+                let address = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), *port);
+                TcpStream::connect(address).await.map(Some)
+            }
+            TcpForwardTarget::Host { host, port, policy } => {
+                let addresses = policy.resolve(host, *port).await?;
+                if addresses.is_empty() {
+                    return Ok(None);
+                }
+                TcpStream::connect(&addresses[..]).await.map(Some)
+            }
+        }
+    };
+    let connect = tokio::time::timeout(TCP_CONNECT_TIMEOUT, connect);
     let mut tcp = tokio::select! {
         () = cancellation.cancelled() => return Ok(()),
         result = connect => match result {
-            Ok(Ok(tcp)) => tcp,
+            Ok(Ok(Some(tcp))) => tcp,
+            Ok(Ok(None)) => {
+                warn!(%target, "rejected TCP forwarding to a host that is not allowed");
+                reject_not_allowed(
+                    request,
+                    &metrics,
+                    "forward-tcp",
+                    "TCP forwarding to this host is not allowed",
+                );
+                return Ok(());
+            }
             Ok(Err(error)) => {
-                debug!(?error, %address, "local TCP forwarding target is unavailable");
-                request.reject(b"local TCP forwarding target is unavailable");
+                debug!(?error, %target, "TCP forwarding target is unavailable");
+                reject_channel(request, &metrics, "forward-tcp", unavailable);
                 return Ok(());
             }
             Err(_) => {
-                debug!(%address, "local TCP forwarding connection timed out");
-                request.reject(b"local TCP forwarding target timed out");
+                debug!(%target, "TCP forwarding connection timed out");
+                reject_channel(request, &metrics, "forward-tcp", timed_out);
                 return Ok(());
             }
         }
     };
 
     let Ok(task_slot) = task_tx.try_reserve_owned() else {
-        request.reject(b"agent task queue is full");
+        reject_channel(request, &metrics, "forward-tcp", "agent task queue is full");
         return Ok(());
     };
+    metrics.channel_accepted("forward-tcp");
     request.accept(move |mut channel| {
         task_slot.send(SupervisedTask::new(TaskKind::TcpForward, async move {
+            let _tracked = metrics.track_channel("forward-tcp", channel.statistics());
             tokio::select! {
                 () = cancellation.cancelled() => Ok(()),
                 result = tokio::io::copy_bidirectional(&mut channel, &mut tcp) => {
@@ -820,12 +2447,82 @@ async fn connect_tcp_forwarding(
     Ok(())
 }
 
+#[cfg(unix)]
+async fn connect_unix_forwarding(
+    request: nexigon_multiplex::ChannelRequest,
+    path: PathBuf,
+    metrics: Arc<AgentMetrics>,
+    task_tx: mpsc::Sender<SupervisedTask>,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    let connect = tokio::time::timeout(TCP_CONNECT_TIMEOUT, tokio::net::UnixStream::connect(&path));
+    let mut stream = tokio::select! {
+        () = cancellation.cancelled() => return Ok(()),
+        result = connect => match result {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(error)) => {
+                debug!(?error, path = %path.display(), "Unix socket forwarding target is unavailable");
+                reject_channel(
+                    request,
+                    &metrics,
+                    "forward-unix",
+                    "Unix socket forwarding target is unavailable",
+                );
+                return Ok(());
+            }
+            Err(_) => {
+                debug!(path = %path.display(), "Unix socket forwarding connection timed out");
+                reject_channel(
+                    request,
+                    &metrics,
+                    "forward-unix",
+                    "Unix socket forwarding target timed out",
+                );
+                return Ok(());
+            }
+        }
+    };
+
+    let Ok(task_slot) = task_tx.try_reserve_owned() else {
+        reject_channel(
+            request,
+            &metrics,
+            "forward-unix",
+            "agent task queue is full",
+        );
+        return Ok(());
+    };
+    metrics.channel_accepted("forward-unix");
+    request.accept(move |mut channel| {
+        task_slot.send(SupervisedTask::new(TaskKind::UnixForward, async move {
+            let _tracked = metrics.track_channel("forward-unix", channel.statistics());
+            tokio::select! {
+                () = cancellation.cancelled() => Ok(()),
+                result = tokio::io::copy_bidirectional(&mut channel, &mut stream) => {
+                    result
+                        .map(|_| ())
+                        .context("Unix socket forwarding relay failed")
+                }
+            }
+        }));
+    });
+    Ok(())
+}
+
 async fn supervise_tasks(
     tasks: &mut JoinSet<TaskCompletion>,
+    active_tasks: &Arc<ActiveTasks>,
     task_rx: &mut mpsc::Receiver<SupervisedTask>,
+    mut watchdog: Option<&mut AgentWatchdog>,
     cancellation: &CancellationToken,
 ) -> anyhow::Result<()> {
     loop {
+        let watchdog_check = async {
+            match watchdog.as_deref_mut() {
+                Some(watchdog) => watchdog.check().await,
+                None => std::future::pending().await,
+            }
+        };
         tokio::select! {
             biased;
             completion = tasks.join_next() => {
@@ -857,8 +2554,9 @@ async fn supervise_tasks(
                 let Some(task) = task else {
                     bail!("agent task queue closed unexpectedly");
                 };
-                spawn_supervised(tasks, task);
+                spawn_supervised(tasks, active_tasks, task);
             }
+            () = watchdog_check => {}
         }
     }
 }
@@ -898,6 +2596,33 @@ async fn shutdown_tasks(tasks: &mut JoinSet<TaskCompletion>) {
     }
 }
 
+/// Return the commands directory, or `None` if commands are disabled.
+fn commands_directory(config: &Config) -> Option<&Path> {
+    let commands = config.commands.as_ref()?;
+    if !commands.enabled.unwrap_or(false) {
+        return None;
+    }
+    Some(
+        commands
+            .directory
+            .as_deref()
+            .unwrap_or(Path::new("/etc/nexigon/agent/commands")),
+    )
+}
+
+fn tasks_directory(config: &Config) -> Option<&Path> {
+    let tasks = config.tasks.as_ref()?;
+    if !tasks.enabled.unwrap_or(false) {
+        return None;
+    }
+    Some(
+        tasks
+            .directory
+            .as_deref()
+            .unwrap_or(Path::new("/etc/nexigon/agent/tasks")),
+    )
+}
+
 fn operation_polling_enabled(config: Option<&OperationsConfig>) -> bool {
     config
         .and_then(|operations| operations.enabled)
@@ -919,10 +2644,171 @@ fn load_command_registry(commands_dir: &Path) -> CommandRegistry {
     }
 }
 
+/// Load optional task support without making it an agent startup dependency.
+fn load_task_registry(tasks_dir: &Path) -> TaskRegistry {
+    match TaskRegistry::load_external(tasks_dir) {
+        Ok(registry) => registry,
+        Err(error) => {
+            warn!(
+                ?tasks_dir,
+                error = ?error,
+                "failed to load task definitions, continuing without tasks"
+            );
+            TaskRegistry::default()
+        }
+    }
+}
+
 fn command_slots() -> Arc<Semaphore> {
     Arc::new(Semaphore::new(MAX_CONCURRENT_COMMANDS))
 }
 
+/// Leased operation step that reports are addressed to.
+struct OperationStepClaim<'a> {
+    device_id: &'a DeviceId,
+    device_operation_id: &'a DeviceOperationId,
+    step_index: u32,
+    claim_id: &'a DeviceOperationWorkClaimId,
+}
+
+/// Run a device task step, relaying its progress to the hub until it finishes.
+///
+/// The step resumes from the checkpoint of `item`. Each new checkpoint is persisted in
+/// the ledger before it is reported, and the latest progress is reported again at
+/// least every [`TASK_HEARTBEAT_INTERVAL`] to renew the lease of the step. Returns
+/// `None` if the task was interrupted or its result could not be persisted; the ledger
+/// then still records the task as running, and it is resumed from its latest
+/// checkpoint when the step is leased again.
+async fn execute_task_step(
+    ledger: &Mutex<OperationLedger>,
+    reporter: &mut impl OperationReporter,
+    registry: Option<&TaskRegistry>,
+    claim: &OperationStepClaim<'_>,
+    item: DeviceOperationWorkItem,
+    cancellation: &CancellationToken,
+) -> Option<DeviceOperationStepReport> {
+    let checkpoint = item.checkpoint.clone();
+    // Tasks are only ever claimed while tasks are enabled, but the work may have
+    // been leased by an earlier agent run with a different configuration.
+    let Some(registry) = registry else {
+        let report = DeviceOperationStepReport {
+            status: DeviceOperationStepReportStatus::Failed,
+            output: None,
+            checkpoint,
+            error: Some("tasks not enabled".to_owned()),
+        };
+        return persist_task_report(ledger, claim, report).await;
+    };
+    if let Err(error) = ledger
+        .lock()
+        .await
+        .mark_task_running(
+            claim.device_operation_id,
+            claim.step_index,
+            claim.claim_id,
+            checkpoint.clone(),
+        )
+        .await
+    {
+        warn!(?error, "failed to persist task start; task not executed");
+        return None;
+    }
+    let mut persisted = checkpoint.clone();
+    let (progress, mut progress_rx) = watch::channel(TaskProgress {
+        data: None,
+        checkpoint,
+    });
+    let timeout_secs = match &item.step {
+        DeviceOperationWorkStep::DeviceTask(step) => step.timeout_secs,
+        DeviceOperationWorkStep::DeviceCommand(_) => None,
+    };
+    let timeout = Duration::from_secs(u64::from(timeout_secs.unwrap_or(DEFAULT_TASK_TIMEOUT_SECS)));
+    let execution = crate::tasks::run_task(registry, item, timeout, &progress, cancellation);
+    tokio::pin!(execution);
+    let mut heartbeat = tokio::time::interval_at(
+        tokio::time::Instant::now() + TASK_HEARTBEAT_INTERVAL,
+        TASK_HEARTBEAT_INTERVAL,
+    );
+    let report = loop {
+        tokio::select! {
+            biased;
+            report = &mut execution => break report?,
+            Ok(()) = progress_rx.changed() => {}
+            _ = heartbeat.tick() => {}
+        }
+        let latest = progress_rx.borrow_and_update().clone();
+        if latest.checkpoint != persisted {
+            match ledger
+                .lock()
+                .await
+                .mark_task_running(
+                    claim.device_operation_id,
+                    claim.step_index,
+                    claim.claim_id,
+                    latest.checkpoint.clone(),
+                )
+                .await
+            {
+                Ok(()) => persisted = latest.checkpoint,
+                Err(error) => warn!(?error, "failed to persist task checkpoint"),
+            }
+        }
+        let report = DeviceOperationStepReport {
+            status: DeviceOperationStepReportStatus::Running,
+            output: latest.data,
+            checkpoint: persisted.clone(),
+            error: None,
+        };
+        // Progress is best effort. The next report carries the latest state, so a
+        // failed one is not retried.
+        let attempt = reporter.report(
+            claim.device_id.clone(),
+            claim.device_operation_id.clone(),
+            claim.step_index,
+            claim.claim_id.clone(),
+            report,
+        );
+        let attempt = tokio::select! {
+            biased;
+            () = cancellation.cancelled() => continue,
+            attempt = attempt => attempt,
+        };
+        match attempt {
+            ReportAttempt::Acknowledged => {}
+            ReportAttempt::Rejected(message) => {
+                warn!(%message, "task progress report rejected");
+            }
+            ReportAttempt::TransportFailed(error) => {
+                debug!(%error, "failed to report task progress");
+            }
+        }
+        heartbeat.reset();
+    };
+    persist_task_report(ledger, claim, report).await
+}
+
+async fn persist_task_report(
+    ledger: &Mutex<OperationLedger>,
+    claim: &OperationStepClaim<'_>,
+    report: DeviceOperationStepReport,
+) -> Option<DeviceOperationStepReport> {
+    if let Err(error) = ledger
+        .lock()
+        .await
+        .mark_completed(
+            claim.device_operation_id,
+            claim.step_index,
+            claim.claim_id,
+            report.clone(),
+        )
+        .await
+    {
+        warn!(?error, "failed to persist task result; result not reported");
+        return None;
+    }
+    Some(report)
+}
+
 #[derive(Debug)]
 enum ReportAttempt {
     Acknowledged,
@@ -967,6 +2853,26 @@ impl OperationReporter for ClientExecutor {
     }
 }
 
+/// Executor shared by the concurrently executed work items of an operation loop.
+///
+/// The executor is only locked for individual requests, so one work item waiting to
+/// retry a report does not hold up the others.
+impl OperationReporter for Arc<Mutex<ClientExecutor>> {
+    async fn report(
+        &mut self,
+        device_id: DeviceId,
+        operation_id: DeviceOperationId,
+        step_index: u32,
+        claim_id: DeviceOperationWorkClaimId,
+        report: DeviceOperationStepReport,
+    ) -> ReportAttempt {
+        self.lock()
+            .await
+            .report(device_id, operation_id, step_index, claim_id, report)
+            .await
+    }
+}
+
 async fn report_operation_step_with_retry(
     reporter: &mut impl OperationReporter,
     device_id: &DeviceId,
@@ -1001,50 +2907,118 @@ async fn report_operation_step_with_retry(
                 warn!(%message, "operation step report rejected");
                 return false;
             }
-            ReportAttempt::TransportFailed(error) => {
-                warn!(%error, attempt, "failed to report operation step");
-                if attempt < 5 {
-                    if let Some(cancellation) = cancellation {
-                        tokio::select! {
-                            biased;
-                            () = cancellation.cancelled() => return false,
-                            () = tokio::time::sleep(retry_delay) => {}
-                        }
-                    } else {
-                        tokio::time::sleep(retry_delay).await;
-                    }
-                    retry_delay *= 2;
+            ReportAttempt::TransportFailed(error) => {
+                warn!(%error, attempt, "failed to report operation step");
+                if attempt < 5 {
+                    if let Some(cancellation) = cancellation {
+                        tokio::select! {
+                            biased;
+                            () = cancellation.cancelled() => return false,
+                            () = tokio::time::sleep(retry_delay) => {}
+                        }
+                    } else {
+                        tokio::time::sleep(retry_delay).await;
+                    }
+                    retry_delay *= 2;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Serve the local API, restarting the listener when its configuration is reloaded.
+#[cfg(unix)]
+async fn run_local_api(
+    state: Arc<AgentState>,
+    hub: watch::Receiver<Option<ConnectionRef>>,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    use crate::local_api::LocalApiContext;
+    use crate::status::StatusSource;
+
+    let context = LocalApiContext {
+        hub,
+        outbox: state.outbox.clone(),
+        tasks: state.task_registry.clone(),
+        status: StatusSource {
+            hub_url: state.hub_url.clone(),
+            device_id: state.device_id.subscribe(),
+            command_registry: state.command_registry.subscribe(),
+            operation_ledger: state.operation_ledger.clone(),
+            active_tasks: state.active_tasks.clone(),
+        },
+        metrics: state.metrics.clone(),
+    };
+    let mut config = state.config.subscribe();
+    loop {
+        let local_api_config = config.borrow_and_update().local_api.clone();
+        let restart = cancellation.child_token();
+        let server = serve_local_api(local_api_config.clone(), context.clone(), restart.clone());
+        tokio::pin!(server);
+        tokio::select! {
+            result = &mut server => {
+                if cancellation.is_cancelled() {
+                    return result;
+                }
+                // A listener that cannot start stays down until its configuration changes.
+                if let Err(error) = result {
+                    warn!(?error, "local API listener failed");
+                }
+                tokio::select! {
+                    () = cancellation.cancelled() => return Ok(()),
+                    () = local_api_config_changed(&mut config, &local_api_config) => {}
+                }
+            }
+            () = local_api_config_changed(&mut config, &local_api_config) => {
+                info!("local API configuration changed, restarting listener");
+                restart.cancel();
+                if let Err(error) = server.await {
+                    warn!(?error, "local API listener failed during restart");
                 }
             }
         }
     }
-    false
 }
 
 #[cfg(unix)]
-fn local_api_task(
-    config: &Config,
-    connection_ref: &nexigon_multiplex::ConnectionRef,
-    cancellation: CancellationToken,
-) -> Option<SupervisedTask> {
+async fn serve_local_api(
+    local_api_config: Option<crate::config::LocalApiConfig>,
+    context: crate::local_api::LocalApiContext,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
     use crate::config::LocalApiConfig;
 
-    let local_api_config = config.local_api.clone();
     let enabled = local_api_config
         .as_ref()
         .and_then(|cfg| cfg.enabled)
         .unwrap_or(true);
     if !enabled {
-        return None;
+        shutdown.cancelled().await;
+        return Ok(());
     }
     let local_api_config = local_api_config.unwrap_or(LocalApiConfig {
         enabled: None,
         socket_path: None,
     });
-    let hub_ref = connection_ref.clone();
-    Some(SupervisedTask::new(TaskKind::LocalApi, async move {
-        crate::local_api::serve(&local_api_config, hub_ref, cancellation.cancelled_owned()).await
-    }))
+    crate::local_api::serve(&local_api_config, context, shutdown.cancelled_owned()).await
+}
+
+/// Resolve once a reload changes the `local-api` section away from `current`.
+#[cfg(unix)]
+async fn local_api_config_changed(
+    config: &mut watch::Receiver<Arc<Config>>,
+    current: &Option<crate::config::LocalApiConfig>,
+) {
+    let current = serde_json::to_value(current).ok();
+    loop {
+        if config.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+        if serde_json::to_value(&config.borrow_and_update().local_api).ok() != current {
+            return;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1062,45 +3036,71 @@ mod tests {
     use bytes::Bytes;
     use futures::StreamExt;
     use futures::future::join_all;
+    use nexigon_agent_protocol::ForwardingRejection;
+    use nexigon_agent_protocol::parse_reverse_tcp_endpoint;
+    use nexigon_agent_protocol::read_datagram;
+    use nexigon_agent_protocol::reverse_tcp_endpoint;
+    use nexigon_agent_protocol::write_datagram;
     use nexigon_api::types::devices::DeviceOperationStepReportStatus;
     use nexigon_ids::Generate;
     use nexigon_multiplex::Connection;
     use nexigon_multiplex::ConnectionEvent;
     use nexigon_multiplex::ConnectionLimits;
     use nexigon_multiplex::ConnectionRef;
+    use nexigon_multiplex::OpenError;
     use nexigon_multiplex::transport::InMemory;
     use tempfile::TempDir;
+    use tokio::io::AsyncRead;
     use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWrite;
     use tokio::io::AsyncWriteExt;
+    use tokio::sync::Notify;
     use tokio::sync::mpsc;
     use tokio::sync::oneshot;
+    use tokio::sync::watch;
     use tokio::task::JoinHandle;
     use tokio::task::JoinSet;
     use tokio_util::sync::CancellationToken;
 
+    use super::AgentState;
+    use super::CommandsDirectoryWatch;
     use super::DeviceId;
     use super::DeviceOperationId;
     use super::DeviceOperationStepReport;
     use super::DeviceOperationWorkClaimId;
     use super::EndpointLimits;
+    use super::Endpoints;
     #[cfg(target_os = "linux")]
     use super::MAX_CONCURRENT_TERMINALS;
     use super::OperationReporter;
+    use super::RECONNECT_INITIAL_DELAY;
+    use super::RECONNECT_MAX_DELAY;
+    use super::ReconnectBackoff;
     use super::ReportAttempt;
     use super::SUPERVISOR_QUEUE_CAPACITY;
     use super::SupervisedTask;
     use super::TaskKind;
+    #[cfg(target_os = "linux")]
+    use super::TerminalRecordings;
+    #[cfg(target_os = "linux")]
+    use super::TerminalSessions;
     use super::command_slots;
     use super::load_command_registry;
     use super::operation_polling_enabled;
+    use super::reload_config;
     use super::report_operation_step_with_retry;
     use super::run_connection_event_loop;
     use super::run_until_cancelled;
     use super::shutdown_tasks;
     use super::spawn_supervised;
     use super::supervise_tasks;
+    use crate::config::CommandsConfig;
     use crate::config::Config;
+    use crate::config::ForwardingConfig;
     use crate::config::OperationsConfig;
+    use crate::config::ReverseForwardingConfig;
+    use crate::forwarding::ForwardingPolicy;
+    use crate::metrics::AgentMetrics;
 
     /// A commands-directory failure leaves an empty registry for agent startup.
     #[test]
@@ -1114,8 +3114,79 @@ mod tests {
         assert!(registry.manifest().commands.is_empty());
     }
 
+    /// A rejected reload leaves both the configuration and the registry untouched.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn invalid_reloads_are_rejected_atomically() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new().unwrap();
+        let config_path = root.path().join("agent.toml");
+        let write_config = |contents: &str| {
+            std::fs::write(&config_path, contents).unwrap();
+            std::fs::set_permissions(&config_path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        };
+        write_config("fingerprint-script = \"fingerprint\"\n");
+        let (config, config_dir) = crate::load_config(&config_path).await.unwrap();
+        let state = AgentState::load(config, &config_dir, None).await.unwrap();
+
+        write_config("fingerprint-script = \"fingerprint\"\n[terminal\n");
+        assert!(reload_config(&state, &config_path).await.is_err());
+        let not_a_directory = root.path().join("commands");
+        std::fs::write(&not_a_directory, b"not a directory").unwrap();
+        write_config(&format!(
+            "fingerprint-script = \"fingerprint\"\n\
+             [terminal]\nenabled = true\n\
+             [commands]\nenabled = true\ndirectory = {:?}\n",
+            not_a_directory.display().to_string(),
+        ));
+        assert!(reload_config(&state, &config_path).await.is_err());
+        assert!(state.config().terminal.is_none());
+        assert!(state.command_registry().is_none());
+
+        std::fs::remove_file(&not_a_directory).unwrap();
+        std::fs::create_dir(&not_a_directory).unwrap();
+        reload_config(&state, &config_path).await.unwrap();
+        assert!(crate::config::terminal_enabled(&state.config()));
+        assert!(state.command_registry().is_some());
+    }
+
+    /// Directory changes rebuild the registry once they have settled.
+    #[cfg(unix)]
+    #[test]
+    fn commands_directory_changes_are_debounced() {
+        let directory = TempDir::new().unwrap();
+        let config = Config::new(PathBuf::from("fingerprint")).with_commands(Some(
+            CommandsConfig::new()
+                .with_enabled(Some(true))
+                .with_directory(Some(directory.path().to_path_buf())),
+        ));
+        let mut watch = CommandsDirectoryWatch::new(&config);
+        assert!(watch.check(&config).unwrap().is_none());
+
+        std::fs::write(directory.path().join("reboot.toml"), b"").unwrap();
+        assert!(watch.check(&config).unwrap().is_none());
+        assert!(watch.check(&config).unwrap().is_some());
+        assert!(watch.check(&config).unwrap().is_none());
+
+        std::fs::remove_file(directory.path().join("reboot.toml")).unwrap();
+        std::fs::write(directory.path().join("notes.txt"), b"").unwrap();
+        assert!(watch.check(&config).unwrap().is_none());
+        assert!(watch.check(&config).unwrap().is_some());
+
+        let disabled = Config::new(PathBuf::from("fingerprint"));
+        assert!(watch.check(&disabled).unwrap().is_none());
+        std::fs::write(directory.path().join("reboot.toml"), b"").unwrap();
+        assert!(watch.check(&disabled).unwrap().is_none());
+        assert!(watch.check(&disabled).unwrap().is_none());
+    }
+
     struct EndpointTestAgent {
         hub_ref: ConnectionRef,
+        /// Reverse forwarding channels opened by the agent.
+        reverse_requests: mpsc::UnboundedReceiver<nexigon_multiplex::ChannelRequest>,
+        operation_wake: Arc<Notify>,
+        metrics: Arc<AgentMetrics>,
         cancellation: CancellationToken,
         agent: JoinHandle<anyhow::Result<()>>,
         hub: JoinHandle<()>,
@@ -1123,6 +3194,15 @@ mod tests {
 
     impl EndpointTestAgent {
         async fn start() -> Self {
+            Self::start_with_config(
+                Config::new(PathBuf::from("unused-fingerprint")).with_forwarding(Some(
+                    ForwardingConfig::new().with_allow(Some(vec!["0-65535".to_owned()])),
+                )),
+            )
+            .await
+        }
+
+        async fn start_with_config(config: Config) -> Self {
             let (hub_transport, agent_transport) = InMemory::<Bytes, Bytes>::new_buffered(64);
             let hub_limits = ConnectionLimits {
                 max_pending_channel_requests: 24,
@@ -1132,10 +3212,17 @@ mod tests {
             let agent_connection =
                 Connection::with_limits(agent_transport, crate::hub_connection_limits());
             let hub_ref = hub_connection.make_ref();
+            let agent_hub_ref = agent_connection.make_ref();
+            let (reverse_tx, reverse_requests) = mpsc::unbounded_channel();
             let hub = tokio::spawn(async move {
                 while let Some(event) = hub_connection.next().await {
                     match event {
                         Ok(ConnectionEvent::Connected) => {}
+                        Ok(ConnectionEvent::RequestChannel(request))
+                            if request.endpoint().starts_with(b"reverse/") =>
+                        {
+                            let _ = reverse_tx.send(request);
+                        }
                         Ok(ConnectionEvent::RequestChannel(request)) => {
                             request.reject(b"not supported by test hub");
                         }
@@ -1144,24 +3231,44 @@ mod tests {
                 }
             });
 
+            let operation_wake = Arc::new(Notify::new());
             let cancellation = CancellationToken::new();
             let agent_cancellation = cancellation.clone();
+            let agent_operation_wake = operation_wake.clone();
+            let metrics = Arc::new(AgentMetrics::default());
+            let agent_metrics = metrics.clone();
             let agent = tokio::spawn(async move {
-                let config = Arc::new(Config::new(PathBuf::from("unused-fingerprint")));
-                let limits = EndpointLimits::new(command_slots());
+                let forwarding_policy = Arc::new(ForwardingPolicy::from_config(&config).unwrap());
+                let config = Arc::new(config);
+                let endpoints = Endpoints {
+                    limits: EndpointLimits::new(command_slots()),
+                    hub: agent_hub_ref,
+                    operation_wake: Some(agent_operation_wake),
+                    metrics: agent_metrics,
+                    #[cfg(target_os = "linux")]
+                    terminal_recordings: Arc::new(TerminalRecordings::new(PathBuf::from(
+                        "unused-data",
+                    ))),
+                    #[cfg(target_os = "linux")]
+                    terminal_sessions: Arc::new(TerminalSessions::new(Arc::new(
+                        TerminalRecordings::new(PathBuf::from("unused-data")),
+                    ))),
+                };
                 let (task_tx, mut task_rx) = mpsc::channel(SUPERVISOR_QUEUE_CAPACITY);
                 let mut tasks = JoinSet::new();
                 spawn_supervised(
                     &mut tasks,
+                    &Arc::default(),
                     SupervisedTask::new(TaskKind::Connection, {
                         let cancellation = agent_cancellation.clone();
                         let task_tx = task_tx.clone();
                         async move {
                             run_connection_event_loop(
                                 agent_connection,
-                                config,
-                                None,
-                                limits,
+                                watch::channel(config).1,
+                                watch::channel(None).1,
+                                watch::channel(forwarding_policy).1,
+                                endpoints,
                                 task_tx,
                                 cancellation,
                             )
@@ -1169,7 +3276,14 @@ mod tests {
                         }
                     }),
                 );
-                let result = supervise_tasks(&mut tasks, &mut task_rx, &agent_cancellation).await;
+                let result = supervise_tasks(
+                    &mut tasks,
+                    &Arc::default(),
+                    &mut task_rx,
+                    None,
+                    &agent_cancellation,
+                )
+                .await;
                 agent_cancellation.cancel();
                 drop(task_tx);
                 task_rx.close();
@@ -1180,6 +3294,9 @@ mod tests {
 
             Self {
                 hub_ref,
+                reverse_requests,
+                operation_wake,
+                metrics,
                 cancellation,
                 agent,
                 hub,
@@ -1210,6 +3327,24 @@ mod tests {
         )));
     }
 
+    #[test]
+    fn reconnect_backoff_grows_with_jitter_and_resets() {
+        let mut backoff = ReconnectBackoff::new();
+        let mut base = RECONNECT_INITIAL_DELAY;
+        for _ in 0..16 {
+            let delay = backoff.next_delay();
+            assert!(
+                delay >= base / 2 && delay <= base,
+                "{delay:?} is outside the jitter window of {base:?}"
+            );
+            base = (base * 2).min(RECONNECT_MAX_DELAY);
+        }
+        assert_eq!(base, RECONNECT_MAX_DELAY);
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= RECONNECT_INITIAL_DELAY);
+    }
+
     #[tokio::test]
     async fn command_execution_slots_are_strictly_bounded() {
         let slots = command_slots();
@@ -1320,6 +3455,31 @@ mod tests {
         assert!(follow_up.is_err());
         assert!(!agent.agent.is_finished());
 
+        let metrics = agent.metrics.render(None, &[]);
+        for line in [
+            "nexigon_agent_channel_requests_total{endpoint=\"invalid\",result=\"rejected\",reason=\"invalid endpoint\"} 1",
+            "nexigon_agent_channel_requests_total{endpoint=\"forward-tcp\",result=\"rejected\",reason=\"invalid TCP forwarding endpoint\"} 1",
+            "nexigon_agent_channel_requests_total{endpoint=\"unknown\",result=\"rejected\",reason=\"unknown endpoint\"} 1",
+        ] {
+            assert!(metrics.lines().any(|l| l == line), "missing `{line}`");
+        }
+
+        agent.stop().await;
+    }
+
+    #[tokio::test]
+    async fn wake_requests_notify_the_operation_loop() {
+        let mut agent = EndpointTestAgent::start().await;
+        let wake = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent.hub_ref.open(b"operations/wake"),
+        )
+        .await
+        .expect("wake request timed out");
+        assert!(wake.is_ok());
+        tokio::time::timeout(Duration::from_secs(2), agent.operation_wake.notified())
+            .await
+            .expect("operation loop was not woken");
         agent.stop().await;
     }
 
@@ -1345,6 +3505,421 @@ mod tests {
         agent.stop().await;
     }
 
+    #[tokio::test]
+    async fn forwarding_to_ports_that_are_not_allowed_is_rejected() {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("bind forwarding target");
+        let port = listener.local_addr().unwrap().port();
+        let config = Config::new(PathBuf::from("unused-fingerprint")).with_forwarding(Some(
+            ForwardingConfig::new()
+                .with_allow(Some(vec!["1-65535".to_owned()]))
+                .with_deny(Some(vec![port.to_string()])),
+        ));
+        let mut agent = EndpointTestAgent::start_with_config(config).await;
+        let endpoint = format!("forward/tcp/{port}");
+        let denied = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent.hub_ref.open(endpoint.as_bytes()),
+        )
+        .await
+        .expect("denied forwarding request timed out");
+        assert!(denied.is_err());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), listener.accept())
+                .await
+                .is_err(),
+            "denied forwarding connected to the target"
+        );
+
+        let metrics = agent.metrics.render(None, &[]);
+        let line = "nexigon_agent_channel_requests_total{endpoint=\"forward-tcp\",result=\"rejected\",reason=\"TCP forwarding to this port is not allowed\"} 1";
+        assert!(metrics.lines().any(|l| l == line), "missing `{line}`");
+        agent.stop().await;
+    }
+
+    #[tokio::test]
+    async fn forwarding_to_hosts_requires_a_host_rule() {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("bind forwarding target");
+        let port = listener.local_addr().unwrap().port();
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap().0 });
+        let config = Config::new(PathBuf::from("unused-fingerprint")).with_forwarding(Some(
+            ForwardingConfig::new().with_hosts(Some(vec![format!("127.0.0.0/8:{port}")])),
+        ));
+        let mut agent = EndpointTestAgent::start_with_config(config).await;
+
+        for (endpoint, expected) in [
+            (
+                format!("forward/tcp/{port}"),
+                ForwardingRejection::NotAllowed,
+            ),
+            (
+                format!("forward/tcp/10.0.0.1/{port}"),
+                ForwardingRejection::NotAllowed,
+            ),
+            (
+                format!("forward/tcp//{port}"),
+                ForwardingRejection::Unavailable,
+            ),
+        ] {
+            let rejected = tokio::time::timeout(
+                Duration::from_secs(2),
+                agent.hub_ref.open(endpoint.as_bytes()),
+            )
+            .await
+            .expect("forwarding request timed out");
+            let Err(OpenError::Rejected(rejection)) = rejected else {
+                panic!("{endpoint} must be rejected");
+            };
+            assert_eq!(
+                ForwardingRejection::classify(rejection.reason()),
+                expected,
+                "{endpoint}"
+            );
+        }
+
+        let endpoint = format!("forward/tcp/127.0.0.1/{port}");
+        let mut channel = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent.hub_ref.open(endpoint.as_bytes()),
+        )
+        .await
+        .expect("forwarding request timed out")
+        .expect("forwarding request rejected");
+        let mut target = tokio::time::timeout(Duration::from_secs(2), accept)
+            .await
+            .expect("forwarding target was not connected")
+            .expect("forwarding target accept task panicked");
+        channel.write_all(b"probe").await.unwrap();
+        channel.flush().await.unwrap();
+        let mut probe = [0u8; 5];
+        tokio::time::timeout(Duration::from_secs(2), target.read_exact(&mut probe))
+            .await
+            .expect("forwarded data was not delivered")
+            .expect("reading forwarded data failed");
+        assert_eq!(&probe, b"probe");
+
+        let metrics = agent.metrics.render(None, &[]);
+        for line in [
+            "nexigon_agent_channel_requests_total{endpoint=\"forward-tcp\",result=\"rejected\",reason=\"TCP forwarding to this port is not allowed\"} 1",
+            "nexigon_agent_channel_requests_total{endpoint=\"forward-tcp\",result=\"rejected\",reason=\"TCP forwarding to this host is not allowed\"} 1",
+            "nexigon_agent_channel_requests_total{endpoint=\"forward-tcp\",result=\"rejected\",reason=\"invalid TCP forwarding endpoint\"} 1",
+        ] {
+            assert!(metrics.lines().any(|l| l == line), "missing `{line}`");
+        }
+        drop(channel);
+        agent.stop().await;
+    }
+
+    #[tokio::test]
+    async fn udp_forwarding_relays_datagrams_with_their_boundaries() {
+        let target = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("bind forwarding target");
+        let port = target.local_addr().unwrap().port();
+        let echo = tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            loop {
+                let (n, peer) = target.recv_from(&mut buf).await.unwrap();
+                target.send_to(&buf[..n], peer).await.unwrap();
+            }
+        });
+        let mut agent = EndpointTestAgent::start().await;
+        let endpoint = format!("forward/udp/{port}");
+        let mut channel = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent.hub_ref.open(endpoint.as_bytes()),
+        )
+        .await
+        .expect("forwarding request timed out")
+        .expect("forwarding request rejected");
+
+        for datagram in [&b"first"[..], b"second datagram"] {
+            write_datagram(&mut channel, datagram).await.unwrap();
+            let echoed = tokio::time::timeout(Duration::from_secs(2), read_datagram(&mut channel))
+                .await
+                .expect("forwarded datagram was not echoed")
+                .unwrap();
+            assert_eq!(echoed.as_deref(), Some(datagram));
+        }
+
+        let invalid = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent.hub_ref.open(b"forward/udp/dns"),
+        )
+        .await
+        .expect("invalid forwarding request timed out");
+        assert!(invalid.is_err());
+
+        echo.abort();
+        drop(channel);
+        agent.stop().await;
+    }
+
+    #[tokio::test]
+    async fn reverse_forwarding_relays_device_connections_to_the_hub() {
+        let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|listener| listener.local_addr())
+            .expect("find free port")
+            .port();
+        let config = Config::new(PathBuf::from("unused-fingerprint")).with_forwarding(Some(
+            ForwardingConfig::new().with_reverse(Some(
+                ReverseForwardingConfig::new()
+                    .with_enabled(Some(true))
+                    .with_allow(Some(vec![port.to_string()])),
+            )),
+        ));
+        let mut agent = EndpointTestAgent::start_with_config(config).await;
+
+        let rejected = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent
+                .hub_ref
+                .open(format!("reverse/tcp/{}", port.wrapping_add(1)).as_bytes()),
+        )
+        .await
+        .expect("reverse forwarding request timed out");
+        assert!(rejected.is_err());
+
+        let endpoint = format!("reverse/tcp/{port}");
+        let control = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent.hub_ref.open(endpoint.as_bytes()),
+        )
+        .await
+        .expect("reverse forwarding request timed out")
+        .expect("reverse forwarding request rejected");
+
+        let mut socket = tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .expect("connect to reverse forwarding port");
+        socket.write_all(b"probe").await.unwrap();
+        let request = tokio::time::timeout(Duration::from_secs(2), agent.reverse_requests.recv())
+            .await
+            .expect("agent did not open a reverse forwarding channel")
+            .unwrap();
+        assert_eq!(request.endpoint(), endpoint.as_bytes());
+        let (channel_tx, channel_rx) = oneshot::channel();
+        request.accept(move |channel| {
+            let _ = channel_tx.send(channel);
+        });
+        let mut channel = channel_rx.await.unwrap();
+        let mut probe = [0u8; 5];
+        tokio::time::timeout(Duration::from_secs(2), channel.read_exact(&mut probe))
+            .await
+            .expect("forwarded data was not delivered")
+            .expect("reading forwarded data failed");
+        assert_eq!(&probe, b"probe");
+        channel.write_all(b"reply").await.unwrap();
+        channel.flush().await.unwrap();
+        let mut reply = [0u8; 5];
+        tokio::time::timeout(Duration::from_secs(2), socket.read_exact(&mut reply))
+            .await
+            .expect("forwarded reply was not delivered")
+            .expect("reading forwarded reply failed");
+        assert_eq!(&reply, b"reply");
+
+        // Closing the control channel stops the listener.
+        drop(control);
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+                .await
+                .is_ok()
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("reverse forwarding listener was not closed");
+
+        drop(channel);
+        agent.stop().await;
+    }
+
+    /// Accept `request` and relay its channel to `stream`.
+    async fn bridge(
+        request: nexigon_multiplex::ChannelRequest,
+        mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    ) {
+        let (channel_tx, channel_rx) = oneshot::channel();
+        request.accept(move |channel| {
+            let _ = channel_tx.send(channel);
+        });
+        if let Ok(mut channel) = channel_rx.await {
+            let _ = tokio::io::copy_bidirectional(&mut channel, &mut stream).await;
+        }
+    }
+
+    /// Drive a reverse forward from a client through a hub that routes its channels as
+    /// documented at [`reverse_tcp_endpoint`].
+    #[tokio::test]
+    async fn reverse_forwards_reach_client_services_through_the_hub() {
+        let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|listener| listener.local_addr())
+            .expect("find free port")
+            .port();
+        let config = Config::new(PathBuf::from("unused-fingerprint")).with_forwarding(Some(
+            ForwardingConfig::new().with_reverse(Some(
+                ReverseForwardingConfig::new()
+                    .with_enabled(Some(true))
+                    .with_allow(Some(vec![port.to_string()])),
+            )),
+        ));
+        let mut agent = EndpointTestAgent::start_with_config(config).await;
+
+        // Service of the client that is exposed on the device.
+        let service = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let service_addr = service.local_addr().unwrap();
+        let service = tokio::spawn(async move {
+            let (mut socket, _) = service.accept().await.unwrap();
+            let mut request = [0u8; 4];
+            socket.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"ping");
+            socket.write_all(b"pong").await.unwrap();
+        });
+
+        // The client relays the reverse channels routed to it to its service.
+        let (client_transport, hub_transport) = InMemory::<Bytes, Bytes>::new_buffered(64);
+        let mut client_connection = Connection::new(client_transport);
+        let mut client_ref = client_connection.make_ref();
+        let client = tokio::spawn(async move {
+            while let Some(Ok(event)) = client_connection.next().await {
+                if let ConnectionEvent::RequestChannel(request) = event {
+                    assert_eq!(parse_reverse_tcp_endpoint(request.endpoint()), Some(port));
+                    let socket = tokio::net::TcpStream::connect(service_addr).await.unwrap();
+                    tokio::spawn(bridge(request, socket));
+                }
+            }
+        });
+
+        // The hub opens the channels the client requests through the device proxy.
+        let mut hub_connection = Connection::new(hub_transport);
+        let mut hub_client_ref = hub_connection.make_ref();
+        let mut hub_device_ref = agent.hub_ref.clone();
+        let hub = tokio::spawn(async move {
+            while let Some(Ok(event)) = hub_connection.next().await {
+                let ConnectionEvent::RequestChannel(request) = event else {
+                    continue;
+                };
+                let Some(endpoint) = request
+                    .endpoint()
+                    .strip_prefix(b"device/test/proxy/".as_slice())
+                    .map(<[u8]>::to_vec)
+                else {
+                    request.reject(b"unknown device");
+                    continue;
+                };
+                match hub_device_ref.open(&endpoint).await {
+                    Ok(channel) => {
+                        tokio::spawn(bridge(request, channel));
+                    }
+                    Err(_) => request.reject(b"rejected by the device"),
+                }
+            }
+        });
+
+        let control_endpoint = format!("device/test/proxy/{}", reverse_tcp_endpoint(port));
+        let control = tokio::time::timeout(
+            Duration::from_secs(2),
+            client_ref.open(control_endpoint.as_bytes()),
+        )
+        .await
+        .expect("reverse forwarding request timed out")
+        .expect("reverse forwarding request rejected");
+
+        // The agent listens once the control channel is open and opens a channel to
+        // the hub for every connection, which the hub routes to the client by its port.
+        let mut socket = tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .expect("connect to reverse forwarding port");
+        socket.write_all(b"ping").await.unwrap();
+        let request = tokio::time::timeout(Duration::from_secs(2), agent.reverse_requests.recv())
+            .await
+            .expect("agent did not open a reverse forwarding channel")
+            .unwrap();
+        assert_eq!(parse_reverse_tcp_endpoint(request.endpoint()), Some(port));
+        let channel = tokio::time::timeout(
+            Duration::from_secs(2),
+            hub_client_ref.open(request.endpoint()),
+        )
+        .await
+        .expect("routing the reverse forwarding channel timed out")
+        .expect("client rejected the reverse forwarding channel");
+        tokio::spawn(bridge(request, channel));
+
+        let mut reply = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(2), socket.read_exact(&mut reply))
+            .await
+            .expect("service reply was not delivered")
+            .expect("reading service reply failed");
+        assert_eq!(&reply, b"pong");
+        tokio::time::timeout(Duration::from_secs(2), service)
+            .await
+            .expect("service did not finish")
+            .expect("service failed");
+
+        drop(control);
+        client.abort();
+        hub.abort();
+        agent.stop().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_forwarding_only_reaches_declared_sockets() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("control.sock");
+        let listener = tokio::net::UnixListener::bind(&path).expect("bind forwarding target");
+        let accept = tokio::spawn(async move { listener.accept().await.unwrap().0 });
+        let sockets = [("control".to_owned(), path.clone())].into_iter().collect();
+        let config = Config::new(PathBuf::from("unused-fingerprint"))
+            .with_forwarding(Some(ForwardingConfig::new().with_sockets(Some(sockets))));
+        let mut agent = EndpointTestAgent::start_with_config(config).await;
+
+        for endpoint in [
+            "forward/unix/other".to_owned(),
+            format!("forward/unix/{}", path.display()),
+        ] {
+            let rejected = tokio::time::timeout(
+                Duration::from_secs(2),
+                agent.hub_ref.open(endpoint.as_bytes()),
+            )
+            .await
+            .expect("forwarding request timed out");
+            assert!(rejected.is_err(), "{endpoint} must be rejected");
+        }
+
+        let mut channel = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent.hub_ref.open(b"forward/unix/control"),
+        )
+        .await
+        .expect("forwarding request timed out")
+        .expect("forwarding request rejected");
+        let mut target = tokio::time::timeout(Duration::from_secs(2), accept)
+            .await
+            .expect("forwarding target was not connected")
+            .expect("forwarding target accept task panicked");
+        channel.write_all(b"probe").await.unwrap();
+        channel.flush().await.unwrap();
+        let mut probe = [0u8; 5];
+        tokio::time::timeout(Duration::from_secs(2), target.read_exact(&mut probe))
+            .await
+            .expect("forwarded data was not delivered")
+            .expect("reading forwarded data failed");
+        assert_eq!(&probe, b"probe");
+
+        let metrics = agent.metrics.render(None, &[]);
+        let line = "nexigon_agent_channel_requests_total{endpoint=\"forward-unix\",result=\"rejected\",reason=\"Unix socket forwarding to this name is not allowed\"} 2";
+        assert!(metrics.lines().any(|l| l == line), "missing `{line}`");
+        drop(channel);
+        agent.stop().await;
+    }
+
     #[tokio::test]
     async fn shutdown_closes_an_active_forwarding_relay() {
         let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
@@ -1407,6 +3982,7 @@ mod tests {
             let ready_tx = ready_tx.clone();
             spawn_supervised(
                 &mut tasks,
+                &Arc::default(),
                 SupervisedTask::new(kind, async move {
                     active.fetch_add(1, Ordering::SeqCst);
                     let _guard = ActiveGuard(active);
@@ -1437,14 +4013,21 @@ mod tests {
         let mut tasks = JoinSet::new();
         spawn_supervised(
             &mut tasks,
+            &Arc::default(),
             SupervisedTask::new(TaskKind::Connection, async {
                 anyhow::bail!("sentinel connection failure")
             }),
         );
 
-        let error = supervise_tasks(&mut tasks, &mut task_rx, &cancellation)
-            .await
-            .expect_err("connection error was swallowed");
+        let error = supervise_tasks(
+            &mut tasks,
+            &Arc::default(),
+            &mut task_rx,
+            None,
+            &cancellation,
+        )
+        .await
+        .expect_err("connection error was swallowed");
         assert!(error.to_string().contains("sentinel connection failure"));
         assert!(!cancellation.is_cancelled());
     }
@@ -0,0 +1,207 @@
+//! Status of the running agent.
+//!
+//! The local API reports the status to on-device diagnostics tools, and the metrics
+//! listener exports the active tasks. Active agent tasks are counted by the task
+//! supervisor; everything else is sampled from the agent state when the status is
+//! requested.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+#[cfg(unix)]
+use nexigon_agent_api::types::status::AgentStatus;
+use nexigon_agent_api::types::status::AgentTaskCount;
+#[cfg(unix)]
+use nexigon_agent_api::types::status::HubStatus;
+#[cfg(unix)]
+use nexigon_agent_api::types::status::OperationLedgerEntry;
+#[cfg(unix)]
+use nexigon_agent_api::types::status::OperationLedgerState;
+#[cfg(unix)]
+use nexigon_ids::ids::DeviceId;
+#[cfg(unix)]
+use nexigon_multiplex::ConnectionRef;
+#[cfg(unix)]
+use tokio::sync::watch;
+
+#[cfg(unix)]
+use crate::config::operation_ledger::OperationExecutionEntry;
+#[cfg(unix)]
+use crate::handlers::CommandRegistry;
+#[cfg(unix)]
+use crate::operation_ledger::OperationLedger;
+
+/// Number of active agent tasks by kind.
+#[derive(Debug, Default)]
+pub(crate) struct ActiveTasks {
+    counts: Mutex<BTreeMap<&'static str, u32>>,
+}
+
+impl ActiveTasks {
+    /// Count a task of the given kind as active until the returned guard is dropped.
+    pub(crate) fn enter(self: &Arc<Self>, kind: &'static str) -> ActiveTask {
+        if let Ok(mut counts) = self.counts.lock() {
+            *counts.entry(kind).or_default() += 1;
+        }
+        ActiveTask {
+            tasks: self.clone(),
+            kind,
+        }
+    }
+
+    /// Number of active tasks of each kind with at least one active task.
+    pub(crate) fn counts(&self) -> Vec<AgentTaskCount> {
+        let Ok(counts) = self.counts.lock() else {
+            return Vec::new();
+        };
+        counts
+            .iter()
+            .map(|(kind, count)| AgentTaskCount {
+                kind: (*kind).to_owned(),
+                count: *count,
+            })
+            .collect()
+    }
+}
+
+/// Active task counted by [`ActiveTasks`].
+pub(crate) struct ActiveTask {
+    tasks: Arc<ActiveTasks>,
+    kind: &'static str,
+}
+
+impl Drop for ActiveTask {
+    fn drop(&mut self) {
+        let Ok(mut counts) = self.tasks.counts.lock() else {
+            return;
+        };
+        if let Some(count) = counts.get_mut(self.kind) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(self.kind);
+            }
+        }
+    }
+}
+
+/// Agent state the status is sampled from.
+#[cfg(unix)]
+#[derive(Clone)]
+pub(crate) struct StatusSource {
+    /// URL of the hub, if known.
+    pub(crate) hub_url: Option<String>,
+    /// ID of the device, once the agent has identified itself to the hub.
+    pub(crate) device_id: watch::Receiver<Option<DeviceId>>,
+    /// Command registry of the running configuration.
+    pub(crate) command_registry: watch::Receiver<Option<Arc<CommandRegistry>>>,
+    /// Operation ledger, if operation polling is enabled.
+    pub(crate) operation_ledger: Option<Arc<tokio::sync::Mutex<OperationLedger>>>,
+    /// Active agent tasks.
+    pub(crate) active_tasks: Arc<ActiveTasks>,
+}
+
+#[cfg(unix)]
+impl StatusSource {
+    /// Status source of an agent that has not loaded any state.
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        Self {
+            hub_url: None,
+            device_id: watch::channel(None).1,
+            command_registry: watch::channel(None).1,
+            operation_ledger: None,
+            active_tasks: Arc::default(),
+        }
+    }
+
+    /// Sample the current status, with `hub` as the current hub connection.
+    pub(crate) async fn status(&self, hub: Option<&ConnectionRef>) -> AgentStatus {
+        let hub = HubStatus {
+            connected: hub.is_some(),
+            url: self.hub_url.clone(),
+            round_trip_time_ms: hub
+                .and_then(ConnectionRef::estimate_round_trip_time)
+                .map(|rtt| u64::try_from(rtt.as_millis()).unwrap_or(u64::MAX)),
+            frames_sent: hub.map(ConnectionRef::estimate_frames_sent),
+            frames_received: hub.map(ConnectionRef::estimate_frames_received),
+        };
+        let operations = match &self.operation_ledger {
+            Some(ledger) => Some(ledger.lock().await.entries().map(ledger_entry).collect()),
+            None => None,
+        };
+        AgentStatus {
+            version: nexigon_version::NEXIGON_GIT_VERSION.to_owned(),
+            hub,
+            device_id: self.device_id.borrow().clone(),
+            tasks: self.active_tasks.counts(),
+            commands: self
+                .command_registry
+                .borrow()
+                .as_ref()
+                .map(|registry| u32::try_from(registry.len()).unwrap_or(u32::MAX)),
+            operations,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn ledger_entry(entry: &OperationExecutionEntry) -> OperationLedgerEntry {
+    let (device_operation_id, step_index, state) = match entry {
+        OperationExecutionEntry::InProgress(entry) => (
+            &entry.device_operation_id,
+            entry.step_index,
+            OperationLedgerState::InProgress,
+        ),
+        OperationExecutionEntry::Completed(entry) => (
+            &entry.device_operation_id,
+            entry.step_index,
+            OperationLedgerState::Completed,
+        ),
+        OperationExecutionEntry::TaskRunning(entry) => (
+            &entry.device_operation_id,
+            entry.step_index,
+            OperationLedgerState::TaskRunning,
+        ),
+    };
+    OperationLedgerEntry {
+        device_operation_id: device_operation_id.clone(),
+        step_index,
+        state,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ActiveTasks;
+
+    #[test]
+    fn active_tasks_are_counted_until_they_finish() {
+        let tasks = Arc::new(ActiveTasks::default());
+        let first = tasks.enter("terminal");
+        let second = tasks.enter("terminal");
+        let handler = tasks.enter("handler");
+        let counts = tasks
+            .counts()
+            .into_iter()
+            .map(|count| (count.kind, count.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            [("handler".to_owned(), 1), ("terminal".to_owned(), 2)]
+        );
+
+        drop(first);
+        drop(handler);
+        let counts = tasks
+            .counts()
+            .into_iter()
+            .map(|count| (count.kind, count.count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [("terminal".to_owned(), 1)]);
+        drop(second);
+        assert!(tasks.counts().is_empty());
+    }
+}
@@ -107,7 +107,8 @@ fn build_agent_config(config: &Config) -> AgentConfig {
         .with_terminal(Some(
             AgentTerminalConfig::new()
                 .with_enabled(Some(terminal_enabled))
-                .with_users(terminal_users(config)),
+                .with_users(terminal_users(config))
+                .with_profiles(terminal_profiles(config)),
         ))
         .with_commands(Some(
             AgentCommandsConfig::new().with_enabled(Some(commands_enabled)),
@@ -132,6 +133,21 @@ fn terminal_users(config: &Config) -> Option<Vec<String>> {
     Some(users)
 }
 
+/// Collect the names of the terminal profiles from the config.
+fn terminal_profiles(config: &Config) -> Option<Vec<String>> {
+    let terminal = config.terminal.as_ref()?;
+    if !crate::config::terminal_enabled(config) {
+        return None;
+    }
+    let profiles = terminal.profiles.as_ref()?;
+    Some(
+        profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect(),
+    )
+}
+
 /// Read Yocto build information from `/etc/buildinfo` (if available).
 fn get_yocto_info() -> Option<YoctoSystemInfo> {
     std::fs::read_to_string("/etc/buildinfo")
@@ -0,0 +1,204 @@
+//! systemd service notifications.
+//!
+//! When the agent runs as a `Type=notify` service, systemd passes the notification
+//! socket in `NOTIFY_SOCKET` and, with `WatchdogSec=` set, the watchdog timeout in
+//! `WATCHDOG_USEC`. The agent reports readiness once it has identified itself to the
+//! hub, keeps the service status line up to date with the connection state, and pings
+//! the watchdog while it is healthy. Without `NOTIFY_SOCKET`, all notifications are
+//! silently dropped. Without `WATCHDOG_USEC`, the watchdog is disabled while readiness
+//! and status notifications are still sent.
+
+use std::time::Duration;
+
+#[cfg(unix)]
+use tracing::debug;
+
+/// Sends notifications to the service manager.
+#[derive(Debug, Default)]
+pub(crate) struct SystemdNotifier {
+    /// Notification socket, if the agent runs under systemd.
+    #[cfg(unix)]
+    socket: Option<NotifySocket>,
+    /// Interval at which the watchdog must be pinged, if it is enabled.
+    watchdog_interval: Option<Duration>,
+}
+
+impl SystemdNotifier {
+    /// Notifier for the service manager described by the environment of the process.
+    pub(crate) fn from_environment() -> Self {
+        let var = |name: &str| std::env::var(name).ok();
+        Self::new(
+            var("NOTIFY_SOCKET").as_deref(),
+            var("WATCHDOG_USEC").as_deref(),
+            var("WATCHDOG_PID").as_deref(),
+        )
+    }
+
+    fn new(
+        notify_socket: Option<&str>,
+        watchdog_usec: Option<&str>,
+        watchdog_pid: Option<&str>,
+    ) -> Self {
+        #[cfg(unix)]
+        let socket = notify_socket.and_then(NotifySocket::open);
+        #[cfg(unix)]
+        let connected = socket.is_some();
+        #[cfg(not(unix))]
+        let connected = {
+            let _ = notify_socket;
+            false
+        };
+        // The watchdog is meant for another process if its PID is set and not ours.
+        let watchdog_process = watchdog_pid.is_none_or(|pid| {
+            pid.parse::<u32>()
+                .is_ok_and(|pid| pid == std::process::id())
+        });
+        // Ping at half the timeout, as recommended by `sd_watchdog_enabled(3)`.
+        let watchdog_interval = watchdog_usec
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && watchdog_process && connected)
+            .map(|usec| Duration::from_micros(usec) / 2);
+        Self {
+            #[cfg(unix)]
+            socket,
+            watchdog_interval,
+        }
+    }
+
+    /// Interval at which [`SystemdNotifier::watchdog`] must be called, if enabled.
+    pub(crate) fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    /// Report that the agent is ready, along with its status.
+    pub(crate) fn ready(&self, status: &str) {
+        self.notify(&format!("READY=1\nSTATUS={status}"));
+    }
+
+    /// Update the status line of the service.
+    pub(crate) fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={status}"));
+    }
+
+    /// Report that the agent is shutting down.
+    pub(crate) fn stopping(&self) {
+        self.notify("STOPPING=1\nSTATUS=shutting down");
+    }
+
+    /// Ping the watchdog.
+    pub(crate) fn watchdog(&self) {
+        self.notify("WATCHDOG=1");
+    }
+
+    fn notify(&self, message: &str) {
+        #[cfg(unix)]
+        if let Some(socket) = &self.socket
+            && let Err(error) = socket.send(message)
+        {
+            debug!(?error, "cannot send service manager notification");
+        }
+        #[cfg(not(unix))]
+        let _ = message;
+    }
+}
+
+/// Datagram socket connected to the notification socket of the service manager.
+#[cfg(unix)]
+#[derive(Debug)]
+struct NotifySocket {
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+#[cfg(unix)]
+impl NotifySocket {
+    fn open(path: &str) -> Option<Self> {
+        let socket = match std::os::unix::net::UnixDatagram::unbound() {
+            Ok(socket) => socket,
+            Err(error) => {
+                debug!(?error, "cannot create service manager notification socket");
+                return None;
+            }
+        };
+        let connected = match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+
+                std::os::unix::net::SocketAddr::from_abstract_name(name)
+                    .and_then(|address| socket.connect_addr(&address))
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => Err(std::io::ErrorKind::Unsupported.into()),
+            None if path.starts_with('/') => socket.connect(path),
+            None => Err(std::io::ErrorKind::Unsupported.into()),
+        };
+        if let Err(error) = connected {
+            debug!(
+                ?error,
+                path, "cannot connect to service manager notification socket"
+            );
+            return None;
+        }
+        Some(Self { socket })
+    }
+
+    fn send(&self, message: &str) -> std::io::Result<()> {
+        self.socket.send(message.as_bytes()).map(|_| ())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
+
+    use super::SystemdNotifier;
+
+    #[test]
+    fn notifications_are_sent_to_the_notify_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let manager = UnixDatagram::bind(&path).unwrap();
+        let notifier = SystemdNotifier::new(path.to_str(), Some("30000000"), None);
+        assert_eq!(notifier.watchdog_interval(), Some(Duration::from_secs(15)));
+
+        let mut buf = [0; 256];
+        notifier.ready("connected");
+        let n = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=connected");
+        notifier.watchdog();
+        let n = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"WATCHDOG=1");
+        notifier.stopping();
+        let n = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1\nSTATUS=shutting down");
+    }
+
+    #[test]
+    fn watchdog_is_disabled_without_watchdog_usec() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let manager = UnixDatagram::bind(&path).unwrap();
+        let notifier = SystemdNotifier::new(path.to_str(), None, None);
+        assert_eq!(notifier.watchdog_interval(), None);
+        let notifier = SystemdNotifier::new(path.to_str(), Some("0"), None);
+        assert_eq!(notifier.watchdog_interval(), None);
+
+        let mut buf = [0; 256];
+        notifier.ready("connected");
+        let n = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=connected");
+    }
+
+    #[test]
+    fn watchdog_of_other_processes_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let _manager = UnixDatagram::bind(&path).unwrap();
+        let other = (std::process::id() + 1).to_string();
+        let notifier = SystemdNotifier::new(path.to_str(), Some("30000000"), Some(&other));
+        assert_eq!(notifier.watchdog_interval(), None);
+        let notifier = SystemdNotifier::new(None, Some("30000000"), None);
+        assert_eq!(notifier.watchdog_interval(), None);
+    }
+}
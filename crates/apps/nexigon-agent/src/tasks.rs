@@ -0,0 +1,642 @@
+//! Device task execution.
+//!
+//! Tasks execute `DeviceTask` operation steps. Like commands, they are defined by TOML
+//! files in a trusted directory and map a name to an external handler. Unlike commands,
+//! a task may run for as long as its step deadline allows: the handler reports progress
+//! and durable checkpoints on stdout, and an interrupted task is started again with its
+//! latest checkpoint instead of being failed.
+//!
+//! Tasks without a definition can also be served by local workers, which subscribe to
+//! them through the local API and receive the claimed work items.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Context;
+use nexigon_agent_protocol::MAX_COMMAND_OUTPUT_LINE_LEN;
+use nexigon_api::types::devices::DeviceOperationStepReport;
+use nexigon_api::types::devices::DeviceOperationStepReportStatus;
+use nexigon_api::types::devices::DeviceOperationWorkItem;
+use nexigon_api::types::devices::DeviceOperationWorkStep;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+use crate::config::TaskDefinition;
+use crate::config::tasks::TaskInvocation;
+use crate::config::tasks::TaskStdoutLine;
+use crate::handlers;
+use crate::handlers::CommandOutputBudget;
+
+/// Work items that can be queued for a local worker before it is considered busy.
+const TASK_WORKER_QUEUE: usize = 8;
+/// Reports of a local worker that can be queued before the worker is slowed down.
+const TASK_REPORT_QUEUE: usize = 16;
+
+/// Registry of loaded task definitions and subscribed local workers.
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: HashMap<String, LoadedTask>,
+    workers: Mutex<Vec<TaskWorkerEntry>>,
+    next_worker_id: AtomicU64,
+}
+
+struct TaskWorkerEntry {
+    id: u64,
+    tasks: HashSet<String>,
+    assignments: mpsc::Sender<TaskAssignment>,
+}
+
+struct LoadedTask {
+    definition: TaskDefinition,
+    executable: PathBuf,
+}
+
+impl TaskRegistry {
+    /// Load valid external task definitions from TOML files in the given directory.
+    ///
+    /// Definitions are subject to the same trust checks as command definitions. Invalid
+    /// individual definitions are logged and skipped.
+    #[tracing::instrument(level = "debug", skip_all, fields(directory = %directory.display()))]
+    pub fn load_external(directory: &Path) -> anyhow::Result<Self> {
+        let mut tasks = HashMap::new();
+        let Some((resolved_directory, names)) = handlers::open_command_directory(directory)? else {
+            info!(
+                ?directory,
+                "tasks directory does not exist, no tasks loaded"
+            );
+            return Ok(Self {
+                tasks,
+                ..Self::default()
+            });
+        };
+        let mut loaded_from = HashMap::new();
+
+        for name in names {
+            let path = directory.join(&name);
+            let task = match load_external_task(&resolved_directory, &name, &path) {
+                Ok(task) => task,
+                Err(error) => {
+                    warn!(?path, error = ?error, "skipping invalid task definition");
+                    continue;
+                }
+            };
+            let task_name = task.definition.task.name.clone();
+            if let Some(previous) = loaded_from.get(&task_name) {
+                warn!(
+                    name = %task_name,
+                    ?path,
+                    previous = ?previous,
+                    "skipping duplicate task definition"
+                );
+                continue;
+            }
+            info!(name = %task_name, ?path, "loaded task");
+            loaded_from.insert(task_name.clone(), path);
+            tasks.insert(task_name, task);
+        }
+
+        info!(count = tasks.len(), "loaded external tasks");
+        Ok(Self {
+            tasks,
+            ..Self::default()
+        })
+    }
+
+    /// Get a task by name.
+    pub fn get(&self, name: &str) -> Option<&TaskDefinition> {
+        self.tasks.get(name).map(|task| &task.definition)
+    }
+
+    /// Subscribe a local worker to the given tasks.
+    ///
+    /// Tasks that have a definition are always executed by their handler and cannot be
+    /// subscribed to.
+    pub(crate) fn subscribe(
+        self: &Arc<Self>,
+        tasks: Vec<String>,
+    ) -> anyhow::Result<TaskWorkerSubscription> {
+        anyhow::ensure!(!tasks.is_empty(), "no tasks to subscribe to");
+        for name in &tasks {
+            handlers::validate_command_name(name)
+                .with_context(|| format!("invalid task name {name:?}"))?;
+            anyhow::ensure!(
+                !self.tasks.contains_key(name),
+                "task {name:?} is provided by a task definition"
+            );
+        }
+        let id = self.next_worker_id.fetch_add(1, Ordering::Relaxed);
+        let (assignments, assignments_rx) = mpsc::channel(TASK_WORKER_QUEUE);
+        self.workers
+            .lock()
+            .map_err(|_| anyhow::anyhow!("task worker mutex poisoned"))?
+            .push(TaskWorkerEntry {
+                id,
+                tasks: tasks.into_iter().collect(),
+                assignments,
+            });
+        Ok(TaskWorkerSubscription {
+            registry: self.clone(),
+            id,
+            assignments: assignments_rx,
+        })
+    }
+
+    /// Assign a work item to a local worker subscribed to `name`.
+    ///
+    /// Returns the receiver of the worker's reports, or `None` if no subscribed worker
+    /// can take the item.
+    fn assign(
+        &self,
+        name: &str,
+        item: DeviceOperationWorkItem,
+    ) -> Option<mpsc::Receiver<DeviceOperationStepReport>> {
+        let workers = self.workers.lock().ok()?;
+        let (reports, reports_rx) = mpsc::channel(TASK_REPORT_QUEUE);
+        let mut assignment = TaskAssignment { item, reports };
+        for worker in workers.iter().filter(|worker| worker.tasks.contains(name)) {
+            match worker.assignments.try_send(assignment) {
+                Ok(()) => return Some(reports_rx),
+                Err(
+                    mpsc::error::TrySendError::Full(returned)
+                    | mpsc::error::TrySendError::Closed(returned),
+                ) => assignment = returned,
+            }
+        }
+        None
+    }
+}
+
+/// Work item assigned to a local worker.
+pub(crate) struct TaskAssignment {
+    /// Claimed work item, carrying the checkpoint to resume from.
+    pub item: DeviceOperationWorkItem,
+    /// Sender for the worker's reports on the item.
+    pub reports: mpsc::Sender<DeviceOperationStepReport>,
+}
+
+/// Subscription of a local worker, removed from the registry when dropped.
+pub(crate) struct TaskWorkerSubscription {
+    registry: Arc<TaskRegistry>,
+    id: u64,
+    /// Work items assigned to the worker.
+    pub assignments: mpsc::Receiver<TaskAssignment>,
+}
+
+impl Drop for TaskWorkerSubscription {
+    fn drop(&mut self) {
+        if let Ok(mut workers) = self.registry.workers.lock() {
+            workers.retain(|worker| worker.id != self.id);
+        }
+    }
+}
+
+/// Load and validate one external task definition.
+fn load_external_task(
+    resolved_tasks_directory: &Path,
+    definition_name: &OsString,
+    definition_path: &Path,
+) -> anyhow::Result<LoadedTask> {
+    let mut file = handlers::open_command_file(resolved_tasks_directory, definition_name)
+        .with_context(|| format!("failed to securely open {}", definition_path.display()))?;
+    let content = handlers::read_bounded_definition(&mut file)
+        .with_context(|| format!("failed to read {}", definition_path.display()))?;
+    let definition: TaskDefinition = toml::from_str(&content)
+        .with_context(|| format!("failed to parse {}", definition_path.display()))?;
+    handlers::validate_command_name(&definition.task.name)
+        .and_then(|()| handlers::validate_handler_parts(&definition.exec.handler))
+        .with_context(|| format!("invalid task definition {}", definition_path.display()))?;
+    let executable = handlers::validate_handler_executable(
+        &definition.exec.handler[0],
+        resolved_tasks_directory,
+    )
+    .with_context(|| format!("invalid task executable in {}", definition_path.display()))?;
+    Ok(LoadedTask {
+        definition,
+        executable,
+    })
+}
+
+/// Latest progress reported by a running task.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TaskProgress {
+    /// Progress data of the latest progress line.
+    pub data: Option<serde_json::Value>,
+    /// Latest checkpoint, kept across progress lines without one.
+    pub checkpoint: Option<serde_json::Value>,
+}
+
+/// Run the task step of a work item to completion and return its final step report.
+///
+/// The step is executed by the task's handler or assigned to a subscribed local worker.
+/// Progress is published through `progress` as it arrives; its initial checkpoint is
+/// the one to resume from. Returns `None` if the task was cancelled or its worker
+/// disconnected; the step can then be resumed from the latest checkpoint later.
+pub(crate) async fn run_task(
+    registry: &TaskRegistry,
+    item: DeviceOperationWorkItem,
+    timeout: Duration,
+    progress: &watch::Sender<TaskProgress>,
+    cancellation: &CancellationToken,
+) -> Option<DeviceOperationStepReport> {
+    let DeviceOperationWorkStep::DeviceTask(step) = &item.step else {
+        return Some(failed_report(
+            "work item is not a task step".to_owned(),
+            progress,
+        ));
+    };
+    let name = step.task.clone();
+    if let Some(task) = registry.tasks.get(&name) {
+        let input = step.input.clone();
+        let checkpoint = progress.borrow().checkpoint.clone();
+        return match execute_task(task, input, checkpoint, timeout, progress, cancellation).await {
+            Ok(report) => report,
+            Err(error) => {
+                warn!(task = %name, error = ?error, "failed to execute task");
+                Some(failed_report(
+                    format!("failed to execute task: {error:#}"),
+                    progress,
+                ))
+            }
+        };
+    }
+    let Some(reports) = registry.assign(&name, item) else {
+        return Some(failed_report(format!("task not found: {name}"), progress));
+    };
+    relay_worker_reports(reports, timeout, progress, cancellation).await
+}
+
+/// Relay the reports of a local worker until it finishes the step.
+async fn relay_worker_reports(
+    mut reports: mpsc::Receiver<DeviceOperationStepReport>,
+    timeout: Duration,
+    progress: &watch::Sender<TaskProgress>,
+    cancellation: &CancellationToken,
+) -> Option<DeviceOperationStepReport> {
+    let relay = async {
+        while let Some(report) = reports.recv().await {
+            if !matches!(report.status, DeviceOperationStepReportStatus::Running) {
+                let checkpoint = report
+                    .checkpoint
+                    .or_else(|| progress.borrow().checkpoint.clone());
+                return Some(DeviceOperationStepReport {
+                    checkpoint,
+                    ..report
+                });
+            }
+            progress.send_modify(|progress| {
+                progress.data = report.output;
+                if report.checkpoint.is_some() {
+                    progress.checkpoint = report.checkpoint;
+                }
+            });
+        }
+        // The worker disconnected before finishing the step.
+        None
+    };
+    tokio::select! {
+        biased;
+        () = cancellation.cancelled() => None,
+        result = tokio::time::timeout(timeout, relay) => result.unwrap_or_else(|_| {
+            Some(failed_report(
+                format!("task timed out after {}s", timeout.as_secs()),
+                progress,
+            ))
+        }),
+    }
+}
+
+async fn execute_task(
+    task: &LoadedTask,
+    input: serde_json::Value,
+    checkpoint: Option<serde_json::Value>,
+    timeout: Duration,
+    progress: &watch::Sender<TaskProgress>,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<Option<DeviceOperationStepReport>> {
+    let (_, args) = task
+        .definition
+        .exec
+        .handler
+        .split_first()
+        .context("handler must have at least one element")?;
+    let mut process = tokio::process::Command::new(&task.executable);
+    process
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    process.process_group(0);
+    let mut child = process.spawn().with_context(|| {
+        format!(
+            "failed to spawn task executable {}",
+            task.executable.display()
+        )
+    })?;
+    let process_group = child.id().and_then(|id| i32::try_from(id).ok());
+
+    let (Some(mut child_stdin), Some(child_stdout), Some(child_stderr)) =
+        (child.stdin.take(), child.stdout.take(), child.stderr.take())
+    else {
+        handlers::terminate_command_child(&mut child, process_group).await;
+        anyhow::bail!("task did not provide its configured standard I/O pipes");
+    };
+
+    let write_stdin = async {
+        let mut line = serde_json::to_vec(&TaskInvocation { input, checkpoint })
+            .context("failed to serialize task invocation")?;
+        line.push(b'\n');
+        child_stdin.write_all(&line).await.ok();
+        drop(child_stdin);
+        Ok::<(), anyhow::Error>(())
+    };
+
+    // Tasks are long-running by design, so only the length of individual lines is
+    // bounded, not the total amount of output.
+    let output_budget = CommandOutputBudget::with_limits(usize::MAX, usize::MAX);
+    let mut stderr_reader = tokio::io::BufReader::new(child_stderr);
+    let stderr_budget = output_budget.clone();
+    let read_stderr = async {
+        while let Some(line) = handlers::read_bounded_line(
+            &mut stderr_reader,
+            &stderr_budget,
+            MAX_COMMAND_OUTPUT_LINE_LEN,
+        )
+        .await?
+        {
+            debug!(
+                line = %String::from_utf8_lossy(handlers::trim_ascii_whitespace(&line)),
+                "task stderr"
+            );
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let mut stdout_reader = tokio::io::BufReader::new(child_stdout);
+    let read_stdout = async {
+        let mut last_output = None;
+        while let Some(line) = handlers::read_bounded_line(
+            &mut stdout_reader,
+            &output_budget,
+            MAX_COMMAND_OUTPUT_LINE_LEN,
+        )
+        .await?
+        {
+            let trimmed = handlers::trim_ascii_whitespace(&line);
+            if trimmed.is_empty() {
+                continue;
+            }
+            // Unknown types are silently ignored for forward compatibility.
+            match serde_json::from_slice::<TaskStdoutLine>(trimmed) {
+                Ok(TaskStdoutLine::Progress(reported)) => {
+                    progress.send_modify(|progress| {
+                        progress.data = reported.data;
+                        if reported.checkpoint.is_some() {
+                            progress.checkpoint = reported.checkpoint;
+                        }
+                    });
+                }
+                Ok(TaskStdoutLine::Output(output)) => {
+                    last_output = Some(output.data);
+                }
+                Err(_) => {}
+            }
+        }
+        Ok::<_, anyhow::Error>(last_output)
+    };
+
+    let io_and_wait = async {
+        let (_, last_output, _) = tokio::try_join!(write_stdin, read_stdout, read_stderr)?;
+        let status = child.wait().await.context("failed to wait for task")?;
+        Ok::<_, anyhow::Error>((status, last_output))
+    };
+    let wait = tokio::select! {
+        biased;
+        () = cancellation.cancelled() => None,
+        result = tokio::time::timeout(timeout, io_and_wait) => Some(result),
+    };
+
+    let error = match wait {
+        None => {
+            handlers::terminate_command_child(&mut child, process_group).await;
+            return Ok(None);
+        }
+        Some(Ok(Ok((status, last_output)))) => {
+            handlers::kill_command_process_group(process_group);
+            if status.success() {
+                return Ok(Some(DeviceOperationStepReport {
+                    status: DeviceOperationStepReportStatus::Succeeded,
+                    output: last_output,
+                    checkpoint: progress.borrow().checkpoint.clone(),
+                    error: None,
+                }));
+            }
+            format!("task exited with status {status}")
+        }
+        Some(Ok(Err(error))) => {
+            handlers::terminate_command_child(&mut child, process_group).await;
+            format!("task output handling failed: {error:#}")
+        }
+        Some(Err(_)) => {
+            handlers::terminate_command_child(&mut child, process_group).await;
+            format!("task timed out after {}s", timeout.as_secs())
+        }
+    };
+    Ok(Some(failed_report(error, progress)))
+}
+
+fn failed_report(
+    error: String,
+    progress: &watch::Sender<TaskProgress>,
+) -> DeviceOperationStepReport {
+    DeviceOperationStepReport {
+        status: DeviceOperationStepReportStatus::Failed,
+        output: None,
+        checkpoint: progress.borrow().checkpoint.clone(),
+        error: Some(error),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use nexigon_api::types::devices::DeviceOperationDeviceTaskStep;
+    use nexigon_api::types::devices::DeviceOperationId;
+    use nexigon_api::types::devices::DeviceOperationStepReport;
+    use nexigon_api::types::devices::DeviceOperationStepReportStatus;
+    use nexigon_api::types::devices::DeviceOperationWorkItem;
+    use nexigon_api::types::devices::DeviceOperationWorkStep;
+    use nexigon_ids::ids::DeviceOperationWorkClaimId;
+    use serde_json::json;
+    use tempfile::TempDir;
+    use tokio::sync::watch;
+    use tokio_util::sync::CancellationToken;
+
+    use super::TaskProgress;
+    use super::TaskRegistry;
+    use super::run_task;
+
+    fn task_registry(directory: &TempDir, script: &str) -> TaskRegistry {
+        let shell = directory.path().join("sh");
+        std::fs::copy("/bin/sh", &shell).unwrap();
+        std::fs::set_permissions(&shell, std::fs::Permissions::from_mode(0o500)).unwrap();
+        let handler = serde_json::to_string(&[
+            std::fs::canonicalize(shell).unwrap().to_string_lossy(),
+            "-c".into(),
+            script.into(),
+        ])
+        .unwrap();
+        std::fs::write(
+            directory.path().join("sync.toml"),
+            format!("[task]\nname = \"sync\"\n\n[exec]\nhandler = {handler}\n"),
+        )
+        .unwrap();
+        TaskRegistry::load_external(directory.path()).unwrap()
+    }
+
+    fn task_item(task: &str, input: serde_json::Value) -> DeviceOperationWorkItem {
+        DeviceOperationWorkItem::new(
+            DeviceOperationId::generate(),
+            DeviceOperationWorkClaimId::generate(),
+            "rollout".to_owned(),
+            0,
+            DeviceOperationWorkStep::DeviceTask(DeviceOperationDeviceTaskStep::new(
+                task.to_owned(),
+                input,
+            )),
+        )
+    }
+
+    #[tokio::test]
+    async fn tasks_resume_from_and_report_checkpoints() {
+        let directory = TempDir::new().unwrap();
+        let registry = task_registry(
+            &directory,
+            r#"read invocation
+echo '{"type":"progress","data":{"percent":50},"checkpoint":{"chunk":4}}'
+echo '{"type":"progress","data":{"percent":90}}'
+printf '{"type":"output","data":%s}\n' "$invocation""#,
+        );
+        assert!(registry.get("sync").is_some());
+
+        let (progress, _) = watch::channel(TaskProgress {
+            data: None,
+            checkpoint: Some(json!({"chunk": 3})),
+        });
+        let report = run_task(
+            &registry,
+            task_item("sync", json!({"target": "a"})),
+            Duration::from_secs(10),
+            &progress,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            report.status,
+            DeviceOperationStepReportStatus::Succeeded
+        ));
+        assert_eq!(
+            report.output,
+            Some(json!({"input": {"target": "a"}, "checkpoint": {"chunk": 3}}))
+        );
+        assert_eq!(report.checkpoint, Some(json!({"chunk": 4})));
+        assert_eq!(progress.borrow().data, Some(json!({"percent": 90})));
+    }
+
+    #[tokio::test]
+    async fn cancelled_tasks_produce_no_report() {
+        let directory = TempDir::new().unwrap();
+        let registry = task_registry(&directory, "sleep 30");
+        let (progress, _) = watch::channel(TaskProgress::default());
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+
+        let report = run_task(
+            &registry,
+            task_item("sync", json!(null)),
+            Duration::from_secs(10),
+            &progress,
+            &cancellation,
+        )
+        .await;
+
+        assert!(report.is_none());
+    }
+
+    #[tokio::test]
+    async fn tasks_without_definition_are_assigned_to_local_workers() {
+        let directory = TempDir::new().unwrap();
+        let registry = Arc::new(task_registry(&directory, "exit 1"));
+        assert!(registry.subscribe(vec!["sync".to_owned()]).is_err());
+        let mut subscription = registry.subscribe(vec!["inventory".to_owned()]).unwrap();
+        let worker = tokio::spawn(async move {
+            let assignment = subscription.assignments.recv().await.unwrap();
+            assert_eq!(assignment.item.checkpoint, None);
+            for status in [
+                DeviceOperationStepReportStatus::Running,
+                DeviceOperationStepReportStatus::Succeeded,
+            ] {
+                let checkpoint = matches!(status, DeviceOperationStepReportStatus::Running)
+                    .then(|| json!({"page": 2}));
+                assignment
+                    .reports
+                    .send(DeviceOperationStepReport {
+                        status,
+                        output: Some(json!("listed")),
+                        checkpoint,
+                        error: None,
+                    })
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let (progress, _) = watch::channel(TaskProgress::default());
+        let report = run_task(
+            &registry,
+            task_item("inventory", json!(null)),
+            Duration::from_secs(10),
+            &progress,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        worker.await.unwrap();
+
+        assert!(matches!(
+            report.status,
+            DeviceOperationStepReportStatus::Succeeded
+        ));
+        assert_eq!(report.checkpoint, Some(json!({"page": 2})));
+
+        let report = run_task(
+            &registry,
+            task_item("inventory", json!(null)),
+            Duration::from_secs(10),
+            &progress,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.error.as_deref(), Some("task not found: inventory"));
+    }
+}
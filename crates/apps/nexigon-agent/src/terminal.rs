@@ -2,35 +2,77 @@ use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 use std::os::fd::AsRawFd;
+use std::os::fd::RawFd;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use anyhow::Context;
 use anyhow::bail;
 use nexigon_agent_protocol::HubTerminalFrame;
+use nexigon_agent_protocol::TERMINAL_EXIT_IDLE_TIMEOUT;
+use nexigon_agent_protocol::TERMINAL_EXIT_MAX_DURATION;
 use nexigon_agent_protocol::read_hub_terminal_frame;
 use nexigon_agent_protocol::write_terminal_data;
 use nexigon_agent_protocol::write_terminal_exit;
 use nix::libc;
 use nix::unistd::User;
 use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
 use tracing::info;
 use tracing::warn;
 
 use crate::config::Config;
+use crate::config::TerminalProfileConfig;
 
 mod child;
+mod exec;
+mod recording;
+mod session;
+
+pub(crate) use exec::handle_exec_session_with_cancellation;
+use recording::Recording;
+pub(crate) use recording::TerminalRecordings;
+pub(crate) use recording::run_recording_delivery;
+pub(crate) use session::AttachMode;
+pub(crate) use session::TerminalSessions;
+pub(crate) use session::valid_session_id;
 
 const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(50);
 const CHILD_TERMINATION_GRACE: Duration = Duration::from_secs(5);
 const PTY_DRAIN_GRACE: Duration = Duration::from_secs(2);
 
+/// Program a terminal session runs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum TerminalTarget {
+    /// Login shell of the requested user, or of the default user.
+    Shell(Option<String>),
+    /// Program of the named profile.
+    Profile(String),
+}
+
+impl TerminalTarget {
+    /// Target of a `terminal[/<user>]` or `terminal/profile/<name>` endpoint.
+    pub(crate) fn from_endpoint(endpoint: &str) -> Option<Self> {
+        if endpoint == "terminal" {
+            return Some(Self::Shell(None));
+        }
+        let target = endpoint.strip_prefix("terminal/")?;
+        Some(match target.strip_prefix("profile/") {
+            Some(name) => Self::Profile(name.to_owned()),
+            None => Self::Shell(Some(target.to_owned())),
+        })
+    }
+}
+
 /// Handle a terminal session over a multiplex channel.
 ///
 /// The channel uses a length-prefixed binary framing protocol:
 /// `[u32 BE: length][u8: type][payload]`
+///
+/// Sessions handled this way are not recorded.
 pub async fn handle_terminal_session(
     channel: nexigon_multiplex::Channel,
     config: &Arc<Config>,
@@ -39,25 +81,431 @@ pub async fn handle_terminal_session(
     handle_terminal_session_with_cancellation(
         channel,
         config,
-        requested_user,
+        &TerminalTarget::Shell(requested_user.map(str::to_owned)),
+        None,
         CancellationToken::new(),
     )
     .await
 }
 
 /// Handle a terminal session that is cancelled with its owning connection.
+///
+/// If `terminal.recording` is enabled, the session is recorded in `recordings`. Sessions
+/// reaching `terminal.idle-timeout-secs` or `terminal.max-session-secs` are ended with a
+/// warning and a negative exit status.
 pub(crate) async fn handle_terminal_session_with_cancellation(
     channel: nexigon_multiplex::Channel,
     config: &Arc<Config>,
-    requested_user: Option<&str>,
+    target: &TerminalTarget,
+    recordings: Option<&Arc<TerminalRecordings>>,
     cancellation: CancellationToken,
 ) -> anyhow::Result<()> {
     if cancellation.is_cancelled() {
         return Ok(());
     }
-    if !crate::config::terminal_enabled(config) {
-        bail!("terminal is not enabled or terminal.user is invalid");
+    let shell = TerminalShell::spawn(config, target, recordings).await?;
+    let child = shell.child;
+    let limits = SessionLimits::new(config);
+    let (mut chan_writer, mut chan_reader) = channel.split();
+
+    let channel_to_pty = async {
+        loop {
+            let frame = read_hub_terminal_frame(&mut chan_reader).await?;
+            limits.received(&frame);
+            match frame {
+                HubTerminalFrame::Data(data) => shell.write(&data).await?,
+                HubTerminalFrame::Resize { cols, rows } => shell.resize(cols, rows)?,
+            }
+        }
+    };
+
+    let pty_to_channel = async {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let n = tokio::select! {
+                n = shell.read(&mut buf) => n?,
+                limit = limits.exceeded() => {
+                    write_terminal_data(&mut chan_writer, limit.warning().as_bytes()).await?;
+                    return Ok(Some(limit));
+                }
+            };
+            if n == 0 {
+                break;
+            }
+            write_terminal_data(&mut chan_writer, &buf[..n]).await?;
+        }
+        Ok::<_, anyhow::Error>(None)
+    };
+
+    let mut channel_to_pty = Box::pin(channel_to_pty);
+    let mut pty_to_channel = Box::pin(pty_to_channel);
+    let mut wait_child = Box::pin(wait_for_terminal_child(child));
+
+    enum SessionEnd {
+        Channel(anyhow::Result<()>),
+        Pty(anyhow::Result<Option<SessionLimit>>),
+        Child(anyhow::Result<i32>),
+        Cancelled,
+    }
+
+    let end = tokio::select! {
+        biased;
+        () = cancellation.cancelled() => SessionEnd::Cancelled,
+        result = &mut wait_child => SessionEnd::Child(result),
+        result = &mut pty_to_channel => SessionEnd::Pty(result),
+        result = &mut channel_to_pty => SessionEnd::Channel(result),
+    };
+
+    let exit_code = match end {
+        SessionEnd::Child(result) => {
+            let code = result.context("failed to reap terminal child")?;
+            debug!(code, "terminal child exited");
+            // Preserve all output that was already in the PTY before publishing
+            // the final status. The bounded grace prevents a broken PTY from
+            // delaying channel closure indefinitely.
+            match tokio::time::timeout(PTY_DRAIN_GRACE, &mut pty_to_channel).await {
+                Ok(Ok(_)) => code,
+                Ok(Err(error)) => {
+                    drop(pty_to_channel);
+                    drop(channel_to_pty);
+                    drop(wait_child);
+                    chan_writer.shutdown().await.ok();
+                    return Err(error).context("failed to drain terminal output");
+                }
+                Err(_) => {
+                    // The canceled write may already have emitted part of a data
+                    // frame. Close instead of appending an exit frame to a stream
+                    // whose alignment is no longer known.
+                    drop(pty_to_channel);
+                    drop(channel_to_pty);
+                    drop(wait_child);
+                    chan_writer.shutdown().await.ok();
+                    anyhow::bail!("timed out draining terminal output");
+                }
+            }
+        }
+        SessionEnd::Pty(Ok(Some(limit))) => {
+            info!(?limit, "terminal session limit reached; terminating child");
+            terminate_terminal_child(child).await;
+            limit.exit_code()
+        }
+        SessionEnd::Pty(Ok(None)) => {
+            // Linux can report PTY EOF immediately before waitpid exposes the
+            // status. Wait for that status instead of inventing a successful exit.
+            match tokio::time::timeout(CHILD_TERMINATION_GRACE, &mut wait_child).await {
+                Ok(result) => result.context("failed to reap terminal child")?,
+                Err(_) => terminate_terminal_child(child).await,
+            }
+        }
+        SessionEnd::Pty(Err(error)) => {
+            debug!(?error, "PTY output relay failed; closing terminal channel");
+            drop(pty_to_channel);
+            drop(channel_to_pty);
+            drop(wait_child);
+            terminate_terminal_child(child).await;
+            chan_writer.shutdown().await.ok();
+            return Err(error);
+        }
+        SessionEnd::Channel(error) => {
+            debug!(?error, "invalid or closed terminal input; closing channel");
+            drop(pty_to_channel);
+            drop(channel_to_pty);
+            drop(wait_child);
+            terminate_terminal_child(child).await;
+            chan_writer.shutdown().await.ok();
+            return error;
+        }
+        SessionEnd::Cancelled => {
+            debug!("terminal session cancelled; terminating child");
+            drop(pty_to_channel);
+            drop(channel_to_pty);
+            drop(wait_child);
+            terminate_terminal_child(child).await;
+            chan_writer.shutdown().await.ok();
+            return Ok(());
+        }
+    };
+
+    // A terminal leader can exit while a non-interactive background descendant keeps
+    // running. The forkpty child owns this process group, so close the entire session
+    // before reporting completion.
+    nix::sys::signal::killpg(child, nix::sys::signal::Signal::SIGKILL).ok();
+
+    // Dropping all relay/wait futures releases their mutable borrows before the
+    // one and only terminal status frame is sent.
+    drop(pty_to_channel);
+    drop(channel_to_pty);
+    drop(wait_child);
+    write_terminal_exit(&mut chan_writer, exit_code).await?;
+    chan_writer.shutdown().await?;
+    Ok(())
+}
+
+/// Shell or profile program of a terminal session, running on a PTY.
+struct TerminalShell {
+    child: nix::unistd::Pid,
+    master_fd: RawFd,
+    reader: tokio::io::unix::AsyncFd<File>,
+    writer: tokio::io::unix::AsyncFd<File>,
+    recording: Option<Recording>,
+}
+
+impl TerminalShell {
+    /// Check the request against the terminal configuration and spawn the shell or the
+    /// program of the requested profile.
+    ///
+    /// If `terminal.recording` is enabled, the session is recorded in `recordings`.
+    async fn spawn(
+        config: &Config,
+        target: &TerminalTarget,
+        recordings: Option<&Arc<TerminalRecordings>>,
+    ) -> anyhow::Result<Self> {
+        if !crate::config::terminal_enabled(config) {
+            bail!("terminal is not enabled or terminal.user is invalid");
+        }
+        let terminal_config = config
+            .terminal
+            .as_ref()
+            .context("terminal configuration is missing")?;
+        let (user, program, mut prepared_child) = match target {
+            TerminalTarget::Shell(requested_user) => {
+                let user = resolve_session_user(config, requested_user.as_deref())?;
+                let shell = terminal_config
+                    .shell
+                    .as_deref()
+                    .map(|s| s.to_owned())
+                    .unwrap_or_else(|| {
+                        let login_shell = user.shell.to_string_lossy().to_string();
+                        if login_shell.is_empty() {
+                            "/bin/sh".to_owned()
+                        } else {
+                            login_shell
+                        }
+                    });
+                info!(username = user.name, shell, "spawning terminal session");
+                let shell_name = std::path::Path::new(&shell)
+                    .file_name()
+                    .unwrap_or(std::ffi::OsStr::new("sh"))
+                    .to_string_lossy()
+                    .to_string();
+                let login_shell_name = format!("-{shell_name}");
+                let prepared_child = child::prepare(&user, &shell, &login_shell_name)
+                    .context("failed to prepare terminal child")?;
+                (user, shell, prepared_child)
+            }
+            TerminalTarget::Profile(name) => {
+                let profile = terminal_config
+                    .profiles
+                    .iter()
+                    .flatten()
+                    .find(|profile| profile.name == *name)
+                    .with_context(|| format!("terminal profile {name:?} does not exist"))?;
+                let user = resolve_profile_user(config, profile)?;
+                let program = profile.command.join(" ");
+                info!(
+                    username = user.name,
+                    profile = name,
+                    program,
+                    "spawning terminal session"
+                );
+                let prepared_child = child::prepare_profile(&user, profile)
+                    .context("failed to prepare terminal child")?;
+                (user, program, prepared_child)
+            }
+        };
+
+        let recording = match recordings {
+            Some(recordings) => recordings
+                .start(config, &user.name, &program)
+                .context("failed to start terminal recording")?,
+            None => None,
+        };
+
+        // SAFETY: We only call async-signal-safe functions in the child.
+        let forkpty_result =
+            unsafe { nix::pty::forkpty(None, None) }.context("failed to forkpty")?;
+
+        match forkpty_result {
+            nix::pty::ForkptyResult::Child => {
+                // SAFETY: All allocating/NSS work and pointer construction happened
+                // before `forkpty`. `enter` uses only raw Linux syscalls and `_exit`.
+                unsafe { child::enter(&mut prepared_child) }
+            }
+            nix::pty::ForkptyResult::Parent { child, master } => {
+                // Raw pointer arrays in the child preparation are not needed by the
+                // parent and must not remain live across any async suspension point.
+                drop(prepared_child);
+                let parent_setup = (|| {
+                    let master = File::from(master);
+                    let master_fd = master.as_raw_fd();
+                    // AsyncFd requires non-blocking mode.
+                    set_nonblocking(master_fd)
+                        .context("failed to set PTY master to non-blocking")?;
+                    let reader = tokio::io::unix::AsyncFd::new(master.try_clone()?)
+                        .context("failed to register PTY reader")?;
+                    let writer = tokio::io::unix::AsyncFd::new(master)
+                        .context("failed to register PTY writer")?;
+                    Ok::<_, anyhow::Error>((master_fd, reader, writer))
+                })();
+                match parent_setup {
+                    Ok((master_fd, reader, writer)) => Ok(Self {
+                        child,
+                        master_fd,
+                        reader,
+                        writer,
+                        recording,
+                    }),
+                    Err(error) => {
+                        terminate_terminal_child(child).await;
+                        Err(error).context("failed to initialize terminal parent")
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read output of the shell, returning zero at EOF.
+    async fn read(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let n = pty_read(&self.reader, buf).await?;
+        if let Some(recording) = &self.recording
+            && n > 0
+        {
+            recording.output(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    /// Write input to the shell.
+    async fn write(&self, data: &[u8]) -> anyhow::Result<()> {
+        pty_write(&self.writer, data).await
     }
+
+    /// Resize the terminal of the shell.
+    fn resize(&self, cols: u16, rows: u16) -> anyhow::Result<()> {
+        let ws = nix::pty::Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: `master_fd` is a live PTY master and `ws` is a correctly initialized
+        // `winsize` value.
+        let result = unsafe { libc::ioctl(self.master_fd, libc::TIOCSWINSZ, &ws as *const _) };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error()).context("failed to resize PTY");
+        }
+        if let Some(recording) = &self.recording {
+            recording.resize(cols, rows);
+        }
+        Ok(())
+    }
+}
+
+/// Limit that ended a terminal session.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SessionLimit {
+    Idle(Duration),
+    MaxDuration(Duration),
+}
+
+impl SessionLimit {
+    /// Warning shown in the terminal before the session is ended.
+    fn warning(self) -> String {
+        match self {
+            Self::Idle(timeout) => format!(
+                "\r\nnexigon-agent: terminal session was idle for {} seconds, closing\r\n",
+                timeout.as_secs()
+            ),
+            Self::MaxDuration(duration) => format!(
+                "\r\nnexigon-agent: terminal session reached its maximum duration of {} \
+                 seconds, closing\r\n",
+                duration.as_secs()
+            ),
+        }
+    }
+
+    /// Exit status reported for the session.
+    fn exit_code(self) -> i32 {
+        match self {
+            Self::Idle(_) => TERMINAL_EXIT_IDLE_TIMEOUT,
+            Self::MaxDuration(_) => TERMINAL_EXIT_MAX_DURATION,
+        }
+    }
+}
+
+/// Idle timeout and maximum duration of a terminal session.
+struct SessionLimits {
+    idle_timeout: Option<Duration>,
+    max_duration: Option<Duration>,
+    started: Instant,
+    last_input: Mutex<Instant>,
+}
+
+impl SessionLimits {
+    fn new(config: &Config) -> Self {
+        let terminal = config.terminal.as_ref();
+        let limit = |secs: Option<u64>| secs.filter(|secs| *secs > 0).map(Duration::from_secs);
+        let started = Instant::now();
+        Self {
+            idle_timeout: limit(terminal.and_then(|terminal| terminal.idle_timeout_secs)),
+            max_duration: limit(terminal.and_then(|terminal| terminal.max_session_secs)),
+            started,
+            last_input: Mutex::new(started),
+        }
+    }
+
+    /// Record a frame from the hub.
+    ///
+    /// Only data counts as input, so that a client resizing the terminal does not keep
+    /// an abandoned session alive.
+    fn received(&self, frame: &HubTerminalFrame) {
+        if matches!(frame, HubTerminalFrame::Data(_)) {
+            self.input();
+        }
+    }
+
+    /// Record input to the session.
+    fn input(&self) {
+        if let Ok(mut last_input) = self.last_input.lock() {
+            *last_input = Instant::now();
+        }
+    }
+
+    fn last_input(&self) -> Instant {
+        self.last_input
+            .lock()
+            .map(|last_input| *last_input)
+            .unwrap_or(self.started)
+    }
+
+    /// Wait until the session reaches a limit.
+    async fn exceeded(&self) -> SessionLimit {
+        loop {
+            let last_input = self.last_input();
+            let idle = self
+                .idle_timeout
+                .map(|timeout| (last_input + timeout, SessionLimit::Idle(timeout)));
+            let max_duration = self
+                .max_duration
+                .map(|duration| (self.started + duration, SessionLimit::MaxDuration(duration)));
+            let Some((deadline, limit)) = idle
+                .into_iter()
+                .chain(max_duration)
+                .min_by_key(|(deadline, _)| *deadline)
+            else {
+                return std::future::pending().await;
+            };
+            tokio::time::sleep_until(deadline).await;
+            // Input that arrived in the meantime moves the idle deadline.
+            if matches!(limit, SessionLimit::MaxDuration(_)) || self.last_input() == last_input {
+                return limit;
+            }
+        }
+    }
+}
+
+/// Look up the user a session runs as, enforcing `terminal.allowed-users`.
+fn resolve_session_user(config: &Config, requested_user: Option<&str>) -> anyhow::Result<User> {
     let terminal_config = config
         .terminal
         .as_ref()
@@ -83,213 +531,52 @@ pub(crate) async fn handle_terminal_session_with_cancellation(
         }
     }
 
-    let user = User::from_name(username)
+    User::from_name(username)
         .context("failed to look up user")?
-        .with_context(|| format!("user {username:?} does not exist"))?;
-
-    let shell = terminal_config
-        .shell
-        .as_deref()
-        .map(|s| s.to_owned())
-        .unwrap_or_else(|| {
-            let login_shell = user.shell.to_string_lossy().to_string();
-            if login_shell.is_empty() {
-                "/bin/sh".to_owned()
-            } else {
-                login_shell
-            }
-        });
-
-    info!(username, shell, "spawning terminal session");
-
-    let shell_name = std::path::Path::new(&shell)
-        .file_name()
-        .unwrap_or(std::ffi::OsStr::new("sh"))
-        .to_string_lossy()
-        .to_string();
-    let login_shell_name = format!("-{shell_name}");
-    let mut prepared_child = child::prepare(&user, &shell, &login_shell_name)
-        .context("failed to prepare terminal child")?;
-
-    // SAFETY: We only call async-signal-safe functions in the child.
-    let forkpty_result = unsafe { nix::pty::forkpty(None, None) }.context("failed to forkpty")?;
-
-    match forkpty_result {
-        nix::pty::ForkptyResult::Child => {
-            // SAFETY: All allocating/NSS work and pointer construction happened
-            // before `forkpty`. `enter` uses only raw Linux syscalls and `_exit`.
-            unsafe { child::enter(&mut prepared_child) }
-        }
-        nix::pty::ForkptyResult::Parent { child, master } => {
-            // Raw pointer arrays in the child preparation are not needed by the
-            // parent and must not remain live across any async suspension point.
-            drop(prepared_child);
-            let parent_setup = (|| {
-                let master = File::from(master);
-                let master_fd = master.as_raw_fd();
-                // AsyncFd requires non-blocking mode.
-                set_nonblocking(master_fd).context("failed to set PTY master to non-blocking")?;
-                let async_master_read = tokio::io::unix::AsyncFd::new(master.try_clone()?)
-                    .context("failed to register PTY reader")?;
-                let async_master_write = tokio::io::unix::AsyncFd::new(master)
-                    .context("failed to register PTY writer")?;
-                Ok::<_, anyhow::Error>((master_fd, async_master_read, async_master_write))
-            })();
-            let (master_fd, async_master_read, async_master_write) = match parent_setup {
-                Ok(setup) => setup,
-                Err(error) => {
-                    terminate_terminal_child(child).await;
-                    return Err(error).context("failed to initialize terminal parent");
-                }
-            };
-            let (mut chan_writer, mut chan_reader) = channel.split();
-
-            let channel_to_pty = async {
-                loop {
-                    match read_hub_terminal_frame(&mut chan_reader).await? {
-                        HubTerminalFrame::Data(data) => {
-                            pty_write(&async_master_write, &data).await?;
-                        }
-                        HubTerminalFrame::Resize { cols, rows } => {
-                            let ws = nix::pty::Winsize {
-                                ws_row: rows,
-                                ws_col: cols,
-                                ws_xpixel: 0,
-                                ws_ypixel: 0,
-                            };
-                            // SAFETY: `master_fd` is a live PTY master and `ws` is a
-                            // correctly initialized `winsize` value.
-                            let result = unsafe {
-                                libc::ioctl(master_fd, libc::TIOCSWINSZ, &ws as *const _)
-                            };
-                            if result < 0 {
-                                return Err(std::io::Error::last_os_error())
-                                    .context("failed to resize PTY");
-                            }
-                        }
-                    }
-                }
-            };
-
-            let pty_to_channel = async {
-                let mut buf = vec![0u8; 4096];
-                loop {
-                    let n = pty_read(&async_master_read, &mut buf).await?;
-                    if n == 0 {
-                        break;
-                    }
-                    write_terminal_data(&mut chan_writer, &buf[..n]).await?;
-                }
-                Ok::<(), anyhow::Error>(())
-            };
-
-            let mut channel_to_pty = Box::pin(channel_to_pty);
-            let mut pty_to_channel = Box::pin(pty_to_channel);
-            let mut wait_child = Box::pin(wait_for_terminal_child(child));
-
-            enum SessionEnd {
-                Channel(anyhow::Result<()>),
-                Pty(anyhow::Result<()>),
-                Child(anyhow::Result<i32>),
-                Cancelled,
-            }
-
-            let end = tokio::select! {
-                biased;
-                () = cancellation.cancelled() => SessionEnd::Cancelled,
-                result = &mut wait_child => SessionEnd::Child(result),
-                result = &mut pty_to_channel => SessionEnd::Pty(result),
-                result = &mut channel_to_pty => SessionEnd::Channel(result),
-            };
+        .with_context(|| format!("user {username:?} does not exist"))
+}
 
-            let exit_code = match end {
-                SessionEnd::Child(result) => {
-                    let code = result.context("failed to reap terminal child")?;
-                    debug!(code, "terminal child exited");
-                    // Preserve all output that was already in the PTY before publishing
-                    // the final status. The bounded grace prevents a broken PTY from
-                    // delaying channel closure indefinitely.
-                    match tokio::time::timeout(PTY_DRAIN_GRACE, &mut pty_to_channel).await {
-                        Ok(Ok(())) => code,
-                        Ok(Err(error)) => {
-                            drop(pty_to_channel);
-                            drop(channel_to_pty);
-                            drop(wait_child);
-                            chan_writer.shutdown().await.ok();
-                            return Err(error).context("failed to drain terminal output");
-                        }
-                        Err(_) => {
-                            // The canceled write may already have emitted part of a data
-                            // frame. Close instead of appending an exit frame to a stream
-                            // whose alignment is no longer known.
-                            drop(pty_to_channel);
-                            drop(channel_to_pty);
-                            drop(wait_child);
-                            chan_writer.shutdown().await.ok();
-                            anyhow::bail!("timed out draining terminal output");
-                        }
-                    }
-                }
-                SessionEnd::Pty(Ok(())) => {
-                    // Linux can report PTY EOF immediately before waitpid exposes the
-                    // status. Wait for that status instead of inventing a successful exit.
-                    match tokio::time::timeout(CHILD_TERMINATION_GRACE, &mut wait_child).await {
-                        Ok(result) => result.context("failed to reap terminal child")?,
-                        Err(_) => terminate_terminal_child(child).await,
-                    }
-                }
-                SessionEnd::Pty(Err(error)) => {
-                    debug!(?error, "PTY output relay failed; closing terminal channel");
-                    drop(pty_to_channel);
-                    drop(channel_to_pty);
-                    drop(wait_child);
-                    terminate_terminal_child(child).await;
-                    chan_writer.shutdown().await.ok();
-                    return Err(error);
-                }
-                SessionEnd::Channel(error) => {
-                    debug!(?error, "invalid or closed terminal input; closing channel");
-                    drop(pty_to_channel);
-                    drop(channel_to_pty);
-                    drop(wait_child);
-                    terminate_terminal_child(child).await;
-                    chan_writer.shutdown().await.ok();
-                    return error;
-                }
-                SessionEnd::Cancelled => {
-                    debug!("terminal session cancelled; terminating child");
-                    drop(pty_to_channel);
-                    drop(channel_to_pty);
-                    drop(wait_child);
-                    terminate_terminal_child(child).await;
-                    chan_writer.shutdown().await.ok();
-                    return Ok(());
-                }
-            };
+/// Look up the user the program of `profile` runs as.
+///
+/// Profiles are configured explicitly, so their user does not have to be allowed for
+/// shell sessions.
+fn resolve_profile_user(config: &Config, profile: &TerminalProfileConfig) -> anyhow::Result<User> {
+    let username = match profile.user.as_deref() {
+        Some(user) => user,
+        None => crate::config::terminal_user(config).context("terminal.user is invalid")?,
+    };
+    User::from_name(username)
+        .context("failed to look up user")?
+        .with_context(|| format!("user {username:?} does not exist"))
+}
 
-            // A terminal leader can exit while a non-interactive background descendant keeps
-            // running. The forkpty child owns this process group, so close the entire session
-            // before reporting completion.
-            nix::sys::signal::killpg(child, nix::sys::signal::Signal::SIGKILL).ok();
+/// How a session child terminated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ChildExit {
+    Exited(i32),
+    Signaled(i32),
+}
 
-            // Dropping all relay/wait futures releases their mutable borrows before the
-            // one and only terminal status frame is sent.
-            drop(pty_to_channel);
-            drop(channel_to_pty);
-            drop(wait_child);
-            write_terminal_exit(&mut chan_writer, exit_code).await?;
-            chan_writer.shutdown().await?;
-            Ok(())
+impl ChildExit {
+    /// Exit code as reported by a shell, i.e., `128 + signal` for signals.
+    fn code(self) -> i32 {
+        match self {
+            ChildExit::Exited(code) => code,
+            ChildExit::Signaled(signal) => 128 + signal,
         }
     }
 }
 
 async fn wait_for_terminal_child(child: nix::unistd::Pid) -> anyhow::Result<i32> {
+    wait_for_child(child).await.map(ChildExit::code)
+}
+
+async fn wait_for_child(child: nix::unistd::Pid) -> anyhow::Result<ChildExit> {
     loop {
         match nix::sys::wait::waitpid(child, Some(nix::sys::wait::WaitPidFlag::WNOHANG)) {
-            Ok(nix::sys::wait::WaitStatus::Exited(_, code)) => return Ok(code),
+            Ok(nix::sys::wait::WaitStatus::Exited(_, code)) => return Ok(ChildExit::Exited(code)),
             Ok(nix::sys::wait::WaitStatus::Signaled(_, signal, _)) => {
-                return Ok(128 + signal as i32);
+                return Ok(ChildExit::Signaled(signal as i32));
             }
             Ok(nix::sys::wait::WaitStatus::StillAlive)
             | Ok(nix::sys::wait::WaitStatus::Stopped(_, _))
@@ -390,6 +677,7 @@ mod tests {
 
     use super::*;
     use crate::config::TerminalConfig;
+    use crate::config::TerminalRecordingConfig;
 
     async fn assert_process_gone(description: &str, pid: i32) {
         tokio::time::timeout(Duration::from_secs(5), async {
@@ -604,6 +892,7 @@ mod tests {
             handle_terminal_session_with_cancellation(
                 agent_channel,
                 &config,
+                &TerminalTarget::Shell(None),
                 None,
                 session_cancellation,
             )
@@ -697,4 +986,197 @@ mod tests {
         let _ = hub_driver.await;
         let _ = agent_driver.await;
     }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn terminal_sessions_are_recorded() {
+        let (hub_transport, agent_transport) = InMemory::<Bytes, Bytes>::new_buffered(64);
+        let mut hub_connection = Connection::new(hub_transport);
+        let mut agent_connection = Connection::new(agent_transport);
+        let mut hub_ref = hub_connection.make_ref();
+        let (channel_tx, mut channel_rx) = tokio::sync::mpsc::channel(1);
+
+        let hub_driver = tokio::spawn(async move {
+            while let Some(event) = hub_connection.next().await {
+                if !matches!(event, Ok(ConnectionEvent::Connected)) {
+                    break;
+                }
+            }
+        });
+        let agent_driver = tokio::spawn(async move {
+            while let Some(event) = agent_connection.next().await {
+                match event {
+                    Ok(ConnectionEvent::Connected) => {}
+                    Ok(ConnectionEvent::RequestChannel(request)) => {
+                        let channel_tx = channel_tx.clone();
+                        request.accept(move |channel| {
+                            let _ = channel_tx.try_send(channel);
+                        });
+                    }
+                    Ok(ConnectionEvent::Closed) | Err(_) => break,
+                }
+            }
+        });
+
+        let data = tempdir().unwrap();
+        let user = User::from_uid(nix::unistd::geteuid())
+            .unwrap()
+            .expect("current user must exist");
+        let username = user.name.clone();
+        let config = Arc::new(
+            Config::new(PathBuf::from("unused-fingerprint")).with_terminal(Some(
+                TerminalConfig::new()
+                    .with_enabled(Some(true))
+                    .with_user(Some(user.name))
+                    .with_shell(Some("/bin/sh".to_owned()))
+                    .with_recording(Some(
+                        TerminalRecordingConfig::new().with_enabled(Some(true)),
+                    )),
+            )),
+        );
+        let recordings = Arc::new(TerminalRecordings::new(data.path().to_owned()));
+        let session_recordings = recordings.clone();
+
+        let mut hub_channel = hub_ref
+            .open(b"terminal")
+            .await
+            .expect("terminal channel rejected");
+        let agent_channel = channel_rx.recv().await.expect("agent driver stopped");
+        let session = tokio::spawn(async move {
+            handle_terminal_session_with_cancellation(
+                agent_channel,
+                &config,
+                &TerminalTarget::Shell(None),
+                Some(&session_recordings),
+                CancellationToken::new(),
+            )
+            .await
+        });
+        nexigon_agent_protocol::write_terminal_resize(&mut hub_channel, 100, 30)
+            .await
+            .unwrap();
+        write_terminal_data(&mut hub_channel, b"echo recorded-output; exit 3\n")
+            .await
+            .unwrap();
+        let exit = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match read_device_terminal_frame(&mut hub_channel).await.unwrap() {
+                    DeviceTerminalFrame::Data(_) => {}
+                    DeviceTerminalFrame::Exit(code) => break code,
+                }
+            }
+        })
+        .await
+        .expect("terminal session did not exit");
+        assert_eq!(exit, 3);
+        tokio::time::timeout(Duration::from_secs(2), session)
+            .await
+            .expect("terminal handler did not finish")
+            .expect("terminal handler panicked")
+            .expect("terminal handler failed");
+        tokio::time::timeout(Duration::from_secs(2), recordings.finished())
+            .await
+            .expect("terminal recording was not finished");
+
+        let recordings = std::fs::read_dir(data.path().join("terminal-recordings"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(recordings.len(), 1);
+        let cast = std::fs::read_to_string(&recordings[0]).unwrap();
+        let mut lines = cast
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap());
+        let header = lines.next().unwrap();
+        assert_eq!(header["version"], 2);
+        assert_eq!(header["user"], username.as_str());
+        assert_eq!(header["env"]["SHELL"], "/bin/sh");
+        let events = lines.collect::<Vec<_>>();
+        // The shell may print before the resize is applied, which is then recorded as
+        // a resize event instead of the size in the header.
+        assert!(
+            (header["width"] == 100 && header["height"] == 30)
+                || events
+                    .iter()
+                    .any(|event| event[1] == "r" && event[2] == "100x30")
+        );
+        let output = events
+            .iter()
+            .filter(|event| event[1] == "o")
+            .map(|event| event[2].as_str().unwrap())
+            .collect::<String>();
+        assert!(output.contains("recorded-output"), "output: {output:?}");
+
+        hub_driver.abort();
+        agent_driver.abort();
+    }
+
+    #[test]
+    fn terminal_endpoints_select_shells_and_profiles() {
+        let target = TerminalTarget::from_endpoint;
+        assert_eq!(target("terminal"), Some(TerminalTarget::Shell(None)));
+        assert_eq!(
+            target("terminal/admin"),
+            Some(TerminalTarget::Shell(Some("admin".to_owned())))
+        );
+        assert_eq!(
+            target("terminal/profile/diagnostics"),
+            Some(TerminalTarget::Profile("diagnostics".to_owned()))
+        );
+        assert_eq!(target("terminal-session/abc"), None);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn idle_sessions_and_overlong_sessions_reach_their_limits() {
+        let config = Config::new(PathBuf::from("unused-fingerprint")).with_terminal(Some(
+            TerminalConfig::new()
+                .with_idle_timeout_secs(Some(10))
+                .with_max_session_secs(Some(25)),
+        ));
+        let limits = SessionLimits::new(&config);
+        tokio::time::sleep(Duration::from_secs(8)).await;
+        limits.input();
+        let started = Instant::now();
+        let limit = limits.exceeded().await;
+        assert_eq!(limit, SessionLimit::Idle(Duration::from_secs(10)));
+        assert_eq!(limit.exit_code(), TERMINAL_EXIT_IDLE_TIMEOUT);
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let exceeded = limits.exceeded();
+        tokio::pin!(exceeded);
+        let limit = loop {
+            tokio::select! {
+                limit = &mut exceeded => break limit,
+                () = tokio::time::sleep(Duration::from_secs(5)) => limits.input(),
+            }
+        };
+        assert_eq!(limit, SessionLimit::MaxDuration(Duration::from_secs(25)));
+        assert_eq!(limit.exit_code(), TERMINAL_EXIT_MAX_DURATION);
+        assert_eq!(limits.started.elapsed(), Duration::from_secs(25));
+        assert!(limit.warning().contains("maximum duration of 25 seconds"));
+
+        let unlimited = SessionLimits::new(&Config::new(PathBuf::from("unused-fingerprint")));
+        assert!(
+            tokio::time::timeout(Duration::from_secs(3600), unlimited.exceeded())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn only_data_moves_the_idle_deadline() {
+        let config = Config::new(PathBuf::from("unused-fingerprint"))
+            .with_terminal(Some(TerminalConfig::new().with_idle_timeout_secs(Some(10))));
+        let limits = SessionLimits::new(&config);
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        limits.received(&HubTerminalFrame::Data(b"ls\n".to_vec()));
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        limits.received(&HubTerminalFrame::Resize {
+            cols: 120,
+            rows: 40,
+        });
+        let limit = limits.exceeded().await;
+        assert_eq!(limit, SessionLimit::Idle(Duration::from_secs(10)));
+        assert_eq!(limits.started.elapsed(), Duration::from_secs(14));
+    }
 }
@@ -1,12 +1,14 @@
-//! Fail-closed terminal child preparation and execution.
+//! Fail-closed terminal and exec child preparation and execution.
 //!
 //! Everything that may allocate, consult NSS, or take a process-global lock is
-//! completed before `forkpty`. The child path itself uses only raw Linux syscalls,
-//! fixed-size stack operations, and `_exit`.
+//! completed before `forkpty` or `fork`. The child path itself uses only raw Linux
+//! syscalls, fixed-size stack operations, and `_exit`.
 
 use std::ffi::CStr;
 use std::ffi::CString;
+use std::os::fd::RawFd;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::ptr;
 
@@ -15,6 +17,9 @@ use anyhow::bail;
 use nix::libc;
 use nix::unistd::User;
 
+use crate::config::TerminalProfileConfig;
+
+const CHILD_EXIT_STDIO: i32 = 118;
 const CHILD_EXIT_SET_GROUPS: i32 = 120;
 const CHILD_EXIT_SET_GID: i32 = 121;
 const CHILD_EXIT_VERIFY_GID: i32 = 122;
@@ -64,6 +69,8 @@ pub(super) struct PreparedChild {
     environment: Vec<CString>,
     environment_pointers: Vec<*const libc::c_char>,
     group_verification_buffer: Vec<libc::gid_t>,
+    /// Standard input, output, and error of an exec child, which runs without a PTY.
+    stdio: Option<[RawFd; 3]>,
 }
 
 /// Build the production login-shell invocation before forking.
@@ -72,6 +79,102 @@ pub(super) fn prepare(user: &User, shell: &str, arg0: &str) -> anyhow::Result<Pr
     prepare_with_argv(user, shell, argv)
 }
 
+/// Build the invocation of the program of a terminal profile before forking.
+///
+/// The profile environment is added to the minimal terminal environment, replacing
+/// variables of the same name.
+pub(super) fn prepare_profile(
+    user: &User,
+    profile: &TerminalProfileConfig,
+) -> anyhow::Result<PreparedChild> {
+    let Some(program) = profile.command.first() else {
+        bail!("terminal profile requires an executable");
+    };
+    let executable = resolve_executable(program)?;
+    let argv = profile
+        .command
+        .iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .context("terminal profile argument contains a NUL byte")?;
+    let mut prepared = prepare_with_argv(user, &executable, argv)?;
+    if let Some(directory) = &profile.working_directory {
+        if !directory.is_absolute() {
+            bail!("terminal profile working directory must be an absolute path");
+        }
+        prepared.cwd = CString::new(directory.as_os_str().as_bytes())
+            .context("terminal profile working directory contains a NUL byte")?;
+    }
+    for (name, value) in profile.environment.iter().flatten() {
+        if name.is_empty() || name.contains('=') {
+            bail!("invalid terminal profile environment variable {name:?}");
+        }
+        let entry = environment_entry(name.as_bytes(), value.as_bytes())?;
+        let prefix = &entry.as_bytes()[..=name.len()];
+        prepared
+            .environment
+            .retain(|existing| !existing.as_bytes().starts_with(prefix));
+        prepared.environment.push(entry);
+    }
+    prepared.environment_pointers = pointers_with_null(&prepared.environment);
+    Ok(prepared)
+}
+
+/// Build an exec child that runs `argv` without a PTY before forking.
+///
+/// The child starts a new session and uses the descriptors in `stdio` as its standard
+/// input, output, and error. They must not be standard descriptors themselves.
+pub(super) fn prepare_command(
+    user: &User,
+    argv: &[String],
+    stdio: [RawFd; 3],
+) -> anyhow::Result<PreparedChild> {
+    let Some(program) = argv.first() else {
+        bail!("command requires an executable");
+    };
+    if stdio.iter().any(|fd| *fd <= libc::STDERR_FILENO) {
+        bail!("command streams must not use standard descriptors");
+    }
+    let executable = resolve_executable(program)?;
+    let argv = argv
+        .iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .context("command argument contains a NUL byte")?;
+    let mut prepared = prepare_with_argv(user, &executable, argv)?;
+    let username =
+        CString::new(user.name.as_bytes()).context("terminal username contains a NUL byte")?;
+    let shell = match user.shell.as_os_str().as_bytes() {
+        [] => b"/bin/sh".as_slice(),
+        shell => shell,
+    };
+    prepared.environment = command_environment(&username, &prepared.cwd, shell)?;
+    prepared.environment_pointers = pointers_with_null(&prepared.environment);
+    prepared.stdio = Some(stdio);
+    Ok(prepared)
+}
+
+/// Resolve a bare executable name through the `PATH` of terminal children.
+fn resolve_executable(program: &str) -> anyhow::Result<String> {
+    if program.contains('/') {
+        if !Path::new(program).is_absolute() {
+            bail!("executable {program:?} must be an absolute path or a bare name");
+        }
+        return Ok(program.to_owned());
+    }
+    let search_path = std::str::from_utf8(TERMINAL_PATH).context("invalid terminal PATH")?;
+    search_path
+        .split(':')
+        .map(|directory| Path::new(directory).join(program))
+        .find(|candidate| {
+            candidate.metadata().is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            })
+        })
+        .and_then(|candidate| candidate.to_str().map(str::to_owned))
+        .with_context(|| format!("executable {program:?} not found"))
+}
+
 fn prepare_with_argv(
     user: &User,
     executable: &str,
@@ -105,6 +208,7 @@ fn prepare_with_argv(
         argv_pointers: Vec::new(),
         environment,
         environment_pointers: Vec::new(),
+        stdio: None,
     };
     prepared.argv_pointers = pointers_with_null(&prepared.argv);
     prepared.environment_pointers = pointers_with_null(&prepared.environment);
@@ -194,6 +298,16 @@ fn minimal_environment(username: &CStr, home: &CStr, shell: &CStr) -> anyhow::Re
     ])
 }
 
+fn command_environment(username: &CStr, home: &CStr, shell: &[u8]) -> anyhow::Result<Vec<CString>> {
+    Ok(vec![
+        environment_entry(b"HOME", home.to_bytes())?,
+        environment_entry(b"USER", username.to_bytes())?,
+        environment_entry(b"LOGNAME", username.to_bytes())?,
+        environment_entry(b"SHELL", shell)?,
+        environment_entry(b"PATH", TERMINAL_PATH)?,
+    ])
+}
+
 fn environment_entry(name: &[u8], value: &[u8]) -> anyhow::Result<CString> {
     let mut entry = Vec::with_capacity(name.len() + 1 + value.len());
     entry.extend_from_slice(name);
@@ -212,6 +326,7 @@ fn pointers_with_null(values: &[CString]) -> Vec<*const libc::c_char> {
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum ChildFailure {
+    Stdio,
     SetGroups,
     SetGid,
     VerifyGid,
@@ -225,6 +340,7 @@ enum ChildFailure {
 impl ChildFailure {
     const fn exit_code(self) -> i32 {
         match self {
+            Self::Stdio => CHILD_EXIT_STDIO,
             Self::SetGroups => CHILD_EXIT_SET_GROUPS,
             Self::SetGid => CHILD_EXIT_SET_GID,
             Self::VerifyGid => CHILD_EXIT_VERIFY_GID,
@@ -238,6 +354,7 @@ impl ChildFailure {
 
     const fn message(self) -> &'static [u8] {
         match self {
+            Self::Stdio => b"nexigon-agent: unable to set up command streams\n",
             Self::SetGroups => b"nexigon-agent: unable to set terminal supplementary groups\n",
             Self::SetGid => b"nexigon-agent: unable to set terminal group ID\n",
             Self::VerifyGid => b"nexigon-agent: terminal group ID verification failed\n",
@@ -260,6 +377,8 @@ trait ChildSyscalls {
     unsafe fn read_res_uid(&mut self, uids: &mut [libc::uid_t; 3]) -> bool;
     unsafe fn group_count(&mut self) -> Option<usize>;
     unsafe fn read_groups(&mut self, groups: *mut libc::gid_t, count: usize) -> bool;
+    unsafe fn new_session(&mut self) -> bool;
+    unsafe fn duplicate(&mut self, fd: RawFd, target: RawFd) -> bool;
     unsafe fn change_directory(&mut self, path: *const libc::c_char) -> bool;
     unsafe fn execute(
         &mut self,
@@ -315,6 +434,17 @@ impl ChildSyscalls for LinuxChildSyscalls {
         unsafe { libc::syscall(libc::SYS_getgroups, count, groups) == count as libc::c_long }
     }
 
+    unsafe fn new_session(&mut self) -> bool {
+        // SAFETY: Direct Linux syscall without arguments.
+        unsafe { libc::syscall(libc::SYS_setsid) >= 0 }
+    }
+
+    unsafe fn duplicate(&mut self, fd: RawFd, target: RawFd) -> bool {
+        // SAFETY: Direct Linux syscall with scalar arguments. The duplicate does not
+        // inherit the close-on-exec flag of `fd`.
+        unsafe { libc::syscall(libc::SYS_dup3, fd, target, 0) >= 0 }
+    }
+
     unsafe fn change_directory(&mut self, path: *const libc::c_char) -> bool {
         // SAFETY: Direct Linux syscall with a valid NUL-terminated path.
         unsafe { libc::syscall(libc::SYS_chdir, path) == 0 }
@@ -340,6 +470,19 @@ unsafe fn configure_and_exec<S: ChildSyscalls>(
     prepared: &mut PreparedChild,
     syscalls: &mut S,
 ) -> Result<(), ChildFailure> {
+    if let Some(stdio) = prepared.stdio {
+        // SAFETY: Raw syscall wrappers with scalar arguments.
+        if !unsafe { syscalls.new_session() } {
+            return Err(ChildFailure::Stdio);
+        }
+        for (target, fd) in (libc::STDIN_FILENO..).zip(stdio) {
+            // SAFETY: `fd` was opened before the fork and is not a standard descriptor.
+            if !unsafe { syscalls.duplicate(fd, target) } {
+                return Err(ChildFailure::Stdio);
+            }
+        }
+    }
+
     if prepared.credential_mode == CredentialMode::SetAndVerify {
         // SAFETY: The group vector was allocated before fork and remains alive.
         if !unsafe {
@@ -432,7 +575,7 @@ fn same_group_set(actual: &[libc::gid_t], expected: &[libc::gid_t]) -> bool {
 /// never runs Rust destructors in the post-fork process.
 pub(super) unsafe fn enter(prepared: &mut PreparedChild) -> ! {
     let mut syscalls = LinuxChildSyscalls;
-    // SAFETY: The caller is the single-threaded `forkpty` child and `prepared`
+    // SAFETY: The caller is the single-threaded `forkpty` or `fork` child and `prepared`
     // owns every buffer referenced by the raw syscall arguments.
     let failure = match unsafe { configure_and_exec(prepared, &mut syscalls) } {
         Ok(()) => ChildFailure::Exec,
@@ -455,6 +598,7 @@ pub(super) unsafe fn enter(prepared: &mut PreparedChild) -> ! {
 mod tests {
     use std::fs::File;
     use std::io::Read;
+    use std::io::Write;
     use std::os::fd::AsRawFd;
 
     use nix::sys::wait::WaitStatus;
@@ -465,6 +609,8 @@ mod tests {
 
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
     enum Operation {
+        NewSession,
+        Duplicate,
         SetGroups,
         SetGid,
         ReadGid,
@@ -559,6 +705,14 @@ mod tests {
             true
         }
 
+        unsafe fn new_session(&mut self) -> bool {
+            !self.fails(Operation::NewSession)
+        }
+
+        unsafe fn duplicate(&mut self, _fd: RawFd, _target: RawFd) -> bool {
+            !self.fails(Operation::Duplicate)
+        }
+
         unsafe fn change_directory(&mut self, _path: *const libc::c_char) -> bool {
             !self.fails(Operation::Chdir)
         }
@@ -625,6 +779,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn stream_failures_prevent_credential_changes_and_exec() {
+        for operation in [Operation::NewSession, Operation::Duplicate] {
+            let mut prepared = switched_test_child();
+            prepared.stdio = Some([10, 11, 12]);
+            let mut syscalls = FakeSyscalls::root(Some(operation));
+            // SAFETY: Fake syscalls operate entirely in the test process.
+            let failure = unsafe { configure_and_exec(&mut prepared, &mut syscalls) };
+            assert_eq!(failure, Err(ChildFailure::Stdio), "fault at {operation:?}");
+            assert_eq!(syscalls.calls.last(), Some(&operation));
+            assert!(!syscalls.calls.contains(&Operation::SetUid));
+        }
+
+        let mut prepared = switched_test_child();
+        prepared.stdio = Some([10, 11, 12]);
+        let mut syscalls = FakeSyscalls::root(None);
+        // SAFETY: Fake syscalls operate entirely in the test process.
+        let _ = unsafe { configure_and_exec(&mut prepared, &mut syscalls) };
+        assert_eq!(
+            syscalls.calls[..4],
+            [
+                Operation::NewSession,
+                Operation::Duplicate,
+                Operation::Duplicate,
+                Operation::Duplicate
+            ]
+        );
+    }
+
+    #[test]
+    fn command_executables_are_resolved_through_the_terminal_path() {
+        let sh = resolve_executable("sh").unwrap();
+        assert!(Path::new(&sh).is_absolute() && sh.ends_with("/sh"), "{sh}");
+        assert_eq!(resolve_executable("/bin/sh").unwrap(), "/bin/sh");
+        assert!(resolve_executable("bin/sh").is_err());
+        assert!(resolve_executable("definitely-not-a-nexigon-command").is_err());
+    }
+
     #[test]
     fn credential_mismatches_prevent_exec() {
         let cases = [
@@ -795,6 +987,78 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn profiles_run_their_command_in_their_directory_and_environment() {
+        let directory = tempfile::tempdir().unwrap();
+        let profile = TerminalProfileConfig::new(
+            "diagnostics".to_owned(),
+            ["sh", "-c", "pwd; echo \"$TERM $MENU\""]
+                .map(str::to_owned)
+                .to_vec(),
+        )
+        .with_environment(Some(
+            [("TERM", "vt100"), ("MENU", "main")]
+                .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                .into_iter()
+                .collect(),
+        ))
+        .with_working_directory(Some(directory.path().to_path_buf()));
+        let prepared = prepare_profile(&current_user(), &profile).unwrap();
+
+        let (code, output) = run_child_and_capture(prepared);
+        assert_eq!(code, 0);
+        let expected = format!(
+            "{}\nvt100 main\n",
+            directory.path().canonicalize().unwrap().display()
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let relative = profile.with_working_directory(Some("relative".into()));
+        assert!(prepare_profile(&current_user(), &relative).is_err());
+    }
+
+    #[test]
+    fn commands_run_in_a_new_session_with_separate_streams() {
+        let pipe = || nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).unwrap();
+        let (stdin_read, stdin_write) = pipe();
+        let (stdout_read, stdout_write) = pipe();
+        let (stderr_read, stderr_write) = pipe();
+        let argv = [
+            "sh",
+            "-c",
+            "read line; echo \"out:$line\"; echo err >&2; \
+             test \"$(cut -d' ' -f6 /proc/$$/stat)\" = $$",
+        ]
+        .map(str::to_owned);
+        let mut prepared = prepare_command(
+            &current_user(),
+            &argv,
+            [
+                stdin_read.as_raw_fd(),
+                stdout_write.as_raw_fd(),
+                stderr_write.as_raw_fd(),
+            ],
+        )
+        .unwrap();
+
+        // SAFETY: The child only enters the production async-signal-safe child path.
+        match unsafe { nix::unistd::fork() }.unwrap() {
+            ForkResult::Child => unsafe { enter(&mut prepared) },
+            ForkResult::Parent { child } => {
+                drop((stdin_read, stdout_write, stderr_write));
+                File::from(stdin_write).write_all(b"input\n").unwrap();
+                let mut stdout = Vec::new();
+                File::from(stdout_read).read_to_end(&mut stdout).unwrap();
+                let mut stderr = Vec::new();
+                File::from(stderr_read).read_to_end(&mut stderr).unwrap();
+                let status = nix::sys::wait::waitpid(child, None).unwrap();
+                assert_eq!(status, WaitStatus::Exited(child, 0));
+                assert_eq!(stdout, b"out:input\n");
+                assert_eq!(stderr, b"err\n");
+            }
+        }
+    }
+
     #[test]
     fn chdir_failure_has_deterministic_exit_and_never_executes() {
         let temporary = tempfile::tempdir().unwrap();
@@ -0,0 +1,481 @@
+//! Non-interactive command execution over `exec` channels.
+//!
+//! The hub starts a session with a command frame holding an [`ExecRequest`], followed
+//! by exec frames carrying standard input. The command runs as a terminal user without
+//! a PTY and in its own session. Standard output and standard error are relayed as
+//! separate exec frames, and the session ends with exactly one exit, signal, or error
+//! frame.
+
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::bail;
+use nexigon_agent_protocol::ExecRequest;
+use nexigon_agent_protocol::ExecStream;
+use nexigon_agent_protocol::FRAME_READ_TIMEOUT;
+use nexigon_agent_protocol::FrameError;
+use nexigon_agent_protocol::HubExecFrame;
+use nexigon_agent_protocol::read_command_frame;
+use nexigon_agent_protocol::read_hub_exec_frame;
+use nexigon_agent_protocol::write_exec_error;
+use nexigon_agent_protocol::write_exec_exit;
+use nexigon_agent_protocol::write_exec_output;
+use nexigon_agent_protocol::write_exec_signal;
+use nix::libc;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::unix::pipe;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+
+use super::ChildExit;
+use super::PTY_DRAIN_GRACE;
+use super::child;
+use super::resolve_session_user;
+use super::terminate_terminal_child;
+use super::wait_for_child;
+use crate::config::Config;
+
+/// Command started for an exec session.
+struct StartedCommand {
+    child: nix::unistd::Pid,
+    stdin: pipe::Sender,
+    stdout: pipe::Receiver,
+    stderr: pipe::Receiver,
+}
+
+/// Handle an exec session that is cancelled with its owning connection.
+///
+/// Commands run as `requested_user`, or the default terminal user, subject to the
+/// same allowlist as terminal sessions.
+pub(crate) async fn handle_exec_session_with_cancellation(
+    channel: nexigon_multiplex::Channel,
+    config: &Arc<Config>,
+    requested_user: Option<&str>,
+    cancellation: CancellationToken,
+) -> anyhow::Result<()> {
+    if cancellation.is_cancelled() {
+        return Ok(());
+    }
+    let (mut chan_writer, mut chan_reader) = channel.split();
+
+    let request = tokio::select! {
+        () = cancellation.cancelled() => return Ok(()),
+        request = tokio::time::timeout(
+            FRAME_READ_TIMEOUT,
+            read_command_frame::<ExecRequest>(&mut chan_reader),
+        ) => request
+            .context("timed out waiting for the exec request")?
+            .context("failed to read exec request")?,
+    };
+
+    let StartedCommand {
+        child,
+        stdin,
+        mut stdout,
+        mut stderr,
+    } = match start_command(config, requested_user, &request.argv).await {
+        Ok(started) => started,
+        Err(error) => {
+            write_exec_error(&mut chan_writer, &format!("{error:#}"))
+                .await
+                .ok();
+            chan_writer.shutdown().await.ok();
+            return Err(error);
+        }
+    };
+
+    let channel_to_stdin = async {
+        let mut stdin = Some(stdin);
+        let mut stdin_closed = false;
+        loop {
+            let frame = match read_hub_exec_frame(&mut chan_reader).await {
+                Ok(frame) => frame,
+                // The hub may close its side of the channel once standard input has
+                // ended. The command keeps running until it exits on its own.
+                Err(FrameError::Io(error))
+                    if stdin_closed && error.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return std::future::pending().await;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            match frame {
+                HubExecFrame::Stdin(data) => {
+                    if let Some(pipe) = &mut stdin
+                        && let Err(error) = pipe.write_all(&data).await
+                    {
+                        // The command no longer reads its standard input; discard the
+                        // rest instead of failing the session.
+                        debug!(?error, "exec command closed its standard input");
+                        stdin = None;
+                    }
+                }
+                HubExecFrame::StdinClose => {
+                    stdin = None;
+                    stdin_closed = true;
+                }
+            }
+        }
+    };
+
+    let output_to_channel = async {
+        let mut stdout_open = true;
+        let mut stderr_open = true;
+        let mut stdout_buf = vec![0u8; 4096];
+        let mut stderr_buf = vec![0u8; 4096];
+        while stdout_open || stderr_open {
+            let (stream, result) = tokio::select! {
+                result = stdout.read(&mut stdout_buf), if stdout_open => {
+                    (ExecStream::Stdout, result)
+                }
+                result = stderr.read(&mut stderr_buf), if stderr_open => {
+                    (ExecStream::Stderr, result)
+                }
+            };
+            let n = result.context("failed to read command output")?;
+            let (open, buf) = match stream {
+                ExecStream::Stdout => (&mut stdout_open, &stdout_buf),
+                ExecStream::Stderr => (&mut stderr_open, &stderr_buf),
+            };
+            if n == 0 {
+                *open = false;
+                continue;
+            }
+            write_exec_output(&mut chan_writer, stream, &buf[..n]).await?;
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let mut channel_to_stdin = Box::pin(channel_to_stdin);
+    let mut output_to_channel = Box::pin(output_to_channel);
+    let mut wait_child = Box::pin(wait_for_child(child));
+
+    enum SessionEnd {
+        Channel(anyhow::Result<()>),
+        Output(anyhow::Error),
+        Child(anyhow::Result<ChildExit>),
+        Cancelled,
+    }
+
+    // Unlike a PTY, the output streams may end long before the command exits.
+    let mut output_done = false;
+    let end = loop {
+        tokio::select! {
+            biased;
+            () = cancellation.cancelled() => break SessionEnd::Cancelled,
+            result = &mut wait_child => break SessionEnd::Child(result),
+            result = &mut output_to_channel, if !output_done => match result {
+                Ok(()) => output_done = true,
+                Err(error) => break SessionEnd::Output(error),
+            },
+            result = &mut channel_to_stdin => break SessionEnd::Channel(result),
+        }
+    };
+
+    let exit = match end {
+        SessionEnd::Child(result) => {
+            let exit = result.context("failed to reap exec child")?;
+            debug!(?exit, "exec child exited");
+            // Background descendants in the session of the command may still hold the
+            // output pipes open. Stop them so the streams end once drained.
+            nix::sys::signal::killpg(child, nix::sys::signal::Signal::SIGKILL).ok();
+            if !output_done {
+                match tokio::time::timeout(PTY_DRAIN_GRACE, &mut output_to_channel).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(error)) => {
+                        drop(output_to_channel);
+                        drop(channel_to_stdin);
+                        drop(wait_child);
+                        chan_writer.shutdown().await.ok();
+                        return Err(error).context("failed to drain command output");
+                    }
+                    Err(_) => {
+                        // The canceled write may already have emitted part of an output
+                        // frame. Close instead of appending an exit frame to a stream
+                        // whose alignment is no longer known.
+                        drop(output_to_channel);
+                        drop(channel_to_stdin);
+                        drop(wait_child);
+                        chan_writer.shutdown().await.ok();
+                        bail!("timed out draining command output");
+                    }
+                }
+            }
+            exit
+        }
+        SessionEnd::Output(error) => {
+            debug!(?error, "command output relay failed; closing exec channel");
+            drop(output_to_channel);
+            drop(channel_to_stdin);
+            drop(wait_child);
+            terminate_terminal_child(child).await;
+            chan_writer.shutdown().await.ok();
+            return Err(error);
+        }
+        SessionEnd::Channel(result) => {
+            debug!(?result, "invalid or closed exec input; closing channel");
+            drop(output_to_channel);
+            drop(channel_to_stdin);
+            drop(wait_child);
+            terminate_terminal_child(child).await;
+            chan_writer.shutdown().await.ok();
+            return result;
+        }
+        SessionEnd::Cancelled => {
+            debug!("exec session cancelled; terminating child");
+            drop(output_to_channel);
+            drop(channel_to_stdin);
+            drop(wait_child);
+            terminate_terminal_child(child).await;
+            chan_writer.shutdown().await.ok();
+            return Ok(());
+        }
+    };
+
+    // Dropping all relay/wait futures releases their mutable borrows before the one
+    // and only status frame is sent.
+    drop(output_to_channel);
+    drop(channel_to_stdin);
+    drop(wait_child);
+    match exit {
+        ChildExit::Exited(code) => write_exec_exit(&mut chan_writer, code).await?,
+        ChildExit::Signaled(signal) => write_exec_signal(&mut chan_writer, signal).await?,
+    }
+    chan_writer.shutdown().await?;
+    Ok(())
+}
+
+/// Check the request against the terminal configuration and start the command.
+async fn start_command(
+    config: &Config,
+    requested_user: Option<&str>,
+    argv: &[String],
+) -> anyhow::Result<StartedCommand> {
+    if !crate::config::terminal_enabled(config) {
+        bail!("terminal is not enabled or terminal.user is invalid");
+    }
+    let user = resolve_session_user(config, requested_user)?;
+
+    let (stdin_read, stdin_write) = command_pipe()?;
+    let (stdout_read, stdout_write) = command_pipe()?;
+    let (stderr_read, stderr_write) = command_pipe()?;
+    let mut prepared_child = child::prepare_command(
+        &user,
+        argv,
+        [
+            stdin_read.as_raw_fd(),
+            stdout_write.as_raw_fd(),
+            stderr_write.as_raw_fd(),
+        ],
+    )
+    .context("failed to prepare exec child")?;
+
+    info!(
+        username = user.name.as_str(),
+        program = argv[0].as_str(),
+        "spawning exec command"
+    );
+
+    // SAFETY: We only call async-signal-safe functions in the child.
+    let fork_result = unsafe { nix::unistd::fork() }.context("failed to fork")?;
+
+    match fork_result {
+        nix::unistd::ForkResult::Child => {
+            // SAFETY: All allocating/NSS work and pointer construction happened
+            // before `fork`. `enter` uses only raw Linux syscalls and `_exit`.
+            unsafe { child::enter(&mut prepared_child) }
+        }
+        nix::unistd::ForkResult::Parent { child } => {
+            // Raw pointer arrays in the child preparation are not needed by the
+            // parent and must not remain live across any async suspension point.
+            drop(prepared_child);
+            // The child owns its ends now. Keeping them open here would prevent the
+            // output streams from ever reaching EOF.
+            drop(stdin_read);
+            drop(stdout_write);
+            drop(stderr_write);
+            let parent_setup = (|| {
+                Ok::<_, anyhow::Error>(StartedCommand {
+                    child,
+                    stdin: pipe::Sender::from_owned_fd(stdin_write)
+                        .context("failed to register stdin pipe")?,
+                    stdout: pipe::Receiver::from_owned_fd(stdout_read)
+                        .context("failed to register stdout pipe")?,
+                    stderr: pipe::Receiver::from_owned_fd(stderr_read)
+                        .context("failed to register stderr pipe")?,
+                })
+            })();
+            match parent_setup {
+                Ok(started) => Ok(started),
+                Err(error) => {
+                    terminate_terminal_child(child).await;
+                    Err(error).context("failed to initialize exec parent")
+                }
+            }
+        }
+    }
+}
+
+/// Create a close-on-exec pipe for one of the standard streams of a command.
+fn command_pipe() -> anyhow::Result<(OwnedFd, OwnedFd)> {
+    let (read, write) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)
+        .context("failed to create command pipe")?;
+    Ok((above_stdio(read)?, above_stdio(write)?))
+}
+
+/// Move a descriptor above the standard descriptors.
+///
+/// If the agent runs with closed standard descriptors, a new pipe may occupy them,
+/// and the child would close it while installing its own standard streams.
+fn above_stdio(fd: OwnedFd) -> anyhow::Result<OwnedFd> {
+    if fd.as_raw_fd() > libc::STDERR_FILENO {
+        return Ok(fd);
+    }
+    let moved = nix::fcntl::fcntl(
+        fd.as_raw_fd(),
+        nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(libc::STDERR_FILENO + 1),
+    )
+    .context("failed to move command pipe")?;
+    // SAFETY: `F_DUPFD_CLOEXEC` returned a new descriptor that nothing else owns.
+    Ok(unsafe { OwnedFd::from_raw_fd(moved) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use nexigon_agent_protocol::DeviceExecFrame;
+    use nexigon_agent_protocol::read_device_exec_frame;
+    use nexigon_agent_protocol::write_command_frame;
+    use nexigon_agent_protocol::write_exec_stdin;
+    use nexigon_agent_protocol::write_exec_stdin_close;
+    use nexigon_multiplex::Connection;
+    use nexigon_multiplex::ConnectionEvent;
+    use nexigon_multiplex::transport::InMemory;
+    use nix::unistd::User;
+
+    use super::*;
+    use crate::config::TerminalConfig;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn exec_sessions_relay_separate_streams_and_report_the_exit() {
+        let (hub_transport, agent_transport) = InMemory::<Bytes, Bytes>::new_buffered(64);
+        let mut hub_connection = Connection::new(hub_transport);
+        let mut agent_connection = Connection::new(agent_transport);
+        let mut hub_ref = hub_connection.make_ref();
+        let (channel_tx, mut channel_rx) = tokio::sync::mpsc::channel(1);
+
+        let hub_driver = tokio::spawn(async move {
+            while let Some(event) = hub_connection.next().await {
+                if !matches!(event, Ok(ConnectionEvent::Connected)) {
+                    break;
+                }
+            }
+        });
+        let agent_driver = tokio::spawn(async move {
+            while let Some(event) = agent_connection.next().await {
+                match event {
+                    Ok(ConnectionEvent::Connected) => {}
+                    Ok(ConnectionEvent::RequestChannel(request)) => {
+                        let channel_tx = channel_tx.clone();
+                        request.accept(move |channel| {
+                            let _ = channel_tx.try_send(channel);
+                        });
+                    }
+                    Ok(ConnectionEvent::Closed) | Err(_) => break,
+                }
+            }
+        });
+
+        let user = User::from_uid(nix::unistd::geteuid())
+            .unwrap()
+            .expect("current user must exist");
+        let username = user.name.clone();
+        let config = Arc::new(
+            Config::new(PathBuf::from("unused-fingerprint")).with_terminal(Some(
+                TerminalConfig::new()
+                    .with_enabled(Some(true))
+                    .with_user(Some(user.name)),
+            )),
+        );
+
+        let cases = [
+            (None, "cat; echo err >&2; exit 5", DeviceExecFrame::Exit(5)),
+            (None, "kill -TERM $$", DeviceExecFrame::Signal(15)),
+            (
+                Some("nexigon-missing-user"),
+                "true",
+                DeviceExecFrame::Error(format!(
+                    "user \"nexigon-missing-user\" is not allowed (only the default user \
+                     {username:?} is permitted; configure `allowed-users` to allow additional \
+                     users)"
+                )),
+            ),
+        ];
+        for (requested_user, script, expected) in cases {
+            let mut hub_channel = hub_ref.open(b"exec").await.expect("exec channel rejected");
+            let agent_channel = channel_rx.recv().await.expect("agent driver stopped");
+            let session_config = config.clone();
+            let session = tokio::spawn(async move {
+                handle_exec_session_with_cancellation(
+                    agent_channel,
+                    &session_config,
+                    requested_user,
+                    CancellationToken::new(),
+                )
+                .await
+            });
+            let request = ExecRequest {
+                argv: ["sh", "-c", script].map(str::to_owned).to_vec(),
+            };
+            write_command_frame(&mut hub_channel, &request)
+                .await
+                .unwrap();
+            if requested_user.is_none() {
+                write_exec_stdin(&mut hub_channel, b"input\n")
+                    .await
+                    .unwrap();
+                write_exec_stdin_close(&mut hub_channel).await.unwrap();
+            }
+
+            let mut stdout = Vec::new();
+            let mut stderr = Vec::new();
+            let end = tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    match read_device_exec_frame(&mut hub_channel).await.unwrap() {
+                        DeviceExecFrame::Stdout(data) => stdout.extend(data),
+                        DeviceExecFrame::Stderr(data) => stderr.extend(data),
+                        end => break end,
+                    }
+                }
+            })
+            .await
+            .expect("exec session did not end");
+            assert_eq!(end, expected, "{script}");
+            if script.starts_with("cat") {
+                assert_eq!(stdout, b"input\n");
+                assert_eq!(stderr, b"err\n");
+            }
+            assert!(
+                read_device_exec_frame(&mut hub_channel).await.is_err(),
+                "a frame followed the end of the exec session"
+            );
+            let result = tokio::time::timeout(Duration::from_secs(2), session)
+                .await
+                .expect("exec handler did not finish")
+                .expect("exec handler panicked");
+            assert_eq!(result.is_err(), requested_user.is_some());
+        }
+
+        hub_driver.abort();
+        agent_driver.abort();
+    }
+}
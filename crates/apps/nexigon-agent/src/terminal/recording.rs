@@ -0,0 +1,711 @@
+//! Terminal session recordings.
+//!
+//! With `terminal.recording` enabled, every terminal session is written to an
+//! [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) file in the recording
+//! directory while it runs. The header names the Unix user and the shell of the session
+//! next to its start time, and the events hold the terminal output and resizes with their
+//! time since the start. Input is not recorded. A session is refused if its recording
+//! cannot be created.
+//!
+//! Recordings are written on a blocking thread of their own, so that a slow disk does not
+//! hold up the session. When a session ends, the oldest recordings are deleted until the
+//! retention limits are met. If finished recordings are uploaded or announced, a
+//! `.pending` marker with the session summary is written next to the recording and
+//! removed once the recording has been delivered, so recordings of sessions that end
+//! while the hub is unreachable are delivered after the agent has reconnected.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use jiff::Timestamp;
+use nexigon_api::types::devices::DeviceEvent;
+use nexigon_api::types::devices::DeviceEventSeverity;
+use nexigon_api::types::devices::PublishDeviceEventsAction;
+use nexigon_api::types::repositories::CreateAssetOutput;
+use nexigon_client::ClientExecutor;
+use nexigon_ids::Generate;
+use nexigon_ids::ids::DeviceEventId;
+use nexigon_ids::ids::DeviceId;
+use serde_json::Value;
+use serde_json::json;
+use tokio::sync::Notify;
+use tokio::sync::mpsc;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::warn;
+
+use crate::config::Config;
+use crate::config::TerminalRecordingConfig;
+
+const DEFAULT_DIRECTORY_NAME: &str = "terminal-recordings";
+const DEFAULT_MAX_RECORDINGS: u32 = 100;
+const DEFAULT_MAX_TOTAL_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Terminal size recorded for sessions whose size the hub does not report.
+const DEFAULT_TERMINAL_SIZE: (u16, u16) = (80, 24);
+const RECORDING_EXTENSION: &str = "cast";
+const PENDING_EXTENSION: &str = "pending";
+/// Category of the event announcing a finished recording.
+const RECORDING_EVENT_CATEGORY: &str = "dev.nexigon.terminal.recording";
+/// Delay before retrying recordings that could not be delivered.
+const DELIVERY_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Recording configuration, if session recording is enabled.
+fn recording_config(config: &Config) -> Option<&TerminalRecordingConfig> {
+    config
+        .terminal
+        .as_ref()?
+        .recording
+        .as_ref()
+        .filter(|recording| recording.enabled == Some(true))
+}
+
+/// Whether finished recordings are uploaded or announced.
+fn delivery_enabled(config: &TerminalRecordingConfig) -> bool {
+    config.upload_repository.is_some() || config.announce == Some(true)
+}
+
+/// Recordings of terminal sessions, shared by the sessions and the delivery task.
+#[derive(Debug)]
+pub(crate) struct TerminalRecordings {
+    /// Agent data directory, relative recording directories are resolved against.
+    data_path: PathBuf,
+    /// Recordings that are still written, which are exempt from retention.
+    active: watch::Sender<HashSet<PathBuf>>,
+    /// Notified when a finished recording is waiting for delivery.
+    pending: Notify,
+}
+
+impl TerminalRecordings {
+    pub(crate) fn new(data_path: PathBuf) -> Self {
+        Self {
+            data_path,
+            active: watch::Sender::new(HashSet::new()),
+            pending: Notify::new(),
+        }
+    }
+
+    fn directory(&self, config: &TerminalRecordingConfig) -> PathBuf {
+        self.data_path.join(
+            config
+                .directory
+                .as_deref()
+                .unwrap_or(Path::new(DEFAULT_DIRECTORY_NAME)),
+        )
+    }
+
+    /// Start recording a session of `user` running `shell`, if recording is enabled.
+    pub(super) fn start(
+        self: &Arc<Self>,
+        config: &Config,
+        user: &str,
+        shell: &str,
+    ) -> anyhow::Result<Option<Recording>> {
+        let Some(config) = recording_config(config) else {
+            return Ok(None);
+        };
+        let directory = self.directory(config);
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&directory)
+            .with_context(|| format!("creating recording directory {}", directory.display()))?;
+        let started_at = Timestamp::now();
+        let path = directory.join(format!(
+            "{}-{:08x}.{RECORDING_EXTENSION}",
+            started_at.strftime("%Y%m%dT%H%M%SZ"),
+            rand::random::<u32>()
+        ));
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .with_context(|| format!("creating recording {}", path.display()))?;
+        self.active.send_modify(|active| {
+            active.insert(path.clone());
+        });
+        debug!(path = %path.display(), "recording terminal session");
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let writer = RecordingWriter {
+            recordings: self.clone(),
+            config: config.clone(),
+            path,
+            user: user.to_owned(),
+            shell: shell.to_owned(),
+            started_at,
+        };
+        tokio::task::spawn_blocking(move || writer.run(file, events_rx));
+        Ok(Some(Recording {
+            started: Instant::now(),
+            events,
+        }))
+    }
+
+    /// Wait until all recordings have been finished.
+    #[cfg(test)]
+    pub(super) async fn finished(&self) {
+        let mut active = self.active.subscribe();
+        let _ = active.wait_for(HashSet::is_empty).await;
+    }
+}
+
+/// Recording of a running terminal session, finished when dropped.
+pub(super) struct Recording {
+    started: Instant,
+    /// Events for the writer of the recording.
+    events: mpsc::UnboundedSender<RecordingEvent>,
+}
+
+impl Recording {
+    /// Record terminal output.
+    pub(super) fn output(&self, data: &[u8]) {
+        let time = self.started.elapsed();
+        let _ = self
+            .events
+            .send(RecordingEvent::Output(time, data.to_vec()));
+    }
+
+    /// Record a resize of the terminal.
+    pub(super) fn resize(&self, cols: u16, rows: u16) {
+        let time = self.started.elapsed();
+        let _ = self.events.send(RecordingEvent::Resize(time, cols, rows));
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        let _ = self
+            .events
+            .send(RecordingEvent::End(self.started.elapsed()));
+    }
+}
+
+/// Event of a recording, with its time since the start of the session.
+enum RecordingEvent {
+    Output(Duration, Vec<u8>),
+    Resize(Duration, u16, u16),
+    End(Duration),
+}
+
+/// Writer of a recording, running on a blocking thread until the session ends.
+struct RecordingWriter {
+    recordings: Arc<TerminalRecordings>,
+    /// Recording configuration at the start of the session.
+    config: TerminalRecordingConfig,
+    path: PathBuf,
+    user: String,
+    shell: String,
+    started_at: Timestamp,
+}
+
+impl RecordingWriter {
+    fn run(self, file: File, mut events: mpsc::UnboundedReceiver<RecordingEvent>) {
+        let header = CastHeader {
+            user: &self.user,
+            shell: &self.shell,
+            started_at: self.started_at,
+        };
+        let max_bytes = self
+            .config
+            .max_total_bytes
+            .unwrap_or(DEFAULT_MAX_TOTAL_BYTES);
+        let mut writer = CastWriter::new(file, max_bytes);
+        let mut duration = Duration::ZERO;
+        while let Some(event) = events.blocking_recv() {
+            match event {
+                RecordingEvent::Output(time, data) => {
+                    duration = time;
+                    writer.output(time, &data, &header);
+                }
+                RecordingEvent::Resize(time, cols, rows) => {
+                    duration = time;
+                    writer.resize(time, cols, rows);
+                }
+                RecordingEvent::End(time) => {
+                    duration = time;
+                    break;
+                }
+            }
+        }
+        let size = writer.finish(duration, &header);
+        drop(writer);
+        if let Err(error) = self.finish(duration, size) {
+            warn!(?error, path = %self.path.display(), "failed to finish terminal recording");
+        }
+        self.recordings.active.send_modify(|active| {
+            active.remove(&self.path);
+        });
+    }
+
+    fn finish(&self, duration: Duration, size: u64) -> anyhow::Result<()> {
+        if delivery_enabled(&self.config) {
+            let summary = json!({
+                "file": self.path.file_name().map(|name| name.to_string_lossy()),
+                "user": self.user,
+                "shell": self.shell,
+                "started-at": self.started_at,
+                "duration-secs": duration.as_secs_f64(),
+                "size": size,
+            });
+            write_marker(&self.path.with_extension(PENDING_EXTENSION), &summary)?;
+            self.recordings.pending.notify_one();
+        }
+        // This recording is finished, so it is subject to retention itself.
+        let mut active = self.recordings.active.borrow().clone();
+        active.remove(&self.path);
+        prune_recordings(
+            &self.recordings.directory(&self.config),
+            &self.config,
+            &active,
+            SystemTime::now(),
+        )
+        .context("applying recording retention")
+    }
+}
+
+/// Session information in the header of a recording.
+struct CastHeader<'a> {
+    user: &'a str,
+    shell: &'a str,
+    started_at: Timestamp,
+}
+
+/// Writer of the asciicast v2 lines of a recording.
+///
+/// The header is written with the first output, so that it carries the size the hub
+/// reported at the start of the session instead of a default.
+struct CastWriter<W> {
+    /// Destination, until the recording fails or reaches its size limit.
+    output: Option<W>,
+    size: (u16, u16),
+    header_written: bool,
+    written: u64,
+    max_bytes: u64,
+    /// Trailing bytes of an incomplete UTF-8 sequence in the output so far.
+    incomplete: Vec<u8>,
+}
+
+impl<W: Write> CastWriter<W> {
+    fn new(output: W, max_bytes: u64) -> Self {
+        Self {
+            output: Some(output),
+            size: DEFAULT_TERMINAL_SIZE,
+            header_written: false,
+            written: 0,
+            max_bytes,
+            incomplete: Vec::new(),
+        }
+    }
+
+    fn output(&mut self, time: Duration, data: &[u8], header: &CastHeader) {
+        self.incomplete.extend_from_slice(data);
+        let (text, incomplete) = decode_utf8(&self.incomplete);
+        self.incomplete = incomplete.to_vec();
+        if !text.is_empty() {
+            self.write_header(header);
+            self.write_line(&json!([event_time(time), "o", text]));
+        }
+    }
+
+    fn resize(&mut self, time: Duration, cols: u16, rows: u16) {
+        if self.header_written {
+            self.write_line(&json!([event_time(time), "r", format!("{cols}x{rows}")]));
+        } else {
+            self.size = (cols, rows);
+        }
+    }
+
+    /// Write what is still buffered and return the size of the recording.
+    fn finish(&mut self, time: Duration, header: &CastHeader) -> u64 {
+        self.write_header(header);
+        if !self.incomplete.is_empty() {
+            let text = String::from_utf8_lossy(&self.incomplete).into_owned();
+            self.incomplete.clear();
+            self.write_line(&json!([event_time(time), "o", text]));
+        }
+        self.output = None;
+        self.written
+    }
+
+    fn write_header(&mut self, header: &CastHeader) {
+        if self.header_written {
+            return;
+        }
+        self.header_written = true;
+        let (width, height) = self.size;
+        self.write_line(&json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": header.started_at.as_second(),
+            "env": {
+                "SHELL": header.shell,
+                "TERM": "xterm-256color",
+            },
+            "user": header.user,
+        }));
+    }
+
+    fn write_line(&mut self, value: &Value) {
+        let Some(output) = &mut self.output else {
+            return;
+        };
+        let mut line = value.to_string();
+        line.push('\n');
+        if self.written + line.len() as u64 > self.max_bytes {
+            warn!("terminal recording reached its size limit, no longer recording the session");
+            self.output = None;
+            return;
+        }
+        if let Err(error) = output.write_all(line.as_bytes()) {
+            warn!(?error, "failed to write terminal recording");
+            self.output = None;
+            return;
+        }
+        self.written += line.len() as u64;
+    }
+}
+
+/// Time of an event in seconds, with microsecond precision.
+fn event_time(time: Duration) -> f64 {
+    time.as_micros() as f64 / 1_000_000.0
+}
+
+/// Decode `bytes` up to a trailing incomplete UTF-8 sequence, which is returned.
+///
+/// Invalid sequences are replaced with `U+FFFD`.
+fn decode_utf8(mut bytes: &[u8]) -> (String, &[u8]) {
+    let mut text = String::new();
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return (text, &[]);
+            }
+            Err(error) => {
+                let (valid, rest) = bytes.split_at(error.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                match error.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        bytes = &rest[len..];
+                    }
+                    None => return (text, rest),
+                }
+            }
+        }
+    }
+}
+
+fn write_marker(path: &Path, summary: &Value) -> anyhow::Result<()> {
+    let temporary = path.with_extension("pending.tmp");
+    std::fs::write(&temporary, summary.to_string())
+        .with_context(|| format!("writing {}", temporary.display()))?;
+    std::fs::rename(&temporary, path).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Delete the oldest finished recordings until the retention limits of `config` are met.
+fn prune_recordings(
+    directory: &Path,
+    config: &TerminalRecordingConfig,
+    active: &HashSet<PathBuf>,
+    now: SystemTime,
+) -> std::io::Result<()> {
+    let max_recordings = config.max_recordings.unwrap_or(DEFAULT_MAX_RECORDINGS);
+    let max_bytes = config.max_total_bytes.unwrap_or(DEFAULT_MAX_TOTAL_BYTES);
+    let max_age = config
+        .max_age_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_MAX_AGE);
+
+    let mut recordings = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension() != Some(RECORDING_EXTENSION.as_ref()) || active.contains(&path) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            recordings.push((metadata.modified()?, metadata.len(), path));
+        }
+    }
+    // Newest first, so that everything after the first recording over a limit is deleted.
+    recordings.sort_by(|a, b| b.cmp(a));
+
+    let mut kept = 0;
+    let mut kept_bytes = 0;
+    let mut full = false;
+    for (modified, size, path) in recordings {
+        let expired = now.duration_since(modified).is_ok_and(|age| age > max_age);
+        full = full || kept >= max_recordings || kept_bytes + size > max_bytes;
+        if !expired && !full {
+            kept += 1;
+            kept_bytes += size;
+            continue;
+        }
+        debug!(path = %path.display(), "deleting terminal recording");
+        std::fs::remove_file(&path)?;
+        match std::fs::remove_file(path.with_extension(PENDING_EXTENSION)) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Deliver finished recordings over the current hub connection until `cancellation`
+/// fires.
+pub(crate) async fn run_recording_delivery(
+    recordings: &TerminalRecordings,
+    config: watch::Receiver<Arc<Config>>,
+    executor: &mut ClientExecutor,
+    device_id: &DeviceId,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<()> {
+    loop {
+        let current = config.borrow().clone();
+        let retry = match recording_config(&current) {
+            Some(config) => !deliver_pending(recordings, config, executor, device_id).await,
+            None => false,
+        };
+        tokio::select! {
+            biased;
+            () = cancellation.cancelled() => return Ok(()),
+            () = recordings.pending.notified() => {}
+            () = tokio::time::sleep(DELIVERY_RETRY_DELAY), if retry => {}
+        }
+    }
+}
+
+/// Deliver all pending recordings and return whether all of them were delivered.
+async fn deliver_pending(
+    recordings: &TerminalRecordings,
+    config: &TerminalRecordingConfig,
+    executor: &mut ClientExecutor,
+    device_id: &DeviceId,
+) -> bool {
+    let directory = recordings.directory(config);
+    let mut markers = Vec::new();
+    match std::fs::read_dir(&directory) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension() == Some(PENDING_EXTENSION.as_ref()) {
+                    markers.push(path);
+                }
+            }
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return true,
+        Err(error) => {
+            warn!(?error, "failed to list pending terminal recordings");
+            return false;
+        }
+    }
+    markers.sort();
+
+    let mut delivered = true;
+    for marker in markers {
+        match deliver_recording(&marker, config, executor, device_id).await {
+            Ok(()) => {
+                if let Err(error) = tokio::fs::remove_file(&marker).await {
+                    warn!(?error, path = %marker.display(), "failed to remove delivered recording marker");
+                }
+            }
+            Err(error) => {
+                warn!(?error, path = %marker.display(), "failed to deliver terminal recording");
+                delivered = false;
+            }
+        }
+    }
+    delivered
+}
+
+async fn deliver_recording(
+    marker: &Path,
+    config: &TerminalRecordingConfig,
+    executor: &mut ClientExecutor,
+    device_id: &DeviceId,
+) -> anyhow::Result<()> {
+    let recording = marker.with_extension(RECORDING_EXTENSION);
+    if !tokio::fs::try_exists(&recording).await? {
+        debug!(path = %recording.display(), "terminal recording deleted before delivery");
+        return Ok(());
+    }
+    let mut summary = match serde_json::from_slice(&tokio::fs::read(marker).await?) {
+        Ok(Value::Object(summary)) => summary,
+        _ => {
+            warn!(path = %marker.display(), "dropping invalid recording marker");
+            return Ok(());
+        }
+    };
+    if let Some(repository_id) = &config.upload_repository {
+        let output =
+            nexigon_common::upload_repository_asset(executor, repository_id.clone(), &recording)
+                .await
+                .context("uploading recording")?;
+        let (CreateAssetOutput::AssetAlreadyExists(asset_id)
+        | CreateAssetOutput::Created(asset_id)) = output;
+        summary.insert("asset-id".to_owned(), json!(asset_id));
+    }
+    if config.announce == Some(true) {
+        let event = DeviceEvent::new(
+            DeviceEventId::generate(),
+            DeviceEventSeverity::Info,
+            Value::Object(summary),
+            HashMap::new(),
+            Timestamp::now(),
+        )
+        .with_category(Some(RECORDING_EVENT_CATEGORY.to_owned()));
+        match executor
+            .execute(PublishDeviceEventsAction::new(
+                device_id.clone(),
+                vec![event],
+            ))
+            .await
+            .context("publishing recording event")?
+        {
+            Ok(_) => {}
+            // Retrying would not change the hub's answer.
+            Err(error) => warn!(message = %error.message, "hub rejected terminal recording event"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use jiff::Timestamp;
+    use serde_json::Value;
+    use serde_json::json;
+
+    use super::CastHeader;
+    use super::CastWriter;
+    use super::prune_recordings;
+    use crate::config::TerminalRecordingConfig;
+
+    fn lines(output: &[u8]) -> Vec<Value> {
+        std::str::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sessions_are_written_as_asciicast_v2() {
+        let header = CastHeader {
+            user: "nexigon",
+            shell: "/bin/bash",
+            started_at: Timestamp::from_second(1_700_000_000).unwrap(),
+        };
+        let mut output = Vec::new();
+        let mut writer = CastWriter::new(&mut output, u64::MAX);
+        writer.resize(Duration::ZERO, 120, 40);
+        // A multi-byte character split across two reads is recorded once it is complete.
+        writer.output(Duration::from_millis(1500), b"caf\xc3", &header);
+        writer.output(Duration::from_millis(1600), b"\xa9 \xff", &header);
+        writer.resize(Duration::from_secs(2), 100, 30);
+        writer.output(Duration::from_secs(3), b"\xe2\x82", &header);
+        let size = writer.finish(Duration::from_secs(4), &header);
+        drop(writer);
+
+        assert_eq!(size, output.len() as u64);
+        assert_eq!(
+            lines(&output),
+            [
+                json!({
+                    "version": 2,
+                    "width": 120,
+                    "height": 40,
+                    "timestamp": 1_700_000_000,
+                    "env": {"SHELL": "/bin/bash", "TERM": "xterm-256color"},
+                    "user": "nexigon",
+                }),
+                json!([1.5, "o", "caf"]),
+                json!([1.6, "o", "\u{e9} \u{fffd}"]),
+                json!([2.0, "r", "100x30"]),
+                json!([4.0, "o", "\u{fffd}"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn recordings_stop_at_the_size_limit() {
+        let header = CastHeader {
+            user: "root",
+            shell: "/bin/sh",
+            started_at: Timestamp::UNIX_EPOCH,
+        };
+        let mut output = Vec::new();
+        let mut writer = CastWriter::new(&mut output, 150);
+        writer.output(Duration::ZERO, b"first", &header);
+        writer.output(Duration::from_secs(1), &[b'x'; 100], &header);
+        writer.output(Duration::from_secs(2), b"last", &header);
+        writer.finish(Duration::from_secs(3), &header);
+        drop(writer);
+        assert_eq!(lines(&output).len(), 2);
+        assert!(output.len() <= 150);
+    }
+
+    #[test]
+    fn retention_deletes_the_oldest_and_expired_recordings() {
+        let directory = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        let write = |name: &str, size: usize, age_secs: u64| {
+            let path = directory.path().join(name);
+            std::fs::write(&path, vec![b'x'; size]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age_secs))
+                .unwrap();
+            path
+        };
+        let expired = write("expired.cast", 10, 3600);
+        std::fs::write(directory.path().join("expired.pending"), b"{}").unwrap();
+        let oldest = write("oldest.cast", 10, 30);
+        let older = write("older.cast", 10, 20);
+        let newer = write("newer.cast", 10, 10);
+        let active = write("active.cast", 100, 40);
+        let unrelated = write("notes.txt", 100, 7200);
+
+        let config = TerminalRecordingConfig::new()
+            .with_max_recordings(Some(2))
+            .with_max_age_secs(Some(60));
+        prune_recordings(
+            directory.path(),
+            &config,
+            &HashSet::from([active.clone()]),
+            now,
+        )
+        .unwrap();
+        assert!(!expired.exists());
+        assert!(!directory.path().join("expired.pending").exists());
+        assert!(!oldest.exists());
+        assert!(older.exists() && newer.exists() && active.exists() && unrelated.exists());
+
+        let config = TerminalRecordingConfig::new().with_max_total_bytes(Some(15));
+        prune_recordings(directory.path(), &config, &HashSet::new(), now).unwrap();
+        assert!(!older.exists() && !active.exists());
+        assert!(newer.exists());
+    }
+}
@@ -0,0 +1,830 @@
+//! Persistent terminal sessions.
+//!
+//! A persistent session is identified by a session ID chosen by the hub. Its shell keeps
+//! running while no channel is attached, for up to the grace period configured in
+//! `terminal.sessions`, and across reconnects of the agent. The recent output is kept in
+//! a scrollback buffer that is replayed to every channel when it attaches.
+//!
+//! One attached channel is the writer, whose input and resizes reach the shell. A writer
+//! that attaches replaces the previous one, whose channel is closed. Any number of
+//! observers can attach alongside; they receive the output and their input is ignored.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::bail;
+use nexigon_agent_protocol::HubTerminalFrame;
+use nexigon_agent_protocol::MAX_TERMINAL_DATA_LEN;
+use nexigon_agent_protocol::read_hub_terminal_frame;
+use nexigon_agent_protocol::write_terminal_data;
+use nexigon_agent_protocol::write_terminal_exit;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+
+use super::CHILD_TERMINATION_GRACE;
+use super::PTY_DRAIN_GRACE;
+use super::SessionLimit;
+use super::SessionLimits;
+use super::TerminalRecordings;
+use super::TerminalShell;
+use super::TerminalTarget;
+use super::resolve_session_user;
+use super::terminate_terminal_child;
+use super::wait_for_terminal_child;
+use crate::config::Config;
+
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_SCROLLBACK_BYTES: u32 = 64 * 1024;
+const MAX_SESSION_ID_LEN: usize = 64;
+/// Output chunks queued for an attached channel before it is considered too slow.
+const OUTPUT_BACKLOG: usize = 256;
+/// Frames from the writer queued for the shell.
+const INPUT_BACKLOG: usize = 16;
+
+/// Task running the shell of a new session, which must be spawned by the caller.
+pub(crate) type SessionTask = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+/// Role of a channel attached to a session.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum AttachMode {
+    /// Sends input and resizes to the shell, creating the session if necessary.
+    Writer,
+    /// Only receives the output of an existing session.
+    Observer,
+}
+
+/// Whether `id` is a valid session ID.
+///
+/// Session IDs consist of up to 64 ASCII letters, digits, `-`, and `_`.
+pub(crate) fn valid_session_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_SESSION_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Persistent terminal sessions of the agent.
+pub(crate) struct TerminalSessions {
+    recordings: Arc<TerminalRecordings>,
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+    /// Held while a session is created, so concurrent writers share one shell.
+    creating: tokio::sync::Mutex<()>,
+    /// Cancelled when the agent shuts down.
+    shutdown: CancellationToken,
+}
+
+impl TerminalSessions {
+    pub(crate) fn new(recordings: Arc<TerminalRecordings>) -> Self {
+        Self {
+            recordings,
+            sessions: Mutex::default(),
+            creating: tokio::sync::Mutex::default(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Whether a session with the given ID is running.
+    pub(crate) fn contains(&self, id: &str) -> bool {
+        self.sessions
+            .lock()
+            .is_ok_and(|sessions| sessions.contains_key(id))
+    }
+
+    /// Terminate all sessions.
+    pub(crate) fn close(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Attach a channel to the session `id`.
+    ///
+    /// A writer creates the session if it is not running, which requires a terminal
+    /// session `permit`, and then also returns the task running its shell. Writers of
+    /// a running session must request the user the session runs as.
+    pub(crate) async fn attach(
+        self: &Arc<Self>,
+        config: &Config,
+        id: &str,
+        requested_user: Option<&str>,
+        mode: AttachMode,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> anyhow::Result<(Viewer, Option<SessionTask>)> {
+        if !valid_session_id(id) {
+            bail!("invalid terminal session ID {id:?}");
+        }
+        if !crate::config::terminal_enabled(config) {
+            bail!("terminal is not enabled or terminal.user is invalid");
+        }
+        if mode == AttachMode::Observer {
+            let viewer = self
+                .attach_running(id, mode, |session| {
+                    resolve_session_user(config, Some(&session.user)).map(|_| ())
+                })?
+                .with_context(|| format!("terminal session {id:?} is not running"))?;
+            return Ok((viewer, None));
+        }
+
+        let user = resolve_session_user(config, requested_user)?;
+        let _creating = self.creating.lock().await;
+        let matches_user = |session: &Session| {
+            if session.user != user.name {
+                bail!("terminal session {id:?} runs as user {:?}", session.user);
+            }
+            Ok(())
+        };
+        if let Some(viewer) = self.attach_running(id, mode, matches_user)? {
+            return Ok((viewer, None));
+        }
+
+        let permit = permit.context("too many concurrent terminal sessions")?;
+        let target = TerminalTarget::Shell(Some(user.name.clone()));
+        let shell = TerminalShell::spawn(config, &target, Some(&self.recordings)).await?;
+        let sessions_config = config
+            .terminal
+            .as_ref()
+            .and_then(|terminal| terminal.sessions.as_ref());
+        let grace_period = sessions_config
+            .and_then(|sessions| sessions.grace_period_secs)
+            .map_or(DEFAULT_GRACE_PERIOD, Duration::from_secs);
+        let scrollback_limit = sessions_config
+            .and_then(|sessions| sessions.scrollback_bytes)
+            .unwrap_or(DEFAULT_SCROLLBACK_BYTES) as usize;
+        let (input_tx, input_rx) = mpsc::channel(INPUT_BACKLOG);
+        let session = Arc::new(Session {
+            id: id.to_owned(),
+            user: user.name,
+            input: input_tx,
+            output: broadcast::Sender::new(OUTPUT_BACKLOG),
+            viewers: watch::Sender::new(0),
+            state: Mutex::new(SessionState {
+                scrollback: VecDeque::new(),
+                scrollback_limit,
+                writer: None,
+            }),
+        });
+        info!(
+            id,
+            user = session.user,
+            "persistent terminal session started"
+        );
+        let viewer = {
+            let mut sessions = self
+                .sessions
+                .lock()
+                .map_err(|_| anyhow::anyhow!("terminal sessions mutex poisoned"))?;
+            sessions.insert(id.to_owned(), session.clone());
+            Viewer::attach(session.clone(), mode)?
+        };
+        let task = Box::pin(run_session(
+            self.clone(),
+            session,
+            shell,
+            input_rx,
+            SessionLimits::new(config),
+            grace_period,
+            permit,
+        ));
+        Ok((viewer, Some(task)))
+    }
+
+    /// Attach to the running session `id` if `check` accepts it.
+    fn attach_running(
+        &self,
+        id: &str,
+        mode: AttachMode,
+        check: impl FnOnce(&Session) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Option<Viewer>> {
+        let sessions = self
+            .sessions
+            .lock()
+            .map_err(|_| anyhow::anyhow!("terminal sessions mutex poisoned"))?;
+        let Some(session) = sessions.get(id) else {
+            return Ok(None);
+        };
+        check(session)?;
+        // Attaching under the lock ensures that an expiring session is either removed
+        // before or sees the new channel.
+        Viewer::attach(session.clone(), mode).map(Some)
+    }
+
+    /// Remove `session` if no channel is attached to it.
+    fn remove_detached(&self, session: &Arc<Session>) -> bool {
+        let Ok(mut sessions) = self.sessions.lock() else {
+            return true;
+        };
+        if *session.viewers.borrow() > 0 {
+            return false;
+        }
+        if sessions
+            .get(&session.id)
+            .is_some_and(|running| Arc::ptr_eq(running, session))
+        {
+            sessions.remove(&session.id);
+        }
+        true
+    }
+
+    /// Remove `session` once its shell has ended.
+    fn remove(&self, session: &Arc<Session>) {
+        if let Ok(mut sessions) = self.sessions.lock()
+            && sessions
+                .get(&session.id)
+                .is_some_and(|running| Arc::ptr_eq(running, session))
+        {
+            sessions.remove(&session.id);
+        }
+    }
+
+    /// Wait until `session` has been without attached channels for `grace_period` and
+    /// remove it.
+    async fn expire_detached(&self, session: &Arc<Session>, grace_period: Duration) {
+        let mut viewers = session.viewers.subscribe();
+        loop {
+            // The session owns the sender, so waiting does not fail.
+            viewers.wait_for(|count| *count == 0).await.ok();
+            let attached = tokio::time::timeout(grace_period, viewers.wait_for(|count| *count > 0))
+                .await
+                .is_ok();
+            if !attached && self.remove_detached(session) {
+                return;
+            }
+        }
+    }
+}
+
+/// Shared state of a persistent session.
+struct Session {
+    id: String,
+    /// Unix user the shell runs as.
+    user: String,
+    /// Input and resizes of the writer.
+    input: mpsc::Sender<HubTerminalFrame>,
+    /// Output of the shell, published while holding the state lock.
+    output: broadcast::Sender<SessionOutput>,
+    /// Number of attached channels.
+    viewers: watch::Sender<usize>,
+    state: Mutex<SessionState>,
+}
+
+struct SessionState {
+    /// Most recent output of the shell.
+    scrollback: VecDeque<u8>,
+    scrollback_limit: usize,
+    /// Cancelled when the current writer is replaced.
+    writer: Option<CancellationToken>,
+}
+
+#[derive(Clone, Debug)]
+enum SessionOutput {
+    Data(Arc<[u8]>),
+    /// The session ended, with the exit code of the shell if it exited.
+    End(Option<i32>),
+}
+
+impl Session {
+    /// Publish output of the shell to the scrollback and the attached channels.
+    fn publish(&self, data: &[u8]) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let limit = state.scrollback_limit;
+        let data_start = data.len().saturating_sub(limit);
+        state.scrollback.extend(&data[data_start..]);
+        let excess = state.scrollback.len().saturating_sub(limit);
+        state.scrollback.drain(..excess);
+        // Without attached channels, sending fails, and the output is only kept in
+        // the scrollback.
+        self.output.send(SessionOutput::Data(data.into())).ok();
+    }
+
+    /// Tell the attached channels that the session ended.
+    fn end(&self, code: Option<i32>) {
+        self.output.send(SessionOutput::End(code)).ok();
+    }
+}
+
+/// A channel attached to a persistent session.
+pub(crate) struct Viewer {
+    attachment: Attachment,
+    /// Scrollback at the time of attaching.
+    scrollback: Vec<u8>,
+    /// Output of the shell after the scrollback.
+    output: broadcast::Receiver<SessionOutput>,
+}
+
+/// Registration of an attached channel, removed when dropped.
+struct Attachment {
+    session: Arc<Session>,
+    /// Cancelled when a writer is replaced by another writer.
+    replaced: Option<CancellationToken>,
+}
+
+impl Attachment {
+    fn is_writer(&self) -> bool {
+        self.replaced.is_some()
+    }
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        if let Some(replaced) = &self.replaced
+            && !replaced.is_cancelled()
+            && let Ok(mut state) = self.session.state.lock()
+        {
+            state.writer = None;
+        }
+        self.session.viewers.send_modify(|count| *count -= 1);
+    }
+}
+
+impl Viewer {
+    fn attach(session: Arc<Session>, mode: AttachMode) -> anyhow::Result<Self> {
+        let (scrollback, output, replaced) = {
+            let mut state = session
+                .state
+                .lock()
+                .map_err(|_| anyhow::anyhow!("terminal session mutex poisoned"))?;
+            session.viewers.send_modify(|count| *count += 1);
+            let replaced = (mode == AttachMode::Writer).then(|| {
+                let replaced = CancellationToken::new();
+                if let Some(previous) = state.writer.replace(replaced.clone()) {
+                    previous.cancel();
+                }
+                replaced
+            });
+            let scrollback = state.scrollback.iter().copied().collect();
+            (scrollback, session.output.subscribe(), replaced)
+        };
+        Ok(Self {
+            attachment: Attachment { session, replaced },
+            scrollback,
+            output,
+        })
+    }
+
+    /// Relay the session over `channel` until either ends or `cancellation` fires.
+    ///
+    /// The channel uses the framing of terminal sessions. The session keeps running
+    /// when the channel closes.
+    pub(crate) async fn serve(
+        self,
+        channel: nexigon_multiplex::Channel,
+        cancellation: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let Self {
+            attachment,
+            scrollback,
+            mut output,
+        } = self;
+        let session = &attachment.session;
+        let (mut chan_writer, mut chan_reader) = channel.split();
+
+        let channel_to_session = async {
+            loop {
+                let frame = read_hub_terminal_frame(&mut chan_reader).await?;
+                // Input of observers is discarded, and so is input for a shell that
+                // has already ended.
+                if attachment.is_writer() {
+                    session.input.send(frame).await.ok();
+                }
+            }
+        };
+
+        let session_to_channel = async {
+            for chunk in scrollback.chunks(MAX_TERMINAL_DATA_LEN) {
+                write_terminal_data(&mut chan_writer, chunk).await?;
+            }
+            loop {
+                match output.recv().await {
+                    Ok(SessionOutput::Data(data)) => {
+                        write_terminal_data(&mut chan_writer, &data).await?;
+                    }
+                    Ok(SessionOutput::End(code)) => return Ok(code),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        bail!("terminal channel cannot keep up with the session output");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(None),
+                }
+            }
+        };
+
+        let replaced = async {
+            match &attachment.replaced {
+                Some(replaced) => replaced.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let mut channel_to_session = Box::pin(channel_to_session);
+        let mut session_to_channel = Box::pin(session_to_channel);
+
+        enum ViewerEnd {
+            Channel(anyhow::Result<()>),
+            Session(anyhow::Result<Option<i32>>),
+            Replaced,
+            Cancelled,
+        }
+
+        let end = tokio::select! {
+            biased;
+            () = cancellation.cancelled() => ViewerEnd::Cancelled,
+            () = replaced => ViewerEnd::Replaced,
+            result = &mut session_to_channel => ViewerEnd::Session(result),
+            result = &mut channel_to_session => ViewerEnd::Channel(result),
+        };
+        drop(channel_to_session);
+        drop(session_to_channel);
+
+        let result = match end {
+            ViewerEnd::Session(Ok(Some(code))) => {
+                write_terminal_exit(&mut chan_writer, code).await?;
+                Ok(())
+            }
+            ViewerEnd::Session(result) => result.map(|_| ()),
+            ViewerEnd::Channel(result) => {
+                debug!(id = session.id, ?result, "terminal session channel closed");
+                result
+            }
+            ViewerEnd::Replaced => {
+                debug!(id = session.id, "terminal session writer replaced");
+                Ok(())
+            }
+            ViewerEnd::Cancelled => Ok(()),
+        };
+        chan_writer.shutdown().await.ok();
+        result
+    }
+}
+
+/// Run the shell of `session` until it exits, expires, or the agent shuts down.
+async fn run_session(
+    sessions: Arc<TerminalSessions>,
+    session: Arc<Session>,
+    shell: TerminalShell,
+    mut input: mpsc::Receiver<HubTerminalFrame>,
+    limits: SessionLimits,
+    grace_period: Duration,
+    permit: OwnedSemaphorePermit,
+) -> anyhow::Result<()> {
+    let _permit = permit;
+    let child = shell.child;
+
+    let input_to_pty = async {
+        while let Some(frame) = input.recv().await {
+            limits.received(&frame);
+            match frame {
+                HubTerminalFrame::Data(data) => shell.write(&data).await?,
+                HubTerminalFrame::Resize { cols, rows } => shell.resize(cols, rows)?,
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let pty_to_session = async {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let n = tokio::select! {
+                n = shell.read(&mut buf) => n?,
+                limit = limits.exceeded() => {
+                    session.publish(limit.warning().as_bytes());
+                    return Ok(Some(limit));
+                }
+            };
+            if n == 0 {
+                break;
+            }
+            session.publish(&buf[..n]);
+        }
+        Ok::<_, anyhow::Error>(None)
+    };
+
+    let mut input_to_pty = Box::pin(input_to_pty);
+    let mut pty_to_session = Box::pin(pty_to_session);
+    let mut wait_child = Box::pin(wait_for_terminal_child(child));
+
+    enum SessionEnd {
+        Input(anyhow::Result<()>),
+        Pty(anyhow::Result<Option<SessionLimit>>),
+        Child(anyhow::Result<i32>),
+        Expired,
+        Shutdown,
+    }
+
+    let end = tokio::select! {
+        biased;
+        () = sessions.shutdown.cancelled() => SessionEnd::Shutdown,
+        result = &mut wait_child => SessionEnd::Child(result),
+        result = &mut pty_to_session => SessionEnd::Pty(result),
+        result = &mut input_to_pty => SessionEnd::Input(result),
+        () = sessions.expire_detached(&session, grace_period) => SessionEnd::Expired,
+    };
+
+    let (code, result) = match end {
+        SessionEnd::Child(Ok(code)) => {
+            debug!(id = session.id, code, "terminal session child exited");
+            // Publish the output that was already in the PTY before the final status.
+            if !matches!(
+                tokio::time::timeout(PTY_DRAIN_GRACE, &mut pty_to_session).await,
+                Ok(Ok(_))
+            ) {
+                debug!(id = session.id, "terminal session output was not drained");
+            }
+            (Some(code), Ok(()))
+        }
+        SessionEnd::Pty(Ok(Some(limit))) => {
+            info!(id = session.id, ?limit, "terminal session limit reached");
+            terminate_terminal_child(child).await;
+            (Some(limit.exit_code()), Ok(()))
+        }
+        SessionEnd::Pty(Ok(None)) => {
+            // Linux can report PTY EOF immediately before waitpid exposes the status.
+            match tokio::time::timeout(CHILD_TERMINATION_GRACE, &mut wait_child).await {
+                Ok(Ok(code)) => (Some(code), Ok(())),
+                Ok(Err(error)) => (None, Err(error).context("failed to reap terminal child")),
+                Err(_) => (Some(terminate_terminal_child(child).await), Ok(())),
+            }
+        }
+        SessionEnd::Child(Err(error)) => {
+            terminate_terminal_child(child).await;
+            (None, Err(error).context("failed to reap terminal child"))
+        }
+        SessionEnd::Pty(Err(error)) | SessionEnd::Input(Err(error)) => {
+            debug!(id = session.id, ?error, "terminal session relay failed");
+            terminate_terminal_child(child).await;
+            (None, Err(error))
+        }
+        SessionEnd::Input(Ok(())) | SessionEnd::Shutdown => {
+            terminate_terminal_child(child).await;
+            (None, Ok(()))
+        }
+        SessionEnd::Expired => {
+            info!(id = session.id, "no channel reattached to terminal session");
+            terminate_terminal_child(child).await;
+            (None, Ok(()))
+        }
+    };
+    // Close the entire process group of the shell, including background descendants.
+    nix::sys::signal::killpg(child, nix::sys::signal::Signal::SIGKILL).ok();
+
+    drop(input_to_pty);
+    drop(pty_to_session);
+    drop(wait_child);
+    // Remove the session before announcing its end, so that channels attaching in
+    // between start a new session instead of attaching to this one.
+    sessions.remove(&session);
+    session.end(code);
+    info!(id = session.id, ?code, "persistent terminal session ended");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use nexigon_agent_protocol::DeviceTerminalFrame;
+    use nexigon_agent_protocol::read_device_terminal_frame;
+    use nexigon_multiplex::Connection;
+    use nexigon_multiplex::ConnectionEvent;
+    use nexigon_multiplex::ConnectionRef;
+    use nexigon_multiplex::transport::InMemory;
+    use nix::unistd::User;
+    use tempfile::tempdir;
+    use tokio::sync::Semaphore;
+
+    use super::*;
+    use crate::config::TerminalConfig;
+    use crate::config::TerminalSessionsConfig;
+
+    /// Read terminal output until it contains `expected`.
+    async fn read_until(channel: &mut nexigon_multiplex::Channel, expected: &str) {
+        let mut output = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !String::from_utf8_lossy(&output).contains(expected) {
+                match read_device_terminal_frame(channel).await.unwrap() {
+                    DeviceTerminalFrame::Data(data) => output.extend(data),
+                    DeviceTerminalFrame::Exit(code) => panic!("session exited with {code}"),
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("{expected:?} not in output {output:?}"));
+    }
+
+    async fn read_exit(channel: &mut nexigon_multiplex::Channel) -> i32 {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match read_device_terminal_frame(channel).await.unwrap() {
+                    DeviceTerminalFrame::Data(_) => {}
+                    DeviceTerminalFrame::Exit(code) => break code,
+                }
+            }
+        })
+        .await
+        .expect("terminal session did not exit")
+    }
+
+    struct Harness {
+        hub_ref: ConnectionRef,
+        channel_rx: mpsc::Receiver<nexigon_multiplex::Channel>,
+        sessions: Arc<TerminalSessions>,
+        config: Arc<Config>,
+        permits: Arc<Semaphore>,
+        _data: tempfile::TempDir,
+    }
+
+    impl Harness {
+        fn new(grace_period_secs: u64) -> Self {
+            let (hub_transport, agent_transport) = InMemory::<Bytes, Bytes>::new_buffered(64);
+            let mut hub_connection = Connection::new(hub_transport);
+            let mut agent_connection = Connection::new(agent_transport);
+            let hub_ref = hub_connection.make_ref();
+            let (channel_tx, channel_rx) = mpsc::channel(1);
+            tokio::spawn(async move {
+                while let Some(event) = hub_connection.next().await {
+                    if !matches!(event, Ok(ConnectionEvent::Connected)) {
+                        break;
+                    }
+                }
+            });
+            tokio::spawn(async move {
+                while let Some(event) = agent_connection.next().await {
+                    match event {
+                        Ok(ConnectionEvent::Connected) => {}
+                        Ok(ConnectionEvent::RequestChannel(request)) => {
+                            let channel_tx = channel_tx.clone();
+                            request.accept(move |channel| {
+                                let _ = channel_tx.try_send(channel);
+                            });
+                        }
+                        Ok(ConnectionEvent::Closed) | Err(_) => break,
+                    }
+                }
+            });
+            let user = User::from_uid(nix::unistd::geteuid())
+                .unwrap()
+                .expect("current user must exist");
+            let data = tempdir().unwrap();
+            Self {
+                hub_ref,
+                channel_rx,
+                sessions: Arc::new(TerminalSessions::new(Arc::new(TerminalRecordings::new(
+                    data.path().to_owned(),
+                )))),
+                config: Arc::new(
+                    Config::new(PathBuf::from("unused-fingerprint")).with_terminal(Some(
+                        TerminalConfig::new()
+                            .with_enabled(Some(true))
+                            .with_user(Some(user.name))
+                            .with_shell(Some("/bin/sh".to_owned()))
+                            .with_sessions(Some(
+                                TerminalSessionsConfig::new()
+                                    .with_grace_period_secs(Some(grace_period_secs)),
+                            )),
+                    )),
+                ),
+                permits: Arc::new(Semaphore::new(1)),
+                _data: data,
+            }
+        }
+
+        /// Attach a new channel to the session `id`, returning the hub side.
+        async fn attach(
+            &mut self,
+            id: &str,
+            mode: AttachMode,
+        ) -> (
+            nexigon_multiplex::Channel,
+            tokio::task::JoinHandle<anyhow::Result<()>>,
+        ) {
+            let hub_channel = self
+                .hub_ref
+                .open(b"terminal-session")
+                .await
+                .expect("terminal session channel rejected");
+            let agent_channel = self.channel_rx.recv().await.expect("agent driver stopped");
+            let (viewer, task) = self
+                .sessions
+                .attach(
+                    &self.config,
+                    id,
+                    None,
+                    mode,
+                    self.permits.clone().try_acquire_owned().ok(),
+                )
+                .await
+                .unwrap();
+            if let Some(task) = task {
+                tokio::spawn(task);
+            }
+            let viewer = tokio::spawn(viewer.serve(agent_channel, CancellationToken::new()));
+            (hub_channel, viewer)
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn sessions_survive_detaching_and_are_shared_with_observers() {
+        let mut harness = Harness::new(60);
+        let (mut first, first_viewer) = harness.attach("debug", AttachMode::Writer).await;
+        write_terminal_data(&mut first, b"echo first-$((40 + 2))\n")
+            .await
+            .unwrap();
+        read_until(&mut first, "first-42").await;
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(5), first_viewer)
+            .await
+            .expect("viewer did not detach")
+            .unwrap()
+            .ok();
+        assert!(harness.sessions.contains("debug"));
+
+        // A reattaching writer replays the scrollback and shares the shell.
+        let (mut writer, _writer_viewer) = harness.attach("debug", AttachMode::Writer).await;
+        read_until(&mut writer, "first-42").await;
+        let (mut observer, _observer_viewer) = harness.attach("debug", AttachMode::Observer).await;
+        read_until(&mut observer, "first-42").await;
+        assert_eq!(harness.permits.available_permits(), 0);
+
+        // Input of observers does not reach the shell.
+        write_terminal_data(&mut observer, b"exit 9\n")
+            .await
+            .unwrap();
+        write_terminal_data(&mut writer, b"echo second-$((40 + 3)); exit 4\n")
+            .await
+            .unwrap();
+        read_until(&mut observer, "second-43").await;
+        assert_eq!(read_exit(&mut writer).await, 4);
+        assert_eq!(read_exit(&mut observer).await, 4);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while harness.permits.available_permits() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("session permit was not released");
+        assert!(!harness.sessions.contains("debug"));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn writers_replace_writers_and_detached_sessions_expire() {
+        let mut harness = Harness::new(0);
+        let (mut first, first_viewer) = harness.attach("pair", AttachMode::Writer).await;
+        write_terminal_data(&mut first, b"echo ready\n")
+            .await
+            .unwrap();
+        read_until(&mut first, "ready").await;
+        let (second, second_viewer) = harness.attach("pair", AttachMode::Writer).await;
+        tokio::time::timeout(Duration::from_secs(5), first_viewer)
+            .await
+            .expect("replaced writer was not closed")
+            .unwrap()
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match read_device_terminal_frame(&mut first).await {
+                    Ok(DeviceTerminalFrame::Data(_)) => {}
+                    Ok(DeviceTerminalFrame::Exit(code)) => {
+                        panic!("replaced writer got exit {code}")
+                    }
+                    Err(_) => break,
+                }
+            }
+        })
+        .await
+        .expect("replaced writer channel was not closed");
+        assert!(harness.sessions.contains("pair"));
+
+        drop(second);
+        tokio::time::timeout(Duration::from_secs(5), second_viewer)
+            .await
+            .expect("viewer did not detach")
+            .unwrap()
+            .ok();
+        tokio::time::timeout(Duration::from_secs(7), async {
+            while harness.sessions.contains("pair") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("detached session did not expire");
+        assert!(
+            harness
+                .sessions
+                .attach(&harness.config, "pair", None, AttachMode::Observer, None)
+                .await
+                .is_err()
+        );
+    }
+}
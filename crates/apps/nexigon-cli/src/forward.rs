@@ -0,0 +1,207 @@
+//! Supervised port forwarding.
+//!
+//! Forwards outlive the hub connection: when it is lost, it is reestablished with
+//! backoff while the local listeners keep running. Local connections accepted while
+//! the hub is unreachable are closed right away. On shutdown, listeners stop accepting
+//! and active connections are closed within a grace period.
+
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Context;
+use nexigon_client::ClientBuilder;
+use nexigon_multiplex::Channel;
+use nexigon_multiplex::ConnectionRef;
+use nexigon_multiplex::OpenError;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Delay before the first reconnection attempt.
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+/// Maximum delay between reconnection attempts.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Connection lifetime after which the backoff starts over.
+const RECONNECT_STABLE_SESSION: Duration = Duration::from_secs(60);
+/// Delay after a failed accept, e.g., when running out of file descriptors.
+pub const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+/// Time active connections get to close on shutdown.
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Hub connection that is reestablished when it is lost.
+#[derive(Debug, Clone)]
+pub struct HubConnection {
+    /// Current connection, `None` while reconnecting.
+    current: watch::Receiver<Option<ConnectionRef>>,
+}
+
+impl HubConnection {
+    /// Supervise the spawned `connection`, reconnecting with `client` when it is lost.
+    pub fn supervise(
+        client: ClientBuilder,
+        connection: ConnectionRef,
+        task: JoinHandle<()>,
+    ) -> Self {
+        let (current_tx, current) = watch::channel(Some(connection));
+        tokio::spawn(async move {
+            let mut task = task;
+            let mut delay = RECONNECT_INITIAL_DELAY;
+            loop {
+                let connected = Instant::now();
+                let _ = task.await;
+                current_tx.send_replace(None);
+                if connected.elapsed() >= RECONNECT_STABLE_SESSION {
+                    delay = RECONNECT_INITIAL_DELAY;
+                }
+                task = loop {
+                    warn!("hub connection lost, reconnecting in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                    match client.connect().await {
+                        Ok(connection) => {
+                            info!("reconnected to the hub");
+                            current_tx.send_replace(Some(connection.make_ref()));
+                            break connection.spawn();
+                        }
+                        Err(error) => warn!("unable to reconnect to the hub: {error}"),
+                    }
+                };
+            }
+        });
+        Self { current }
+    }
+
+    /// Open a channel to `endpoint` on the current connection.
+    pub async fn open(&self, endpoint: &str) -> Result<Channel, OpenError> {
+        let connection = self.current.borrow().clone();
+        match connection {
+            Some(mut connection) => connection.open(endpoint.as_bytes()).await,
+            None => Err(OpenError::Closed),
+        }
+    }
+}
+
+/// Forwarder relaying local connections to device endpoints.
+#[derive(Debug, Clone)]
+pub struct Forwarder {
+    hub: HubConnection,
+    cancellation: CancellationToken,
+    connections: TaskTracker,
+}
+
+impl Forwarder {
+    /// Create a forwarder opening channels on `hub`.
+    pub fn new(hub: HubConnection) -> Self {
+        Self {
+            hub,
+            cancellation: CancellationToken::new(),
+            connections: TaskTracker::new(),
+        }
+    }
+
+    /// Forward connections to the TCP listener `bind` to `endpoint`.
+    pub async fn forward_tcp(self, bind: SocketAddr, endpoint: String) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(bind)
+            .await
+            .with_context(|| format!("binding {bind}"))?;
+        info!("forward {bind} to endpoint {endpoint}");
+        loop {
+            let accepted = tokio::select! {
+                () = self.cancellation.cancelled() => return Ok(()),
+                accepted = listener.accept() => accepted,
+            };
+            match accepted {
+                Ok((socket, peer)) => self.relay(peer, &endpoint, socket),
+                Err(error) => {
+                    warn!("unable to accept connection on {bind}: {error}");
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Forward connections to the Unix socket listener `path` to `endpoint`.
+    #[cfg(unix)]
+    pub async fn forward_unix(
+        self,
+        path: std::path::PathBuf,
+        endpoint: String,
+    ) -> anyhow::Result<()> {
+        let listener = tokio::net::UnixListener::bind(&path)
+            .with_context(|| format!("binding socket {}", path.display()))?;
+        info!("forward socket {} to endpoint {endpoint}", path.display());
+        loop {
+            let accepted = tokio::select! {
+                () = self.cancellation.cancelled() => return Ok(()),
+                accepted = listener.accept() => accepted,
+            };
+            match accepted {
+                Ok((socket, _)) => self.relay(path.display(), &endpoint, socket),
+                Err(error) => {
+                    warn!("unable to accept connection on {}: {error}", path.display());
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Stop accepting connections and close active ones within a grace period.
+    pub async fn shutdown(self) {
+        self.cancellation.cancel();
+        self.connections.close();
+        if tokio::time::timeout(SHUTDOWN_GRACE, self.connections.wait())
+            .await
+            .is_err()
+        {
+            warn!("forwarded connections did not close within the grace period");
+        }
+    }
+
+    /// Relay a local connection from `peer` over a channel to `endpoint`.
+    fn relay(
+        &self,
+        peer: impl Display,
+        endpoint: &str,
+        mut socket: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    ) {
+        let peer = peer.to_string();
+        let endpoint = endpoint.to_owned();
+        let hub = self.hub.clone();
+        let cancellation = self.cancellation.clone();
+        self.connections.spawn(async move {
+            let mut channel = match hub.open(&endpoint).await {
+                Ok(channel) => channel,
+                Err(OpenError::Rejected(rejection)) => {
+                    let reason = String::from_utf8_lossy(rejection.reason());
+                    error!("connection from {peer} rejected by {endpoint}: {reason}");
+                    return;
+                }
+                Err(error) => {
+                    error!("unable to open channel to {endpoint} for {peer}: {error}");
+                    return;
+                }
+            };
+            tokio::select! {
+                () = cancellation.cancelled() => {
+                    info!("closing connection from {peer} to {endpoint}");
+                }
+                result = tokio::io::copy_bidirectional(&mut socket, &mut channel) => match result {
+                    Ok((sent, received)) => info!(
+                        "connection from {peer} to {endpoint} closed: \
+                        {sent} bytes sent, {received} bytes received"
+                    ),
+                    Err(error) => warn!("connection from {peer} to {endpoint} failed: {error}"),
+                },
+            }
+        });
+    }
+}
@@ -1,4 +1,4 @@
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
@@ -9,8 +9,7 @@ use clap::Parser;
 
 use nexigon_api::types::devices;
 use nexigon_api::types::projects;
-use tokio::net::TcpListener;
-use tracing::error;
+use tokio::task::JoinSet;
 use tracing::info;
 
 use nexigon_api::types::actor::GetActorAction;
@@ -23,12 +22,20 @@ use nexigon_ids::ids::DeviceId;
 use nexigon_ids::ids::OrganizationId;
 use nexigon_ids::ids::ProjectId;
 use nexigon_ids::ids::RepositoryId;
-use nexigon_multiplex::ConnectionRef;
-use nexigon_multiplex::OpenError;
 
 use crate::config::Config;
+use crate::forward::Forwarder;
+use crate::forward::HubConnection;
+use crate::reverse::ReverseForward;
 
 pub mod config;
+pub mod forward;
+pub mod reverse;
+pub mod socks;
+pub mod stdio;
+#[cfg(unix)]
+pub mod terminal;
+pub mod udp;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -82,13 +89,11 @@ async fn main() -> anyhow::Result<()> {
         String::from_utf8(config_contents).context("config is not valid UTF-8")?;
     let config = toml::from_str::<Config>(&config_contents).context("cannot parse config")?;
     nexigon_client::install_crypto_provider();
-    let connection = nexigon_client::ClientBuilder::new(
+    let client = nexigon_client::ClientBuilder::new(
         config.hub_url.parse().unwrap(),
         ClientToken::from(config.token.clone()),
-    )
-    .connect()
-    .await
-    .unwrap();
+    );
+    let connection = client.connect().await.unwrap();
     let mut connection_ref = connection.make_ref();
     let join_handle = connection.spawn();
     let mut executor = connect_executor(&mut connection_ref).await.unwrap();
@@ -113,15 +118,84 @@ async fn main() -> anyhow::Result<()> {
         Cmd::Configure { .. } => {
             unreachable!()
         }
-        Cmd::Forward { device, forward } => {
+        Cmd::Forward {
+            device,
+            bind,
+            udp,
+            stdio,
+            forward,
+            unix,
+        } => {
+            if *stdio {
+                let [forward] = forward.as_slice() else {
+                    bail!("stdio forwarding requires exactly one remote port");
+                };
+                if *udp || !unix.is_empty() || forward.local.is_some() {
+                    bail!("stdio forwarding only supports a single TCP `[host:]remote` port");
+                }
+                let endpoint = forward.tcp_endpoint(device);
+                let code = stdio::forward_stdio(connection_ref.clone(), &endpoint).await?;
+                std::process::exit(code);
+            }
+            let hub = HubConnection::supervise(client, connection_ref.clone(), join_handle);
+            let forwarder = Forwarder::new(hub.clone());
+            let mut forwards = JoinSet::new();
+            for forward in unix {
+                let endpoint = format!("device/{device}/proxy/forward/unix/{}", forward.name);
+                match &forward.local {
+                    UnixForwardLocal::Port(port) => {
+                        let bind = SocketAddr::new(*bind, *port);
+                        forwards.spawn(forwarder.clone().forward_tcp(bind, endpoint));
+                    }
+                    #[cfg(unix)]
+                    UnixForwardLocal::Socket(path) => {
+                        forwards.spawn(forwarder.clone().forward_unix(path.clone(), endpoint));
+                    }
+                    #[cfg(not(unix))]
+                    UnixForwardLocal::Socket(_) => {
+                        bail!("local Unix sockets are not supported on this platform");
+                    }
+                }
+            }
             for forward in forward {
-                tokio::spawn(forward_tcp(
-                    connection_ref.clone(),
+                let Some(local) = forward.local else {
+                    bail!("missing local port, forwards are given as `local:[host:]remote`");
+                };
+                let bind = SocketAddr::new(*bind, local);
+                if !*udp {
+                    let endpoint = forward.tcp_endpoint(device);
+                    forwards.spawn(forwarder.clone().forward_tcp(bind, endpoint));
+                    continue;
+                }
+                if forward.host.is_some() {
+                    bail!("UDP forwarding to hosts behind the device is not supported");
+                }
+                forwards.spawn(udp::forward_udp(
+                    hub.clone(),
                     device.clone(),
-                    forward.clone(),
+                    bind,
+                    forward.remote,
                 ));
             }
-            join_handle.await.unwrap();
+            let result = tokio::select! {
+                result = tokio::signal::ctrl_c() => {
+                    info!("shutting down");
+                    result.context("waiting for Ctrl-C")
+                }
+                Some(result) = forwards.join_next() => {
+                    result.context("forward panicked").and_then(|result| result)
+                }
+            };
+            forwards.abort_all();
+            forwarder.shutdown().await;
+            result?;
+        }
+        Cmd::Reverse { device, forward } => {
+            reverse::run_reverse(&client, device, forward).await?;
+        }
+        Cmd::Socks { device, listen } => {
+            let hub = HubConnection::supervise(client, connection_ref.clone(), join_handle);
+            socks::run_socks(hub, device.clone(), *listen).await?;
         }
         Cmd::HttpProxy(cmd) => match cmd {
             HttpProxyCmd::IssueUrl {
@@ -489,6 +563,19 @@ async fn main() -> anyhow::Result<()> {
                     write_json(&output);
                 }
             },
+            DevicesCmd::Terminal { device, user } => {
+                #[cfg(unix)]
+                {
+                    let code = terminal::run_terminal(&mut connection_ref, device, user.as_deref())
+                        .await?;
+                    std::process::exit(code);
+                }
+                #[cfg(not(unix))]
+                {
+                    let _ = (device, user);
+                    bail!("interactive terminals are only supported on Unix");
+                }
+            }
         },
     }
     Ok(())
@@ -522,8 +609,39 @@ enum Cmd {
     Forward {
         /// Device id.
         device: DeviceId,
-        /// Forward settings.
+        /// Local address to bind forwarded ports to.
+        #[clap(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
+        /// Forward UDP instead of TCP ports.
+        #[clap(long)]
+        udp: bool,
+        /// Forward stdin and stdout to a single `[host:]remote` port, e.g., for use as an
+        /// SSH `ProxyCommand`.
+        #[clap(long)]
+        stdio: bool,
+        /// Forward settings, `local:remote` or `local:host:remote`.
         forward: Vec<ForwardPorts>,
+        /// Forward a Unix socket declared on the device, `local:name`, where the local
+        /// side is a TCP port or a Unix socket path.
+        #[clap(long = "unix", value_name = "LOCAL:NAME")]
+        unix: Vec<UnixForward>,
+    },
+    /// Expose local services to a device on ports of its loopback interface.
+    Reverse {
+        /// Device id.
+        device: DeviceId,
+        /// Reverse forward settings, `device_port:local_port` or
+        /// `device_port:local_host:local_port`.
+        #[clap(required = true)]
+        forward: Vec<ReverseForward>,
+    },
+    /// Run a SOCKS5 server forwarding connections through a device.
+    Socks {
+        /// Device id.
+        device: DeviceId,
+        /// Address to listen on.
+        #[clap(long, default_value = "127.0.0.1:1080")]
+        listen: SocketAddr,
     },
     /// HTTP reverse proxy command.
     #[clap(subcommand)]
@@ -742,6 +860,14 @@ pub enum DevicesCmd {
     /// Manage on-demand device commands.
     #[clap(subcommand)]
     Commands(DeviceCommandsCmd),
+    /// Open an interactive terminal on a device.
+    Terminal {
+        /// Device ID.
+        device: DeviceId,
+        /// User to run the shell as.
+        #[clap(long)]
+        user: Option<String>,
+    },
 }
 
 /// Device properties subcommand.
@@ -847,11 +973,13 @@ pub enum DeviceCommandsCmd {
     },
 }
 
-/// Forward ports.
+/// Forward ports, given as `[local:][host:]remote`.
 #[derive(Debug, Clone)]
 pub struct ForwardPorts {
-    /// Local port.
-    local: u16,
+    /// Local port, omitted for stdio forwarding.
+    local: Option<u16>,
+    /// Host behind the device, if not forwarding to the device itself.
+    host: Option<String>,
     /// Remote port.
     remote: u16,
 }
@@ -860,16 +988,90 @@ impl std::str::FromStr for ForwardPorts {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split(':');
-        let local = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("missing local port"))?
-            .parse()?;
-        let remote = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("missing remote port"))?
-            .parse()?;
-        Ok(Self { local, remote })
+        let (rest, remote) = s.rsplit_once(':').unwrap_or(("", s));
+        let (local, host) = if rest.is_empty() {
+            (None, None)
+        } else if let Ok(local) = rest.parse() {
+            (Some(local), None)
+        } else {
+            match rest.split_once(':') {
+                Some((local, host)) if local.parse::<u16>().is_ok() => {
+                    (Some(local.parse()?), Some(host))
+                }
+                _ => (None, Some(rest)),
+            }
+        };
+        let host = match host {
+            Some(host) => {
+                // IPv6 addresses are given in brackets, e.g., `8080:[fd00::1]:80`.
+                let host = host
+                    .strip_prefix('[')
+                    .and_then(|host| host.strip_suffix(']'))
+                    .unwrap_or(host);
+                if host.is_empty() {
+                    bail!("missing host");
+                }
+                Some(host.to_owned())
+            }
+            None => None,
+        };
+        Ok(Self {
+            local,
+            host,
+            remote: remote.parse()?,
+        })
+    }
+}
+
+impl ForwardPorts {
+    /// TCP forwarding endpoint of the remote port on `device`.
+    fn tcp_endpoint(&self, device: &DeviceId) -> String {
+        match &self.host {
+            Some(host) => format!("device/{device}/proxy/forward/tcp/{host}/{}", self.remote),
+            None => format!("device/{device}/proxy/forward/tcp/{}", self.remote),
+        }
+    }
+}
+
+/// Forward of a Unix socket declared on the device, given as `local:name`.
+#[derive(Debug, Clone)]
+pub struct UnixForward {
+    /// Local side of the forward.
+    local: UnixForwardLocal,
+    /// Name of the socket in the agent configuration.
+    name: String,
+}
+
+/// Local side of a Unix socket forward.
+#[derive(Debug, Clone)]
+pub enum UnixForwardLocal {
+    /// TCP port on localhost.
+    Port(u16),
+    /// Unix socket path.
+    Socket(PathBuf),
+}
+
+impl std::str::FromStr for UnixForward {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (local, name) = s
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow::anyhow!("missing socket name"))?;
+        if local.is_empty() {
+            bail!("missing local port or socket path");
+        }
+        if name.is_empty() || name.contains('/') {
+            bail!("invalid socket name {name:?}");
+        }
+        let local = match local.parse() {
+            Ok(port) => UnixForwardLocal::Port(port),
+            Err(_) => UnixForwardLocal::Socket(PathBuf::from(local)),
+        };
+        Ok(Self {
+            local,
+            name: name.to_owned(),
+        })
     }
 }
 
@@ -893,37 +1095,6 @@ pub fn get_config_path(args: &Args) -> anyhow::Result<PathBuf> {
     bail!("unable to find configuration file")
 }
 
-/// Forward a local TCP port to a remote device.
-pub async fn forward_tcp(connection: ConnectionRef, device: DeviceId, forward: ForwardPorts) {
-    let endpoint = format!("device/{}/proxy/forward/tcp/{}", device, forward.remote);
-    info!("forward port {} to endpoint {endpoint}", forward.local);
-    let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), forward.local))
-        .await
-        .unwrap();
-    loop {
-        let (mut socket, _) = listener.accept().await.unwrap();
-        let mut connection = connection.clone();
-        let endpoint = endpoint.clone();
-        tokio::spawn(async move {
-            let open_future = connection.open(endpoint.as_bytes());
-            let mut channel = match open_future.await {
-                Ok(channel) => channel,
-                Err(error) => {
-                    error!("error opening channel: {error}");
-                    if let OpenError::Rejected(rejection) = &error {
-                        let reason = std::str::from_utf8(rejection.reason()).unwrap();
-                        println!("reason: {reason}");
-                    }
-                    return;
-                }
-            };
-            tokio::io::copy_bidirectional(&mut socket, &mut channel)
-                .await
-                .unwrap();
-        });
-    }
-}
-
 fn deployment_token_flags(auto_accept: Option<bool>) -> Option<projects::DeploymentTokenFlags> {
     auto_accept.map(|auto_accept| {
         projects::DeploymentTokenFlags::new().with_auto_accept(Some(auto_accept))
@@ -0,0 +1,233 @@
+//! Reverse forwarding, exposing local services to a device.
+//!
+//! Every forward opens a control channel to `device/<id>/proxy/reverse/tcp/<port>`,
+//! for which the agent listens on `port` of the loopback interface of the device. For
+//! each connection accepted there, the agent opens a `reverse/tcp/<port>` channel that
+//! the hub routes back to the connection holding the control channel. Reverse forwards
+//! therefore use a connection of their own which accepts these channel requests. Which
+//! ports can be bound is up to the reverse forwarding policy of the agent. The endpoint
+//! contract is documented at [`reverse_tcp_endpoint`].
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::bail;
+use futures::StreamExt;
+use nexigon_agent_protocol::parse_reverse_tcp_endpoint;
+use nexigon_agent_protocol::reverse_tcp_endpoint;
+use nexigon_client::ClientBuilder;
+use nexigon_ids::ids::DeviceId;
+use nexigon_multiplex::ChannelRequest;
+use nexigon_multiplex::ConnectionEvent;
+use nexigon_multiplex::ConnectionRef;
+use nexigon_multiplex::OpenError;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Timeout for connecting to a local service.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reverse forward, given as `device_port:[local_host:]local_port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReverseForward {
+    /// Port on the loopback interface of the device.
+    device_port: u16,
+    /// Host of the local service, defaults to `localhost`.
+    local_host: String,
+    /// Port of the local service.
+    local_port: u16,
+}
+
+impl std::str::FromStr for ReverseForward {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (device_port, local) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("missing local port"))?;
+        let (local_host, local_port) = local.rsplit_once(':').unwrap_or(("localhost", local));
+        // IPv6 addresses are given in brackets, e.g., `8080:[::1]:80`.
+        let local_host = local_host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(local_host);
+        if local_host.is_empty() {
+            bail!("missing local host");
+        }
+        Ok(Self {
+            device_port: device_port.parse().context("invalid device port")?,
+            local_host: local_host.to_owned(),
+            local_port: local_port.parse().context("invalid local port")?,
+        })
+    }
+}
+
+/// Expose the local services of `forwards` to `device` until interrupted.
+pub async fn run_reverse(
+    client: &ClientBuilder,
+    device: &DeviceId,
+    forwards: &[ReverseForward],
+) -> anyhow::Result<()> {
+    let mut connection = client.connect().await.context("connecting to the hub")?;
+    let connection_ref = connection.make_ref();
+    let (requests_tx, requests) = mpsc::unbounded_channel();
+    let events = tokio::spawn(async move {
+        while let Some(event) = connection.next().await {
+            match event {
+                Ok(ConnectionEvent::RequestChannel(request)) => {
+                    let _ = requests_tx.send(request);
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    error!("connection error: {error}");
+                    break;
+                }
+            }
+        }
+    });
+    let result = serve(connection_ref, device, forwards, requests).await;
+    events.abort();
+    result
+}
+
+async fn serve(
+    mut connection: ConnectionRef,
+    device: &DeviceId,
+    forwards: &[ReverseForward],
+    mut requests: mpsc::UnboundedReceiver<ChannelRequest>,
+) -> anyhow::Result<()> {
+    let mut targets = HashMap::new();
+    let mut controls = JoinSet::new();
+    for forward in forwards {
+        let port = forward.device_port;
+        if targets.insert(port, forward.clone()).is_some() {
+            bail!("device port {port} is forwarded more than once");
+        }
+        let endpoint = format!("device/{device}/proxy/{}", reverse_tcp_endpoint(port));
+        let mut control = match connection.open(endpoint.as_bytes()).await {
+            Ok(control) => control,
+            Err(OpenError::Rejected(rejection)) => {
+                bail!(
+                    "reverse forwarding of device port {port} rejected: {}",
+                    String::from_utf8_lossy(rejection.reason())
+                );
+            }
+            Err(error) => return Err(error).context("opening reverse forwarding channel"),
+        };
+        info!(
+            "forward device port {port} to {}:{}",
+            forward.local_host, forward.local_port
+        );
+        // The control channel carries no data, the agent closes it when it stops listening.
+        controls.spawn(async move {
+            let mut buf = [0u8; 64];
+            while control.read(&mut buf).await.is_ok_and(|n| n > 0) {}
+            port
+        });
+    }
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                info!("shutting down");
+                return result.context("waiting for Ctrl-C");
+            }
+            Some(port) = controls.join_next() => {
+                let port = port.context("control channel task panicked")?;
+                bail!("reverse forwarding of device port {port} closed by the device");
+            }
+            Some(_) = connections.join_next() => {}
+            request = requests.recv() => {
+                let Some(request) = request else {
+                    bail!("hub connection closed");
+                };
+                let forward = parse_reverse_tcp_endpoint(request.endpoint())
+                    .and_then(|port| targets.get(&port));
+                let Some(forward) = forward else {
+                    warn!(
+                        "rejecting channel request for {}",
+                        String::from_utf8_lossy(request.endpoint())
+                    );
+                    request.reject(b"unknown reverse forwarding endpoint");
+                    continue;
+                };
+                connections.spawn(relay(request, forward.clone()));
+            }
+        }
+    }
+}
+
+/// Relay a connection from the device to the local service of `forward`.
+async fn relay(request: ChannelRequest, forward: ReverseForward) {
+    let ReverseForward {
+        device_port,
+        local_host,
+        local_port,
+    } = forward;
+    let target = format!("{local_host}:{local_port}");
+    // Connect before accepting, so that the device sees unavailable services right away.
+    let connect = TcpStream::connect((local_host.as_str(), local_port));
+    let mut socket = match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+        Ok(Ok(socket)) => socket,
+        Ok(Err(error)) => {
+            warn!("unable to connect to {target} for device port {device_port}: {error}");
+            request.reject(b"local service is unavailable");
+            return;
+        }
+        Err(_) => {
+            warn!("connecting to {target} for device port {device_port} timed out");
+            request.reject(b"local service timed out");
+            return;
+        }
+    };
+    let (channel_tx, channel_rx) = oneshot::channel();
+    request.accept(move |channel| {
+        let _ = channel_tx.send(channel);
+    });
+    let Ok(mut channel) = channel_rx.await else {
+        return;
+    };
+    match tokio::io::copy_bidirectional(&mut socket, &mut channel).await {
+        Ok((to_device, from_device)) => info!(
+            "connection from device port {device_port} to {target} closed: \
+            {from_device} bytes from the device, {to_device} bytes to the device"
+        ),
+        Err(error) => {
+            warn!("connection from device port {device_port} to {target} failed: {error}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReverseForward;
+
+    #[test]
+    fn reverse_forwards_default_to_localhost() {
+        let forward = "8080:3000".parse::<ReverseForward>().unwrap();
+        assert_eq!(forward.device_port, 8080);
+        assert_eq!(forward.local_host, "localhost");
+        assert_eq!(forward.local_port, 3000);
+
+        let forward = "5432:db.internal:5432".parse::<ReverseForward>().unwrap();
+        assert_eq!(forward.local_host, "db.internal");
+
+        let forward = "8080:[::1]:80".parse::<ReverseForward>().unwrap();
+        assert_eq!(forward.local_host, "::1");
+        assert_eq!(forward.local_port, 80);
+
+        for invalid in ["8080", "8080::80", "web:80", "8080:web"] {
+            assert!(
+                invalid.parse::<ReverseForward>().is_err(),
+                "{invalid:?} must be rejected"
+            );
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! SOCKS5 server forwarding connections through a device.
+//!
+//! Every `CONNECT` request opens a forwarding channel to the device. Loopback
+//! destinations are forwarded to local ports of the device, i.e., through the
+//! `device/<id>/proxy/forward/tcp/<port>` endpoint, and all other destinations to hosts
+//! behind the device through `device/<id>/proxy/forward/tcp/<host>/<port>`. Which ports
+//! and hosts can be reached is up to the forwarding policy of the agent. Only
+//! unauthenticated `CONNECT` requests are supported.
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use anyhow::bail;
+use nexigon_agent_protocol::ForwardingRejection;
+use nexigon_ids::ids::DeviceId;
+use nexigon_multiplex::OpenError;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+use crate::forward::ACCEPT_ERROR_DELAY;
+use crate::forward::HubConnection;
+use crate::forward::SHUTDOWN_GRACE;
+
+const VERSION: u8 = 0x05;
+const METHOD_NO_AUTHENTICATION: u8 = 0x00;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+const COMMAND_CONNECT: u8 = 0x01;
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN_NAME: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_NOT_ALLOWED: u8 = 0x02;
+const REPLY_CONNECTION_REFUSED: u8 = 0x05;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Run a SOCKS5 server on `listen` forwarding connections through `device`.
+///
+/// Connections accepted while the hub connection is reestablished are refused. On
+/// Ctrl-C, the server stops accepting and closes active connections within a grace
+/// period.
+pub async fn run_socks(
+    hub: HubConnection,
+    device: DeviceId,
+    listen: SocketAddr,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("binding {listen}"))?;
+    info!("SOCKS5 server for device {device} listening on {listen}");
+    let cancellation = CancellationToken::new();
+    let connections = TaskTracker::new();
+    let result = loop {
+        let accepted = tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                info!("shutting down");
+                break result.context("waiting for Ctrl-C");
+            }
+            accepted = listener.accept() => accepted,
+        };
+        let (socket, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                warn!("unable to accept connection on {listen}: {error}");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        let hub = hub.clone();
+        let device = device.clone();
+        let cancellation = cancellation.clone();
+        connections.spawn(async move {
+            tokio::select! {
+                () = cancellation.cancelled() => {
+                    debug!("closing SOCKS5 connection from {peer}");
+                }
+                result = serve(&hub, &device, socket) => {
+                    if let Err(error) = result {
+                        debug!("SOCKS5 connection from {peer} failed: {error:#}");
+                    }
+                }
+            }
+        });
+    };
+    cancellation.cancel();
+    connections.close();
+    if tokio::time::timeout(SHUTDOWN_GRACE, connections.wait())
+        .await
+        .is_err()
+    {
+        warn!("SOCKS5 connections did not close within the grace period");
+    }
+    result
+}
+
+/// Destination of a `CONNECT` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Destination {
+    host: Host,
+    port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Host {
+    Address(IpAddr),
+    Name(String),
+}
+
+impl Destination {
+    /// Forwarding endpoint of the destination on `device`.
+    fn endpoint(&self, device: &DeviceId) -> String {
+        let port = self.port;
+        match &self.host {
+            Host::Address(address) if address.is_loopback() => {
+                format!("device/{device}/proxy/forward/tcp/{port}")
+            }
+            Host::Name(name) if name.eq_ignore_ascii_case("localhost") => {
+                format!("device/{device}/proxy/forward/tcp/{port}")
+            }
+            Host::Address(address) => format!("device/{device}/proxy/forward/tcp/{address}/{port}"),
+            Host::Name(name) => format!("device/{device}/proxy/forward/tcp/{name}/{port}"),
+        }
+    }
+}
+
+async fn serve(
+    hub: &HubConnection,
+    device: &DeviceId,
+    mut socket: impl AsyncRead + AsyncWrite + Unpin,
+) -> anyhow::Result<()> {
+    let Some(destination) = handshake(&mut socket).await? else {
+        return Ok(());
+    };
+    let endpoint = destination.endpoint(device);
+    debug!("SOCKS5 connect to {endpoint}");
+    let mut channel = match hub.open(&endpoint).await {
+        Ok(channel) => channel,
+        Err(error) => {
+            let reply = match &error {
+                OpenError::Rejected(rejection) => {
+                    let reason = String::from_utf8_lossy(rejection.reason());
+                    warn!("forwarding to {endpoint} rejected: {reason}");
+                    match ForwardingRejection::classify(rejection.reason()) {
+                        ForwardingRejection::NotAllowed => REPLY_NOT_ALLOWED,
+                        ForwardingRejection::Unavailable => REPLY_CONNECTION_REFUSED,
+                    }
+                }
+                _ => REPLY_GENERAL_FAILURE,
+            };
+            write_reply(&mut socket, reply).await?;
+            return Err(error).context("opening forwarding channel");
+        }
+    };
+    write_reply(&mut socket, REPLY_SUCCEEDED).await?;
+    tokio::io::copy_bidirectional(&mut socket, &mut channel)
+        .await
+        .context("relaying connection")?;
+    Ok(())
+}
+
+/// Negotiate the method and read the request of a client.
+///
+/// Returns `None` if the client has been sent a failure reply.
+async fn handshake(
+    socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+) -> anyhow::Result<Option<Destination>> {
+    let [version, method_count] = read_array(socket).await?;
+    if version != VERSION {
+        bail!("unsupported SOCKS version {version}");
+    }
+    let mut methods = vec![0; method_count.into()];
+    socket.read_exact(&mut methods).await?;
+    if !methods.contains(&METHOD_NO_AUTHENTICATION) {
+        socket.write_all(&[VERSION, METHOD_NOT_ACCEPTABLE]).await?;
+        return Ok(None);
+    }
+    socket
+        .write_all(&[VERSION, METHOD_NO_AUTHENTICATION])
+        .await?;
+
+    let [version, command, _, address_type] = read_array(socket).await?;
+    if version != VERSION {
+        bail!("unsupported SOCKS version {version}");
+    }
+    let host = match address_type {
+        ADDRESS_IPV4 => Host::Address(Ipv4Addr::from(read_array::<4>(socket).await?).into()),
+        ADDRESS_IPV6 => Host::Address(Ipv6Addr::from(read_array::<16>(socket).await?).into()),
+        ADDRESS_DOMAIN_NAME => {
+            let [len] = read_array(socket).await?;
+            let mut name = vec![0; len.into()];
+            socket.read_exact(&mut name).await?;
+            match String::from_utf8(name) {
+                Ok(name) if !name.is_empty() && !name.contains('/') => Host::Name(name),
+                _ => {
+                    write_reply(socket, REPLY_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+                    return Ok(None);
+                }
+            }
+        }
+        _ => {
+            write_reply(socket, REPLY_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Ok(None);
+        }
+    };
+    let port = u16::from_be_bytes(read_array(socket).await?);
+    if command != COMMAND_CONNECT {
+        write_reply(socket, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Ok(None);
+    }
+    Ok(Some(Destination { host, port }))
+}
+
+/// Write a reply without a bound address, which is unknown for channels.
+async fn write_reply(socket: &mut (impl AsyncWrite + Unpin), reply: u8) -> anyhow::Result<()> {
+    socket
+        .write_all(&[VERSION, reply, 0x00, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+async fn read_array<const N: usize>(
+    socket: &mut (impl AsyncRead + Unpin),
+) -> anyhow::Result<[u8; N]> {
+    let mut data = [0; N];
+    socket.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use nexigon_ids::Generate;
+    use nexigon_ids::ids::DeviceId;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::Destination;
+    use super::Host;
+    use super::handshake;
+
+    async fn request(bytes: &[u8]) -> (Option<Destination>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(bytes).await.unwrap();
+        let destination = handshake(&mut server).await.unwrap();
+        drop(server);
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        (destination, replies)
+    }
+
+    #[tokio::test]
+    async fn connect_requests_are_forwarded_to_device_ports_and_hosts() {
+        let device = DeviceId::generate();
+
+        let (destination, replies) =
+            request(&[5, 2, 2, 0, 5, 1, 0, 1, 127, 0, 0, 1, 0x1f, 0x90]).await;
+        assert_eq!(replies, [5, 0]);
+        let destination = destination.unwrap();
+        assert_eq!(
+            destination.endpoint(&device),
+            format!("device/{device}/proxy/forward/tcp/8080")
+        );
+
+        let mut name = vec![5, 1, 0, 5, 1, 0, 3, 10];
+        name.extend_from_slice(b"camera.lan");
+        name.extend_from_slice(&554u16.to_be_bytes());
+        let (destination, _) = request(&name).await;
+        let destination = destination.unwrap();
+        assert_eq!(destination.host, Host::Name("camera.lan".to_owned()));
+        assert_eq!(
+            destination.endpoint(&device),
+            format!("device/{device}/proxy/forward/tcp/camera.lan/554")
+        );
+    }
+
+    #[tokio::test]
+    async fn unsupported_methods_and_commands_are_refused() {
+        let (destination, replies) = request(&[5, 1, 2]).await;
+        assert_eq!(destination, None);
+        assert_eq!(replies, [5, 0xff]);
+
+        // UDP ASSOCIATE.
+        let (destination, replies) = request(&[5, 1, 0, 5, 3, 0, 1, 192, 168, 1, 1, 0, 53]).await;
+        assert_eq!(destination, None);
+        assert_eq!(replies, [5, 0, 5, 7, 0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+}
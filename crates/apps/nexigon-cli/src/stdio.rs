@@ -0,0 +1,65 @@
+//! Forwarding of stdin and stdout over a channel.
+//!
+//! This makes the CLI usable as an SSH `ProxyCommand`, e.g.:
+//!
+//! ```plain
+//! ssh -o ProxyCommand="nexigon-cli forward --stdio <device> 22" root@device
+//! ```
+//!
+//! Stdout carries the forwarded data only, diagnostics are written to stderr.
+
+use anyhow::Context;
+use nexigon_agent_protocol::ForwardingRejection;
+use nexigon_multiplex::ConnectionRef;
+use nexigon_multiplex::OpenError;
+use tokio::io::AsyncWriteExt;
+
+/// Exit status if the forwarding policy of the agent denies the target (`EX_NOPERM`).
+pub const EXIT_NOT_ALLOWED: i32 = 77;
+/// Exit status if the channel cannot be opened for any other reason (`EX_UNAVAILABLE`).
+pub const EXIT_UNAVAILABLE: i32 = 69;
+
+/// Bridge stdin and stdout to a channel to `endpoint` and return the exit status.
+///
+/// EOF on stdin is propagated to the remote side, which may still send data. The
+/// bridge ends when the remote side closes the channel.
+pub async fn forward_stdio(mut connection: ConnectionRef, endpoint: &str) -> anyhow::Result<i32> {
+    let channel = match connection.open(endpoint.as_bytes()).await {
+        Ok(channel) => channel,
+        Err(OpenError::Rejected(rejection)) => {
+            let reason = String::from_utf8_lossy(rejection.reason());
+            eprintln!("forwarding rejected: {reason}");
+            return Ok(match ForwardingRejection::classify(rejection.reason()) {
+                ForwardingRejection::NotAllowed => EXIT_NOT_ALLOWED,
+                ForwardingRejection::Unavailable => EXIT_UNAVAILABLE,
+            });
+        }
+        Err(error) => {
+            eprintln!("unable to open forwarding channel: {error}");
+            return Ok(EXIT_UNAVAILABLE);
+        }
+    };
+    let (mut sender, mut receiver) = channel.split();
+    let upstream = async {
+        tokio::io::copy(&mut tokio::io::stdin(), &mut sender)
+            .await
+            .context("forwarding stdin")?;
+        sender.shutdown().await.context("closing channel")
+    };
+    let downstream = async {
+        let mut stdout = tokio::io::stdout();
+        tokio::io::copy(&mut receiver, &mut stdout)
+            .await
+            .context("forwarding to stdout")?;
+        stdout.flush().await.context("flushing stdout")
+    };
+    tokio::pin!(downstream);
+    tokio::select! {
+        result = &mut downstream => result?,
+        result = upstream => {
+            result?;
+            downstream.await?;
+        }
+    }
+    Ok(0)
+}
@@ -0,0 +1,180 @@
+//! Interactive terminal sessions on devices.
+//!
+//! The terminal is opened through the `device/<id>/proxy/terminal[/<user>]` endpoint
+//! of the hub. While the session runs, the local TTY is put into raw mode, such that
+//! all input, including control characters, is forwarded to the remote shell.
+
+use std::convert::Infallible;
+use std::io::IsTerminal;
+
+use anyhow::Context;
+use anyhow::bail;
+use nexigon_agent_protocol::DeviceTerminalFrame;
+use nexigon_agent_protocol::read_device_terminal_frame;
+use nexigon_agent_protocol::write_terminal_data;
+use nexigon_agent_protocol::write_terminal_resize;
+use nexigon_ids::ids::DeviceId;
+use nexigon_multiplex::ConnectionRef;
+use nexigon_multiplex::OpenError;
+use nix::sys::termios;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::signal::unix::Signal;
+use tokio::signal::unix::SignalKind;
+use tokio::signal::unix::signal;
+
+/// Run an interactive terminal session on `device` and return its exit status.
+pub async fn run_terminal(
+    connection: &mut ConnectionRef,
+    device: &DeviceId,
+    user: Option<&str>,
+) -> anyhow::Result<i32> {
+    let endpoint = terminal_endpoint(device, user)?;
+    let channel = match connection.open(endpoint.as_bytes()).await {
+        Ok(channel) => channel,
+        Err(OpenError::Rejected(rejection)) => {
+            bail!(
+                "terminal rejected: {}",
+                String::from_utf8_lossy(rejection.reason())
+            );
+        }
+        Err(error) => return Err(error).context("opening terminal channel"),
+    };
+    let (mut writer, mut reader) = channel.split();
+    let mut resized = signal(SignalKind::window_change()).context("installing SIGWINCH handler")?;
+    // Restores the TTY when the session ends, also on errors.
+    let _raw_mode = if std::io::stdin().is_terminal() {
+        Some(RawMode::enable()?)
+    } else {
+        None
+    };
+    tokio::select! {
+        result = relay_output(&mut reader) => result,
+        result = relay_input(&mut writer, &mut resized) => match result? {},
+    }
+}
+
+/// Terminal endpoint for a shell of `user`, or of the default user, on `device`.
+fn terminal_endpoint(device: &DeviceId, user: Option<&str>) -> anyhow::Result<String> {
+    match user {
+        // A slash would select another endpoint, e.g., `profile/<name>` a terminal profile.
+        Some(user) if user.is_empty() || user.contains('/') => {
+            bail!("invalid user name {user:?}")
+        }
+        Some(user) => Ok(format!("device/{device}/proxy/terminal/{user}")),
+        None => Ok(format!("device/{device}/proxy/terminal")),
+    }
+}
+
+/// Relay terminal output to stdout until the session exits.
+async fn relay_output(reader: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<i32> {
+    let mut stdout = tokio::io::stdout();
+    loop {
+        match read_device_terminal_frame(reader)
+            .await
+            .context("reading terminal frame")?
+        {
+            DeviceTerminalFrame::Data(data) => {
+                stdout.write_all(&data).await?;
+                stdout.flush().await?;
+            }
+            DeviceTerminalFrame::Exit(code) => return Ok(code),
+        }
+    }
+}
+
+/// Relay stdin and window size changes to the terminal.
+async fn relay_input(
+    writer: &mut (impl AsyncWrite + Unpin),
+    resized: &mut Signal,
+) -> anyhow::Result<Infallible> {
+    if let Some((cols, rows)) = window_size() {
+        write_terminal_resize(writer, cols, rows).await?;
+    }
+    let mut stdin = tokio::io::stdin();
+    let mut stdin_open = true;
+    let mut buf = vec![0u8; 4096];
+    loop {
+        tokio::select! {
+            n = stdin.read(&mut buf), if stdin_open => {
+                let n = n.context("reading from stdin")?;
+                if n == 0 {
+                    // Keep relaying output until the remote shell exits.
+                    stdin_open = false;
+                } else {
+                    write_terminal_data(writer, &buf[..n]).await?;
+                }
+            }
+            _ = resized.recv() => {
+                if let Some((cols, rows)) = window_size() {
+                    write_terminal_resize(writer, cols, rows).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Size of the local terminal as columns and rows, if stdout is a terminal.
+fn window_size() -> Option<(u16, u16)> {
+    let mut ws = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: `TIOCGWINSZ` only writes a `winsize` value to the provided pointer.
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws as *mut _) };
+    (result == 0 && ws.ws_col > 0 && ws.ws_row > 0).then_some((ws.ws_col, ws.ws_row))
+}
+
+/// Raw mode of the local TTY, restoring the original mode when dropped.
+struct RawMode {
+    original: termios::Termios,
+}
+
+impl RawMode {
+    fn enable() -> anyhow::Result<Self> {
+        let stdin = std::io::stdin();
+        let original = termios::tcgetattr(&stdin).context("reading terminal attributes")?;
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(&stdin, termios::SetArg::TCSANOW, &raw)
+            .context("enabling terminal raw mode")?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(std::io::stdin(), termios::SetArg::TCSANOW, &self.original);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nexigon_ids::Generate;
+    use nexigon_ids::ids::DeviceId;
+
+    use super::terminal_endpoint;
+
+    #[test]
+    fn user_names_cannot_select_other_endpoints() {
+        let device = DeviceId::generate();
+        assert_eq!(
+            terminal_endpoint(&device, None).unwrap(),
+            format!("device/{device}/proxy/terminal")
+        );
+        assert_eq!(
+            terminal_endpoint(&device, Some("admin")).unwrap(),
+            format!("device/{device}/proxy/terminal/admin")
+        );
+        for invalid in ["", "profile/diagnostics", "../admin"] {
+            assert!(
+                terminal_endpoint(&device, Some(invalid)).is_err(),
+                "{invalid:?} must be rejected"
+            );
+        }
+    }
+}
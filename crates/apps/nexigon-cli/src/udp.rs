@@ -0,0 +1,131 @@
+//! UDP forwarding to devices.
+//!
+//! UDP has no connections, so every peer sending datagrams to the local socket gets an
+//! association of its own, i.e., a `device/<id>/proxy/forward/udp/<port>` channel. An
+//! association without datagrams in either direction expires after
+//! [`UDP_ASSOCIATION_IDLE_TIMEOUT`](nexigon_agent_protocol::UDP_ASSOCIATION_IDLE_TIMEOUT)
+//! and is reopened by the next datagram of the peer.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::bail;
+use nexigon_agent_protocol::MAX_DATAGRAM_LEN;
+use nexigon_agent_protocol::UdpAssociationExpiry;
+use nexigon_agent_protocol::read_datagram;
+use nexigon_agent_protocol::write_datagram;
+use nexigon_ids::ids::DeviceId;
+use nexigon_multiplex::OpenError;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::debug;
+use tracing::error;
+use tracing::info;
+
+use crate::forward::HubConnection;
+
+/// Datagrams of a peer queued while its association is opened or congested.
+const ASSOCIATION_QUEUE_CAPACITY: usize = 64;
+
+/// Forward a local UDP socket bound to `bind` to a remote device.
+pub async fn forward_udp(
+    hub: HubConnection,
+    device: DeviceId,
+    bind: SocketAddr,
+    remote: u16,
+) -> anyhow::Result<()> {
+    let endpoint = format!("device/{device}/proxy/forward/udp/{remote}");
+    info!("forward UDP {bind} to endpoint {endpoint}");
+    let socket = Arc::new(
+        UdpSocket::bind(bind)
+            .await
+            .with_context(|| format!("binding UDP {bind}"))?,
+    );
+    let mut associations = HashMap::<SocketAddr, mpsc::Sender<Vec<u8>>>::new();
+    let mut buf = vec![0; MAX_DATAGRAM_LEN];
+    loop {
+        let (n, peer) = socket
+            .recv_from(&mut buf)
+            .await
+            .context("receiving datagram")?;
+        let datagrams = match associations.get(&peer) {
+            Some(datagrams) if !datagrams.is_closed() => datagrams,
+            _ => {
+                associations.retain(|_, datagrams| !datagrams.is_closed());
+                let (datagrams_tx, datagrams_rx) = mpsc::channel(ASSOCIATION_QUEUE_CAPACITY);
+                let hub = hub.clone();
+                let endpoint = endpoint.clone();
+                let socket = socket.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = associate(hub, &endpoint, &socket, peer, datagrams_rx).await
+                    {
+                        error!("UDP forwarding for {peer} failed: {error:#}");
+                    }
+                });
+                associations.insert(peer, datagrams_tx);
+                &associations[&peer]
+            }
+        };
+        // Like a congested network, drop datagrams the association cannot keep up with.
+        if datagrams.try_send(buf[..n].to_vec()).is_err() {
+            debug!("dropped datagram from {peer}");
+        }
+    }
+}
+
+/// Relay the datagrams of `peer` over a channel until the association expires.
+async fn associate(
+    hub: HubConnection,
+    endpoint: &str,
+    socket: &UdpSocket,
+    peer: SocketAddr,
+    mut datagrams: mpsc::Receiver<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let channel = match hub.open(endpoint).await {
+        Ok(channel) => channel,
+        Err(OpenError::Rejected(rejection)) => {
+            bail!(
+                "UDP forwarding rejected: {}",
+                String::from_utf8_lossy(rejection.reason())
+            );
+        }
+        Err(error) => return Err(error).context("opening UDP forwarding channel"),
+    };
+    debug!("opened UDP association for {peer}");
+    let (mut sender, mut receiver) = channel.split();
+    let expiry = UdpAssociationExpiry::new();
+
+    let outbound = async {
+        while let Some(datagram) = datagrams.recv().await {
+            expiry.touch();
+            write_datagram(&mut sender, &datagram)
+                .await
+                .context("writing forwarded datagram")?;
+        }
+        anyhow::Ok(())
+    };
+    let inbound = async {
+        while let Some(datagram) = read_datagram(&mut receiver)
+            .await
+            .context("reading forwarded datagram")?
+        {
+            expiry.touch();
+            socket
+                .send_to(&datagram, peer)
+                .await
+                .context("sending forwarded datagram")?;
+        }
+        anyhow::Ok(())
+    };
+
+    tokio::select! {
+        result = outbound => result,
+        result = inbound => result,
+        () = expiry.expired() => {
+            debug!("UDP association for {peer} expired");
+            Ok(())
+        }
+    }
+}
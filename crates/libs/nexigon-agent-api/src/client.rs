@@ -1,12 +1,16 @@
 //! Client for the agent local API Unix socket.
 //!
 //! Lets in-host code execute hub actions through the agent's existing
-//! connection instead of opening a fresh hub link of its own.
+//! connection instead of opening a fresh hub link of its own, hand
+//! events and property writes to the agent's durable outbox, work on
+//! device tasks claimed by the agent, or inspect the agent's status.
 
 use std::io;
 use std::path::Path;
 
 use nexigon_api::Action;
+use nexigon_api::types::devices::DeviceOperationStepReport;
+use nexigon_api::types::devices::DeviceOperationWorkItem;
 use nexigon_api::types::errors::ActionError;
 use nexigon_client::Execute;
 use nexigon_rpc::ExecuteError;
@@ -19,10 +23,15 @@ use tokio::net::UnixStream;
 
 use crate::MAGIC;
 use crate::MAX_HANDSHAKE_LEN;
+use crate::MAX_MESSAGE_LEN;
 use crate::VERSION;
 use crate::types::handshake::ClientHello;
 use crate::types::handshake::ServerError;
 use crate::types::handshake::ServerHello;
+use crate::types::outbox::OutboxItem;
+use crate::types::status::AgentStatus;
+use crate::types::tasks::TaskReport;
+use crate::types::tasks::TaskSubscription;
 
 /// Connect to the agent's local API and request the `executor` endpoint.
 ///
@@ -47,6 +56,186 @@ pub async fn connect_local_executor(
     }
 }
 
+/// Queue an item in the agent's durable outbox.
+///
+/// Returns once the agent has persisted the item; it is delivered to the hub
+/// when the agent is connected. Agents with the outbox disabled reject the
+/// handshake with [`ServerErrorCode::EndpointDisabled`].
+///
+/// [`ServerErrorCode::EndpointDisabled`]: crate::types::handshake::ServerErrorCode::EndpointDisabled
+pub async fn enqueue_outbox_item(
+    socket_path: &Path,
+    item: &OutboxItem,
+) -> Result<(), LocalConnectError> {
+    let mut stream = UnixStream::connect(socket_path).await?;
+    let hello = ClientHello {
+        version: VERSION,
+        endpoint: "outbox".to_owned(),
+        options: Some(serde_json::to_value(item).expect("OutboxItem serialization is infallible")),
+    };
+    write_hello(&mut stream, &hello).await?;
+    match read_hello(&mut stream).await? {
+        ServerHello::Ok(_) => Ok(()),
+        ServerHello::Error(error) => Err(LocalConnectError::Rejected(error)),
+    }
+}
+
+/// Query the status of the running agent.
+pub async fn agent_status(socket_path: &Path) -> Result<AgentStatus, AgentStatusError> {
+    let mut stream = UnixStream::connect(socket_path)
+        .await
+        .map_err(LocalConnectError::Io)?;
+    let hello = ClientHello {
+        version: VERSION,
+        endpoint: "status".to_owned(),
+        options: None,
+    };
+    write_hello(&mut stream, &hello)
+        .await
+        .map_err(LocalConnectError::Io)?;
+    if let ServerHello::Error(error) = read_hello(&mut stream).await? {
+        return Err(LocalConnectError::Rejected(error).into());
+    }
+    let mut len_buf = [0u8; 4];
+    stream.read_exact(&mut len_buf).await?;
+    let len = u32::from_be_bytes(len_buf);
+    if len > MAX_MESSAGE_LEN {
+        return Err(AgentStatusError::MessageTooLarge { len });
+    }
+    let mut body = vec![0u8; len as usize];
+    stream.read_exact(&mut body).await?;
+    serde_json::from_slice(&body).map_err(AgentStatusError::MalformedStatus)
+}
+
+/// Connect to the agent's local API as a worker for the given tasks.
+///
+/// The agent assigns claimed `DeviceTask` work of these tasks to the returned
+/// [`TaskWorker`]. Agents with tasks disabled reject the handshake with
+/// [`ServerErrorCode::EndpointDisabled`].
+///
+/// [`ServerErrorCode::EndpointDisabled`]: crate::types::handshake::ServerErrorCode::EndpointDisabled
+pub async fn connect_task_worker(
+    socket_path: &Path,
+    tasks: Vec<String>,
+) -> Result<TaskWorker, LocalConnectError> {
+    let mut stream = UnixStream::connect(socket_path).await?;
+    let hello = ClientHello {
+        version: VERSION,
+        endpoint: "tasks".to_owned(),
+        options: Some(
+            serde_json::to_value(TaskSubscription { tasks })
+                .expect("TaskSubscription serialization is infallible"),
+        ),
+    };
+    write_hello(&mut stream, &hello).await?;
+    match read_hello(&mut stream).await? {
+        ServerHello::Ok(_) => {
+            let (rx, tx) = tokio::io::split(stream);
+            Ok(TaskWorker {
+                work: TaskWorkReceiver { rx },
+                reports: TaskReporter { tx },
+            })
+        }
+        ServerHello::Error(error) => Err(LocalConnectError::Rejected(error)),
+    }
+}
+
+/// Worker for device tasks claimed by the agent.
+///
+/// The agent takes care of claims, checkpoint persistence, and report retries. A step
+/// that was interrupted, for instance because the worker disconnected, is assigned
+/// again later with its latest checkpoint, possibly to the same worker.
+pub struct TaskWorker {
+    work: TaskWorkReceiver,
+    reports: TaskReporter,
+}
+
+impl TaskWorker {
+    /// Wait for the next assigned work item.
+    ///
+    /// Returns `None` once the agent closed the connection.
+    pub async fn next_work(&mut self) -> Result<Option<DeviceOperationWorkItem>, TaskWorkerError> {
+        self.work.next_work().await
+    }
+
+    /// Report progress or the outcome of an assigned work item.
+    pub async fn report(
+        &mut self,
+        item: &DeviceOperationWorkItem,
+        report: DeviceOperationStepReport,
+    ) -> Result<(), TaskWorkerError> {
+        self.reports.report(item, report).await
+    }
+
+    /// Split the worker so that work can be received while reporting concurrently.
+    pub fn into_split(self) -> (TaskWorkReceiver, TaskReporter) {
+        (self.work, self.reports)
+    }
+}
+
+/// Receiving half of a [`TaskWorker`].
+pub struct TaskWorkReceiver {
+    rx: ReadHalf<UnixStream>,
+}
+
+impl TaskWorkReceiver {
+    /// Wait for the next assigned work item.
+    ///
+    /// Returns `None` once the agent closed the connection.
+    pub async fn next_work(&mut self) -> Result<Option<DeviceOperationWorkItem>, TaskWorkerError> {
+        let mut len_buf = [0u8; 4];
+        match self.rx.read_exact(&mut len_buf).await {
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error.into()),
+        }
+        let len = u32::from_be_bytes(len_buf);
+        if len > MAX_MESSAGE_LEN {
+            return Err(TaskWorkerError::MessageTooLarge { len });
+        }
+        let mut body = vec![0u8; len as usize];
+        self.rx.read_exact(&mut body).await?;
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(TaskWorkerError::MalformedMessage)
+    }
+}
+
+/// Reporting half of a [`TaskWorker`].
+pub struct TaskReporter {
+    tx: WriteHalf<UnixStream>,
+}
+
+impl TaskReporter {
+    /// Report progress or the outcome of an assigned work item.
+    ///
+    /// `Running` reports carry progress and an optional checkpoint to resume from; any
+    /// other status finishes the work item.
+    pub async fn report(
+        &mut self,
+        item: &DeviceOperationWorkItem,
+        report: DeviceOperationStepReport,
+    ) -> Result<(), TaskWorkerError> {
+        let report = TaskReport {
+            device_operation_id: item.device_operation_id.clone(),
+            step_index: item.step_index,
+            report,
+        };
+        let body = serde_json::to_vec(&report).expect("TaskReport serialization is infallible");
+        if body.len() > MAX_MESSAGE_LEN as usize {
+            return Err(TaskWorkerError::MessageTooLarge {
+                len: u32::try_from(body.len()).unwrap_or(u32::MAX),
+            });
+        }
+        self.tx
+            .write_all(&(body.len() as u32).to_be_bytes())
+            .await?;
+        self.tx.write_all(&body).await?;
+        self.tx.flush().await?;
+        Ok(())
+    }
+}
+
 /// Executor that runs hub actions over the agent local API.
 pub struct LocalExecutor {
     rx: ReadHalf<UnixStream>,
@@ -104,6 +293,43 @@ pub enum LocalConnectError {
     Rejected(ServerError),
 }
 
+/// Error querying the status of the agent with [`agent_status`].
+#[derive(Debug, Error)]
+pub enum AgentStatusError {
+    /// Handshake with the agent failed.
+    #[error(transparent)]
+    Connect(#[from] LocalConnectError),
+    /// I/O error while reading the status.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Status exceeds [`MAX_MESSAGE_LEN`].
+    #[error("status exceeds maximum size: {len} > {max}", max = MAX_MESSAGE_LEN)]
+    MessageTooLarge {
+        /// Length the agent announced.
+        len: u32,
+    },
+    /// Status could not be parsed as JSON.
+    #[error("malformed status: {0}")]
+    MalformedStatus(#[source] serde_json::Error),
+}
+
+/// Error exchanging messages with the agent as a [`TaskWorker`].
+#[derive(Debug, Error)]
+pub enum TaskWorkerError {
+    /// I/O error on the underlying Unix socket.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Message exceeds [`MAX_MESSAGE_LEN`].
+    #[error("message exceeds maximum size: {len} > {max}", max = MAX_MESSAGE_LEN)]
+    MessageTooLarge {
+        /// Length of the message.
+        len: u32,
+    },
+    /// Message from the agent could not be parsed as JSON.
+    #[error("malformed message: {0}")]
+    MalformedMessage(#[source] serde_json::Error),
+}
+
 async fn write_hello(stream: &mut UnixStream, hello: &ClientHello) -> io::Result<()> {
     let body = serde_json::to_vec(hello).expect("ClientHello serialization is infallible");
     stream.write_all(&MAGIC).await?;
@@ -30,6 +30,10 @@ pub const MAGIC: [u8; 4] = *b"NXAC";
 /// passed the [`MAGIC`] check.
 pub const MAX_HANDSHAKE_LEN: u32 = 64 * 1024;
 
+/// Maximum size, in bytes, of a single JSON payload exchanged after the handshake
+/// with the `"tasks"` and `"status"` endpoints.
+pub const MAX_MESSAGE_LEN: u32 = 8 * 1024 * 1024;
+
 /// Current protocol version.
 pub const VERSION: u32 = 1;
 
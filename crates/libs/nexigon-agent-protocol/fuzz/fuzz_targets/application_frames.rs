@@ -6,6 +6,7 @@ use libfuzzer_sys::fuzz_target;
 use nexigon_agent_protocol::MAX_COMMAND_FRAME_LEN;
 use nexigon_agent_protocol::MAX_TERMINAL_FRAME_LEN;
 use nexigon_agent_protocol::read_command_frame;
+use nexigon_agent_protocol::read_datagram;
 use nexigon_agent_protocol::read_device_terminal_frame;
 use nexigon_agent_protocol::read_hub_terminal_frame;
 use tokio::io::AsyncWriteExt;
@@ -25,7 +26,7 @@ fuzz_target!(|input: &[u8]| {
     }
 
     RUNTIME.block_on(async {
-        for direction in 0..3 {
+        for direction in 0..4 {
             let (mut tx, mut rx) = tokio::io::duplex(input.len().max(1));
             tx.write_all(input).await.unwrap();
             tx.shutdown().await.unwrap();
@@ -39,6 +40,9 @@ fuzz_target!(|input: &[u8]| {
                 2 => {
                     let _ = read_command_frame::<serde_json::Value>(&mut rx).await;
                 }
+                3 => {
+                    let _ = read_datagram(&mut rx).await;
+                }
                 _ => {}
             }
         }
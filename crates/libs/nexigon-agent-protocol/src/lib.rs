@@ -5,8 +5,10 @@
 //! limit or sends a malformed frame has left the protocol; callers must close the
 //! multiplex channel instead of trying to recover stream alignment.
 
+use std::sync::Mutex;
 use std::time::Duration;
 
+use serde::Deserialize;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use thiserror::Error;
@@ -14,13 +16,32 @@ use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
 
 /// Maximum terminal data carried by one application frame.
 pub const MAX_TERMINAL_DATA_LEN: usize = 1024 * 1024;
 /// Maximum terminal frame body, including the one-byte message type.
 pub const MAX_TERMINAL_FRAME_LEN: usize = MAX_TERMINAL_DATA_LEN + 1;
+/// Exit status of a terminal session that the agent ended because it was idle.
+///
+/// Statuses set by the agent are negative, so they never collide with exit codes.
+pub const TERMINAL_EXIT_IDLE_TIMEOUT: i32 = -1;
+/// Exit status of a terminal session that the agent ended at its maximum duration.
+pub const TERMINAL_EXIT_MAX_DURATION: i32 = -2;
+/// Maximum stream data carried by one exec frame.
+pub const MAX_EXEC_DATA_LEN: usize = 1024 * 1024;
+/// Maximum exec frame body, including the one-byte message type.
+pub const MAX_EXEC_FRAME_LEN: usize = MAX_EXEC_DATA_LEN + 1;
 /// Maximum JSON payload carried by one command frame.
 pub const MAX_COMMAND_FRAME_LEN: usize = 16 * 1024 * 1024;
+/// Maximum UDP datagram carried by one datagram frame.
+pub const MAX_DATAGRAM_LEN: usize = u16::MAX as usize;
+/// Expiry of a UDP forwarding association without datagrams in either direction.
+pub const UDP_ASSOCIATION_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+/// Code prefixed to the reject reasons of forwarding channels denied by policy.
+const FORWARDING_NOT_ALLOWED_CODE: &str = "not-allowed: ";
+/// Prefix of the endpoints of reverse TCP forwarding channels.
+const REVERSE_TCP_ENDPOINT_PREFIX: &str = "reverse/tcp/";
 /// Deadline for the rest of a frame after its first header byte arrives.
 pub const FRAME_READ_TIMEOUT: Duration = Duration::from_secs(60);
 
@@ -39,6 +60,14 @@ const TERMINAL_DATA: u8 = 0x00;
 const TERMINAL_RESIZE: u8 = 0x01;
 const TERMINAL_EXIT: u8 = 0x02;
 
+const EXEC_STDIN: u8 = 0x00;
+const EXEC_STDIN_CLOSE: u8 = 0x01;
+const EXEC_STDOUT: u8 = 0x02;
+const EXEC_STDERR: u8 = 0x03;
+const EXEC_EXIT: u8 = 0x04;
+const EXEC_SIGNAL: u8 = 0x05;
+const EXEC_ERROR: u8 = 0x06;
+
 /// A terminal frame sent from the hub to an agent.
 #[derive(Debug, Eq, PartialEq)]
 pub enum HubTerminalFrame {
@@ -53,8 +82,50 @@ pub enum HubTerminalFrame {
 pub enum DeviceTerminalFrame {
     /// Bytes read from the terminal.
     Data(Vec<u8>),
-    /// Final child process status.
+    /// Final child process status, or a negative `TERMINAL_EXIT_*` status if the agent
+    /// ended the session.
+    Exit(i32),
+}
+
+/// Command started over an `exec` channel, sent by the hub as a command frame before
+/// any exec frames.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExecRequest {
+    /// Executable followed by its arguments.
+    ///
+    /// A bare executable name is resolved through the `PATH` of the command.
+    pub argv: Vec<String>,
+}
+
+/// An exec frame sent from the hub to an agent.
+#[derive(Debug, Eq, PartialEq)]
+pub enum HubExecFrame {
+    /// Bytes to write to the standard input of the command.
+    Stdin(Vec<u8>),
+    /// The standard input of the command ends.
+    StdinClose,
+}
+
+/// Output stream of a command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExecStream {
+    Stdout,
+    Stderr,
+}
+
+/// An exec frame sent from an agent to the hub.
+#[derive(Debug, Eq, PartialEq)]
+pub enum DeviceExecFrame {
+    /// Bytes read from the standard output of the command.
+    Stdout(Vec<u8>),
+    /// Bytes read from the standard error of the command.
+    Stderr(Vec<u8>),
+    /// The command exited with the given code.
     Exit(i32),
+    /// The command was terminated by the given signal.
+    Signal(i32),
+    /// The command could not be started.
+    Error(String),
 }
 
 /// A malformed, oversized, truncated, or stalled application frame.
@@ -79,6 +150,16 @@ pub enum FrameError {
     /// The message type is unknown or invalid in this direction.
     #[error("terminal frame type {0:#04x} is not valid in this direction")]
     InvalidTerminalType(u8),
+    /// A fixed-size exec frame has the wrong body length.
+    #[error("exec frame type {kind:#04x} has length {actual}; expected {expected}")]
+    InvalidExecLength {
+        kind: u8,
+        actual: usize,
+        expected: usize,
+    },
+    /// The exec message type is unknown or invalid in this direction.
+    #[error("exec frame type {0:#04x} is not valid in this direction")]
+    InvalidExecType(u8),
     /// The peer stopped making progress partway through a frame.
     #[error("timed out while reading an application frame")]
     Timeout,
@@ -161,6 +242,126 @@ pub async fn write_terminal_exit(
     write_parts(writer, TERMINAL_EXIT, &code.to_be_bytes()).await
 }
 
+/// Read and validate one hub-to-agent exec frame.
+pub async fn read_hub_exec_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<HubExecFrame, FrameError> {
+    let (frame_len, kind) = read_typed_header(reader, MAX_EXEC_FRAME_LEN).await?;
+    match kind {
+        EXEC_STDIN => Ok(HubExecFrame::Stdin(
+            read_payload(reader, frame_len - 1).await?,
+        )),
+        EXEC_STDIN_CLOSE => {
+            require_exec_len(kind, frame_len, 1)?;
+            Ok(HubExecFrame::StdinClose)
+        }
+        _ => Err(FrameError::InvalidExecType(kind)),
+    }
+}
+
+/// Read and validate one agent-to-hub exec frame.
+pub async fn read_device_exec_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<DeviceExecFrame, FrameError> {
+    let (frame_len, kind) = read_typed_header(reader, MAX_EXEC_FRAME_LEN).await?;
+    match kind {
+        EXEC_STDOUT => Ok(DeviceExecFrame::Stdout(
+            read_payload(reader, frame_len - 1).await?,
+        )),
+        EXEC_STDERR => Ok(DeviceExecFrame::Stderr(
+            read_payload(reader, frame_len - 1).await?,
+        )),
+        EXEC_EXIT | EXEC_SIGNAL => {
+            require_exec_len(kind, frame_len, 5)?;
+            let value = i32::from_be_bytes(read_array::<4>(reader).await?);
+            Ok(if kind == EXEC_EXIT {
+                DeviceExecFrame::Exit(value)
+            } else {
+                DeviceExecFrame::Signal(value)
+            })
+        }
+        EXEC_ERROR => {
+            let message = read_payload(reader, frame_len - 1).await?;
+            Ok(DeviceExecFrame::Error(
+                String::from_utf8_lossy(&message).into_owned(),
+            ))
+        }
+        _ => Err(FrameError::InvalidExecType(kind)),
+    }
+}
+
+/// Write one hub-to-agent stdin frame.
+pub async fn write_exec_stdin(
+    writer: &mut (impl AsyncWrite + Unpin),
+    data: &[u8],
+) -> Result<(), FrameError> {
+    write_exec_data(writer, EXEC_STDIN, data).await
+}
+
+/// Write one hub-to-agent frame that closes the standard input of the command.
+pub async fn write_exec_stdin_close(
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), FrameError> {
+    write_parts(writer, EXEC_STDIN_CLOSE, &[]).await
+}
+
+/// Write one agent-to-hub output frame.
+pub async fn write_exec_output(
+    writer: &mut (impl AsyncWrite + Unpin),
+    stream: ExecStream,
+    data: &[u8],
+) -> Result<(), FrameError> {
+    let kind = match stream {
+        ExecStream::Stdout => EXEC_STDOUT,
+        ExecStream::Stderr => EXEC_STDERR,
+    };
+    write_exec_data(writer, kind, data).await
+}
+
+/// Write one agent-to-hub frame with the exit code of the command.
+pub async fn write_exec_exit(
+    writer: &mut (impl AsyncWrite + Unpin),
+    code: i32,
+) -> Result<(), FrameError> {
+    write_parts(writer, EXEC_EXIT, &code.to_be_bytes()).await
+}
+
+/// Write one agent-to-hub frame with the signal that terminated the command.
+pub async fn write_exec_signal(
+    writer: &mut (impl AsyncWrite + Unpin),
+    signal: i32,
+) -> Result<(), FrameError> {
+    write_parts(writer, EXEC_SIGNAL, &signal.to_be_bytes()).await
+}
+
+/// Write one agent-to-hub frame explaining why the command could not be started.
+///
+/// Messages longer than [`MAX_EXEC_DATA_LEN`] are truncated.
+pub async fn write_exec_error(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &str,
+) -> Result<(), FrameError> {
+    let mut end = message.len().min(MAX_EXEC_DATA_LEN);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    write_parts(writer, EXEC_ERROR, &message.as_bytes()[..end]).await
+}
+
+async fn write_exec_data(
+    writer: &mut (impl AsyncWrite + Unpin),
+    kind: u8,
+    data: &[u8],
+) -> Result<(), FrameError> {
+    if data.len() > MAX_EXEC_DATA_LEN {
+        return Err(FrameError::TooLarge {
+            actual: data.len(),
+            limit: MAX_EXEC_DATA_LEN,
+        });
+    }
+    write_parts(writer, kind, data).await
+}
+
 /// Read one bounded length-prefixed JSON command frame.
 pub async fn read_command_frame<T: DeserializeOwned>(
     reader: &mut (impl AsyncRead + Unpin),
@@ -189,10 +390,149 @@ pub async fn write_command_frame<T: Serialize>(
     Ok(())
 }
 
+/// Read one length-prefixed UDP datagram in either direction.
+///
+/// Unlike other frames, datagrams have a two-byte length and may be empty. Returns
+/// `None` if the channel ends before the next datagram.
+pub async fn read_datagram(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<Vec<u8>>, FrameError> {
+    let mut len = [0; 2];
+    if reader.read(&mut len[..1]).await? == 0 {
+        return Ok(None);
+    }
+    read_exact_with_timeout(reader, &mut len[1..]).await?;
+    Ok(Some(
+        read_payload(reader, u16::from_be_bytes(len).into()).await?,
+    ))
+}
+
+/// Write one length-prefixed UDP datagram in either direction.
+pub async fn write_datagram(
+    writer: &mut (impl AsyncWrite + Unpin),
+    datagram: &[u8],
+) -> Result<(), FrameError> {
+    let len = u16::try_from(datagram.len()).map_err(|_| FrameError::TooLarge {
+        actual: datagram.len(),
+        limit: MAX_DATAGRAM_LEN,
+    })?;
+    writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(datagram).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Endpoint of the reverse TCP forwarding channels for `port` of a device.
+///
+/// Reverse forwarding exposes a service of a client, e.g., the CLI, on `port` of the
+/// loopback interface of a device. All of its channels use this endpoint:
+///
+/// 1. The client opens `device/<id>/proxy/reverse/tcp/<port>` on the hub, which opens
+///    the control channel `reverse/tcp/<port>` to the agent. The agent accepts it once
+///    it listens on `port` and closes it when it stops listening. The control channel
+///    carries no data, the client closes it to end the forward.
+/// 2. For every connection accepted on `port`, the agent opens a `reverse/tcp/<port>`
+///    channel to the hub. The hub routes it to the client connection that holds the
+///    control channel for `port` of the device, as a `reverse/tcp/<port>` channel
+///    request, and rejects it if there is none. As the agent binds a port at most once,
+///    the port identifies the control channel.
+/// 3. The client relays the channel to its service, or rejects it if the service is
+///    unavailable, in which case the agent closes the accepted connection.
+pub fn reverse_tcp_endpoint(port: u16) -> String {
+    format!("{REVERSE_TCP_ENDPOINT_PREFIX}{port}")
+}
+
+/// Port of a [reverse TCP forwarding endpoint](reverse_tcp_endpoint).
+pub fn parse_reverse_tcp_endpoint(endpoint: &[u8]) -> Option<u16> {
+    std::str::from_utf8(endpoint)
+        .ok()?
+        .strip_prefix(REVERSE_TCP_ENDPOINT_PREFIX)?
+        .parse()
+        .ok()
+}
+
+/// Class of a rejected forwarding channel.
+///
+/// Reject reasons are meant for humans, except for a stable code that the agent prefixes
+/// to the reasons of channels denied by its forwarding policy. Clients classify
+/// rejections by this code instead of the wording of the reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardingRejection {
+    /// The forwarding policy of the agent denies the target.
+    NotAllowed,
+    /// The target is unavailable or the channel cannot be served otherwise.
+    Unavailable,
+}
+
+impl ForwardingRejection {
+    /// Reject reason for a channel denied by the forwarding policy.
+    pub fn not_allowed_reason(message: &str) -> String {
+        format!("{FORWARDING_NOT_ALLOWED_CODE}{message}")
+    }
+
+    /// Classify the reject reason of a forwarding channel.
+    pub fn classify(reason: &[u8]) -> Self {
+        if reason.starts_with(FORWARDING_NOT_ALLOWED_CODE.as_bytes()) {
+            Self::NotAllowed
+        } else {
+            Self::Unavailable
+        }
+    }
+}
+
+/// Idle expiry of a UDP forwarding association, shared by both ends of the channel.
+#[derive(Debug)]
+pub struct UdpAssociationExpiry {
+    last_datagram: Mutex<Instant>,
+}
+
+impl UdpAssociationExpiry {
+    /// Start the expiry of a new association.
+    pub fn new() -> Self {
+        Self {
+            last_datagram: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Record a datagram in either direction.
+    pub fn touch(&self) {
+        if let Ok(mut last_datagram) = self.last_datagram.lock() {
+            *last_datagram = Instant::now();
+        }
+    }
+
+    /// Wait until the association has been idle for [`UDP_ASSOCIATION_IDLE_TIMEOUT`].
+    pub async fn expired(&self) {
+        loop {
+            let deadline = match self.last_datagram.lock() {
+                Ok(last_datagram) => *last_datagram + UDP_ASSOCIATION_IDLE_TIMEOUT,
+                Err(_) => return,
+            };
+            if deadline <= Instant::now() {
+                return;
+            }
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
+
+impl Default for UdpAssociationExpiry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 async fn read_terminal_header(
     reader: &mut (impl AsyncRead + Unpin),
 ) -> Result<(usize, u8), FrameError> {
-    let frame_len = read_length(reader, MAX_TERMINAL_FRAME_LEN).await?;
+    read_typed_header(reader, MAX_TERMINAL_FRAME_LEN).await
+}
+
+async fn read_typed_header(
+    reader: &mut (impl AsyncRead + Unpin),
+    limit: usize,
+) -> Result<(usize, u8), FrameError> {
+    let frame_len = read_length(reader, limit).await?;
     let kind = read_array::<1>(reader).await?[0];
     Ok((frame_len, kind))
 }
@@ -208,6 +548,17 @@ fn require_terminal_len(kind: u8, actual: usize, expected: usize) -> Result<(),
     Ok(())
 }
 
+fn require_exec_len(kind: u8, actual: usize, expected: usize) -> Result<(), FrameError> {
+    if actual != expected {
+        return Err(FrameError::InvalidExecLength {
+            kind,
+            actual,
+            expected,
+        });
+    }
+    Ok(())
+}
+
 async fn read_length(
     reader: &mut (impl AsyncRead + Unpin),
     limit: usize,
@@ -259,7 +610,7 @@ async fn write_parts(
     kind: u8,
     payload: &[u8],
 ) -> Result<(), FrameError> {
-    let frame_len = u32::try_from(payload.len() + 1).expect("terminal frame limit fits in u32");
+    let frame_len = u32::try_from(payload.len() + 1).expect("frame limits fit in u32");
     writer.write_all(&frame_len.to_be_bytes()).await?;
     writer.write_all(&[kind]).await?;
     writer.write_all(payload).await?;
@@ -381,6 +732,63 @@ mod tests {
         assert!(matches!(read.await.unwrap(), Err(FrameError::Timeout)));
     }
 
+    #[tokio::test]
+    async fn exec_frames_round_trip_in_their_direction_only() {
+        let (mut tx, mut rx) = tokio::io::duplex(256);
+        write_exec_stdin(&mut tx, b"input").await.unwrap();
+        write_exec_stdin_close(&mut tx).await.unwrap();
+        assert_eq!(
+            read_hub_exec_frame(&mut rx).await.unwrap(),
+            HubExecFrame::Stdin(b"input".to_vec())
+        );
+        assert_eq!(
+            read_hub_exec_frame(&mut rx).await.unwrap(),
+            HubExecFrame::StdinClose
+        );
+
+        write_exec_output(&mut tx, ExecStream::Stdout, b"\x00\xff")
+            .await
+            .unwrap();
+        write_exec_output(&mut tx, ExecStream::Stderr, b"warning")
+            .await
+            .unwrap();
+        write_exec_exit(&mut tx, -1).await.unwrap();
+        write_exec_signal(&mut tx, 9).await.unwrap();
+        write_exec_error(&mut tx, "no such user").await.unwrap();
+        for expected in [
+            DeviceExecFrame::Stdout(b"\x00\xff".to_vec()),
+            DeviceExecFrame::Stderr(b"warning".to_vec()),
+            DeviceExecFrame::Exit(-1),
+            DeviceExecFrame::Signal(9),
+            DeviceExecFrame::Error("no such user".to_owned()),
+        ] {
+            assert_eq!(read_device_exec_frame(&mut rx).await.unwrap(), expected);
+        }
+
+        let mut stdout_from_hub = terminal_input(2, &[EXEC_STDOUT, 0]).await;
+        assert!(matches!(
+            read_hub_exec_frame(&mut stdout_from_hub).await,
+            Err(FrameError::InvalidExecType(EXEC_STDOUT))
+        ));
+        let mut oversized_close = terminal_input(2, &[EXEC_STDIN_CLOSE, 0]).await;
+        assert!(matches!(
+            read_hub_exec_frame(&mut oversized_close).await,
+            Err(FrameError::InvalidExecLength {
+                kind: EXEC_STDIN_CLOSE,
+                actual: 2,
+                expected: 1,
+            })
+        ));
+        let mut short_exit = terminal_input(3, &[EXEC_EXIT, 0, 0]).await;
+        assert!(matches!(
+            read_device_exec_frame(&mut short_exit).await,
+            Err(FrameError::InvalidExecLength {
+                kind: EXEC_EXIT,
+                ..
+            })
+        ));
+    }
+
     #[tokio::test]
     async fn command_frames_reject_zero_limit_plus_one_and_max_u32() {
         let mut exact = terminal_input(MAX_COMMAND_FRAME_LEN as u32, &[]).await;
@@ -418,6 +826,76 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn datagrams_keep_their_boundaries() {
+        let (mut tx, mut rx) = tokio::io::duplex(256);
+        write_datagram(&mut tx, b"first").await.unwrap();
+        write_datagram(&mut tx, b"").await.unwrap();
+        write_datagram(&mut tx, b"\x00\xff").await.unwrap();
+        tx.shutdown().await.unwrap();
+        for expected in [&b"first"[..], b"", b"\x00\xff"] {
+            assert_eq!(
+                read_datagram(&mut rx).await.unwrap().as_deref(),
+                Some(expected)
+            );
+        }
+        assert_eq!(read_datagram(&mut rx).await.unwrap(), None);
+
+        let oversized = vec![0; MAX_DATAGRAM_LEN + 1];
+        assert!(matches!(
+            write_datagram(&mut tokio::io::sink(), &oversized).await,
+            Err(FrameError::TooLarge { .. })
+        ));
+
+        let (mut tx, mut rx) = tokio::io::duplex(8);
+        tx.write_all(&[0, 4, 1, 2]).await.unwrap();
+        tx.shutdown().await.unwrap();
+        assert!(matches!(
+            read_datagram(&mut rx).await,
+            Err(FrameError::Io(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn reverse_tcp_endpoints_carry_their_port() {
+        assert_eq!(reverse_tcp_endpoint(8080), "reverse/tcp/8080");
+        assert_eq!(parse_reverse_tcp_endpoint(b"reverse/tcp/8080"), Some(8080));
+        for invalid in [
+            &b"reverse/tcp/"[..],
+            b"reverse/tcp/65536",
+            b"reverse/udp/8080",
+            b"forward/tcp/8080",
+        ] {
+            assert_eq!(parse_reverse_tcp_endpoint(invalid), None);
+        }
+    }
+
+    #[test]
+    fn forwarding_rejections_are_classified_by_their_code() {
+        let reason = ForwardingRejection::not_allowed_reason("TCP forwarding is not allowed");
+        assert_eq!(
+            ForwardingRejection::classify(reason.as_bytes()),
+            ForwardingRejection::NotAllowed
+        );
+        for reason in [&b"TCP forwarding is not allowed"[..], b"", b"not-allowed"] {
+            assert_eq!(
+                ForwardingRejection::classify(reason),
+                ForwardingRejection::Unavailable
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn udp_associations_expire_after_the_last_datagram() {
+        let expiry = UdpAssociationExpiry::new();
+        let started = Instant::now();
+        tokio::join!(expiry.expired(), async {
+            tokio::time::sleep(UDP_ASSOCIATION_IDLE_TIMEOUT / 2).await;
+            expiry.touch();
+        });
+        assert_eq!(started.elapsed(), UDP_ASSOCIATION_IDLE_TIMEOUT * 3 / 2);
+    }
+
     proptest! {
         #[test]
         fn any_oversized_declared_terminal_length_is_rejected(
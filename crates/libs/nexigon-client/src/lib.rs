@@ -60,6 +60,16 @@ pub struct ClientIdentity {
     private_key_der: PrivateKeyDer<'static>,
 }
 
+impl Clone for ClientIdentity {
+    fn clone(&self) -> Self {
+        Self {
+            certificate_pem: self.certificate_pem.clone(),
+            certificate_chain_der: self.certificate_chain_der.clone(),
+            private_key_der: self.private_key_der.clone_key(),
+        }
+    }
+}
+
 impl ClientIdentity {
     /// Create a new [`ClientIdentity`] with the given PEM-encoded certificate and private
     /// key.
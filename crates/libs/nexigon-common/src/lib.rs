@@ -62,6 +62,8 @@ use nexigon_ids::ids::RepositoryId;
 mod repository_upload;
 pub mod secure_file;
 
+pub use repository_upload::upload_repository_asset;
+
 // ── Value parsing helpers ────────────────────────────────────────────
 
 fn parse_json_object(s: &str) -> Result<serde_json::Value, String> {
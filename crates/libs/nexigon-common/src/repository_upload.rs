@@ -264,7 +264,12 @@ impl UploadStream {
     }
 }
 
-pub(super) async fn upload_repository_asset(
+/// Upload the file at `path` as an asset of a repository.
+///
+/// The file is hashed before the asset is registered, streamed to storage in bounded
+/// chunks, and rejected if it changes in the meantime. Uploading content the repository
+/// already holds only finalizes the existing asset.
+pub async fn upload_repository_asset(
     executor: &mut impl Execute,
     repository_id: RepositoryId,
     path: &Path,
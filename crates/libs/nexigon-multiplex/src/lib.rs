@@ -322,6 +322,18 @@ impl ConnectionRef {
         *self.shared.smoothened_rtt.read()
     }
 
+    /// Obtain the time since the peer last answered a liveness ping.
+    ///
+    /// Returns `None` until the first pong has been received. While the connection is
+    /// polled, it fails once a ping stays unanswered for the configured pong timeout, so
+    /// a much longer duration indicates that the connection is no longer polled.
+    pub fn time_since_last_pong(&self) -> Option<Duration> {
+        self.shared
+            .last_pong
+            .read()
+            .map(|last_pong| last_pong.elapsed())
+    }
+
     /// Obtain an estimate on the number of frames sent over the connection.
     pub fn estimate_frames_sent(&self) -> u64 {
         self.shared.frames_sent.load(atomic::Ordering::Relaxed)
@@ -332,6 +344,20 @@ impl ConnectionRef {
         self.shared.frames_received.load(atomic::Ordering::Relaxed)
     }
 
+    /// Obtain an estimate on the number of bytes sent over the connection.
+    ///
+    /// Counts encoded frames, including frame headers and control frames.
+    pub fn estimate_bytes_sent(&self) -> u64 {
+        self.shared.bytes_sent.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Obtain an estimate on the number of bytes received over the connection.
+    ///
+    /// Counts encoded frames, including frame headers and control frames.
+    pub fn estimate_bytes_received(&self) -> u64 {
+        self.shared.bytes_received.load(atomic::Ordering::Relaxed)
+    }
+
     /// Send a frame over the connection.
     ///
     /// Returns `true` if the frame has been successfully queued for sending.
@@ -545,10 +571,16 @@ struct ConnectionShared {
     limits: ConnectionLimits,
     /// Smoothened estimated round-trip time.
     smoothened_rtt: RwLock<Option<Duration>>,
+    /// Time at which the last pong has been received.
+    last_pong: RwLock<Option<Instant>>,
     /// Frames sent over the connection.
     frames_sent: AtomicU64,
     /// Frames received over the connection.
     frames_received: AtomicU64,
+    /// Encoded frame bytes sent over the connection.
+    bytes_sent: AtomicU64,
+    /// Encoded frame bytes received over the connection.
+    bytes_received: AtomicU64,
 }
 
 /// Bounded queues shared between a connection and its references.
@@ -968,8 +1000,11 @@ impl<T: ConnectionTransport> Connection<T> {
             pending_incoming_requests: atomic::AtomicUsize::new(0),
             limits,
             smoothened_rtt: RwLock::new(None),
+            last_pong: RwLock::new(None),
             frames_sent: AtomicU64::new(0),
             frames_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
         });
         let this_ref = ConnectionRef { shared };
         assert!(
@@ -1576,6 +1611,7 @@ impl<T: ConnectionTransport> Connection<T> {
             self.smoothened_rtt = Some(latest_rtt);
         }
         *self.this_ref.shared.smoothened_rtt.write() = self.smoothened_rtt;
+        *self.this_ref.shared.last_pong.write() = Some(Instant::now());
         Ok(())
     }
 
@@ -1659,6 +1695,10 @@ impl<T: ConnectionTransport> Connection<T> {
                             .shared
                             .frames_sent
                             .fetch_add(1, atomic::Ordering::Relaxed);
+                        self.this_ref
+                            .shared
+                            .bytes_sent
+                            .fetch_add(frame.as_bytes().len() as u64, atomic::Ordering::Relaxed);
                         if let Err(error) = self.transport.start_send_unpin(frame.into()) {
                             error!(%error, "transport send error");
                             return Poll::Ready(Err(ConnectionError::TransportError(
@@ -1736,6 +1776,7 @@ impl<T: ConnectionTransport> Connection<T> {
                             ResourceLimitExceeded("an incoming frame exceeded the size limit"),
                         )));
                     }
+                    let frame_len = frame.len() as u64;
                     match Frame::parse(frame) {
                         Ok(frame) => {
                             trace!(frame = %frame, "received frame from transport");
@@ -1743,6 +1784,10 @@ impl<T: ConnectionTransport> Connection<T> {
                                 .shared
                                 .frames_received
                                 .fetch_add(1, atomic::Ordering::Relaxed);
+                            self.this_ref
+                                .shared
+                                .bytes_received
+                                .fetch_add(frame_len, atomic::Ordering::Relaxed);
                             if handshake_expired
                                 && !self.hello_received
                                 && !matches!(&frame, Frame::Hello(_))
@@ -3167,6 +3212,8 @@ mod tests {
             Frame::Hello(_)
         ));
 
+        let connection_ref = connection.make_ref();
+        assert!(connection_ref.time_since_last_pong().is_none());
         let mut next = Box::pin(connection.next());
         assert!(futures::poll!(next.as_mut()).is_pending());
         assert!(matches!(
@@ -3177,6 +3224,7 @@ mod tests {
         send_peer_frame(&mut peer, FramePong::new().into()).await;
         assert!(futures::poll!(next.as_mut()).is_pending());
         drop(next);
+        assert!(connection_ref.time_since_last_pong().is_some());
 
         send_peer_frame(
             &mut peer,
@@ -115,9 +115,16 @@ Description=Nexigon Agent
 After=network.target
 
 [Service]
-Type=exec
+Type=notify
+NotifyAccess=main
+# The agent reports readiness once it is connected to the hub, which may take
+# arbitrarily long on devices that boot offline.
+TimeoutStartSec=infinity
+# The agent withholds watchdog pings when its hub connection is hung.
+WatchdogSec=120s
 PIDFile=/run/nexigon-agent.pid
 ExecStart=/usr/bin/nexigon-agent run
+ExecReload=/bin/kill -HUP \$MAINPID
 ExecStop=-/sbin/start-stop-daemon --quiet --stop --retry QUIT/5 --pidfile /run/nexigon-agent.pid
 TimeoutStopSec=5
 KillMode=mixed